@@ -0,0 +1,727 @@
+//! Arithmetic entropy decoding
+//!
+//! This implements the QM-coder described in Annex D of the specification,
+//! the procedures for decoding DCT coefficients in section F.2.4 and
+//! those for decoding lossless differences in Annex H.
+//!
+//! Unlike Huffman decoding, which works on a bit level, the arithmetic decoder
+//! reads a byte at a time and adapts its probability estimates after every
+//! decision, so there isn't much room for tricks here, we follow the spec
+//! (and libjpeg's `jdarith.c`, which does the same) closely.
+//!
+//! Statistics bins are kept per conditioning table and are reset at the start of every
+//! scan and on restart markers.
+use std::io::Cursor;
+
+use crate::decoder::MAX_COMPONENTS;
+use crate::errors::DecodeErrors;
+use crate::marker::Marker;
+use crate::misc::{find_marker, UN_ZIGZAG};
+
+/// Probability estimation state machine, Table D.2
+///
+/// Each entry contains `(Qe_Value, Next_Index_LPS, Next_Index_MPS, Switch_MPS)`
+///
+/// The last entry is not part of the spec, it is a fixed probability estimate of
+/// 0.5 used for decoding signs of AC coefficients (ITU-T T.851 Table 5).
+#[rustfmt::skip]
+const QE_TABLE: [(u16, u8, u8, u8); 114] = [
+    (0x5A1D, 1, 1, 1), (0x2586, 14, 2, 0), (0x1114, 16, 3, 0), (0x080B, 18, 4, 0),
+    (0x03D8, 20, 5, 0), (0x01DA, 23, 6, 0), (0x00E5, 25, 7, 0), (0x006F, 28, 8, 0),
+    (0x0036, 30, 9, 0), (0x001A, 33, 10, 0), (0x000D, 35, 11, 0), (0x0006, 9, 12, 0),
+    (0x0003, 10, 13, 0), (0x0001, 12, 13, 0), (0x5A7F, 15, 15, 1), (0x3F25, 36, 16, 0),
+    (0x2CF2, 38, 17, 0), (0x207C, 39, 18, 0), (0x17B9, 40, 19, 0), (0x1182, 42, 20, 0),
+    (0x0CEF, 43, 21, 0), (0x09A1, 45, 22, 0), (0x072F, 46, 23, 0), (0x055C, 48, 24, 0),
+    (0x0406, 49, 25, 0), (0x0303, 51, 26, 0), (0x0240, 52, 27, 0), (0x01B1, 54, 28, 0),
+    (0x0144, 56, 29, 0), (0x00F5, 57, 30, 0), (0x00B7, 59, 31, 0), (0x008A, 60, 32, 0),
+    (0x0068, 62, 33, 0), (0x004E, 63, 34, 0), (0x003B, 32, 35, 0), (0x002C, 33, 9, 0),
+    (0x5AE1, 37, 37, 1), (0x484C, 64, 38, 0), (0x3A0D, 65, 39, 0), (0x2EF1, 67, 40, 0),
+    (0x261F, 68, 41, 0), (0x1F33, 69, 42, 0), (0x19A8, 70, 43, 0), (0x1518, 72, 44, 0),
+    (0x1177, 73, 45, 0), (0x0E74, 74, 46, 0), (0x0BFB, 75, 47, 0), (0x09F8, 77, 48, 0),
+    (0x0861, 78, 49, 0), (0x0706, 79, 50, 0), (0x05CD, 48, 51, 0), (0x04DE, 50, 52, 0),
+    (0x040F, 50, 53, 0), (0x0363, 51, 54, 0), (0x02D4, 52, 55, 0), (0x025C, 53, 56, 0),
+    (0x01F8, 54, 57, 0), (0x01A4, 55, 58, 0), (0x0160, 56, 59, 0), (0x0125, 57, 60, 0),
+    (0x00F6, 58, 61, 0), (0x00CB, 59, 62, 0), (0x00AB, 61, 63, 0), (0x008F, 61, 32, 0),
+    (0x5B12, 65, 65, 1), (0x4D04, 80, 66, 0), (0x412C, 81, 67, 0), (0x37D8, 82, 68, 0),
+    (0x2FE8, 83, 69, 0), (0x293C, 84, 70, 0), (0x2379, 86, 71, 0), (0x1EDF, 87, 72, 0),
+    (0x1AA9, 87, 73, 0), (0x174E, 72, 74, 0), (0x1424, 72, 75, 0), (0x119C, 74, 76, 0),
+    (0x0F6B, 74, 77, 0), (0x0D51, 75, 78, 0), (0x0BB6, 77, 79, 0), (0x0A40, 77, 48, 0),
+    (0x5832, 80, 81, 1), (0x4D1C, 88, 82, 0), (0x438E, 89, 83, 0), (0x3BDD, 90, 84, 0),
+    (0x34EE, 91, 85, 0), (0x2EAE, 92, 86, 0), (0x299A, 93, 87, 0), (0x2516, 86, 71, 0),
+    (0x5570, 88, 89, 1), (0x4CA9, 95, 90, 0), (0x44D9, 96, 91, 0), (0x3E22, 97, 92, 0),
+    (0x3824, 99, 93, 0), (0x32B4, 99, 94, 0), (0x2E17, 93, 86, 0), (0x56A8, 95, 96, 1),
+    (0x4F46, 101, 97, 0), (0x47E5, 102, 98, 0), (0x41CF, 103, 99, 0), (0x3C3D, 104, 100, 0),
+    (0x375E, 99, 93, 0), (0x5231, 105, 102, 0), (0x4C0F, 106, 103, 0), (0x4639, 107, 104, 0),
+    (0x415E, 103, 99, 0), (0x5627, 105, 106, 1), (0x50E7, 108, 107, 0), (0x4B85, 109, 103, 0),
+    (0x5597, 110, 109, 0), (0x504F, 111, 107, 0), (0x5A10, 110, 111, 1), (0x5522, 112, 109, 0),
+    (0x59EB, 112, 111, 1), (0x5A1D, 113, 113, 0),];
+
+/// Index of the fixed probability estimate in `QE_TABLE`
+const FIXED_BIN: u8 = 113;
+
+/// Conditioning tables for arithmetic coding, defined by the DAC marker
+///
+/// Tables not defined by DAC use the default values in section F.1.4.4
+#[derive(Copy, Clone)]
+pub(crate) struct ArithmeticConditioning
+{
+    /// Lower bound (L) of the DC conditioning for each table
+    pub dc_l: [u8; MAX_COMPONENTS],
+    /// Upper bound (U) of the DC conditioning for each table
+    pub dc_u: [u8; MAX_COMPONENTS],
+    /// AC conditioning (Kx) for each table
+    pub ac_k: [u8; MAX_COMPONENTS],
+}
+
+impl Default for ArithmeticConditioning
+{
+    fn default() -> Self
+    {
+        ArithmeticConditioning {
+            dc_l: [0; MAX_COMPONENTS],
+            dc_u: [1; MAX_COMPONENTS],
+            ac_k: [5; MAX_COMPONENTS],
+        }
+    }
+}
+
+/// State of the QM-coder decoder, section D.2
+struct QmDecoder
+{
+    /// The code register (C)
+    c:      i64,
+    /// The probability interval (A)
+    a:      i64,
+    /// Bit counter, negative while the initial bytes are being read
+    ct:     i32,
+    /// Marker found while reading the entropy coded data
+    marker: Option<Marker>,
+}
+
+impl QmDecoder
+{
+    const fn new() -> QmDecoder
+    {
+        QmDecoder {
+            c:      0,
+            a:      0,
+            ct:     -16, // read two initial bytes
+            marker: None,
+        }
+    }
+
+    /// Read the next byte of entropy coded data, removing stuffed zeroes.
+    ///
+    /// Once a marker is found, zeroes are returned, hitting a marker before
+    /// decoding completes is legal in arithmetic coding.
+    fn next_byte(&mut self, reader: &mut Cursor<Vec<u8>>) -> Result<i64, DecodeErrors>
+    {
+        if self.marker.is_some()
+        {
+            return Ok(0);
+        }
+        let byte = read_u8(reader);
+
+        if byte != 0xFF
+        {
+            return Ok(i64::from(byte));
+        }
+        let mut next_byte = read_u8(reader);
+        // swallow fill bytes
+        while next_byte == 0xFF
+        {
+            next_byte = read_u8(reader);
+        }
+
+        if next_byte == 0x00
+        {
+            // stuffed zero
+            return Ok(0xFF);
+        }
+        self.marker = Some(Marker::from_u8(next_byte).ok_or_else(|| {
+            DecodeErrors::Format(format!("Unknown marker 0xFF{next_byte:X}"))
+        })?);
+
+        Ok(0)
+    }
+
+    /// Decode a single binary decision using the statistics bin `st`,
+    /// updating the probability estimate stored in the bin.
+    ///
+    /// The bin stores the index into `QE_TABLE` in the lower 7 bits and the
+    /// more probable symbol in the top bit.
+    ///
+    /// See the `DECODE`, `RENORM_D` and `BYTEIN` procedures in section D.2
+    #[inline]
+    fn decode(&mut self, reader: &mut Cursor<Vec<u8>>, st: &mut u8) -> Result<bool, DecodeErrors>
+    {
+        // renormalization and data input
+        while self.a < 0x8000
+        {
+            self.ct -= 1;
+
+            if self.ct < 0
+            {
+                let data = self.next_byte(reader)?;
+
+                self.c = (self.c << 8) | data;
+                self.ct += 8;
+
+                if self.ct < 0
+                {
+                    // need more initial bytes
+                    self.ct += 1;
+
+                    if self.ct == 0
+                    {
+                        // got the two initial bytes, re-init A and exit the loop.
+                        self.a = 0x8000; // => a = 0x10000 after the shift below
+                    }
+                }
+            }
+            self.a <<= 1;
+        }
+
+        let sv = *st;
+        let (qe, next_lps, next_mps, switch_mps) = QE_TABLE[usize::from(sv & 0x7F)];
+        let qe = i64::from(qe);
+        let mps = sv & 0x80;
+
+        self.a -= qe;
+
+        let temp = self.a << self.ct;
+
+        if self.c >= temp
+        {
+            self.c -= temp;
+            // conditional LPS exchange
+            if self.a < qe
+            {
+                self.a = qe;
+                *st = mps | next_mps;
+
+                return Ok(mps != 0);
+            }
+            self.a = qe;
+            *st = (mps ^ (switch_mps << 7)) | next_lps;
+
+            return Ok(mps == 0);
+        }
+        if self.a < 0x8000
+        {
+            // conditional MPS exchange
+            if self.a < qe
+            {
+                *st = (mps ^ (switch_mps << 7)) | next_lps;
+
+                return Ok(mps == 0);
+            }
+            *st = mps | next_mps;
+        }
+        Ok(mps != 0)
+    }
+}
+
+/// An arithmetic decoder for DCT coefficients.
+///
+/// This plays the role [`BitStream`](crate::bitstream::BitStream) plays for
+/// Huffman coded images.
+pub(crate) struct ArithmeticDecoder
+{
+    decoder:         QmDecoder,
+    conditioning:    ArithmeticConditioning,
+    /// DC statistics bins for each conditioning table, lossless images use
+    /// 158 of them, DCT images 64
+    dc_stats:        [[u8; 158]; MAX_COMPONENTS],
+    /// AC statistics bins for each conditioning table
+    ac_stats:        [[u8; 256]; MAX_COMPONENTS],
+    /// Statistics bin for the fixed probability estimate
+    fixed_bin:       u8,
+    /// DC conditioning context of each component, section F.1.4.4.1.2
+    dc_context:      [usize; MAX_COMPONENTS],
+
+    /// Progressive decoding
+    successive_low:  u8,
+    spec_start:      u8,
+    spec_end:        u8,
+}
+
+impl ArithmeticDecoder
+{
+    /// Create a new arithmetic decoder for sequential images
+    pub(crate) fn new(conditioning: ArithmeticConditioning) -> ArithmeticDecoder
+    {
+        ArithmeticDecoder::new_progressive(conditioning, 0, 0, 63)
+    }
+
+    /// Create a new arithmetic decoder for progressive decoding
+    pub(crate) fn new_progressive(
+        conditioning: ArithmeticConditioning, al: u8, spec_start: u8, spec_end: u8,
+    ) -> ArithmeticDecoder
+    {
+        ArithmeticDecoder {
+            decoder: QmDecoder::new(),
+            conditioning,
+            dc_stats: [[0; 158]; MAX_COMPONENTS],
+            ac_stats: [[0; 256]; MAX_COMPONENTS],
+            fixed_bin: FIXED_BIN,
+            dc_context: [0; MAX_COMPONENTS],
+            successive_low: al,
+            spec_start,
+            spec_end,
+        }
+    }
+
+    pub(crate) fn update_progressive_params(&mut self, al: u8, spec_start: u8, spec_end: u8)
+    {
+        self.successive_low = al;
+        self.spec_start = spec_start;
+        self.spec_end = spec_end;
+    }
+
+    /// Take the marker found while decoding, if any
+    pub(crate) fn take_marker(&mut self) -> Option<Marker>
+    {
+        self.decoder.marker.take()
+    }
+
+    /// Reset the decoder, this should be called at the start of a scan and after
+    /// restart markers.
+    ///
+    /// Dc predictions are stored in components and should be reset by the caller.
+    pub(crate) fn reset(&mut self, conditioning: ArithmeticConditioning)
+    {
+        *self = ArithmeticDecoder::new_progressive(
+            conditioning,
+            self.successive_low,
+            self.spec_start,
+            self.spec_end,
+        );
+    }
+
+    /// Read until we find a marker, return it and reset the decoder.
+    ///
+    /// The decoder only reads the bytes it needs, so at restart intervals the
+    /// RST marker may not have been seen yet.
+    pub(crate) fn restart(&mut self, reader: &mut Cursor<Vec<u8>>) -> Option<Marker>
+    {
+        let conditioning = self.conditioning;
+
+        let marker = match self.decoder.marker
+        {
+            Some(marker) => Some(marker),
+            None => find_marker(reader),
+        };
+        self.reset(conditioning);
+
+        marker
+    }
+
+    /// Decode a single 8x8 block of a sequential image, section F.2.4
+    ///
+    /// # Arguments
+    /// - reader: The entropy coded data
+    /// - dc_table: The DC conditioning table of the component
+    /// - ac_table: The AC conditioning table of the component
+    /// - component: Position of the component in the frame, used for its DC context
+    /// - block: Where to write the decoded coefficients, in natural order
+    /// - dc_prediction: Last DC value for this component
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn decode_mcu_block(
+        &mut self, reader: &mut Cursor<Vec<u8>>, dc_table: usize, ac_table: usize,
+        component: usize, block: &mut [i16; 64], dc_prediction: &mut i32,
+    ) -> Result<(), DecodeErrors>
+    {
+        self.decode_dc(reader, dc_table, component, dc_prediction)?;
+
+        block[0] = *dc_prediction as i16;
+
+        self.decode_ac(reader, ac_table, block, 1, 63, 0)
+    }
+
+    /// Decode the DC coefficient of the first scan of a progressive image, section G.1.3.1
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn decode_prog_dc_first(
+        &mut self, reader: &mut Cursor<Vec<u8>>, dc_table: usize, component: usize,
+        block: &mut i16, dc_prediction: &mut i32,
+    ) -> Result<(), DecodeErrors>
+    {
+        self.decode_dc(reader, dc_table, component, dc_prediction)?;
+
+        *block = (*dc_prediction as i16).wrapping_mul(1_i16 << self.successive_low);
+
+        Ok(())
+    }
+
+    /// Decode a DC refinement bit, section G.1.3.2
+    ///
+    /// Refinement bits are coded with the fixed probability estimate.
+    pub(crate) fn decode_prog_dc_refine(
+        &mut self, reader: &mut Cursor<Vec<u8>>, block: &mut i16,
+    ) -> Result<(), DecodeErrors>
+    {
+        if self.decoder.decode(reader, &mut self.fixed_bin)?
+        {
+            *block |= 1 << self.successive_low;
+        }
+        Ok(())
+    }
+
+    /// Decode the first scan of AC coefficients in a spectral band, section G.1.3.2
+    pub(crate) fn decode_mcu_ac_first(
+        &mut self, reader: &mut Cursor<Vec<u8>>, ac_table: usize, block: &mut [i16; 64],
+    ) -> Result<(), DecodeErrors>
+    {
+        let (start, end) = (usize::from(self.spec_start), usize::from(self.spec_end));
+
+        self.decode_ac(reader, ac_table, block, start, end, self.successive_low)
+    }
+
+    /// Decode a refinement scan of AC coefficients in a spectral band, section G.1.3.3
+    pub(crate) fn decode_mcu_ac_refine(
+        &mut self, reader: &mut Cursor<Vec<u8>>, ac_table: usize, block: &mut [i16; 64],
+    ) -> Result<(), DecodeErrors>
+    {
+        let ac_table = ac_table & 3;
+        let start = usize::from(self.spec_start).max(1);
+        let end = usize::from(self.spec_end).min(63);
+
+        // 1 in the bit position being coded
+        let p1 = 1_i16 << self.successive_low;
+        // -1 in the bit position being coded
+        let m1 = -1_i16 << self.successive_low;
+
+        // end of block of the previous stage, coefficients before it
+        // don't have an EOB decision
+        let mut previous_eob = end;
+
+        while previous_eob > 0 && block[UN_ZIGZAG[previous_eob] & 63] == 0
+        {
+            previous_eob -= 1;
+        }
+
+        let mut k = start;
+
+        while k <= end
+        {
+            let mut st = 3 * (k - 1);
+
+            if k > previous_eob && self.decoder.decode(reader, &mut self.ac_stats[ac_table][st])?
+            {
+                // end of block
+                break;
+            }
+            loop
+            {
+                let coefficient = &mut block[UN_ZIGZAG[k] & 63];
+
+                if *coefficient != 0
+                {
+                    // previously non-zero coefficient, read a correction bit
+                    if self.decoder.decode(reader, &mut self.ac_stats[ac_table][st + 2])?
+                    {
+                        if *coefficient < 0
+                        {
+                            *coefficient = coefficient.wrapping_add(m1);
+                        }
+                        else
+                        {
+                            *coefficient = coefficient.wrapping_add(p1);
+                        }
+                    }
+                    break;
+                }
+                if self.decoder.decode(reader, &mut self.ac_stats[ac_table][st + 1])?
+                {
+                    // newly non-zero coefficient
+                    *coefficient = if self.decoder.decode(reader, &mut self.fixed_bin)?
+                    {
+                        m1
+                    }
+                    else
+                    {
+                        p1
+                    };
+                    break;
+                }
+                st += 3;
+                k += 1;
+
+                if k > end
+                {
+                    return Err(DecodeErrors::MCUError(
+                        "Corrupt arithmetic coded data, spectral overflow".to_string(),
+                    ));
+                }
+            }
+            k += 1;
+        }
+        Ok(())
+    }
+
+    /// Decode the difference of a lossless sample, section H.1.4.3
+    ///
+    /// # Arguments
+    /// - reader: The entropy coded data
+    /// - table: The conditioning table of the component
+    /// - da: The difference decoded for the sample to the left
+    /// - db: The difference decoded for the sample above
+    pub(crate) fn decode_lossless_diff(
+        &mut self, reader: &mut Cursor<Vec<u8>>, table: usize, da: i32, db: i32,
+    ) -> Result<i32, DecodeErrors>
+    {
+        let table = table & 3;
+        let lower = self.conditioning.dc_l[table];
+        let upper = self.conditioning.dc_u[table];
+
+        let db = conditioning_category(db, lower, upper);
+
+        // the 25 contexts of the two dimensional statistical model, Figure H.2
+        let mut st = (5 * conditioning_category(da, lower, upper) + db) * 4;
+
+        let stats = &mut self.dc_stats[table];
+
+        if !self.decoder.decode(reader, &mut stats[st])?
+        {
+            return Ok(0);
+        }
+        let sign = usize::from(self.decoder.decode(reader, &mut stats[st + 1])?);
+
+        st += 2 + sign;
+
+        let mut m = i32::from(self.decoder.decode(reader, &mut stats[st])?);
+
+        if m != 0
+        {
+            // X1 depends on whether the difference above is large, Table H.3
+            st = if db > 2 { 129 } else { 100 };
+
+            while self.decoder.decode(reader, &mut stats[st])?
+            {
+                m <<= 1;
+
+                if m == 0x8000
+                {
+                    return Err(DecodeErrors::MCUError(
+                        "Corrupt arithmetic coded data, lossless magnitude overflow".to_string(),
+                    ));
+                }
+                st += 1;
+            }
+        }
+        self.decode_magnitude(reader, m, st + 14, sign == 1, true, table)
+    }
+
+    /// Decode a DC difference and update the prediction, Figure F.19
+    #[allow(clippy::cast_possible_truncation)]
+    fn decode_dc(
+        &mut self, reader: &mut Cursor<Vec<u8>>, dc_table: usize, component: usize,
+        dc_prediction: &mut i32,
+    ) -> Result<(), DecodeErrors>
+    {
+        let dc_table = dc_table & 3;
+        let dc_stats = &mut self.dc_stats[dc_table];
+
+        let mut st = self.dc_context[component];
+
+        if !self.decoder.decode(reader, &mut dc_stats[st])?
+        {
+            // zero difference
+            self.dc_context[component] = 0;
+
+            return Ok(());
+        }
+        let sign = usize::from(self.decoder.decode(reader, &mut dc_stats[st + 1])?);
+
+        st += 2 + sign;
+
+        let mut m = i32::from(self.decoder.decode(reader, &mut dc_stats[st])?);
+
+        if m != 0
+        {
+            // X1
+            st = 20;
+
+            while self.decoder.decode(reader, &mut dc_stats[st])?
+            {
+                m <<= 1;
+
+                if m == 0x8000
+                {
+                    return Err(DecodeErrors::MCUError(
+                        "Corrupt arithmetic coded data, DC magnitude overflow".to_string(),
+                    ));
+                }
+                st += 1;
+            }
+        }
+        // establish the conditioning category for the next DC value
+        let lower = (1_i32 << self.conditioning.dc_l[dc_table]) >> 1;
+        let upper = (1_i32 << self.conditioning.dc_u[dc_table]) >> 1;
+
+        self.dc_context[component] = if m < lower
+        {
+            0
+        }
+        else if m > upper
+        {
+            12 + sign * 4
+        }
+        else
+        {
+            4 + sign * 4
+        };
+
+        let v = self.decode_magnitude(reader, m, st + 14, sign == 1, true, dc_table)?;
+
+        // DC values are modulo 2^16
+        *dc_prediction = i32::from(dc_prediction.wrapping_add(v) as i16);
+
+        Ok(())
+    }
+
+    /// Decode AC coefficients `start..=end` of a block, Figure F.20
+    ///
+    /// Coefficients are scaled by `shift` for progressive images.
+    #[allow(clippy::cast_possible_truncation)]
+    fn decode_ac(
+        &mut self, reader: &mut Cursor<Vec<u8>>, ac_table: usize, block: &mut [i16; 64],
+        start: usize, end: usize, shift: u8,
+    ) -> Result<(), DecodeErrors>
+    {
+        let ac_table = ac_table & 3;
+        let kx = usize::from(self.conditioning.ac_k[ac_table]);
+        let end = end.min(63);
+
+        let mut k = start.max(1);
+
+        while k <= end
+        {
+            let mut st = 3 * (k - 1);
+
+            if self.decoder.decode(reader, &mut self.ac_stats[ac_table][st])?
+            {
+                // end of block
+                break;
+            }
+            while !self.decoder.decode(reader, &mut self.ac_stats[ac_table][st + 1])?
+            {
+                st += 3;
+                k += 1;
+
+                if k > end
+                {
+                    return Err(DecodeErrors::MCUError(
+                        "Corrupt arithmetic coded data, spectral overflow".to_string(),
+                    ));
+                }
+            }
+            let sign = self.decoder.decode(reader, &mut self.fixed_bin)?;
+
+            st += 2;
+
+            let ac_stats = &mut self.ac_stats[ac_table];
+
+            let mut m = i32::from(self.decoder.decode(reader, &mut ac_stats[st])?);
+
+            if m != 0 && self.decoder.decode(reader, &mut ac_stats[st])?
+            {
+                m <<= 1;
+
+                st = if k <= kx { 189 } else { 217 };
+
+                while self.decoder.decode(reader, &mut ac_stats[st])?
+                {
+                    m <<= 1;
+
+                    if m == 0x8000
+                    {
+                        return Err(DecodeErrors::MCUError(
+                            "Corrupt arithmetic coded data, AC magnitude overflow".to_string(),
+                        ));
+                    }
+                    st += 1;
+                }
+            }
+            let v = self.decode_magnitude(reader, m, st + 14, sign, false, ac_table)?;
+
+            block[UN_ZIGZAG[k] & 63] = (v as i16).wrapping_mul(1 << shift);
+
+            k += 1;
+        }
+        Ok(())
+    }
+
+    /// Decode the magnitude bit pattern of a value whose magnitude category
+    /// is `m` and apply its sign, Figure F.24
+    #[inline]
+    fn decode_magnitude(
+        &mut self, reader: &mut Cursor<Vec<u8>>, mut m: i32, st: usize, sign: bool, is_dc: bool,
+        table: usize,
+    ) -> Result<i32, DecodeErrors>
+    {
+        let stats: &mut [u8] = if is_dc
+        {
+            &mut self.dc_stats[table]
+        }
+        else
+        {
+            &mut self.ac_stats[table]
+        };
+
+        let mut v = m;
+
+        m >>= 1;
+
+        while m != 0
+        {
+            if self.decoder.decode(reader, &mut stats[st])?
+            {
+                v |= m;
+            }
+            m >>= 1;
+        }
+        v += 1;
+
+        if sign
+        {
+            v = -v;
+        }
+        Ok(v)
+    }
+}
+
+/// Classify a difference for conditioning, section F.1.4.4.1.2
+///
+/// Returns 0 for zero, 1 and 2 for small positive and negative and
+/// 3 and 4 for large positive and negative differences.
+fn conditioning_category(diff: i32, lower: u8, upper: u8) -> usize
+{
+    let magnitude = diff.unsigned_abs();
+    let negative = usize::from(diff < 0);
+
+    if magnitude <= (1 << lower) >> 1
+    {
+        0
+    }
+    else if magnitude <= 1 << upper
+    {
+        1 + negative
+    }
+    else
+    {
+        3 + negative
+    }
+}
+
+/// Read a byte from the underlying buffer, returning zero at the end of data
+#[inline(always)]
+#[allow(clippy::cast_possible_truncation)]
+fn read_u8(reader: &mut Cursor<Vec<u8>>) -> u8
+{
+    let pos = reader.position();
+
+    reader.set_position(pos + 1);
+
+    *reader.get_ref().get(pos as usize).unwrap_or(&0)
+}
@@ -296,6 +296,44 @@ impl BitStream
         return Ok(true);
     }
 
+    /// Decode the difference of a lossless sample, section H.1.2.2
+    ///
+    /// This differs from DC decoding in that a magnitude category of 16
+    /// represents a difference of 32768 with no extra bits following.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    #[inline]
+    pub(crate) fn decode_lossless_diff(
+        &mut self, reader: &mut Cursor<Vec<u8>>, table: &HuffmanTable,
+    ) -> Result<i32, DecodeErrors>
+    {
+        let mut symbol;
+
+        // a code (16 bits) and 15 extra bits
+        if self.bits_left < 32
+        {
+            self.refill(reader)?;
+        }
+        symbol = self.peek_bits::<HUFF_LOOKAHEAD>();
+        symbol = table.lookup[symbol as usize];
+
+        decode_huff!(self, symbol, table);
+
+        match symbol
+        {
+            0 => Ok(0),
+            1..=15 =>
+            {
+                let r = self.get_bits(symbol as u8);
+
+                Ok(huff_extend(r, symbol))
+            }
+            16 => Ok(32768),
+            _ => Err(DecodeErrors::HuffmanDecode(format!(
+                "Invalid lossless difference category {symbol}"
+            ))),
+        }
+    }
+
     /// Decode a Minimum Code Unit(MCU) as quickly as possible
     ///
     /// # Arguments
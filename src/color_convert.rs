@@ -16,6 +16,8 @@
 //! Currently supported conversions are
 //!
 //! - `YCbCr` to `RGB,RGBA,GRAYSCALE,RGBX`.
+//! - `RGB` to `RGB,RGBA,GRAYSCALE,RGBX`.
+//! - `CMYK` and `YCCK` to `CMYK,RGB,RGBA,GRAYSCALE,RGBX`.
 //!
 //!
 //! Hey there, if your reading this it means you probably need something, so let me help you.
@@ -44,14 +46,19 @@
 pub use crate::color_convert::avx::{ycbcr_to_rgb_avx2, ycbcr_to_rgba_avx2, ycbcr_to_rgbx_avx2};
 #[cfg(feature = "x86")]
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-pub use crate::color_convert::sse::{ycbcr_to_rgb_sse, ycbcr_to_rgb_sse_16, ycbcr_to_rgba_sse_16};
+pub use crate::color_convert::sse::{
+    rgb_to_rgb_sse_16, rgb_to_rgba_sse_16, ycbcr_to_rgb_sse_16, ycbcr_to_rgba_sse_16,
+};
 use crate::decoder::ColorConvert16Ptr;
 
 mod avx;
 mod scalar;
 mod sse;
 
-pub use scalar::{ycbcr_to_grayscale, ycbcr_to_ycbcr};
+pub use scalar::{
+    cmyk_to_output, cmyk_to_output_pixel, rgb_to_grayscale, rgb_to_luma, rgb_to_rgb_u16,
+    ycbcr_to_grayscale, ycbcr_to_rgb_pixel_u16, ycbcr_to_rgb_u16, ycbcr_to_ycbcr,
+};
 
 use crate::misc::ColorSpace;
 
@@ -105,3 +112,36 @@ pub fn choose_ycbcr_to_rgb_convert_func(
         _ => None,
     };
 }
+
+/// This function determines the best function to interleave the components of
+/// an RGB image based on the colorspace needed
+///
+/// There is no AVX version, the SSE functions are used on CPU's with AVX2 since
+/// only the samples are moved around.
+pub fn choose_rgb_convert_func(type_need: ColorSpace, use_unsafe: bool)
+    -> Option<ColorConvert16Ptr>
+{
+    if use_unsafe
+    {
+        #[cfg(feature = "x86")]
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            if is_x86_feature_detected!("sse4.1")
+            {
+                debug!("Using sse RGB interleaving functions");
+                return match type_need
+                {
+                    ColorSpace::RGB => Some(rgb_to_rgb_sse_16),
+                    ColorSpace::RGBA | ColorSpace::RGBX => Some(rgb_to_rgba_sse_16),
+                    _ => None,
+                };
+            }
+        }
+    }
+    match type_need
+    {
+        ColorSpace::RGB => Some(scalar::rgb_to_rgb_16_scalar),
+        ColorSpace::RGBA | ColorSpace::RGBX => Some(scalar::rgb_to_rgba_16_scalar),
+        _ => None,
+    }
+}
@@ -1,6 +1,9 @@
 use std::cmp::{max, min};
 use std::convert::TryInto;
 
+use crate::misc::ColorSpace;
+use crate::worker::Sample;
+
 /// Limit values to 0 and 255
 #[inline]
 #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss, dead_code)]
@@ -88,42 +91,84 @@ pub fn ycbcr_to_rgb_16_scalar(
     *pos += 48;
 }
 
-pub fn ycbcr_to_grayscale(y: &[i16], width: usize, output: &mut [u8])
+/// RGB to RGBA conversion, the samples are copied and the alpha channel set to 255
+pub fn rgb_to_rgba_16_scalar(
+    r: &[i16; 16], g: &[i16; 16], b: &[i16; 16], output: &mut [u8], pos: &mut usize,
+)
 {
-    // Convert i16's to u8's
-    let temp_output = y.iter().map(|x| *x as u8).collect::<Vec<u8>>();
-    // chunk according to width.
+    let (_, output_position) = output.split_at_mut(*pos);
 
-    let width_mcu = y.len() / width;
+    let opt: &mut [u8; 64] = output_position
+        .get_mut(0..64)
+        .expect("Slice to small cannot write")
+        .try_into()
+        .unwrap();
 
-    let width_chunk = y.len() / width_mcu;
+    for (((r, g), b), out) in r
+        .iter()
+        .zip(g.iter())
+        .zip(b.iter())
+        .zip(opt.chunks_exact_mut(4))
+    {
+        out[0] = clamp(*r);
+        out[1] = clamp(*g);
+        out[2] = clamp(*b);
+        out[3] = 255;
+    }
+    *pos += 64;
+}
 
-    let mut start = 0;
+/// RGB to RGB conversion, the separate component samples are interleaved
+pub fn rgb_to_rgb_16_scalar(
+    r: &[i16; 16], g: &[i16; 16], b: &[i16; 16], output: &mut [u8], pos: &mut usize,
+)
+{
+    let (_, output_position) = output.split_at_mut(*pos);
 
-    let mut end = width;
+    let opt: &mut [u8; 48] = output_position
+        .get_mut(0..48)
+        .expect("Slice to small cannot write")
+        .try_into()
+        .unwrap();
+
+    for (((r, g), b), out) in r
+        .iter()
+        .zip(g.iter())
+        .zip(b.iter())
+        .zip(opt.chunks_exact_mut(3))
+    {
+        out[0] = clamp(*r);
+        out[1] = clamp(*g);
+        out[2] = clamp(*b);
+    }
+    *pos += 48;
+}
 
-    for chunk in temp_output.chunks_exact(width_chunk)
+/// Write out the Y component
+///
+/// `y` contains rows `stride` samples wide (fill samples included) of which
+/// the first `width` samples are written.
+pub fn ycbcr_to_grayscale<T: Sample>(y: &[i16], width: usize, stride: usize, output: &mut [T])
+{
+    for (chunk, out) in y.chunks_exact(stride).zip(output.chunks_exact_mut(width))
     {
         // copy data, row wise, we do it row wise to discard fill bits if the
         // image has an uneven width not divisible by 8.
-
-        output[start..end].copy_from_slice(&chunk[0..width]);
-        start += width;
-        end += width;
+        for (sample, out) in chunk.iter().zip(out.iter_mut())
+        {
+            *out = T::from_i16(*sample);
+        }
     }
 }
 
 /// Convert YcbCr to YCbCr
 ///
-/// Basically all we do is remove fill bytes (if there) in the edges
-pub fn ycbcr_to_ycbcr(
-    channels: &[Vec<i16>; 3], width: usize, h_samp: usize, v_samp: usize, output: &mut [u8],
+/// Basically all we do is remove fill bytes (if there) in the edges,
+/// rows are `width_chunk` samples wide.
+pub fn ycbcr_to_ycbcr<T: Sample>(
+    channels: &[Vec<i16>; 3], width: usize, width_chunk: usize, output: &mut [T],
 )
 {
-    // copy to a temporary vector.
-
-    let mcu_chunks = channels[0].len() / (h_samp * v_samp);
-
     // pixels we write per width. since this is YcbCr we write
     // width times color components.
     let stride = width * 3;
@@ -134,10 +179,8 @@ pub fn ycbcr_to_ycbcr(
 
     let addition = width * 3;
 
-    // width which accounts number of fill bytes
-    let width_chunk = mcu_chunks >> 3;
     // vector for temporary storage.
-    let mut temp_output = vec![0; width_chunk * 3];
+    let mut temp_output = vec![T::default(); width_chunk * 3];
 
     for ((y_chunk, cb_chunk), cr_chunk) in channels[0]
         .chunks_exact(width_chunk)
@@ -155,9 +198,9 @@ pub fn ycbcr_to_ycbcr(
             .zip(cr_chunk.iter())
             .zip(temp_output.chunks_exact_mut(3))
         {
-            out[0] = *y as u8;
-            out[1] = *cb as u8;
-            out[2] = *cr as u8;
+            out[0] = T::from_i16(*y);
+            out[1] = T::from_i16(*cb);
+            out[2] = T::from_i16(*cr);
         }
 
         output[start..end].copy_from_slice(&temp_output[0..stride]);
@@ -167,3 +210,263 @@ pub fn ycbcr_to_ycbcr(
         end += addition;
     }
 }
+
+/// YCbCr to RGB(A/X) conversion for images with a sample precision above 8 bits.
+///
+/// `channels` contains rows `stride` samples wide (fill samples included) of which
+/// the first `width` samples are converted.
+///
+/// The alpha channel is set to the maximum value for the precision.
+pub fn ycbcr_to_rgb_u16(
+    channels: &[Vec<i16>; 3], width: usize, stride: usize, precision: u8,
+    output_colorspace: ColorSpace, output: &mut [u16],
+)
+{
+    let num_components = output_colorspace.num_components();
+
+    for (((y_row, cb_row), cr_row), out_row) in channels[0]
+        .chunks_exact(stride)
+        .zip(channels[1].chunks_exact(stride))
+        .zip(channels[2].chunks_exact(stride))
+        .zip(output.chunks_exact_mut(width * num_components))
+    {
+        for (((y, cb), cr), pixel) in y_row
+            .iter()
+            .zip(cb_row.iter())
+            .zip(cr_row.iter())
+            .zip(out_row.chunks_exact_mut(num_components))
+        {
+            ycbcr_to_rgb_pixel_u16(i32::from(*y), i32::from(*cb), i32::from(*cr), precision, pixel);
+        }
+    }
+}
+
+/// Convert a single YCbCr sample of up to 16 bits to RGB(A/X)
+///
+/// The number of components written is the length of `pixel`, either 3 or 4.
+#[inline]
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub fn ycbcr_to_rgb_pixel_u16(y: i32, cb: i32, cr: i32, precision: u8, pixel: &mut [u16])
+{
+    // 1.402, 0.344136, 0.714136 and 1.772 scaled by 1<<14
+    const CR_R: i32 = 22970;
+    const CB_G: i32 = 5638;
+    const CR_G: i32 = 11700;
+    const CB_B: i32 = 29032;
+    const ROUND: i32 = 1 << 13;
+
+    let center = 1_i32 << (precision - 1);
+    let max_value = (1_i32 << precision) - 1;
+
+    let y = y << 14;
+    let cb = cb - center;
+    let cr = cr - center;
+
+    let r = (y + CR_R * cr + ROUND) >> 14;
+    let g = (y - CB_G * cb - CR_G * cr + ROUND) >> 14;
+    let b = (y + CB_B * cb + ROUND) >> 14;
+
+    pixel[0] = r.clamp(0, max_value) as u16;
+    pixel[1] = g.clamp(0, max_value) as u16;
+    pixel[2] = b.clamp(0, max_value) as u16;
+
+    if pixel.len() == 4
+    {
+        pixel[3] = max_value as u16;
+    }
+}
+
+/// RGB to RGB(A/X) conversion for images with a sample precision above 8 bits.
+///
+/// `channels` contains rows `stride` samples wide (fill samples included) of which
+/// the first `width` samples are copied.
+///
+/// The alpha channel is set to the maximum value for the precision.
+#[allow(clippy::cast_sign_loss)]
+pub fn rgb_to_rgb_u16(
+    channels: &[Vec<i16>; 3], width: usize, stride: usize, precision: u8,
+    output_colorspace: ColorSpace, output: &mut [u16],
+)
+{
+    let num_components = output_colorspace.num_components();
+    let max_value = ((1_u32 << precision) - 1) as u16;
+
+    for (((r_row, g_row), b_row), out_row) in channels[0]
+        .chunks_exact(stride)
+        .zip(channels[1].chunks_exact(stride))
+        .zip(channels[2].chunks_exact(stride))
+        .zip(output.chunks_exact_mut(width * num_components))
+    {
+        for (((r, g), b), pixel) in r_row
+            .iter()
+            .zip(g_row.iter())
+            .zip(b_row.iter())
+            .zip(out_row.chunks_exact_mut(num_components))
+        {
+            pixel[0] = *r as u16;
+            pixel[1] = *g as u16;
+            pixel[2] = *b as u16;
+
+            if num_components == 4
+            {
+                pixel[3] = max_value;
+            }
+        }
+    }
+}
+
+/// RGB to grayscale conversion for images of any precision
+///
+/// `channels` contains rows `stride` samples wide (fill samples included) of which
+/// the first `width` samples are converted.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub fn rgb_to_grayscale<T: Sample>(
+    channels: &[Vec<i16>; 3], width: usize, stride: usize, output: &mut [T],
+)
+{
+    for (((r_row, g_row), b_row), out_row) in channels[0]
+        .chunks_exact(stride)
+        .zip(channels[1].chunks_exact(stride))
+        .zip(channels[2].chunks_exact(stride))
+        .zip(output.chunks_exact_mut(width))
+    {
+        for (((r, g), b), out) in r_row
+            .iter()
+            .zip(g_row.iter())
+            .zip(b_row.iter())
+            .zip(out_row.iter_mut())
+        {
+            let luma = rgb_to_luma(i32::from(*r), i32::from(*g), i32::from(*b));
+
+            *out = T::from_u16(luma as u16);
+        }
+    }
+}
+
+/// ITU-R BT.601 luma of an RGB sample
+#[inline]
+#[allow(clippy::cast_possible_truncation)]
+pub fn rgb_to_luma(r: i32, g: i32, b: i32) -> i32
+{
+    // coefficients scaled by 1<<16
+    ((19595 * i64::from(r) + 38470 * i64::from(g) + 7471 * i64::from(b) + 32768) >> 16) as i32
+}
+
+/// CMYK and YCCK conversion for images of any precision
+///
+/// `channels` contains rows `stride` samples wide (fill samples included) of which
+/// the first `width` samples are converted.
+pub fn cmyk_to_output<T: Sample>(
+    channels: &[Vec<i16>; 4], width: usize, stride: usize, precision: u8,
+    input_colorspace: ColorSpace, inverted: bool, output_colorspace: ColorSpace, output: &mut [T],
+)
+{
+    let num_components = output_colorspace.num_components();
+
+    let mut pixel = [0_u16; 4];
+    let pixel = &mut pixel[..num_components];
+
+    for ((((c_row, m_row), y_row), k_row), out_row) in channels[0]
+        .chunks_exact(stride)
+        .zip(channels[1].chunks_exact(stride))
+        .zip(channels[2].chunks_exact(stride))
+        .zip(channels[3].chunks_exact(stride))
+        .zip(output.chunks_exact_mut(width * num_components))
+    {
+        for (x, out) in out_row.chunks_exact_mut(num_components).enumerate()
+        {
+            let samples = [c_row[x], m_row[x], y_row[x], k_row[x]].map(i32::from);
+
+            cmyk_to_output_pixel(
+                samples,
+                input_colorspace,
+                inverted,
+                precision,
+                output_colorspace,
+                pixel,
+            );
+
+            for (value, p) in out.iter_mut().zip(pixel.iter())
+            {
+                *value = T::from_u16(*p);
+            }
+        }
+    }
+}
+
+/// Convert a single CMYK or YCCK sample of up to 16 bits to the output colorspace
+///
+/// Adobe applications write CMYK samples inverted, `inverted` undoes this so that
+/// CMYK output is the amount of ink with zero meaning no ink. YCCK images always come
+/// from Adobe applications, YCCK output gives the samples as they are stored.
+///
+/// The number of components written is the length of `pixel`.
+#[inline]
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub fn cmyk_to_output_pixel(
+    samples: [i32; 4], input_colorspace: ColorSpace, inverted: bool, precision: u8,
+    output_colorspace: ColorSpace, pixel: &mut [u16],
+)
+{
+    let max_value = (1_i32 << precision) - 1;
+
+    if output_colorspace == ColorSpace::YCCK
+    {
+        for (out, sample) in pixel.iter_mut().zip(samples)
+        {
+            *out = sample as u16;
+        }
+        return;
+    }
+
+    // amount of ink for every component
+    let ink = if input_colorspace == ColorSpace::YCCK
+    {
+        // inverted CMY samples were converted to YCC, so RGB gives the ink
+        let mut rgb = [0; 3];
+
+        ycbcr_to_rgb_pixel_u16(samples[0], samples[1], samples[2], precision, &mut rgb);
+
+        [
+            i32::from(rgb[0]),
+            i32::from(rgb[1]),
+            i32::from(rgb[2]),
+            max_value - samples[3],
+        ]
+    }
+    else if inverted
+    {
+        samples.map(|x| max_value - x)
+    }
+    else
+    {
+        samples
+    };
+
+    if output_colorspace == ColorSpace::CMYK
+    {
+        for (out, ink) in pixel.iter_mut().zip(ink)
+        {
+            *out = ink as u16;
+        }
+        return;
+    }
+
+    let white = max_value - ink[3];
+    let rgb = [0, 1, 2].map(|i| ((max_value - ink[i]) * white + max_value / 2) / max_value);
+
+    if output_colorspace == ColorSpace::GRAYSCALE
+    {
+        pixel[0] = rgb_to_luma(rgb[0], rgb[1], rgb[2]) as u16;
+        return;
+    }
+
+    for (out, value) in pixel.iter_mut().zip(rgb)
+    {
+        *out = value as u16;
+    }
+    if pixel.len() == 4
+    {
+        pixel[3] = max_value as u16;
+    }
+}
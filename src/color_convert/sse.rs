@@ -306,3 +306,133 @@ pub fn ycbcr_to_rgba_sse_16(
         );
     }
 }
+
+/// Interleave 8 R, G and B samples into two registers of 4 RGBA pixels each
+///
+/// The alpha channel is set to `X`
+#[inline]
+#[target_feature(enable = "sse4.1")]
+unsafe fn interleave_rgb_sse41<const X: i16>(
+    r: &[i16; 8], g: &[i16; 8], b: &[i16; 8],
+) -> (__m128i, __m128i)
+{
+    let r = _mm_loadu_si128(r.as_ptr().cast());
+
+    let g = _mm_loadu_si128(g.as_ptr().cast());
+
+    let b = _mm_loadu_si128(b.as_ptr().cast());
+
+    // packus also clamps the samples to 0..255
+    let e = _mm_packus_epi16(r, g);
+
+    let f = _mm_packus_epi16(b, _mm_set1_epi16(X));
+
+    let g = _mm_unpacklo_epi8(e, f);
+
+    let h = _mm_unpackhi_epi8(e, f);
+
+    (_mm_unpacklo_epi8(g, h), _mm_unpackhi_epi8(g, h))
+}
+
+#[inline]
+#[target_feature(enable = "sse4.1")]
+unsafe fn rgb_to_rgba_sse41(
+    r: &[i16; 16], g: &[i16; 16], b: &[i16; 16], out: &mut [u8], offset: &mut usize,
+)
+{
+    let (i, j) = interleave_rgb_sse41::<255>(
+        r[0..8].try_into().unwrap(),
+        g[0..8].try_into().unwrap(),
+        b[0..8].try_into().unwrap(),
+    );
+
+    let (k, l) = interleave_rgb_sse41::<255>(
+        r[8..16].try_into().unwrap(),
+        g[8..16].try_into().unwrap(),
+        b[8..16].try_into().unwrap(),
+    );
+
+    let ptr = out.as_mut_ptr().add(*offset);
+
+    _mm_storeu_si128(ptr.cast(), i);
+
+    _mm_storeu_si128(ptr.add(16).cast(), j);
+
+    _mm_storeu_si128(ptr.add(32).cast(), k);
+
+    _mm_storeu_si128(ptr.add(48).cast(), l);
+
+    *offset += 64;
+}
+
+#[inline]
+#[target_feature(enable = "sse4.1")]
+unsafe fn rgb_to_rgb_sse41(
+    r: &[i16; 16], g: &[i16; 16], b: &[i16; 16], out: &mut [u8], offset: &mut usize,
+)
+{
+    let (i, j) = interleave_rgb_sse41::<0>(
+        r[0..8].try_into().unwrap(),
+        g[0..8].try_into().unwrap(),
+        b[0..8].try_into().unwrap(),
+    );
+
+    let (k, l) = interleave_rgb_sse41::<0>(
+        r[8..16].try_into().unwrap(),
+        g[8..16].try_into().unwrap(),
+        b[8..16].try_into().unwrap(),
+    );
+
+    // drop the alpha bytes, moving the 12 RGB bytes of every register to the bottom
+    // and zeroing the top 4
+    let mask = _mm_setr_epi8(0, 1, 2, 4, 5, 6, 8, 9, 10, 12, 13, 14, -1, -1, -1, -1);
+
+    let i = _mm_shuffle_epi8(i, mask);
+
+    let j = _mm_shuffle_epi8(j, mask);
+
+    let k = _mm_shuffle_epi8(k, mask);
+
+    let l = _mm_shuffle_epi8(l, mask);
+
+    // then join the 4 registers of 12 bytes into 3 registers of 16 bytes
+    let first = _mm_or_si128(i, _mm_slli_si128::<12>(j));
+
+    let second = _mm_or_si128(_mm_srli_si128::<4>(j), _mm_slli_si128::<8>(k));
+
+    let third = _mm_or_si128(_mm_srli_si128::<8>(k), _mm_slli_si128::<4>(l));
+
+    let ptr = out.as_mut_ptr().add(*offset);
+
+    _mm_storeu_si128(ptr.cast(), first);
+
+    _mm_storeu_si128(ptr.add(16).cast(), second);
+
+    _mm_storeu_si128(ptr.add(32).cast(), third);
+
+    *offset += 48;
+}
+
+pub fn rgb_to_rgb_sse_16(
+    r: &[i16; 16], g: &[i16; 16], b: &[i16; 16], out: &mut [u8], offset: &mut usize,
+)
+{
+    // check if out has enough space
+    out.get_mut(*offset..*offset + 48)
+        .expect("Slice to small cannot write");
+    unsafe {
+        rgb_to_rgb_sse41(r, g, b, out, offset);
+    }
+}
+
+pub fn rgb_to_rgba_sse_16(
+    r: &[i16; 16], g: &[i16; 16], b: &[i16; 16], out: &mut [u8], offset: &mut usize,
+)
+{
+    // check if out has enough space
+    out.get_mut(*offset..*offset + 64)
+        .expect("Slice to small cannot write");
+    unsafe {
+        rgb_to_rgba_sse41(r, g, b, out, offset);
+    }
+}
@@ -10,14 +10,18 @@ use crate::upsampler::upsample_no_op;
 
 /// Represents an up-sampler function, this function will be called to upsample
 /// a down-sampled image
-
-pub type UpSampler = fn(&[i16], usize) -> Vec<i16>;
+///
+/// The arguments are the input samples, the rows above and below them in the image,
+/// the width of an input row, the width of an output row and the number of output
+/// samples.
+pub type UpSampler = fn(&[i16], &[i16], &[i16], usize, usize, usize) -> Vec<i16>;
 
 /// Component Data from start of frame
 #[derive(Clone)]
 pub(crate) struct Components
 {
-    /// The type of component that has the metadata below, can be Y,Cb or Cr
+    /// The role of the component, given by its position in the frame header,
+    /// can be Y,Cb,Cr or K
     pub component_id:              ComponentID,
     /// Sub-sampling ratio of this component in the x-plane
     pub vertical_sample:           usize,
@@ -38,27 +42,33 @@ pub(crate) struct Components
     pub up_sampler:                UpSampler,
     /// How pixels do we need to go to get to the next line?
     pub width_stride:              usize,
-    /// Component ID for progressive
+    /// Component identifier from the frame header, scans refer to components by it
     pub(crate) id:                 u8,
 }
 
 impl Components
 {
     /// Create a new instance from three bytes from the start of frame
+    ///
+    /// `position` is the index of the component in the frame header, the
+    /// component identifier byte is arbitrary and only used to match scans to
+    /// components.
     #[inline]
-    pub fn from(a: [u8; 3]) -> Result<Components, DecodeErrors>
+    pub fn from(a: [u8; 3], position: usize) -> Result<Components, DecodeErrors>
     {
-        let id = match a[0]
+        let id = match position
         {
-            1 => ComponentID::Y,
-            2 => ComponentID::Cb,
-            3 => ComponentID::Cr,
+            0 => ComponentID::Y,
+            1 => ComponentID::Cb,
+            2 => ComponentID::Cr,
+            3 => ComponentID::K,
             r =>
             {
                 return Err(DecodeErrors::Format(format!(
-                        "Unknown component id found,{}, expected value between 1 and 3\nNote I and Q components are not supported yet",
-                        r
-                    )));
+                    "Too many components, found component number {}, expected at most {}",
+                    r + 1,
+                    MAX_COMPONENTS
+                )));
             }
         };
 
@@ -73,20 +83,19 @@ impl Components
                 quantization_table_number, MAX_COMPONENTS
             )));
         }
-        // check that upsampling ratios are powers of two
-        // if these fail, it's probably a corrupt image.
-        if !horizontal_sample.is_power_of_two()
+        // sampling factors range from 1 to 4, if these fail it's probably a corrupt image.
+        if !(1..=4).contains(&horizontal_sample)
         {
             return Err(DecodeErrors::Format(format!(
-                "Horizontal sample is not a power of two({}) cannot decode",
+                "Horizontal sample factor {} is not between 1 and 4, cannot decode",
                 horizontal_sample
             )));
         }
 
-        if !vertical_sample.is_power_of_two()
+        if !(1..=4).contains(&vertical_sample)
         {
             return Err(DecodeErrors::Format(format!(
-                "Vertical sub-sample is not power of two({}) cannot decode",
+                "Vertical sample factor {} is not between 1 and 4, cannot decode",
                 vertical_sample
             )));
         }
@@ -115,6 +124,9 @@ impl Components
 }
 
 /// Component ID's
+///
+/// These are the roles of the components in order of appearance in the frame
+/// header, for RGB and CMYK images they are the R,G,B and C,M,Y,K components.
 #[derive(Copy, Debug, Clone, PartialEq, Eq)]
 pub enum ComponentID
 {
@@ -124,13 +136,6 @@ pub enum ComponentID
     Cb,
     /// Red chrominance
     Cr,
-}
-
-#[derive(Copy, Debug, Clone, PartialEq, Eq)]
-pub enum SubSampRatios
-{
-    HV,
-    V,
-    H,
-    None,
+    /// Black, the fourth component of CMYK and YCCK images
+    K,
 }
@@ -1,22 +1,33 @@
 //! Main image logic.
 #![allow(clippy::doc_markdown)]
 
+use std::cmp::min;
 use std::fs::read;
-use std::io::{BufRead, Cursor, Read};
+use std::io::{BufRead, Cursor};
 use std::num::NonZeroU32;
 use std::path::Path;
 
-use crate::color_convert::choose_ycbcr_to_rgb_convert_func;
-use crate::components::{ComponentID, Components, SubSampRatios};
-use crate::errors::{DecodeErrors, UnsupportedSchemes};
-use crate::headers::{parse_dqt, parse_huffman, parse_sos, parse_start_of_frame};
+use crate::arithmetic::ArithmeticConditioning;
+use crate::color_convert::{choose_rgb_convert_func, choose_ycbcr_to_rgb_convert_func};
+use crate::components::Components;
+use crate::errors::DecodeErrors;
+use crate::exif::Exif;
+use crate::headers::{
+    extended_xmp_guid, parse_app0, parse_app1, parse_app13, parse_app14, parse_app2, parse_dac,
+    parse_dhp, parse_dnl, parse_dqt, parse_exp, parse_huffman, parse_sos, parse_start_of_frame,
+    read_segment, IccChunk, XmpChunk,
+};
+use crate::hierarchical::Hierarchy;
 use crate::huffman::HuffmanTable;
 use crate::idct::choose_idct_func;
+use crate::iptc::{parse_image_resources, ImageResource, Iptc, RESOURCE_IPTC};
 use crate::marker::Marker;
 use crate::misc::{read_byte, read_u16_be, Aligned32, ColorSpace, SOFMarkers};
-use crate::upsampler::{
-    choose_horizontal_samp_function, choose_hv_samp_function, upsample_vertical,
-};
+use crate::orientation::{orient, Orientation};
+use crate::quality::estimate_quality;
+use crate::structure::{next_marker, JpegStructure};
+use crate::upsampler::choose_upsampler;
+use crate::worker::Sample;
 use crate::ZuneJpegOptions;
 
 /// Maximum components
@@ -56,7 +67,7 @@ pub type ColorConvert16Ptr = fn(&[i16; 16], &[i16; 16], &[i16; 16], &mut [u8], &
 pub type IDCTPtr = fn(&[i16], &Aligned32<[i32; 64]>, usize, usize, usize) -> Vec<i16>;
 
 /// A Decoder Instance
-#[allow(clippy::upper_case_acronyms)]
+#[allow(clippy::upper_case_acronyms, clippy::struct_excessive_bools)]
 pub struct Decoder
 {
     /// Struct to hold image information from SOI
@@ -64,6 +75,8 @@ pub struct Decoder
     ///  Quantization tables, will be set to none and the tables will
     /// be moved to `components` field
     pub(crate) qt_tables:         [Option<[i32; 64]>; MAX_COMPONENTS],
+    /// Quantization tables as defined by DQT segments, in natural order, these are kept
+    pub(crate) quantization_tables: [Option<[u16; 64]>; MAX_COMPONENTS],
     /// DC Huffman Tables with a maximum of 4 tables for each  component
     pub(crate) dc_huffman_tables: [Option<HuffmanTable>; MAX_COMPONENTS],
     /// AC Huffman Tables with a maximum of 4 tables for each component
@@ -85,13 +98,38 @@ pub struct Decoder
     pub(crate) mcu_y:             usize,
     /// Is the image interleaved?
     pub(crate) interleaved:       bool,
-    pub(crate) sub_sample_ratio:  SubSampRatios,
     /// Image input colorspace, should be YCbCr for a sane image, might be
     /// grayscale too
     pub(crate) input_colorspace:  ColorSpace,
+    /// Sample precision of the image, 8 or 12 bits
+    pub(crate) precision:         u8,
     // Progressive image details
     /// Is the image progressive?
     pub(crate) is_progressive:    bool,
+    /// Is the image arithmetic coded?
+    pub(crate) is_arithmetic:     bool,
+    /// Is the image lossless?
+    pub(crate) is_lossless:       bool,
+    /// Arithmetic conditioning tables, defined by DAC markers
+    pub(crate) arithmetic_conditioning: ArithmeticConditioning,
+    /// Dimensions and components of a hierarchical image, defined by the DHP marker
+    pub(crate) hierarchy:        Option<Hierarchy>,
+    /// Color transform from an Adobe APP14 segment
+    pub(crate) adobe_transform:  Option<u8>,
+    /// Whether the image has a JFIF APP0 segment
+    pub(crate) jfif:             bool,
+    /// Exif data from an APP1 segment
+    pub(crate) exif:             Option<Exif>,
+    /// Chunks of the ICC profile from APP2 segments, in the order they were found
+    pub(crate) icc_chunks:       Vec<IccChunk>,
+    /// XMP packet from an APP1 segment
+    pub(crate) xmp:              Option<String>,
+    /// Chunks of the extended XMP packet from APP1 segments, in the order they were found
+    pub(crate) xmp_chunks:       Vec<XmpChunk>,
+    /// Photoshop image resource blocks from APP13 segments
+    pub(crate) photoshop_resources: Vec<u8>,
+    /// COM segments and APPn segments which aren't interpreted, in file order
+    pub(crate) segments:         Vec<Segment>,
 
     /// Start of spectral scan
     pub(crate) spec_start:       u8,
@@ -129,6 +167,7 @@ impl Decoder
         Decoder {
             info: ImageInfo::default(),
             qt_tables: [None, None, None, None],
+            quantization_tables: [None, None, None, None],
             dc_huffman_tables: [None, None, None, None],
             ac_huffman_tables: [None, None, None, None],
             components: vec![],
@@ -141,10 +180,21 @@ impl Decoder
             mcu_x: 0,
             mcu_y: 0,
             interleaved: false,
-            sub_sample_ratio: SubSampRatios::None,
 
             // Progressive information
             is_progressive: false,
+            is_arithmetic: false,
+            is_lossless: false,
+            arithmetic_conditioning: ArithmeticConditioning::default(),
+            hierarchy: None,
+            adobe_transform: None,
+            jfif: false,
+            exif: None,
+            icc_chunks: vec![],
+            xmp: None,
+            xmp_chunks: vec![],
+            photoshop_resources: vec![],
+            segments: vec![],
             spec_start: 0,
             spec_end: 0,
             succ_high: 0,
@@ -152,11 +202,12 @@ impl Decoder
             num_scans: 0,
 
             // Function pointers
-            idct_func: choose_idct_func(options.get_use_unsafe()),
+            idct_func: choose_idct_func(options.get_use_unsafe(), 8),
             color_convert_16: color_convert,
 
             // Colorspace
             input_colorspace: ColorSpace::YCbCr,
+            precision: 8,
             // This should be kept at par with MAX_COMPONENTS, or until the RFC at
             // https://github.com/rust-lang/rfcs/pull/2920 is accepted
             // Store MCU blocks
@@ -173,6 +224,10 @@ impl Decoder
     /// The buffer should be a valid jpeg file, perhaps created by the command
     /// `std:::fs::read()` or a JPEG file downloaded from the internet.
     ///
+    /// Images with a sample precision above 8 bits are scaled down to 8 bits,
+    /// use [`decode_buffer_u16`](Self::decode_buffer_u16) to get the full precision samples.
+    /// Lossless images with less than 8 bits are scaled up to 8 bits.
+    ///
     /// # Errors
     /// See DecodeErrors for an explanation
     pub fn decode_buffer(&mut self, buf: &[u8]) -> Result<Vec<u8>, DecodeErrors>
@@ -180,6 +235,21 @@ impl Decoder
         self.decode_internal(Cursor::new(buf.to_vec()))
     }
 
+    /// Decode a buffer already in memory returning samples in their full precision
+    ///
+    /// Samples lie between `0` and `(1<<precision)-1` where precision is the sample
+    /// precision of the image ( see [`precision`](Self::precision)), i.e 8 bit images
+    /// will have samples between 0 and 255 and 12 bit images samples between 0 and 4095.
+    ///
+    /// Lossless images are returned exactly as they were encoded, apart from color conversion.
+    ///
+    /// # Errors
+    /// See DecodeErrors for an explanation
+    pub fn decode_buffer_u16(&mut self, buf: &[u8]) -> Result<Vec<u16>, DecodeErrors>
+    {
+        self.decode_internal_u16(Cursor::new(buf.to_vec()))
+    }
+
     /// Create a new Decoder instance
     #[must_use]
     #[allow(clippy::new_without_default)]
@@ -201,6 +271,20 @@ impl Decoder
         self.decode_internal(buffer)
     }
 
+    /// Decode a valid jpeg file returning samples in their full precision
+    ///
+    /// See [`decode_buffer_u16`](Self::decode_buffer_u16) for the range of the samples
+    pub fn decode_file_u16<P>(&mut self, file: P) -> Result<Vec<u16>, DecodeErrors>
+    where
+        P: AsRef<Path> + Clone,
+    {
+        //Read to an in memory buffer
+        let buffer = Cursor::new(read(file)?);
+
+        info!("File size: {} bytes", buffer.get_ref().len());
+        self.decode_internal_u16(buffer)
+    }
+
     /// Returns the image information
     ///
     /// This **must** be called after a subsequent call to `decode_file` or
@@ -217,8 +301,43 @@ impl Decoder
         {
             return None;
         }
+        let mut info = self.info.clone();
+
+        if self.orientation().swaps_dimensions()
+        {
+            std::mem::swap(&mut info.width, &mut info.height);
+            std::mem::swap(&mut info.x_density, &mut info.y_density);
+        }
+
+        return Some(info);
+    }
+
+    /// Returns the number of scans of an image, more than one for progressive images
+    /// and images with components in separate scans
+    ///
+    /// Scans are found by walking every marker of `buf` without decoding them. This
+    /// goes through the whole image, so unlike [`info`](Self::info) it's only done when
+    /// asked for.
+    ///
+    /// # Errors
+    /// If the markers can't be walked, see [`JpegStructure::parse`]
+    pub fn scans(buf: &[u8]) -> Result<usize, DecodeErrors>
+    {
+        Ok(JpegStructure::parse(buf)?.find(Marker::SOS).count())
+    }
 
-        return Some(self.info.clone());
+    /// How decoded pixels are turned upright, from the Exif orientation if the options
+    /// ask for it
+    pub(crate) fn orientation(&self) -> Orientation
+    {
+        match self.exif.as_ref().and_then(Exif::orientation)
+        {
+            Some(orientation) if self.options.get_auto_orientation() =>
+            {
+                Orientation::from_exif(orientation)
+            }
+            _ => Orientation::default(),
+        }
     }
 
     /// Decode Decoder headers
@@ -228,17 +347,24 @@ impl Decoder
     ///
     /// # Supported Headers
     ///  - APP(0)
-    ///  - SOF(O)
+    ///  - APP(1) -> Exif, XMP and extended XMP
+    ///  - APP(2) -> ICC profile
+    ///  - APP(13) -> Photoshop image resources and IPTC
+    ///  - APP(14) -> Adobe color transform
+    ///  - COM and other APP(n) -> Kept as they are, see [`Decoder::segments`]
+    ///  - SOF(0), SOF(1), SOF(2), SOF(3), SOF(9), SOF(10), SOF(11)
+    ///  - SOF(5), SOF(6), SOF(7), SOF(13), SOF(14), SOF(15) -> Differential frames of
+    ///    hierarchical images
     ///  - DQT -> Quantization tables
     ///  - DHT -> Huffman tables
+    ///  - DAC -> Arithmetic conditioning tables
+    ///  - DHP, EXP -> Hierarchical images
     ///  - SOS -> Start of Scan
     /// # Unsupported Headers
-    ///  - SOF(n) -> Decoder images which are not baseline/progressive
-    ///  - DAC -> Images using Arithmetic tables
     ///  - JPG(n)
-    fn decode_headers_internal<R>(&mut self, buf: &mut R) -> Result<(), DecodeErrors>
+    fn decode_headers_internal<T>(&mut self, buf: &mut Cursor<T>) -> Result<(), DecodeErrors>
     where
-        R: Read + BufRead,
+        T: AsRef<[u8]>,
     {
         // First two bytes should be jpeg soi marker
         let magic_bytes = read_u16_be(buf)?;
@@ -250,6 +376,19 @@ impl Decoder
         {
             return Err(DecodeErrors::IllegalMagicBytes(magic_bytes));
         }
+        // metadata collected from several segments starts over if headers are read again
+        self.hierarchy = None;
+        self.adobe_transform = None;
+        self.jfif = false;
+        self.info.jfif_version = None;
+        self.info.thumbnail = None;
+        self.exif = None;
+        self.icc_chunks.clear();
+        self.xmp = None;
+        self.xmp_chunks.clear();
+        self.photoshop_resources.clear();
+        self.segments.clear();
+
         loop
         {
             // read a byte
@@ -268,10 +407,17 @@ impl Decoder
 
                     if n == Marker::SOS
                     {
+                        if self.info.height == 0
+                        {
+                            self.find_dnl(buf)?;
+                        }
+                        self.set_image_info();
+
                         return Ok(());
                     }
                 }
-                else
+                // a 0xFF is a fill byte which may come before a marker, see B.1.1.2 of the spec
+                else if m != 0xFF
                 {
                     warn!("Marker 0xFF{:X} not known", m);
 
@@ -301,49 +447,113 @@ impl Decoder
             }
         }
     }
-    pub(crate) fn parse_marker_inner<R: Read + BufRead>(
-        &mut self, m: Marker, buf: &mut R,
+
+    /// Find the height of an image whose frame header has a height of zero
+    ///
+    /// The height is defined by a DNL segment after the first scan, we need it
+    /// before decoding so skip the entropy coded data following the scan header
+    /// and walk the segments after it up to the DNL segment.
+    fn find_dnl<T>(&mut self, buf: &Cursor<T>) -> Result<(), DecodeErrors>
+    where
+        T: AsRef<[u8]>,
+    {
+        let data = buf.get_ref().as_ref();
+        let start = min(usize::try_from(buf.position()).unwrap_or(usize::MAX), data.len());
+        let mut position = next_marker(data, start, true);
+
+        while position + 1 < data.len() && data[position] == 0xFF
+        {
+            // a 0xFF is a fill byte which may come before a marker, see B.1.1.2 of the spec
+            if data[position + 1] == 0xFF
+            {
+                position += 1;
+                continue;
+            }
+            match Marker::from_u8(data[position + 1])
+            {
+                // skip the marker, parse_dnl reads from the length
+                Some(Marker::DNL) => return parse_dnl(self, &mut &data[position + 2..]),
+                // the segment only follows the first scan
+                Some(Marker::SOS | Marker::EOI) | None => break,
+                Some(marker) if marker.is_standalone() => position += 2,
+                Some(_) =>
+                {
+                    let length = read_u16_be(&mut data.get(position + 2..).unwrap_or_default())?;
+
+                    position += 2 + usize::from(length);
+                }
+            }
+        }
+
+        Err(DecodeErrors::FormatStatic(
+            "Image height is zero but no DNL segment defines it",
+        ))
+    }
+
+    #[allow(clippy::too_many_lines)]
+    pub(crate) fn parse_marker_inner<T: AsRef<[u8]>>(
+        &mut self, m: Marker, buf: &mut Cursor<T>,
     ) -> Result<(), DecodeErrors>
     {
         match m
         {
-            Marker::SOF(0 | 2) =>
+            Marker::SOF(0..=2 | 5 | 6) =>
             {
-                let marker = {
-                    // choose marker
-                    if m == Marker::SOF(0)
-                    {
-                        SOFMarkers::BaselineDct
-                    }
-                    else
-                    {
-                        self.is_progressive = true;
-
-                        SOFMarkers::ProgressiveDctHuffman
-                    }
+                let marker = match m
+                {
+                    Marker::SOF(0) => SOFMarkers::BaselineDct,
+                    Marker::SOF(1) => SOFMarkers::ExtendedSequentialHuffman,
+                    Marker::SOF(2) => SOFMarkers::ProgressiveDctHuffman,
+                    Marker::SOF(5) => SOFMarkers::DifferentialSequentialDctHuffman,
+                    _ => SOFMarkers::DifferentialProgressiveDctHuffman,
                 };
 
+                // frames of hierarchical images may each use another process
+                self.is_progressive = marker.is_progressive();
+                self.is_lossless = false;
+                self.is_arithmetic = false;
+
                 info!("Image encoding scheme =`{:?}`", marker);
                 // get components
                 parse_start_of_frame(buf, marker, self)?;
             }
-            // Start of Frame Segments not supported
-            Marker::SOF(v) =>
+            Marker::SOF(3 | 7) =>
             {
-                let feature = UnsupportedSchemes::from_int(v);
-
-                if let Some(feature) = feature
+                let marker = if m == Marker::SOF(3)
                 {
-                    return Err(DecodeErrors::Unsupported(feature));
+                    SOFMarkers::LosslessHuffman
                 }
+                else
+                {
+                    SOFMarkers::DifferentialLosslessHuffman
+                };
+
+                self.is_progressive = false;
+                self.is_lossless = true;
+                self.is_arithmetic = false;
 
-                return Err(DecodeErrors::Format("Unsupported image format".to_string()));
+                info!("Image encoding scheme =`{marker:?}`");
+                parse_start_of_frame(buf, marker, self)?;
+            }
+            Marker::SOF(9..=11 | 13..=15) =>
+            {
+                let marker = match m
+                {
+                    Marker::SOF(9) => SOFMarkers::ExtendedSequentialDctArithmetic,
+                    Marker::SOF(10) => SOFMarkers::ProgressiveDctArithmetic,
+                    Marker::SOF(11) => SOFMarkers::LosslessArithmetic,
+                    Marker::SOF(13) => SOFMarkers::DifferentialSequentialDctArithmetic,
+                    Marker::SOF(14) => SOFMarkers::DifferentialProgressiveDctArithmetic,
+                    _ => SOFMarkers::DifferentialLosslessArithmetic,
+                };
+
+                self.is_progressive = marker.is_progressive();
+                self.is_lossless = marker.is_lossless();
+                self.is_arithmetic = true;
+
+                info!("Image encoding scheme =`{marker:?}`");
+                parse_start_of_frame(buf, marker, self)?;
             }
-            // APP(0) segment
-            // Marker::APP(0 | 1) =>
-            // {
-            //     parse_app(buf, m, &mut self.info)?;
-            // }
             // Quantization tables
             Marker::DQT =>
             {
@@ -359,19 +569,59 @@ impl Decoder
             {
                 parse_sos(buf, self)?;
 
+                self.set_input_colorspace();
+
                 // break after reading the start of scan.
                 // what follows is the image data
                 return Ok(());
             }
             Marker::EOI => return Err(DecodeErrors::Format("Premature End of image".to_string())),
 
-            Marker::DAC | Marker::DNL =>
+            // Arithmetic conditioning tables
+            Marker::DAC =>
             {
-                return Err(DecodeErrors::Format(format!(
-                    "Parsing of the following header `{:?}` is not supported,\
-                                cannot continue",
-                    m
-                )));
+                parse_dac(self, buf)?;
+            }
+            Marker::APP(_) | Marker::COM =>
+            {
+                // the marker has just been read
+                let offset = usize::try_from(buf.position())
+                    .unwrap_or(usize::MAX)
+                    .saturating_sub(2);
+                let data = read_segment(buf, m)?;
+
+                let interpreted = match m
+                {
+                    Marker::APP(0) => parse_app0(self, &data),
+                    Marker::APP(1) => parse_app1(self, &data),
+                    Marker::APP(2) => parse_app2(self, &data),
+                    Marker::APP(13) => parse_app13(self, &data),
+                    Marker::APP(14) => parse_app14(self, &data),
+                    _ => false,
+                };
+
+                if !interpreted
+                {
+                    info!("Keeping {m:?} segment at offset {offset}");
+
+                    self.segments.push(Segment {
+                        marker: m.to_u8(),
+                        offset,
+                        data,
+                    });
+                }
+            }
+            Marker::DHP =>
+            {
+                parse_dhp(self, buf)?;
+            }
+            Marker::EXP =>
+            {
+                parse_exp(self, buf)?;
+            }
+            Marker::DNL =>
+            {
+                parse_dnl(self, buf)?;
             }
             Marker::DRI =>
             {
@@ -387,6 +637,11 @@ impl Decoder
                 self.restart_interval = usize::from(read_u16_be(buf)?);
                 self.todo = self.restart_interval;
             }
+            // markers without a segment, there is nothing to skip
+            _ if m.is_standalone() =>
+            {
+                warn!("Ignoring marker \"{m:?}\" found between headers");
+            }
             _ =>
             {
                 warn!(
@@ -417,19 +672,327 @@ impl Decoder
         return self.options.get_out_colorspace();
     }
 
+    /// Get the sample precision of the image in bits
+    ///
+    /// This is 8 for most images, but extended and progressive images may be 12 bits,
+    /// and lossless images anything between 2 and 16 bits.
+    ///
+    /// This **must** be called after headers have been decoded, otherwise it returns 8
+    #[must_use]
+    pub fn precision(&self) -> u8
+    {
+        self.precision
+    }
+
+    /// Get the Exif metadata of the image
+    ///
+    /// This **must** be called after headers have been decoded, otherwise it returns None.
+    /// It also returns None if the image has no Exif segment
+    #[must_use]
+    pub fn exif(&self) -> Option<&Exif>
+    {
+        self.exif.as_ref()
+    }
+
+    /// Get the ICC profile of the image
+    ///
+    /// Profiles split into chunks over several APP2 segments are put back together
+    /// in the order of their sequence numbers.
+    ///
+    /// This **must** be called after headers have been decoded, otherwise it returns None.
+    /// It also returns None if the image has no ICC profile or some of its chunks are missing
+    #[must_use]
+    pub fn icc_profile(&self) -> Option<Vec<u8>>
+    {
+        let count = self.icc_chunks.first()?.count;
+
+        if self.icc_chunks.len() != usize::from(count)
+        {
+            warn!(
+                "Found {} of {} ICC profile chunks, ignoring the profile",
+                self.icc_chunks.len(),
+                count
+            );
+            return None;
+        }
+        let mut chunks = self.icc_chunks.iter().collect::<Vec<_>>();
+
+        chunks.sort_by_key(|x| x.sequence);
+
+        Some(chunks.iter().flat_map(|x| x.data.iter().copied()).collect())
+    }
+
+    /// Get quantization table `index`, from 0 to 3, in natural order
+    ///
+    /// Tables are as they were last defined by a DQT segment before the first scan.
+    ///
+    /// This **must** be called after headers have been decoded, otherwise it returns None.
+    /// It also returns None if the image doesn't define the table, e.g. lossless images
+    #[must_use]
+    pub fn quantization_table(&self, index: usize) -> Option<[u16; 64]>
+    {
+        *self.quantization_tables.get(index)?
+    }
+
+    /// Get DC Huffman table `index`, from 0 to 3, as defined by a DHT segment
+    ///
+    /// Tables are as they were last defined before the first scan, progressive images
+    /// may define others between scans.
+    ///
+    /// This **must** be called after headers have been decoded, otherwise it returns None.
+    /// It also returns None if the image doesn't define the table, e.g. arithmetic coded
+    /// images
+    #[must_use]
+    pub fn dc_huffman_table(&self, index: usize) -> Option<HuffmanCodes>
+    {
+        self.dc_huffman_tables.get(index)?.as_ref().map(HuffmanCodes::new)
+    }
+
+    /// Get AC Huffman table `index`, from 0 to 3, as defined by a DHT segment
+    ///
+    /// See [`Decoder::dc_huffman_table`]
+    #[must_use]
+    pub fn ac_huffman_table(&self, index: usize) -> Option<HuffmanCodes>
+    {
+        self.ac_huffman_tables.get(index)?.as_ref().map(HuffmanCodes::new)
+    }
+
+    /// Estimate the quality between 1 and 100 the image was encoded with
+    ///
+    /// This is the quality of libjpeg and encoders derived from it, whose scaled
+    /// example tables are closest to the quantization tables of the image. It is exact
+    /// for images from those encoders, and an approximation for others.
+    ///
+    /// This **must** be called after headers have been decoded, otherwise it returns None.
+    /// It also returns None for lossless images, which aren't quantized
+    #[must_use]
+    pub fn estimated_quality(&self) -> Option<u8>
+    {
+        estimate_quality(&self.quantization_tables)
+    }
+
+    /// Get the COM segments and the APPn segments the decoder doesn't interpret
+    ///
+    /// Segments are in the order they appear in the file, with their marker and offset,
+    /// e.g. to write them back when re-encoding the image or to find which software
+    /// edited it. Exif, XMP, ICC profile, Photoshop, JFIF, JFXX and Adobe segments are
+    /// interpreted so they aren't included.
+    ///
+    /// This **must** be called after headers have been decoded, otherwise it returns
+    /// an empty list
+    #[must_use]
+    pub fn segments(&self) -> &[Segment]
+    {
+        &self.segments
+    }
+
+    /// Get the Photoshop image resources of the image
+    ///
+    /// This **must** be called after headers have been decoded, otherwise it returns
+    /// an empty list. It is also empty if the image has no Photoshop APP13 segment
+    #[must_use]
+    pub fn image_resources(&self) -> Vec<ImageResource>
+    {
+        parse_image_resources(&self.photoshop_resources)
+    }
+
+    /// Get the IPTC metadata of the image, from its Photoshop image resources
+    ///
+    /// This **must** be called after headers have been decoded, otherwise it returns None.
+    /// It also returns None if the image has no IPTC resource
+    #[must_use]
+    pub fn iptc(&self) -> Option<Iptc>
+    {
+        self.image_resources()
+            .iter()
+            .find(|x| x.id == RESOURCE_IPTC)
+            .map(|x| Iptc::parse(&x.data))
+    }
+
+    /// Get the XMP packet of the image
+    ///
+    /// This **must** be called after headers have been decoded, otherwise it returns None.
+    /// It also returns None if the image has no XMP segment
+    #[must_use]
+    pub fn xmp(&self) -> Option<&str>
+    {
+        self.xmp.as_deref()
+    }
+
+    /// Get the extended XMP packet of the image
+    ///
+    /// Properties which don't fit in the segment of the XMP packet are moved to an
+    /// extended packet, split into chunks over several segments. The chunks put back
+    /// together are a packet of their own which should be merged with the main one.
+    ///
+    /// Only chunks with the GUID given by the `xmpNote:HasExtendedXMP` property of the
+    /// main packet are used.
+    ///
+    /// This **must** be called after headers have been decoded, otherwise it returns None.
+    /// It also returns None if the image has no extended XMP or some of its chunks are missing
+    #[must_use]
+    pub fn extended_xmp(&self) -> Option<String>
+    {
+        let guid = extended_xmp_guid(self.xmp.as_ref()?)?;
+        let mut chunks = self
+            .xmp_chunks
+            .iter()
+            .filter(|x| x.guid == guid)
+            .collect::<Vec<_>>();
+        let length = chunks.first()?.length;
+
+        if chunks.iter().any(|x| x.length != length)
+        {
+            warn!("Extended XMP chunks don't agree on the length of the packet, ignoring it");
+            return None;
+        }
+        chunks.sort_by_key(|x| x.offset);
+
+        let mut packet = Vec::new();
+
+        for chunk in chunks
+        {
+            // chunks are allowed to be repeated
+            if chunk.offset as usize != packet.len()
+            {
+                if (chunk.offset as usize) < packet.len()
+                {
+                    continue;
+                }
+                warn!("Extended XMP is missing data at offset {}, ignoring it", packet.len());
+                return None;
+            }
+            packet.extend_from_slice(&chunk.data);
+        }
+        if packet.len() != length as usize
+        {
+            warn!(
+                "Extended XMP is {} bytes instead of {}, ignoring it",
+                packet.len(),
+                length
+            );
+            return None;
+        }
+
+        let packet = String::from_utf8(packet).ok();
+
+        if packet.is_none()
+        {
+            warn!("Extended XMP isn't valid UTF-8, ignoring it");
+        }
+        packet
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
     fn decode_internal(&mut self, buf: Cursor<Vec<u8>>) -> Result<Vec<u8>, DecodeErrors>
     {
         let mut buf = buf;
 
         self.decode_headers_internal(&mut buf)?;
 
-        if self.is_progressive
+        if self.precision > 8
         {
-            self.decode_mcu_ycbcr_progressive(&mut buf)
+            // decode in full precision and then drop the lower bits
+            let shift = self.precision - 8;
+
+            let pixels = self.decode_samples::<u16>(&mut buf)?;
+
+            return Ok(pixels.iter().map(|x| (x >> shift) as u8).collect());
+        }
+        if self.precision < 8
+        {
+            // only lossless images can have less than 8 bits, scale them up.
+            let shift = 8 - self.precision;
+
+            let pixels = self.decode_samples::<u8>(&mut buf)?;
+
+            return Ok(pixels.iter().map(|x| x << shift).collect());
+        }
+        self.decode_samples(&mut buf)
+    }
+
+    fn decode_internal_u16(&mut self, buf: Cursor<Vec<u8>>) -> Result<Vec<u16>, DecodeErrors>
+    {
+        let mut buf = buf;
+
+        self.decode_headers_internal(&mut buf)?;
+
+        if self.precision > 8
+        {
+            return self.decode_samples(&mut buf);
+        }
+        let pixels = self.decode_samples::<u8>(&mut buf)?;
+
+        Ok(pixels.iter().map(|x| u16::from(*x)).collect())
+    }
+
+    /// Decode the image data after headers have been parsed into samples of type `T`
+    fn decode_samples<T: Sample>(&mut self, buf: &mut Cursor<Vec<u8>>)
+        -> Result<Vec<T>, DecodeErrors>
+    {
+        let output = self.options.get_out_colorspace();
+
+        // four component images can't be turned into YCbCr, and fewer components can't give CMYK.
+        // RGB images aren't transformed back to YCbCr.
+        let unsupported = matches!(
+            (self.input_colorspace, output),
+            (ColorSpace::RGB | ColorSpace::CMYK | ColorSpace::YCCK, ColorSpace::YCbCr)
+                | (ColorSpace::CMYK, ColorSpace::YCCK)
+                | (
+                    ColorSpace::GRAYSCALE | ColorSpace::YCbCr | ColorSpace::RGB,
+                    ColorSpace::CMYK | ColorSpace::YCCK
+                )
+        );
+        if unsupported
+        {
+            return Err(DecodeErrors::Format(format!(
+                "Conversion from {:?} to {:?} is not supported",
+                self.input_colorspace, output
+            )));
+        }
+
+        // pick the function that interleaves 16 pixels for the colorspaces in use,
+        // other conversions don't go through it.
+        let use_unsafe = self.options.get_use_unsafe();
+        let color_convert_16 = match self.input_colorspace
+        {
+            ColorSpace::RGB => choose_rgb_convert_func(output, use_unsafe),
+            ColorSpace::YCbCr => choose_ycbcr_to_rgb_convert_func(output, use_unsafe),
+            _ => None,
+        };
+        if let Some(color_convert_16) = color_convert_16
+        {
+            self.color_convert_16 = color_convert_16;
+        }
+
+        if self.hierarchy.is_some() || self.is_lossless
+        {
+            // these aren't decoded in bands, turn the whole image upright
+            let pixels = if self.hierarchy.is_some()
+            {
+                self.decode_hierarchical(buf)?
+            }
+            else
+            {
+                self.decode_lossless(buf)?
+            };
+
+            let width = usize::from(self.info.width);
+            let height = usize::from(self.info.height);
+            // grayscale lossless images are always returned with one component
+            let components = pixels.len() / (width * height).max(1);
+
+            Ok(orient(pixels, width, height, components, self.orientation()))
+        }
+        else if self.is_progressive || usize::from(self.num_scans) < self.components.len()
+        {
+            // sequential images with components in separate scans are decoded like
+            // progressive ones, buffering coefficients until the last scan
+            self.decode_mcu_ycbcr_progressive(buf)
         }
         else
         {
-            self.decode_mcu_ycbcr_baseline(&mut buf)
+            self.decode_mcu_ycbcr_baseline(buf)
         }
     }
     /// Read only headers from a jpeg image buffer
@@ -464,62 +1027,91 @@ impl Decoder
         Decoder::default(options)
     }
 
-    /// Set up-sampling routines in case an image is down sampled
-    pub(crate) fn set_upsampling(&mut self) -> Result<(), DecodeErrors>
+    /// Record what the headers say about the image in `info`, from `data`, the whole
+    /// image
+    fn set_image_info(&mut self)
     {
-        // no sampling, return early
-        // check if horizontal max ==1
-        if self.h_max == self.v_max && self.h_max == 1
+        let components = match &self.hierarchy
         {
-            return Ok(());
-        }
+            Some(hierarchy) => &hierarchy.components,
+            None => &self.components,
+        };
+        let info = &mut self.info;
 
-        // match for other ratios
-        match (self.h_max, self.v_max)
-        {
-            (2, 1) =>
-            {
-                self.sub_sample_ratio = SubSampRatios::H;
-                // horizontal sub-sampling
-                info!("Horizontal sub-sampling (2,1)");
+        info.component_info = components
+            .iter()
+            .map(|x| ComponentInfo {
+                id:                  x.id,
+                horizontal_sampling: u8::try_from(x.horizontal_sample).unwrap_or_default(),
+                vertical_sampling:   u8::try_from(x.vertical_sample).unwrap_or_default(),
+                quantization_table:  x.quantization_table_number,
+            })
+            .collect();
+        info.precision = self.precision;
+        info.restart_interval = u16::try_from(self.restart_interval).unwrap_or(u16::MAX);
+        info.jfif = self.jfif;
+        info.adobe_transform = self.adobe_transform;
+        info.colorspace = self.input_colorspace;
+    }
 
-                let up_sampler = choose_horizontal_samp_function(self.options.get_use_unsafe());
+    /// Decide the colorspace of three and four component images
+    ///
+    /// The frame header doesn't say what its components are, so like libjpeg,
+    /// three component images are YCbCr if there is a JFIF segment. Otherwise an
+    /// Adobe transform of 0, or component identifiers 'R','G','B' without an Adobe
+    /// segment, mean RGB. Four component images are CMYK, or YCCK if an Adobe
+    /// segment says they were transformed.
+    pub(crate) fn set_input_colorspace(&mut self)
+    {
+        let components = match &self.hierarchy
+        {
+            Some(hierarchy) => &hierarchy.components,
+            None => &self.components,
+        };
+        let ids = components.iter().map(|x| x.id).collect::<Vec<u8>>();
 
-                self.components[1..]
-                    .iter_mut()
-                    .for_each(|x| x.up_sampler = up_sampler);
-            }
-            (1, 2) =>
+        self.input_colorspace = match (ids.as_slice(), self.adobe_transform)
+        {
+            ([_, _, _], _) if self.jfif => ColorSpace::YCbCr,
+            ([_, _, _], Some(0)) | ([b'R', b'G', b'B'], None) => ColorSpace::RGB,
+            ([_, _, _], Some(1) | None) => ColorSpace::YCbCr,
+            ([_, _, _, _], Some(0) | None) => ColorSpace::CMYK,
+            ([_, _, _, _], Some(2)) => ColorSpace::YCCK,
+            ([_, _, _], Some(transform)) =>
             {
-                self.sub_sample_ratio = SubSampRatios::V;
-                // Vertical sub-sampling
-                info!("Vertical sub-sampling (1,2)");
-
-                self.components[1..]
-                    .iter_mut()
-                    .for_each(|x| x.up_sampler = upsample_vertical);
+                warn!("Unknown Adobe color transform {transform}, assuming YCbCr");
+                ColorSpace::YCbCr
             }
-            (2, 2) =>
+            ([_, _, _, _], Some(transform)) =>
             {
-                self.sub_sample_ratio = SubSampRatios::HV;
-                // vertical and horizontal sub sampling
-                info!("Vertical and horizontal sub-sampling(2,2)");
-
-                self.components[1..].iter_mut().for_each(|x| {
-                    x.up_sampler = choose_hv_samp_function(self.options.get_use_unsafe());
-                });
+                warn!("Unknown Adobe color transform {transform}, assuming YCCK");
+                ColorSpace::YCCK
             }
-            (_, _) =>
+            _ => return,
+        };
+    }
+
+    /// Set up-sampling routines in case an image is down sampled
+    pub(crate) fn set_upsampling(&mut self)
+    {
+        let use_unsafe = self.options.get_use_unsafe();
+
+        for component in &mut self.components
+        {
+            // how many pixels a sample of this component covers
+            let h_ratio = self.h_max / component.horizontal_sample;
+            let v_ratio = self.v_max / component.vertical_sample;
+
+            info!(
+                "Up-sampling component {:?} by ({},{})",
+                component.component_id, h_ratio, v_ratio
+            );
+
+            if let Some(up_sampler) = choose_upsampler(h_ratio, v_ratio, use_unsafe)
             {
-                // no op. Do nothing
-                // Jokes , panic...
-                return Err(DecodeErrors::Format(
-                    "Unknown down-sampling method, cannot continue".to_string(),
-                ));
+                component.up_sampler = up_sampler;
             }
         }
-
-        return Ok(());
     }
 
     /// Set output colorspace to be RGBA
@@ -557,18 +1149,28 @@ impl Decoder
     #[must_use]
     /// Get the width of the image as a u16
     ///
-    /// The width lies between 0 and 65535
+    /// The width lies between 0 and 65535, it is the height of the stored image
+    /// if the image is turned upright by rotating it 90 or 270 degrees.
     pub fn width(&self) -> u16
     {
+        if self.orientation().swaps_dimensions()
+        {
+            return self.info.height;
+        }
         self.info.width
     }
 
     /// Get the height of the image as a u16
     ///
-    /// The height lies between 0 and 65535
+    /// The height lies between 0 and 65535, it is the width of the stored image
+    /// if the image is turned upright by rotating it 90 or 270 degrees.
     #[must_use]
     pub fn height(&self) -> u16
     {
+        if self.orientation().swaps_dimensions()
+        {
+            return self.info.width;
+        }
         self.info.height
     }
 
@@ -604,41 +1206,34 @@ impl Decoder
     /// Check that all components have the correct width and height
     /// before continuing to decode
     ///
+    /// Every component may have its own sampling factors, but they have to divide
+    /// the maximum factors so that it can be up-sampled by a whole ratio.
+    ///
     /// This helps to identify some corrupt images that may have invalid widths and heights and error out
     /// before trying to decode.
     pub(crate) fn check_component_dimensions(&self) -> Result<(), DecodeErrors>
     {
-        // find  y component
-        let y_comp = self
-            .components
-            .iter()
-            .find(|c| c.component_id == ComponentID::Y)
-            .ok_or(DecodeErrors::FormatStatic(
-                "Could not find Y component for the image",
-            ))?;
-
-        let y_width = y_comp.width_stride;
-        let cb_cr_width = y_width / self.h_max;
-
         for comp in &self.components
         {
-            if comp.component_id == ComponentID::Y
+            if !self.h_max.is_multiple_of(comp.horizontal_sample)
+                || !self.v_max.is_multiple_of(comp.vertical_sample)
             {
-                continue;
+                // e.g a 3x1 luma with 2x1 chroma, libjpeg doesn't decode these either
+                return Err(DecodeErrors::Format(format!(
+                    "Sampling factors {}x{} of component {:?} don't divide the maximum sampling factors {}x{}",
+                    comp.horizontal_sample,
+                    comp.vertical_sample,
+                    comp.component_id,
+                    self.h_max,
+                    self.v_max
+                )));
             }
 
-            if comp.width_stride != cb_cr_width
-            {
-                return Err(DecodeErrors::Format(format!("Invalid image width and height stride for component {:?}, expected {}, but found {}", comp.component_id, cb_cr_width, comp.width_stride)));
-            }
+            let expected_width = self.mcu_x * 8 * comp.horizontal_sample;
 
-            if (comp.horizontal_sample != 1 || comp.vertical_sample != 1)
-                && comp.component_id != ComponentID::Y
+            if comp.width_stride != expected_width
             {
-                return Err(DecodeErrors::Format(format!(
-                    "Invalid component sample for component {:?}, expected (1,1), found ({},{})",
-                    comp.component_id, comp.vertical_sample, comp.horizontal_sample
-                )));
+                return Err(DecodeErrors::Format(format!("Invalid image width and height stride for component {:?}, expected {}, but found {}", comp.component_id, expected_width, comp.width_stride)));
             }
         }
 
@@ -655,16 +1250,138 @@ pub struct ImageInfo
     pub width:         u16,
     /// Height of image
     pub height:        u16,
-    /// PixelDensity
+    /// Units of `x_density` and `y_density` from the JFIF segment
+    ///
+    /// 0 means there are no units and the densities only give the pixel aspect
+    /// ratio, 1 means dots per inch and 2 dots per centimeter.
     pub pixel_density: u8,
     /// Start of frame markers
     pub sof:           SOFMarkers,
-    /// Horizontal sample
+    /// Horizontal pixel density from the JFIF segment
     pub x_density:     u16,
-    /// Vertical sample
+    /// Vertical pixel density from the JFIF segment
     pub y_density:     u16,
     /// Number of components
     pub components:    u8,
+    /// JFIF version as `(major, minor)`, if the image has a JFIF segment
+    pub jfif_version:  Option<(u8, u8)>,
+    /// Thumbnail from the JFIF segment or a JFXX extension segment
+    pub thumbnail:     Option<Thumbnail>,
+    /// Identifiers and sampling factors of the components, in frame header order
+    pub component_info: Vec<ComponentInfo>,
+    /// Sample precision in bits, 8 for most images
+    pub precision:     u8,
+    /// Number of MCUs between restart markers, 0 if the image has none
+    pub restart_interval: u16,
+    /// Whether the image has a JFIF APP0 segment
+    pub jfif:          bool,
+    /// Color transform from an Adobe APP14 segment, if the image has one
+    pub adobe_transform: Option<u8>,
+    /// Colorspace of the components, inferred from the component count, identifiers
+    /// and JFIF and Adobe segments
+    pub colorspace:    ColorSpace,
+}
+
+/// A component as defined by the frame header
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ComponentInfo
+{
+    /// Component identifier
+    pub id:                  u8,
+    /// Horizontal sampling factor, from 1 to 4
+    pub horizontal_sampling: u8,
+    /// Vertical sampling factor, from 1 to 4
+    pub vertical_sampling:   u8,
+    /// Quantization table of the component, from 0 to 3
+    pub quantization_table:  u8,
+}
+
+/// A Huffman table as defined by a DHT segment
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HuffmanCodes
+{
+    /// Number of codes of each length, from 1 to 16 bits
+    pub counts: [u8; 16],
+    /// Symbols in order of increasing code length
+    pub values: Vec<u8>,
+}
+
+impl HuffmanCodes
+{
+    fn new(table: &HuffmanTable) -> HuffmanCodes
+    {
+        let mut counts = [0; 16];
+
+        counts.copy_from_slice(&table.bits[1..]);
+
+        let count = counts.iter().map(|x| usize::from(*x)).sum::<usize>();
+
+        HuffmanCodes {
+            counts,
+            values: table.values[..count].to_vec(),
+        }
+    }
+}
+
+/// A COM segment, or an APPn segment the decoder doesn't interpret
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Segment
+{
+    /// Second byte of the marker, `0xE0..=0xEF` for APP0 to APP15 and `0xFE` for COM
+    pub marker: u8,
+    /// Offset of the marker from the start of the image
+    pub offset: usize,
+    /// Data of the segment, without the marker and length
+    pub data:   Vec<u8>,
+}
+
+impl Segment
+{
+    /// Whether this is a COM segment
+    #[must_use]
+    pub fn is_comment(&self) -> bool
+    {
+        self.marker == 0xFE
+    }
+
+    /// `n` for an APPn segment, None for a COM segment
+    #[must_use]
+    pub fn app(&self) -> Option<u8>
+    {
+        (0xE0..=0xEF)
+            .contains(&self.marker)
+            .then(|| self.marker - 0xE0)
+    }
+}
+
+/// A thumbnail embedded in a JFIF APP0 segment or a JFXX extension segment
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Thumbnail
+{
+    /// A JPEG image, from a JFXX segment
+    Jpeg(Vec<u8>),
+    /// Pixels which index into a palette of 256 RGB colors, from a JFXX segment
+    Palette
+    {
+        /// Width of the thumbnail
+        width:   u8,
+        /// Height of the thumbnail
+        height:  u8,
+        /// 256 RGB entries, 768 bytes
+        palette: Vec<u8>,
+        /// `width * height` indices into the palette
+        pixels:  Vec<u8>,
+    },
+    /// RGB pixels, from the JFIF segment or a JFXX segment
+    Rgb
+    {
+        /// Width of the thumbnail
+        width:  u8,
+        /// Height of the thumbnail
+        height: u8,
+        /// `width * height` RGB pixels
+        pixels: Vec<u8>,
+    },
 }
 
 impl ImageInfo
@@ -687,9 +1404,9 @@ impl ImageInfo
         self.height = height;
     }
 
-    /// Set the image density
+    /// Set the units of the image density
     ///
-    /// Found in the start of frame
+    /// Found in the APP(0) marker
 
     pub(crate) fn set_density(&mut self, density: u8)
     {
@@ -708,7 +1425,6 @@ impl ImageInfo
     /// Set image x-density(dots per pixel)
     ///
     /// Found in the APP(0) marker
-    #[allow(dead_code)]
     pub(crate) fn set_x(&mut self, sample: u16)
     {
         self.x_density = sample;
@@ -717,9 +1433,29 @@ impl ImageInfo
     /// Set image y-density
     ///
     /// Found in the APP(0) marker
-    #[allow(dead_code)]
     pub(crate) fn set_y(&mut self, sample: u16)
     {
         self.y_density = sample;
     }
+
+    /// Horizontal and vertical dots per inch, from the JFIF segment
+    ///
+    /// Returns `None` if the image doesn't say its density in inches or centimeters,
+    /// densities in dots per centimeter are converted and rounded.
+    #[must_use]
+    pub fn dpi(&self) -> Option<(u16, u16)>
+    {
+        match self.pixel_density
+        {
+            1 => Some((self.x_density, self.y_density)),
+            2 =>
+            {
+                let to_inch =
+                    |x: u16| u16::try_from((u32::from(x) * 254 + 50) / 100).unwrap_or(u16::MAX);
+
+                Some((to_inch(self.x_density), to_inch(self.y_density)))
+            }
+            _ => None,
+        }
+    }
 }
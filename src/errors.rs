@@ -5,10 +5,6 @@ use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
 
 use crate::decoder::MAX_DIMENSIONS;
-use crate::misc::{
-    START_OF_FRAME_EXT_AR, START_OF_FRAME_EXT_SEQ, START_OF_FRAME_LOS_SEQ,
-    START_OF_FRAME_LOS_SEQ_AR, START_OF_FRAME_PROG_DCT_AR,
-};
 
 /// Common Decode errors
 #[allow(clippy::module_name_repetitions)]
@@ -33,6 +29,7 @@ pub enum DecodeErrors
     /// Start of frame errors
     SofError(String),
     /// UnsupportedImages
+    #[allow(deprecated)]
     Unsupported(UnsupportedSchemes),
     /// MCU errors
     MCUError(String),
@@ -133,52 +130,23 @@ impl From<std::io::Error> for DecodeErrors
 }
 
 /// Contains Unsupported/Yet-to-be supported Decoder image encoding types.
+///
+/// Every encoding scheme can currently be decoded, so this has no variants.
+#[deprecated(since = "0.2.0", note = "Every encoding scheme can be decoded")]
 #[derive(Eq, PartialEq, Copy, Clone)]
 
-pub enum UnsupportedSchemes
-{
-    /// SOF_1 Extended sequential DCT,Huffman coding
-    ExtendedSequentialHuffman,
-    /// Lossless (sequential), huffman coding,
-    LosslessHuffman,
-    /// Extended sequential DEC, arithmetic coding
-    ExtendedSequentialDctArithmetic,
-    /// Progressive DCT, arithmetic coding,
-    ProgressiveDctArithmetic,
-    /// Lossless ( sequential), arithmetic coding
-    LosslessArithmetic,
-}
+pub enum UnsupportedSchemes {}
 
+#[allow(deprecated)]
 impl Debug for UnsupportedSchemes
 {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result
+    fn fmt(&self, _: &mut Formatter<'_>) -> std::fmt::Result
     {
-        match &self
-        {
-            Self::ExtendedSequentialHuffman =>
-            {
-                write!(f,"The library cannot yet decode images encoded using Extended Sequential Huffman  encoding scheme yet.")
-            }
-            Self::LosslessHuffman =>
-            {
-                write!(f,"The library cannot yet decode images encoded with Lossless Huffman encoding scheme")
-            }
-            Self::ExtendedSequentialDctArithmetic =>
-            {
-                write!(f,"The library cannot yet decode Images Encoded with Extended Sequential DCT Arithmetic scheme")
-            }
-            Self::ProgressiveDctArithmetic =>
-            {
-                write!(f,"The library cannot yet decode images encoded with Progressive DCT Arithmetic scheme")
-            }
-            Self::LosslessArithmetic =>
-            {
-                write!(f,"The library cannot yet decode images encoded with Lossless Arithmetic encoding scheme")
-            }
-        }
+        match *self {}
     }
 }
 
+#[allow(deprecated)]
 impl UnsupportedSchemes
 {
     #[must_use]
@@ -188,18 +156,9 @@ impl UnsupportedSchemes
     /// `Some(UnsupportedScheme)` if the int refers to a specific scheme,
     /// otherwise returns `None`
 
-    pub fn from_int(int: u8) -> Option<UnsupportedSchemes>
+    #[deprecated(since = "0.2.0", note = "Every encoding scheme can be decoded")]
+    pub fn from_int(_int: u8) -> Option<UnsupportedSchemes>
     {
-        let int = u16::from_be_bytes([0xff, int]);
-
-        match int
-        {
-            START_OF_FRAME_PROG_DCT_AR => Some(Self::ProgressiveDctArithmetic),
-            START_OF_FRAME_LOS_SEQ => Some(Self::LosslessHuffman),
-            START_OF_FRAME_LOS_SEQ_AR => Some(Self::LosslessArithmetic),
-            START_OF_FRAME_EXT_SEQ => Some(Self::ExtendedSequentialHuffman),
-            START_OF_FRAME_EXT_AR => Some(Self::ExtendedSequentialDctArithmetic),
-            _ => None,
-        }
+        None
     }
 }
@@ -0,0 +1,492 @@
+//! Exif metadata
+//!
+//! Exif data is stored in an APP1 segment starting with `Exif\0\0`, followed by a
+//! TIFF structure. The TIFF structure is made of image file directories (IFDs),
+//! lists of tagged values, which may be big or little endian.
+//!
+//! The primary IFD (IFD0) describes the image and points to the Exif IFD, which
+//! describes how the picture was taken, and the GPS IFD which says where it was taken.
+//! The values of these three IFDs are parsed, the raw TIFF data is kept too for
+//! anything else.
+//!
+//! Corrupt Exif data doesn't stop an image from decoding, whatever could be read
+//! before the corruption is kept.
+
+use std::collections::HashSet;
+
+/// Tag of the orientation of the image, in the primary IFD
+pub const TAG_ORIENTATION: u16 = 0x0112;
+/// Tag of the camera manufacturer, in the primary IFD
+pub const TAG_MAKE: u16 = 0x010F;
+/// Tag of the camera model, in the primary IFD
+pub const TAG_MODEL: u16 = 0x0110;
+/// Tag of the time the file was last changed, in the primary IFD
+pub const TAG_DATE_TIME: u16 = 0x0132;
+/// Tag of the offset of the Exif IFD, in the primary IFD
+pub const TAG_EXIF_IFD: u16 = 0x8769;
+/// Tag of the offset of the GPS IFD, in the primary IFD
+pub const TAG_GPS_IFD: u16 = 0x8825;
+/// Tag of the time the picture was taken, in the Exif IFD
+pub const TAG_DATE_TIME_ORIGINAL: u16 = 0x9003;
+/// Tag of the north or south latitude reference, in the GPS IFD
+pub const TAG_GPS_LATITUDE_REF: u16 = 0x0001;
+/// Tag of the latitude as degrees, minutes and seconds, in the GPS IFD
+pub const TAG_GPS_LATITUDE: u16 = 0x0002;
+/// Tag of the east or west longitude reference, in the GPS IFD
+pub const TAG_GPS_LONGITUDE_REF: u16 = 0x0003;
+/// Tag of the longitude as degrees, minutes and seconds, in the GPS IFD
+pub const TAG_GPS_LONGITUDE: u16 = 0x0004;
+/// Tag of whether the altitude is above or below sea level, in the GPS IFD
+pub const TAG_GPS_ALTITUDE_REF: u16 = 0x0005;
+/// Tag of the altitude in meters, in the GPS IFD
+pub const TAG_GPS_ALTITUDE: u16 = 0x0006;
+
+/// Maximum number of entries read from all IFDs, protects against corrupt counts
+const MAX_ENTRIES: usize = 4096;
+
+/// The image file directory a value was found in
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Ifd
+{
+    /// IFD0, describing the image
+    Primary,
+    /// The Exif IFD, describing how the picture was taken
+    Exif,
+    /// The GPS IFD, describing where the picture was taken
+    Gps,
+}
+
+/// The value of a tag
+///
+/// TIFF values are arrays of one of twelve types, most tags hold a single one.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExifValue
+{
+    /// 8 bit unsigned integers
+    Byte(Vec<u8>),
+    /// A string, without its terminating NUL
+    Ascii(String),
+    /// 16 bit unsigned integers
+    Short(Vec<u16>),
+    /// 32 bit unsigned integers
+    Long(Vec<u32>),
+    /// Unsigned fractions as `(numerator, denominator)`
+    Rational(Vec<(u32, u32)>),
+    /// 8 bit signed integers
+    SByte(Vec<i8>),
+    /// Bytes whose meaning depends on the tag
+    Undefined(Vec<u8>),
+    /// 16 bit signed integers
+    SShort(Vec<i16>),
+    /// 32 bit signed integers
+    SLong(Vec<i32>),
+    /// Signed fractions as `(numerator, denominator)`
+    SRational(Vec<(i32, i32)>),
+    /// 32 bit floats
+    Float(Vec<f32>),
+    /// 64 bit floats
+    Double(Vec<f64>),
+}
+
+impl ExifValue
+{
+    /// The first value as an unsigned integer, if it is one
+    #[must_use]
+    pub fn as_u32(&self) -> Option<u32>
+    {
+        match self
+        {
+            ExifValue::Byte(x) => x.first().map(|x| u32::from(*x)),
+            ExifValue::Short(x) => x.first().map(|x| u32::from(*x)),
+            ExifValue::Long(x) => x.first().copied(),
+            _ => None,
+        }
+    }
+
+    /// The value as a string, if it is one
+    #[must_use]
+    pub fn as_str(&self) -> Option<&str>
+    {
+        match self
+        {
+            ExifValue::Ascii(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    /// The values as floats, if they are rationals
+    ///
+    /// Fractions with a zero denominator are skipped.
+    #[must_use]
+    pub fn as_f64s(&self) -> Option<Vec<f64>>
+    {
+        match self
+        {
+            ExifValue::Rational(x) => Some(
+                x.iter()
+                    .filter(|(_, d)| *d != 0)
+                    .map(|(n, d)| f64::from(*n) / f64::from(*d))
+                    .collect(),
+            ),
+            ExifValue::SRational(x) => Some(
+                x.iter()
+                    .filter(|(_, d)| *d != 0)
+                    .map(|(n, d)| f64::from(*n) / f64::from(*d))
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+}
+
+/// A tag and its value
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExifEntry
+{
+    /// The IFD the tag was found in
+    pub ifd:   Ifd,
+    /// The tag number
+    pub tag:   u16,
+    /// The value of the tag
+    pub value: ExifValue,
+}
+
+/// Where a picture was taken, from the GPS IFD
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GpsPosition
+{
+    /// Latitude in degrees, positive north of the equator
+    pub latitude:  f64,
+    /// Longitude in degrees, positive east of the prime meridian
+    pub longitude: f64,
+    /// Altitude in meters, negative below sea level
+    pub altitude:  Option<f64>,
+}
+
+/// Exif metadata of an image
+#[derive(Clone, Debug, PartialEq)]
+pub struct Exif
+{
+    raw:        Vec<u8>,
+    big_endian: bool,
+    entries:    Vec<ExifEntry>,
+}
+
+impl Exif
+{
+    /// Parse the TIFF structure following `Exif\0\0` in an APP1 segment
+    ///
+    /// Returns `None` if it doesn't start with a TIFF header.
+    pub(crate) fn parse(raw: &[u8]) -> Option<Exif>
+    {
+        let big_endian = match raw.get(..4)?
+        {
+            b"II*\0" => false,
+            b"MM\0*" => true,
+            _ => return None,
+        };
+        let mut exif = Exif {
+            raw: raw.to_vec(),
+            big_endian,
+            entries: Vec::new(),
+        };
+        let mut seen = HashSet::new();
+
+        let ifd0 = exif.read_u32(4)?;
+
+        exif.read_ifd(ifd0, Ifd::Primary, &mut seen);
+
+        // the primary IFD points to the others
+        let pointers = [(TAG_EXIF_IFD, Ifd::Exif), (TAG_GPS_IFD, Ifd::Gps)];
+
+        for (tag, ifd) in pointers
+        {
+            if let Some(offset) = exif.get(Ifd::Primary, tag).and_then(ExifValue::as_u32)
+            {
+                exif.read_ifd(offset, ifd, &mut seen);
+            }
+        }
+
+        Some(exif)
+    }
+
+    /// The TIFF data, starting with its `II` or `MM` byte order mark
+    ///
+    /// This can be given to other Exif readers for tags this one doesn't parse.
+    #[must_use]
+    pub fn raw(&self) -> &[u8]
+    {
+        &self.raw
+    }
+
+    /// Whether the TIFF data is big endian
+    #[must_use]
+    pub fn is_big_endian(&self) -> bool
+    {
+        self.big_endian
+    }
+
+    /// Every tag read from the primary, Exif and GPS IFDs
+    #[must_use]
+    pub fn entries(&self) -> &[ExifEntry]
+    {
+        &self.entries
+    }
+
+    /// The value of `tag` in `ifd`
+    #[must_use]
+    pub fn get(&self, ifd: Ifd, tag: u16) -> Option<&ExifValue>
+    {
+        self.entries
+            .iter()
+            .find(|x| x.ifd == ifd && x.tag == tag)
+            .map(|x| &x.value)
+    }
+
+    /// Orientation of the image, from 1 to 8
+    ///
+    /// 1 means the image is stored upright, the others say how to rotate and
+    /// flip it to display it, see the Exif specification.
+    #[must_use]
+    pub fn orientation(&self) -> Option<u16>
+    {
+        let orientation = self.get(Ifd::Primary, TAG_ORIENTATION)?.as_u32()?;
+
+        u16::try_from(orientation)
+            .ok()
+            .filter(|x| (1..=8).contains(x))
+    }
+
+    /// Camera manufacturer
+    #[must_use]
+    pub fn make(&self) -> Option<&str>
+    {
+        self.get(Ifd::Primary, TAG_MAKE)?.as_str()
+    }
+
+    /// Camera model
+    #[must_use]
+    pub fn model(&self) -> Option<&str>
+    {
+        self.get(Ifd::Primary, TAG_MODEL)?.as_str()
+    }
+
+    /// When the picture was taken, as `YYYY:MM:DD HH:MM:SS`
+    ///
+    /// This is the original date and time from the Exif IFD, or if it is missing
+    /// the date and time the file was changed from the primary IFD.
+    #[must_use]
+    pub fn capture_time(&self) -> Option<&str>
+    {
+        self.get(Ifd::Exif, TAG_DATE_TIME_ORIGINAL)
+            .or_else(|| self.get(Ifd::Primary, TAG_DATE_TIME))?
+            .as_str()
+    }
+
+    /// Where the picture was taken
+    #[must_use]
+    pub fn gps(&self) -> Option<GpsPosition>
+    {
+        let coordinate = |tag, ref_tag, negative: &str| {
+            let dms = self.get(Ifd::Gps, tag)?.as_f64s()?;
+
+            if dms.len() != 3
+            {
+                return None;
+            }
+            let degrees = dms[0] + dms[1] / 60.0 + dms[2] / 3600.0;
+
+            match self.get(Ifd::Gps, ref_tag).and_then(ExifValue::as_str)
+            {
+                Some(x) if x == negative => Some(-degrees),
+                _ => Some(degrees),
+            }
+        };
+
+        let latitude = coordinate(TAG_GPS_LATITUDE, TAG_GPS_LATITUDE_REF, "S")?;
+        let longitude = coordinate(TAG_GPS_LONGITUDE, TAG_GPS_LONGITUDE_REF, "W")?;
+
+        let altitude = self
+            .get(Ifd::Gps, TAG_GPS_ALTITUDE)
+            .and_then(ExifValue::as_f64s)
+            .and_then(|x| x.first().copied())
+            .map(|altitude| {
+                // 1 means below sea level
+                match self.get(Ifd::Gps, TAG_GPS_ALTITUDE_REF).and_then(ExifValue::as_u32)
+                {
+                    Some(1) => -altitude,
+                    _ => altitude,
+                }
+            });
+
+        Some(GpsPosition {
+            latitude,
+            longitude,
+            altitude,
+        })
+    }
+
+    /// Read the entries of the IFD at `offset`
+    fn read_ifd(&mut self, offset: u32, ifd: Ifd, seen: &mut HashSet<u32>)
+    {
+        // corrupt files may point back to an IFD already read
+        if !seen.insert(offset)
+        {
+            warn!("Exif IFD at offset {offset} was already read, ignoring it");
+            return;
+        }
+        let Some(count) = self.read_u16(offset)
+        else
+        {
+            warn!("Exif IFD offset {offset} is out of bounds");
+            return;
+        };
+
+        for i in 0..u32::from(count)
+        {
+            if self.entries.len() >= MAX_ENTRIES
+            {
+                warn!("Too many Exif entries, ignoring the rest");
+                return;
+            }
+            let position = offset + 2 + 12 * i;
+
+            match self.read_entry(position)
+            {
+                Some((tag, Some(value))) => self.entries.push(ExifEntry { ifd, tag, value }),
+                // a type this reader doesn't know
+                Some((_, None)) => (),
+                None =>
+                {
+                    warn!("Exif entry at offset {position} is out of bounds");
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Read the entry at `position`, returning its tag and value if its type is known
+    #[allow(clippy::cast_possible_wrap)]
+    fn read_entry(&self, position: u32) -> Option<(u16, Option<ExifValue>)>
+    {
+        let tag = self.read_u16(position)?;
+        let kind = self.read_u16(position + 2)?;
+        let count = self.read_u32(position + 4)?;
+
+        let size = match kind
+        {
+            1 | 2 | 6 | 7 => 1,
+            3 | 8 => 2,
+            4 | 9 | 11 => 4,
+            5 | 10 | 12 => 8,
+            _ => return Some((tag, None)),
+        };
+        let length = count.checked_mul(size)?;
+        // values that fit in four bytes are stored in the entry itself
+        let start = if length <= 4
+        {
+            position + 8
+        }
+        else
+        {
+            self.read_u32(position + 8)?
+        };
+        let end = start.checked_add(length)?;
+        let data = self.raw.get(start as usize..end as usize)?;
+
+        let value = match kind
+        {
+            1 => ExifValue::Byte(data.to_vec()),
+            2 =>
+            {
+                let end = data.iter().position(|x| *x == 0).unwrap_or(data.len());
+
+                ExifValue::Ascii(String::from_utf8_lossy(&data[..end]).into_owned())
+            }
+            3 => ExifValue::Short(data.chunks_exact(2).map(|x| self.u16_from(x)).collect()),
+            4 => ExifValue::Long(data.chunks_exact(4).map(|x| self.u32_from(x)).collect()),
+            5 => ExifValue::Rational(
+                data.chunks_exact(8)
+                    .map(|x| (self.u32_from(&x[..4]), self.u32_from(&x[4..])))
+                    .collect(),
+            ),
+            6 => ExifValue::SByte(data.iter().map(|x| *x as i8).collect()),
+            8 => ExifValue::SShort(
+                data.chunks_exact(2)
+                    .map(|x| self.u16_from(x) as i16)
+                    .collect(),
+            ),
+            9 => ExifValue::SLong(
+                data.chunks_exact(4)
+                    .map(|x| self.u32_from(x) as i32)
+                    .collect(),
+            ),
+            10 => ExifValue::SRational(
+                data.chunks_exact(8)
+                    .map(|x| (self.u32_from(&x[..4]) as i32, self.u32_from(&x[4..]) as i32))
+                    .collect(),
+            ),
+            11 => ExifValue::Float(
+                data.chunks_exact(4)
+                    .map(|x| f32::from_bits(self.u32_from(x)))
+                    .collect(),
+            ),
+            12 => ExifValue::Double(
+                data.chunks_exact(8)
+                    .map(|x| {
+                        let (high, low) = if self.big_endian { (x, &x[4..]) } else { (&x[4..], x) };
+
+                        f64::from_bits(
+                            u64::from(self.u32_from(high)) << 32 | u64::from(self.u32_from(low)),
+                        )
+                    })
+                    .collect(),
+            ),
+            _ => ExifValue::Undefined(data.to_vec()),
+        };
+
+        Some((tag, Some(value)))
+    }
+
+    fn read_u16(&self, position: u32) -> Option<u16>
+    {
+        let start = position as usize;
+
+        Some(self.u16_from(self.raw.get(start..start + 2)?))
+    }
+
+    fn read_u32(&self, position: u32) -> Option<u32>
+    {
+        let start = position as usize;
+
+        Some(self.u32_from(self.raw.get(start..start + 4)?))
+    }
+
+    fn u16_from(&self, bytes: &[u8]) -> u16
+    {
+        let bytes = [bytes[0], bytes[1]];
+
+        if self.big_endian
+        {
+            u16::from_be_bytes(bytes)
+        }
+        else
+        {
+            u16::from_le_bytes(bytes)
+        }
+    }
+
+    fn u32_from(&self, bytes: &[u8]) -> u32
+    {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+
+        if self.big_endian
+        {
+            u32::from_be_bytes(bytes)
+        }
+        else
+        {
+            u32::from_le_bytes(bytes)
+        }
+    }
+}
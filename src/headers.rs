@@ -7,9 +7,12 @@ use std::cmp::max;
 use std::io::{BufRead, Read};
 
 use crate::components::Components;
-use crate::decoder::{Decoder, ImageInfo, MAX_COMPONENTS};
+use crate::decoder::{Decoder, Thumbnail, MAX_COMPONENTS};
 use crate::errors::DecodeErrors;
+use crate::exif::Exif;
+use crate::hierarchical::Hierarchy;
 use crate::huffman::HuffmanTable;
+use crate::idct::choose_idct_func;
 use crate::marker::Marker;
 use crate::misc::{read_byte, read_u16_be, Aligned32, ColorSpace, SOFMarkers, UN_ZIGZAG};
 
@@ -121,7 +124,7 @@ where
 }
 
 ///**B.2.4.1 Quantization table-specification syntax**
-#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
 pub(crate) fn parse_dqt<R>(decoder: &mut Decoder, buf: &mut R) -> Result<(), DecodeErrors>
 where
     R: Read,
@@ -167,10 +170,21 @@ where
             1 =>
             {
                 // 16 bit quantization tables
-                //(cae) Before we enable this. Should 16 bit QT cause any other lib changes
-                return Err(DecodeErrors::DqtError(
-                    "Support for 16 bit quantization table is not complete".to_string(),
-                ));
+                let mut qt_bytes = [0; 128];
+
+                buf.read_exact(&mut qt_bytes).map_err(|x| {
+                    DecodeErrors::Format(format!("Could not read symbols into the buffer\n{x}"))
+                })?;
+                qt_length -= (precision_value as u16) + 1 /*QT BIT*/;
+
+                // values are stored in big endian
+                let mut qt_values = [0_u16; 64];
+
+                for (value, bytes) in qt_values.iter_mut().zip(qt_bytes.chunks_exact(2))
+                {
+                    *value = u16::from_be_bytes([bytes[0], bytes[1]]);
+                }
+                un_zig_zag(&qt_values)
             }
             _ =>
             {
@@ -189,14 +203,262 @@ where
             )));
         }
 
+        // entries are at most 16 bits
+        decoder.quantization_tables[table_position] = Some(dct_table.map(|x| x as u16));
         decoder.qt_tables[table_position] = Some(dct_table);
     }
 
     return Ok(());
 }
 
+///**B.2.4.3 Arithmetic conditioning table-specification syntax**
+pub(crate) fn parse_dac<R>(decoder: &mut Decoder, buf: &mut R) -> Result<(), DecodeErrors>
+where
+    R: Read,
+{
+    let mut buf = buf;
+    // read length
+    let length = read_u16_be(&mut buf)
+        .map_err(|c| DecodeErrors::Format(format!("Could not read DAC length {c}")))?
+        .checked_sub(2)
+        .ok_or(DecodeErrors::FormatStatic("Invalid DAC length"))?;
+
+    if length % 2 != 0
+    {
+        return Err(DecodeErrors::Format(format!(
+            "Invalid DAC length {length}, expected a multiple of two"
+        )));
+    }
+
+    for _ in 0..length / 2
+    {
+        let table_info = read_byte(&mut buf)?;
+        let value = read_byte(&mut buf)?;
+        // 0 = DC table, 1 = AC table
+        let table_class = table_info >> 4;
+        let table_position = usize::from(table_info & 0x0F);
+
+        if table_position >= MAX_COMPONENTS
+        {
+            return Err(DecodeErrors::Format(format!(
+                "Invalid DAC table position {table_position}, expected between 0 and 3"
+            )));
+        }
+        let conditioning = &mut decoder.arithmetic_conditioning;
+
+        match table_class
+        {
+            0 =>
+            {
+                let lower = value & 0x0F;
+                let upper = value >> 4;
+
+                if lower > upper
+                {
+                    return Err(DecodeErrors::Format(format!(
+                        "Invalid DC conditioning, lower bound {lower} above upper bound {upper}"
+                    )));
+                }
+                conditioning.dc_l[table_position] = lower;
+                conditioning.dc_u[table_position] = upper;
+            }
+            1 =>
+            {
+                if !(1..=63).contains(&value)
+                {
+                    return Err(DecodeErrors::Format(format!(
+                        "Invalid AC conditioning {value}, expected between 1 and 63"
+                    )));
+                }
+                conditioning.ac_k[table_position] = value;
+            }
+            _ =>
+            {
+                return Err(DecodeErrors::Format(format!(
+                    "Invalid DAC table class {table_class}, should be 0 or 1"
+                )));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Section:`B.3.2 Define hierarchical progression segment syntax`
+///
+/// This has the syntax of a frame header and describes the full image
+/// of a hierarchical image.
+pub(crate) fn parse_dhp<R>(decoder: &mut Decoder, buf: &mut R) -> Result<(), DecodeErrors>
+where
+    R: Read,
+{
+    if decoder.hierarchy.is_some() || !decoder.components.is_empty()
+    {
+        return Err(DecodeErrors::FormatStatic(
+            "DHP segment should appear once, before the first frame",
+        ));
+    }
+
+    let length = read_u16_be(buf)
+        .map_err(|_| DecodeErrors::FormatStatic("Cannot read DHP length, exhausted data"))?;
+
+    let precision = read_byte(buf)?;
+
+    // lossless frames can be anything from 2 to 16 bits, DCT frames check
+    // their precision against their own limits
+    if !(2..=16).contains(&precision)
+    {
+        return Err(DecodeErrors::Format(format!(
+            "Unsupported sample precision {precision} for hierarchical images, expected 2 to 16"
+        )));
+    }
+
+    let height = read_u16_be(buf)?;
+    let width = read_u16_be(buf)?;
+
+    if width == 0 || height == 0
+    {
+        return Err(DecodeErrors::ZeroError);
+    }
+    if width > decoder.options.get_max_width() || height > decoder.options.get_max_height()
+    {
+        return Err(DecodeErrors::Format(format!(
+            "Image dimensions {}x{} greater than limits {}x{}",
+            width,
+            height,
+            decoder.options.get_max_width(),
+            decoder.options.get_max_height()
+        )));
+    }
+
+    let num_components = read_byte(buf)?;
+
+    if num_components == 0 || length != 8 + 3 * u16::from(num_components)
+    {
+        return Err(DecodeErrors::Format(format!(
+            "Invalid DHP length {length} for {num_components} components"
+        )));
+    }
+
+    let mut components = Vec::with_capacity(usize::from(num_components));
+    let mut temp = [0; 3];
+
+    for position in 0..usize::from(num_components)
+    {
+        buf.read_exact(&mut temp)?;
+
+        let component = Components::from(temp, position)?;
+
+        decoder.h_max = max(decoder.h_max, component.horizontal_sample);
+        decoder.v_max = max(decoder.v_max, component.vertical_sample);
+
+        components.push(component);
+    }
+
+    if num_components == 1
+    {
+        decoder.input_colorspace = ColorSpace::GRAYSCALE;
+        decoder.options = decoder.options.set_out_colorspace(ColorSpace::GRAYSCALE);
+    }
+
+    decoder.precision = precision;
+    decoder.info.set_width(width);
+    decoder.info.set_height(height);
+    decoder.info.components = num_components;
+
+    decoder.hierarchy = Some(Hierarchy {
+        width,
+        height,
+        precision,
+        components,
+        frame_width: width,
+        frame_height: height,
+        expand_h: false,
+        expand_v: false,
+    });
+
+    Ok(())
+}
+
+/// Section:`B.3.3 Expand reference components segment syntax`
+pub(crate) fn parse_exp<R>(decoder: &mut Decoder, buf: &mut R) -> Result<(), DecodeErrors>
+where
+    R: Read,
+{
+    if read_u16_be(buf)? != 3
+    {
+        return Err(DecodeErrors::FormatStatic("Bad EXP length, corrupt JPEG"));
+    }
+
+    let expand = read_byte(buf)?;
+    let (horizontal, vertical) = (expand >> 4, expand & 0x0F);
+
+    if horizontal > 1 || vertical > 1
+    {
+        return Err(DecodeErrors::Format(format!(
+            "Invalid EXP expansion {horizontal}x{vertical}, expected 0 or 1"
+        )));
+    }
+
+    let hierarchy = decoder.hierarchy.as_mut().ok_or(DecodeErrors::FormatStatic(
+        "EXP segment outside of a hierarchical image",
+    ))?;
+
+    hierarchy.expand_h = horizontal == 1;
+    hierarchy.expand_v = vertical == 1;
+
+    Ok(())
+}
+
+/// Section:`B.2.5 Define number of lines syntax`
+///
+/// Sets the image height if the frame header left it as zero, the segment
+/// follows the first scan of the image.
+pub(crate) fn parse_dnl<R>(decoder: &mut Decoder, buf: &mut R) -> Result<(), DecodeErrors>
+where
+    R: Read,
+{
+    if read_u16_be(buf)? != 4
+    {
+        return Err(DecodeErrors::FormatStatic("Bad DNL length, corrupt JPEG"));
+    }
+
+    let lines = read_u16_be(buf)?;
+
+    if lines == 0
+    {
+        return Err(DecodeErrors::ZeroError);
+    }
+
+    if decoder.info.height != 0
+    {
+        if decoder.info.height != lines
+        {
+            warn!(
+                "DNL segment defines {} lines but the image height is {}, ignoring it",
+                lines, decoder.info.height
+            );
+        }
+        return Ok(());
+    }
+
+    if lines > decoder.options.get_max_height()
+    {
+        return Err(DecodeErrors::Format(format!("Image height {} greater than height limit {}. If use `set_limits` if you want to support huge images", lines, decoder.options.get_max_height())));
+    }
+
+    info!("Image height from DNL :{lines}");
+
+    decoder.info.set_height(lines);
+    // the number of MCU rows couldn't be found with a zero height
+    decoder.mcu_y = usize::from(lines).div_ceil(decoder.mcu_height);
+
+    Ok(())
+}
+
 /// Section:`B.2.2 Frame header syntax`
 
+#[allow(clippy::too_many_lines)]
 pub(crate) fn parse_start_of_frame<R>(
     buf: &mut R, sof: SOFMarkers, img: &mut Decoder,
 ) -> Result<(), DecodeErrors>
@@ -207,19 +469,42 @@ where
     let length = read_u16_be(buf)
         .map_err(|_| DecodeErrors::Format("Cannot read SOF length, exhausted data".to_string()))?;
 
-    // usually 8, but can be 12 and 16, we currently support only 8
-    // so sorry about that 12 bit images
+    // usually 8, but extended sequential and progressive images
+    // can also be 12 bits, lossless images can be anything from 2 to 16 bits.
     let dt_precision = read_byte(buf)?;
 
-    if dt_precision != 8
+    let supports_12_bit = matches!(
+        sof,
+        SOFMarkers::ExtendedSequentialHuffman
+            | SOFMarkers::ProgressiveDctHuffman
+            | SOFMarkers::ExtendedSequentialDctArithmetic
+            | SOFMarkers::ProgressiveDctArithmetic
+            | SOFMarkers::DifferentialSequentialDctHuffman
+            | SOFMarkers::DifferentialProgressiveDctHuffman
+            | SOFMarkers::DifferentialSequentialDctArithmetic
+            | SOFMarkers::DifferentialProgressiveDctArithmetic
+    );
+
+    if sof.is_lossless()
+    {
+        if !(2..=16).contains(&dt_precision)
+        {
+            return Err(DecodeErrors::SofError(format!(
+                "Unsupported sample precision {dt_precision} for lossless images, expected 2 to 16"
+            )));
+        }
+    }
+    else if dt_precision != 8 && !(dt_precision == 12 && supports_12_bit)
     {
         return Err(DecodeErrors::SofError(format!(
-            "The library can only parse 8-bit images, the image has {} bits of precision",
-            dt_precision
+            "Unsupported sample precision {} for {:?} images",
+            dt_precision, sof
         )));
     }
 
-    img.info.set_density(dt_precision);
+    img.precision = dt_precision;
+    // 12 bit samples need wider intermediates during IDCT
+    img.idct_func = choose_idct_func(img.options.get_use_unsafe(), dt_precision);
 
     // read  and set the image height.
     let img_height = read_u16_be(buf).map_err(|_| {
@@ -247,8 +532,9 @@ where
         return Err(DecodeErrors::Format(format!("Image height {} greater than height limit {}. If use `set_limits` if you want to support huge images", img_height, img.options.get_max_height())));
     }
 
-    // Check image width or height is zero
-    if img_width == 0 || img_height == 0
+    // Check image width or height is zero, a zero height is defined later by a DNL
+    // segment, but not for frames of hierarchical images
+    if img_width == 0 || (img_height == 0 && img.hierarchy.is_some())
     {
         return Err(DecodeErrors::ZeroError);
     }
@@ -275,7 +561,9 @@ where
 
     info!("Image components : {}", num_components);
 
-    if num_components == 1
+    // the colorspace of hierarchical images is set by the DHP segment, frames
+    // may contain only some of its components
+    if num_components == 1 && img.hierarchy.is_none()
     {
         // SOF sets the number of image components
         // and that to us translates to setting input and output
@@ -290,38 +578,49 @@ where
     let mut components = Vec::with_capacity(num_components as usize);
     let mut temp = [0; 3];
 
-    for _ in 0..num_components
+    for position in 0..usize::from(num_components)
     {
         // read 3 bytes for each component
         buf.read_exact(&mut temp)
             .map_err(|x| DecodeErrors::Format(format!("Could not read component data\n{}", x)))?;
         // create a component.
-        let component = Components::from(temp)?;
+        let component = Components::from(temp, position)?;
 
         components.push(component);
     }
 
     img.info.set_sof_marker(sof);
 
-    for component in &mut components
+    for component in &components
     {
         // compute interleaved image info
         // h_max contains the maximum horizontal component
         img.h_max = max(img.h_max, component.horizontal_sample);
         // v_max contains the maximum vertical component
         img.v_max = max(img.v_max, component.vertical_sample);
-        img.mcu_width = img.h_max * 8;
-        img.mcu_height = img.v_max * 8;
-        // Number of MCU's per width
-        img.mcu_x = (usize::from(img.info.width) + img.mcu_width - 1) / img.mcu_width;
-        // Number of MCU's per height
-        img.mcu_y = (usize::from(img.info.height) + img.mcu_height - 1) / img.mcu_height;
-
-        if img.h_max != 1 || img.v_max != 1
+    }
+    // the MCU size depends on the largest sampling factors, so it can only be found
+    // after seeing every component, luma needn't have the largest ones.
+    img.mcu_width = img.h_max * 8;
+    img.mcu_height = img.v_max * 8;
+    // Number of MCU's per width
+    img.mcu_x = usize::from(img.info.width).div_ceil(img.mcu_width);
+    // Number of MCU's per height
+    img.mcu_y = usize::from(img.info.height).div_ceil(img.mcu_height);
+
+    if img.h_max != 1 || img.v_max != 1
+    {
+        // interleaved images have horizontal and vertical sampling factors
+        // not equal to 1.
+        img.interleaved = true;
+    }
+
+    for component in &mut components
+    {
+        // lossless images aren't quantized
+        if sof.is_lossless()
         {
-            // interleaved images have horizontal and vertical sampling factors
-            // not equal to 1.
-            img.interleaved = true;
+            continue;
         }
         // Extract quantization tables from the arrays into components
         let qt_table = *img.qt_tables[component.quantization_table_number as usize]
@@ -339,11 +638,14 @@ where
     }
 
     // delete quantization tables, we'll extract them from the components when
-    // needed
-    img.qt_tables = [None, None, None, None];
+    // needed. Tables persist across the frames of hierarchical images, see B.2.4
+    if img.hierarchy.is_none()
+    {
+        img.qt_tables = [None, None, None, None];
+    }
     img.components = components;
 
-    Ok(())
+    img.start_hierarchical_frame(sof)
 }
 
 /// Parse a start of scan data
@@ -369,10 +671,10 @@ where
 
     // Check number of components.
     // Currently ths library doesn't support images with more than 4 components
-    if !(1..4).contains(&ns)
+    if !(1..=4).contains(&ns)
     {
         return Err(DecodeErrors::SosError(format!(
-            "Number of components in start of scan should be less than 3 but more than 0. Found {}",
+            "Number of components in start of scan should be between 1 and 4. Found {}",
             ns
         )));
     }
@@ -389,48 +691,34 @@ where
     {
         // CS_i parameter, I don't need it so I might as well delete it
         let id = read_byte(buf)?;
-
-        if usize::from(id) > image.components.len()
-        {
-            return Err(DecodeErrors::SofError(format!(
-                "Too large component ID {}, expected value between 0 and {}",
-                id,
-                image.components.len()
-            )));
-        }
-        if seen[usize::from(id)]
-        {
-            return Err(DecodeErrors::SofError(format!(
-                "Duplicate ID {} seen twice in the same component",
-                id
-            )));
-        }
-        seen[usize::from(id)] = true;
         // DC and AC huffman table position
         // top 4 bits contain dc huffman destination table
         // lower four bits contain ac huffman destination table
         let y = read_byte(buf)?;
 
-        let mut j = 0;
+        // components are matched by their identifier, if several components share
+        // it, they are taken in the order of the frame header.
+        let matching = || {
+            (0..image.info.components).filter(|j| image.components[usize::from(*j)].id == id)
+        };
 
-        while j < image.info.components
+        let j = match matching().find(|j| !seen[usize::from(*j)])
         {
-            if image.components[j as usize].id == id
+            Some(j) => j,
+            None if matching().next().is_some() =>
             {
-                break;
+                return Err(DecodeErrors::SofError(format!(
+                    "Duplicate ID {id} seen twice in the same component"
+                )));
             }
-
-            j += 1;
-        }
-
-        if j == image.info.components
-        {
-            return Err(DecodeErrors::SofError(format!(
-                "Invalid component id {}, expected a value between 0 and {}",
-                id,
-                image.components.len()
-            )));
-        }
+            None =>
+            {
+                return Err(DecodeErrors::SofError(format!(
+                    "Invalid component id {id}, no such component in the frame header"
+                )));
+            }
+        };
+        seen[usize::from(j)] = true;
 
         image.components[usize::from(j)].dc_huff_table = usize::from((y >> 4) & 0xF);
         image.components[usize::from(j)].ac_huff_table = usize::from(y & 0xF);
@@ -459,13 +747,14 @@ where
     {
         return Err(DecodeErrors::SofError(format!(
             "Invalid Ah parameter {}, range should be 0-13",
-            image.succ_low
+            image.succ_high
         )));
     }
-    // successive approximation bit position low
+    // successive approximation bit position low, the point transform of lossless
+    // images which is checked against their precision instead
     image.succ_low = bit_approx & 0xF;
 
-    if image.succ_low > 13
+    if image.succ_low > 13 && !image.is_lossless
     {
         return Err(DecodeErrors::SofError(format!(
             "Invalid Al parameter {}, range should be 0-13",
@@ -476,67 +765,331 @@ where
     Ok(())
 }
 
-pub(crate) fn _parse_app<R>(
-    buf: &mut R, marker: Marker, _info: &mut ImageInfo,
-) -> Result<(), DecodeErrors>
+/// Read the payload of an APPn or COM segment, what follows its length
+pub(crate) fn read_segment<R>(buf: &mut R, marker: Marker) -> Result<Vec<u8>, DecodeErrors>
 where
-    R: BufRead + Read,
+    R: Read,
 {
-    let length = read_u16_be(buf)?
-        .checked_sub(2)
-        .ok_or(DecodeErrors::Format(
-            "Invalid APP0 length. Length should be greater than 2".to_string(),
-        ))?;
+    let length = usize::from(
+        read_u16_be(buf)?
+            .checked_sub(2)
+            .ok_or_else(|| DecodeErrors::Format(format!("Invalid {marker:?} length")))?,
+    );
 
-    let mut bytes_read = 0;
+    let mut data = vec![0; length];
 
-    match marker
+    buf.read_exact(&mut data)
+        .map_err(|x| DecodeErrors::Format(format!("Could not read {marker:?} data\n{x}")))?;
+
+    Ok(data)
+}
+
+/// Parse an APP0 segment
+///
+/// JFIF segments give the pixel density and may hold a thumbnail, their
+/// presence also means three component images are YCbCr. JFXX extension segments
+/// hold a thumbnail.
+///
+/// Returns whether the segment was interpreted.
+pub(crate) fn parse_app0(decoder: &mut Decoder, data: &[u8]) -> bool
+{
+    if let Some(jfif) = data.strip_prefix(b"JFIF\0")
+    {
+        info!("JFIF APP0 segment");
+
+        decoder.jfif = true;
+
+        // version, units, x and y density and the thumbnail dimensions
+        if jfif.len() < 9
+        {
+            warn!("JFIF segment is too short, ignoring its contents");
+            return true;
+        }
+        let info = &mut decoder.info;
+
+        info.jfif_version = Some((jfif[0], jfif[1]));
+        info.set_density(jfif[2]);
+        info.set_x(u16::from_be_bytes([jfif[3], jfif[4]]));
+        info.set_y(u16::from_be_bytes([jfif[5], jfif[6]]));
+
+        info!(
+            "JFIF version {}.{:02}, density {}x{} in units {}",
+            jfif[0], jfif[1], info.x_density, info.y_density, info.pixel_density
+        );
+
+        if jfif[7] != 0 && jfif[8] != 0
+        {
+            info.thumbnail = parse_rgb_thumbnail(&jfif[7..]);
+        }
+    }
+    else if let Some(jfxx) = data.strip_prefix(b"JFXX\0")
+    {
+        info!("JFXX APP0 segment");
+
+        decoder.info.thumbnail = parse_jfxx_thumbnail(jfxx);
+    }
+    else
     {
-        Marker::APP(0) =>
+        return false;
+    }
+
+    true
+}
+
+/// Parse the thumbnail of a JFXX segment, from its extension code on
+fn parse_jfxx_thumbnail(data: &[u8]) -> Option<Thumbnail>
+{
+    match data
+    {
+        [0x10, jpeg @ ..] => Some(Thumbnail::Jpeg(jpeg.to_vec())),
+        [0x11, width, height, rest @ ..] =>
         {
-            if length != 14
+            let size = usize::from(*width) * usize::from(*height);
+
+            if rest.len() < 768 + size
             {
-                warn!("Incorrect length of APP0 ,{}, should be 14", length);
+                warn!("JFXX palette thumbnail is truncated, ignoring it");
+                return None;
             }
-            // Don't handle APP0 as of now
-            buf.consume(length as usize);
+            Some(Thumbnail::Palette {
+                width:   *width,
+                height:  *height,
+                palette: rest[..768].to_vec(),
+                pixels:  rest[768..768 + size].to_vec(),
+            })
         }
-        Marker::APP(1) =>
+        [0x13, ..] => parse_rgb_thumbnail(&data[1..]),
+        _ =>
         {
-            if length >= 6
-            {
-                let mut buffer = [0_u8; 6];
+            warn!("Unknown JFXX extension, ignoring it");
+            None
+        }
+    }
+}
 
-                buf.read_exact(&mut buffer).map_err(|x| {
-                    DecodeErrors::Format(format!("Could not read Exif data\n{}", x))
-                })?;
+/// Parse an RGB thumbnail, preceded by its width and height
+fn parse_rgb_thumbnail(data: &[u8]) -> Option<Thumbnail>
+{
+    let (width, height) = (*data.first()?, *data.get(1)?);
+    let size = 3 * usize::from(width) * usize::from(height);
 
-                bytes_read += 6;
+    if let Some(pixels) = data.get(2..2 + size)
+    {
+        return Some(Thumbnail::Rgb {
+            width,
+            height,
+            pixels: pixels.to_vec(),
+        });
+    }
+    warn!("RGB thumbnail is truncated, ignoring it");
 
-                // https://web.archive.org/web/20190624045241if_/http://www.cipa.jp:80/std/documents/e/DC-008-Translation-2019-E.pdf
-                // 4.5.4 Basic Structure of Decoder Compressed Data
-                if &buffer == b"Exif\x00\x00"
-                {
-                    buf.consume(length as usize - bytes_read);
-                }
+    None
+}
+
+/// Parse an APP14 segment
+///
+/// Adobe applications write one to record the color transform applied to the image,
+/// 0 for none (RGB or CMYK), 1 for YCbCr and 2 for YCCK. Returns whether the
+/// segment was interpreted.
+pub(crate) fn parse_app14(decoder: &mut Decoder, data: &[u8]) -> bool
+{
+    // "Adobe", version, flags0, flags1 and the transform
+    if data.len() >= 12 && data.starts_with(b"Adobe")
+    {
+        let transform = data[11];
+
+        info!("Adobe APP14 segment, color transform {transform}");
+
+        decoder.adobe_transform = Some(transform);
+    }
+    else
+    {
+        return false;
+    }
+
+    true
+}
+
+/// A chunk of the extended XMP packet from an APP1 segment
+pub(crate) struct XmpChunk
+{
+    /// GUID of the packet, the MD5 digest of the whole packet as 32 hex digits
+    pub(crate) guid:   [u8; 32],
+    /// Length of the whole packet
+    pub(crate) length: u32,
+    /// Offset of the chunk in the packet
+    pub(crate) offset: u32,
+    pub(crate) data:   Vec<u8>,
+}
+
+/// The GUID of the extended XMP packet, from the `xmpNote:HasExtendedXMP` property
+/// of the main packet
+pub(crate) fn extended_xmp_guid(packet: &str) -> Option<[u8; 32]>
+{
+    // the property is written as an attribute or an element
+    let start = packet.find("HasExtendedXMP")? + "HasExtendedXMP".len();
+    let value = packet[start..].trim_start_matches(|x: char| "=\"' >".contains(x));
+
+    value.as_bytes().get(..32)?.try_into().ok()
+}
+
+/// Parse an APP1 segment
+///
+/// Exif data and XMP packets are kept on the decoder, returns whether the segment
+/// was interpreted.
+///
+/// XMP packets have to fit in a segment, what doesn't fit is moved to an extended
+/// packet split into chunks with a GUID, the length of the packet and the offset of
+/// the chunk.
+pub(crate) fn parse_app1(decoder: &mut Decoder, data: &[u8]) -> bool
+{
+    // https://web.archive.org/web/20190624045241if_/http://www.cipa.jp:80/std/documents/e/DC-008-Translation-2019-E.pdf
+    // 4.5.4 Basic Structure of Decoder Compressed Data
+    if let Some(tiff) = data.strip_prefix(b"Exif\x00\x00")
+    {
+        if decoder.exif.is_some()
+        {
+            warn!("More than one Exif segment, ignoring the later ones");
+        }
+        else
+        {
+            decoder.exif = Exif::parse(tiff);
+
+            if decoder.exif.is_none()
+            {
+                warn!("Exif segment doesn't contain a TIFF header, ignoring it");
             }
         }
-        _ =>
-        {}
+    }
+    else if let Some(packet) = data.strip_prefix(b"http://ns.adobe.com/xap/1.0/\0")
+    {
+        if decoder.xmp.is_some()
+        {
+            warn!("More than one XMP segment, ignoring the later ones");
+        }
+        else
+        {
+            match String::from_utf8(packet.to_vec())
+            {
+                Ok(packet) => decoder.xmp = Some(packet),
+                Err(_) => warn!("XMP packet isn't valid UTF-8, ignoring it"),
+            }
+        }
+    }
+    else if let Some(chunk) = data.strip_prefix(b"http://ns.adobe.com/xmp/extension/\0")
+    {
+        if chunk.len() < 40
+        {
+            warn!("Extended XMP chunk is too short, ignoring it");
+            return true;
+        }
+        let mut guid = [0; 32];
+
+        guid.copy_from_slice(&chunk[..32]);
+
+        decoder.xmp_chunks.push(XmpChunk {
+            guid,
+            length: u32::from_be_bytes(chunk[32..36].try_into().unwrap()),
+            offset: u32::from_be_bytes(chunk[36..40].try_into().unwrap()),
+            data: chunk[40..].to_vec(),
+        });
+    }
+    else
+    {
+        return false;
     }
 
-    Ok(())
+    true
+}
+
+/// Parse an APP13 segment
+///
+/// Photoshop image resources are kept on the decoder, resources may continue from one
+/// segment to the next so the resources of every segment are put together.
+/// Returns whether the segment was interpreted.
+pub(crate) fn parse_app13(decoder: &mut Decoder, data: &[u8]) -> bool
+{
+    if let Some(resources) = data.strip_prefix(b"Photoshop 3.0\0")
+    {
+        decoder.photoshop_resources.extend_from_slice(resources);
+    }
+    else
+    {
+        return false;
+    }
+
+    true
+}
+
+/// A chunk of an ICC profile from an APP2 segment
+pub(crate) struct IccChunk
+{
+    /// Position of the chunk in the profile, starting from 1
+    pub(crate) sequence: u8,
+    /// Number of chunks the profile is split into
+    pub(crate) count:    u8,
+    pub(crate) data:     Vec<u8>,
+}
+
+/// Parse an APP2 segment
+///
+/// ICC profiles are split into chunks small enough for a segment, each APP2 segment
+/// holds `ICC_PROFILE\0`, the sequence number of the chunk, the number of chunks
+/// and the chunk. Returns whether the segment was interpreted.
+pub(crate) fn parse_app2(decoder: &mut Decoder, data: &[u8]) -> bool
+{
+    let Some(chunk) = data.strip_prefix(b"ICC_PROFILE\0")
+    else
+    {
+        return false;
+    };
+
+    if chunk.len() < 2
+    {
+        warn!("ICC profile chunk is too short, ignoring it");
+        return true;
+    }
+    let (sequence, count) = (chunk[0], chunk[1]);
+    let chunks = &decoder.icc_chunks;
+
+    if sequence == 0 || sequence > count
+    {
+        warn!(
+            "ICC profile chunk {sequence} of {count} is out of range, ignoring it"
+        );
+    }
+    else if let Some(first) = chunks.iter().find(|x| x.count != count)
+    {
+        warn!(
+            "ICC profile chunk {} says there are {} chunks instead of {}, ignoring it",
+            sequence, count, first.count
+        );
+    }
+    else if chunks.iter().any(|x| x.sequence == sequence)
+    {
+        warn!("Duplicate ICC profile chunk {sequence}, ignoring it");
+    }
+    else
+    {
+        decoder.icc_chunks.push(IccChunk {
+            sequence,
+            count,
+            data: chunk[2..].to_vec(),
+        });
+    }
+
+    true
 }
 
 /// Small utility function to print Un-zig-zagged quantization tables
 
-fn un_zig_zag(a: &[u8]) -> [i32; 64]
+fn un_zig_zag<T: Copy + Into<i32>>(a: &[T]) -> [i32; 64]
 {
     let mut output = [0; 64];
 
     for i in 0..64
     {
-        output[UN_ZIGZAG[i]] = i32::from(a[i]);
+        output[UN_ZIGZAG[i]] = a[i].into();
     }
 
     output
@@ -0,0 +1,436 @@
+//! Routines for hierarchical decoding
+//!
+//! Hierarchical images (Annex J of the spec) are coded as a sequence of frames. The DHP
+//! segment describes the full image, the first frame containing a component codes it
+//! at some (usually reduced) resolution and later, differential, frames code the
+//! difference between the component and the previous frame, optionally expanded to
+//! twice its width and/or height as requested by an EXP segment.
+//!
+//! Every frame, lossless or DCT based, is decoded into per component planes of samples
+//! before color conversion, so that the planes can serve as the reference of the next
+//! frame. The IDCT of differential DCT frames gives differences, which aren't level
+//! shifted.
+
+use std::io::Cursor;
+
+use crate::components::Components;
+use crate::errors::DecodeErrors;
+use crate::idct::dequantize_and_idct_differential;
+use crate::lossless::Plane;
+use crate::marker::Marker;
+use crate::misc::{find_marker, SOFMarkers};
+use crate::worker::Sample;
+use crate::Decoder;
+
+/// The full image of a hierarchical image, as described by the DHP segment
+pub(crate) struct Hierarchy
+{
+    /// Width of the image
+    pub width:        u16,
+    /// Height of the image
+    pub height:       u16,
+    /// Sample precision of every frame
+    pub precision:    u8,
+    /// Components of the image
+    pub components:   Vec<Components>,
+    /// Width of the current frame
+    pub frame_width:  u16,
+    /// Height of the current frame
+    pub frame_height: u16,
+    /// Whether reference components of the next frame are expanded horizontally
+    pub expand_h:     bool,
+    /// Whether reference components of the next frame are expanded vertically
+    pub expand_v:     bool,
+}
+
+impl Decoder
+{
+    /// Dimensions of the frame being decoded
+    ///
+    /// These are the image dimensions, unless the image is hierarchical.
+    pub(crate) fn frame_dimensions(&self) -> (usize, usize)
+    {
+        match &self.hierarchy
+        {
+            Some(hierarchy) => (
+                usize::from(hierarchy.frame_width),
+                usize::from(hierarchy.frame_height),
+            ),
+            None => (usize::from(self.info.width), usize::from(self.info.height)),
+        }
+    }
+
+    /// Check a frame header just parsed into `self` against the DHP segment
+    ///
+    /// The image keeps the dimensions from the DHP segment, the frame
+    /// dimensions are kept in the hierarchy.
+    pub(crate) fn start_hierarchical_frame(&mut self, sof: SOFMarkers)
+        -> Result<(), DecodeErrors>
+    {
+        let hierarchy = match &mut self.hierarchy
+        {
+            Some(hierarchy) => hierarchy,
+            None if sof.is_differential() =>
+            {
+                return Err(DecodeErrors::FormatStatic(
+                    "Differential frame outside of a hierarchical image",
+                ));
+            }
+            None => return Ok(()),
+        };
+
+        if self.precision != hierarchy.precision
+        {
+            return Err(DecodeErrors::Format(format!(
+                "Frame precision {} differs from image precision {}",
+                self.precision, hierarchy.precision
+            )));
+        }
+        if self.info.width > hierarchy.width || self.info.height > hierarchy.height
+        {
+            return Err(DecodeErrors::Format(format!(
+                "Frame dimensions {}x{} larger than image dimensions {}x{}",
+                self.info.width, self.info.height, hierarchy.width, hierarchy.height
+            )));
+        }
+
+        for component in &self.components
+        {
+            let matches = hierarchy.components.iter().any(|x| {
+                x.id == component.id
+                    && x.horizontal_sample == component.horizontal_sample
+                    && x.vertical_sample == component.vertical_sample
+            });
+
+            if !matches
+            {
+                return Err(DecodeErrors::Format(format!(
+                    "Component {} of frame doesn't match a component of the DHP segment",
+                    component.id
+                )));
+            }
+        }
+
+        hierarchy.frame_width = self.info.width;
+        hierarchy.frame_height = self.info.height;
+
+        self.info.width = hierarchy.width;
+        self.info.height = hierarchy.height;
+
+        Ok(())
+    }
+
+    /// Decode a hierarchical image
+    ///
+    /// The frame header and first scan header of the first frame have been parsed.
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn decode_hierarchical<T: Sample>(
+        &mut self, reader: &mut Cursor<Vec<u8>>,
+    ) -> Result<Vec<T>, DecodeErrors>
+    {
+        let num_components = self.hierarchy.as_ref().map_or(0, |x| x.components.len());
+
+        // the latest reconstruction of every component
+        let mut references: Vec<Option<Plane>> = (0..num_components).map(|_| None).collect();
+
+        let mut seen_frames = 1;
+
+        loop
+        {
+            let (planes, marker) = if self.is_lossless
+            {
+                self.decode_lossless_frame(reader)?
+            }
+            else
+            {
+                self.decode_dct_frame(reader)?
+            };
+
+            self.add_hierarchical_frame(planes, &mut references)?;
+
+            match marker
+            {
+                Some(Marker::EOI) | None => break,
+                Some(marker) =>
+                {
+                    seen_frames += 1;
+
+                    if seen_frames > self.options.get_max_scans()
+                    {
+                        return Err(DecodeErrors::Format(format!(
+                            "Too many frames, exceeded limit of {}",
+                            self.options.get_max_scans()
+                        )));
+                    }
+
+                    self.parse_frame_headers(marker, reader)?;
+                }
+            }
+        }
+
+        let hierarchy = self.hierarchy.as_ref().unwrap();
+
+        // the last frame of every component should reach the full resolution
+        let mut planes = Vec::with_capacity(num_components);
+
+        for (reference, component) in references.into_iter().zip(&hierarchy.components)
+        {
+            let reference = reference.ok_or_else(|| {
+                DecodeErrors::Format(format!("No frame for component {}", component.id))
+            })?;
+
+            let width =
+                (usize::from(hierarchy.width) * component.horizontal_sample).div_ceil(self.h_max);
+            let height =
+                (usize::from(hierarchy.height) * component.vertical_sample).div_ceil(self.v_max);
+
+            if reference.width != width || reference.height != height
+            {
+                return Err(DecodeErrors::Format(format!(
+                    "Component {} was only decoded to {}x{}, expected {}x{}",
+                    component.id, reference.width, reference.height, width, height
+                )));
+            }
+            planes.push(reference);
+        }
+
+        self.components = hierarchy.components.clone();
+        self.info.components = hierarchy.components.len() as u8;
+
+        self.lossless_to_colorspace(&planes)
+    }
+
+    /// Decode the scans of a DCT based frame into per component planes
+    ///
+    /// Samples of non-differential frames are level shifted like in any DCT image,
+    /// differential frames give differences to the reference, stored modulo 2^16.
+    #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+    fn decode_dct_frame(&mut self, reader: &mut Cursor<Vec<u8>>)
+        -> Result<(Vec<Plane>, Option<Marker>), DecodeErrors>
+    {
+        let (coefficients, marker) = self.decode_coefficients(reader)?;
+        let (width, height) = self.frame_dimensions();
+        let differential = self.info.sof.is_differential();
+
+        let planes = self
+            .components
+            .iter()
+            .zip(coefficients)
+            .map(|(component, coefficients)| {
+                let stride = component.width_stride;
+                let qt_table = &component.quantization_table;
+
+                let samples = if differential
+                {
+                    dequantize_and_idct_differential(&coefficients, qt_table, stride)
+                        .into_iter()
+                        .map(|x| x as u16)
+                        .collect()
+                }
+                else
+                {
+                    // the IDCT transforms whole rows of blocks, 8 rows of samples each
+                    let rows = coefficients.len() / (8 * stride);
+
+                    (self.idct_func)(&coefficients, qt_table, stride, rows, 1)
+                        .into_iter()
+                        .map(|x| x as u16)
+                        .collect()
+                };
+
+                Plane {
+                    samples,
+                    stride,
+                    width: (width * component.horizontal_sample).div_ceil(self.h_max),
+                    height: (height * component.vertical_sample).div_ceil(self.v_max),
+                    pt: 0,
+                    complete: true,
+                }
+            })
+            .collect();
+
+        Ok((planes, Some(marker)))
+    }
+
+    /// Parse the headers of the next frame, up to and including its first scan header
+    fn parse_frame_headers(&mut self, marker: Marker, reader: &mut Cursor<Vec<u8>>)
+        -> Result<(), DecodeErrors>
+    {
+        let mut marker = marker;
+
+        loop
+        {
+            match marker
+            {
+                Marker::SOS =>
+                {
+                    if self.components.is_empty()
+                    {
+                        return Err(DecodeErrors::FormatStatic("Scan before frame header"));
+                    }
+                    return self.parse_marker_inner(marker, reader);
+                }
+                Marker::SOF(_)
+                | Marker::EXP
+                | Marker::DHT
+                | Marker::DAC
+                | Marker::DQT
+                | Marker::DRI
+                | Marker::COM
+                | Marker::APP(_) =>
+                {
+                    self.parse_marker_inner(marker, reader)?;
+                }
+                _ =>
+                {
+                    return Err(DecodeErrors::Format(format!(
+                        "Unexpected marker {marker:?} between frames of a hierarchical image"
+                    )));
+                }
+            }
+
+            marker = find_marker(reader).ok_or(DecodeErrors::FormatStatic(
+                "Marker missing where expected",
+            ))?;
+        }
+    }
+
+    /// Add the components decoded from a frame to the reconstruction of the image
+    #[allow(clippy::cast_possible_wrap, clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+    fn add_hierarchical_frame(
+        &mut self, planes: Vec<Plane>, references: &mut [Option<Plane>],
+    ) -> Result<(), DecodeErrors>
+    {
+        let hierarchy = self.hierarchy.as_mut().unwrap();
+        let differential = self.info.sof.is_differential();
+        let max = (1_i32 << self.precision) - 1;
+
+        for (component, mut plane) in self.components.iter().zip(planes)
+        {
+            let pos = hierarchy
+                .components
+                .iter()
+                .position(|x| x.id == component.id)
+                .unwrap();
+
+            // undo the point transform
+            plane
+                .samples
+                .iter_mut()
+                .for_each(|x| *x = x.wrapping_shl(u32::from(plane.pt)));
+            plane.pt = 0;
+
+            if differential
+            {
+                let reference = references[pos].take().ok_or_else(|| {
+                    DecodeErrors::Format(format!(
+                        "Differential frame for component {} which has no reference",
+                        component.id
+                    ))
+                })?;
+
+                let reference = expand(reference, hierarchy.expand_h, hierarchy.expand_v);
+
+                if reference.width < plane.width || reference.height < plane.height
+                {
+                    return Err(DecodeErrors::Format(format!(
+                        "Reference of component {} is {}x{}, too small for a {}x{} frame",
+                        component.id, reference.width, reference.height, plane.width, plane.height
+                    )));
+                }
+
+                for (row, reference_row) in plane
+                    .samples
+                    .chunks_exact_mut(plane.stride)
+                    .zip(reference.samples.chunks_exact(reference.stride))
+                {
+                    // differences are added modulo 2^16, section J.2.1
+                    for (sample, reference) in row.iter_mut().zip(reference_row)
+                    {
+                        *sample = sample.wrapping_add(*reference);
+                    }
+                    // DCT frames only approximate the differences, so the sum may
+                    // leave the range of samples
+                    if !self.is_lossless
+                    {
+                        for sample in row.iter_mut()
+                        {
+                            *sample = i32::from(*sample as i16).clamp(0, max) as u16;
+                        }
+                    }
+                }
+            }
+            else if references[pos].is_some()
+            {
+                return Err(DecodeErrors::Format(format!(
+                    "Component {} is in more than one non-differential frame",
+                    component.id
+                )));
+            }
+
+            references[pos] = Some(plane);
+        }
+
+        hierarchy.expand_h = false;
+        hierarchy.expand_v = false;
+
+        Ok(())
+    }
+}
+
+/// Expand a reference component to twice its width and/or height, section J.1.1.2
+///
+/// Inserted samples are the average of their neighbours, rounded down, samples on
+/// the right and bottom edge are replicated. Expansion is first done horizontally,
+/// then vertically.
+#[allow(clippy::cast_possible_truncation)]
+fn expand(plane: Plane, horizontal: bool, vertical: bool) -> Plane
+{
+    let mut plane = plane;
+
+    if horizontal
+    {
+        let width = plane.width * 2;
+        let mut samples = vec![0; width * plane.height];
+
+        for (out, row) in samples
+            .chunks_exact_mut(width)
+            .zip(plane.samples.chunks_exact(plane.stride))
+        {
+            let row = &row[..plane.width];
+
+            for (x, pair) in out.chunks_exact_mut(2).enumerate()
+            {
+                let next = row[(x + 1).min(plane.width - 1)];
+
+                pair[0] = row[x];
+                pair[1] = ((u32::from(row[x]) + u32::from(next)) >> 1) as u16;
+            }
+        }
+        plane.samples = samples;
+        plane.width = width;
+        plane.stride = width;
+    }
+    if vertical
+    {
+        let height = plane.height * 2;
+        let mut samples = vec![0; plane.stride * height];
+
+        for (y, rows) in samples.chunks_exact_mut(plane.stride * 2).enumerate()
+        {
+            let (even, odd) = rows.split_at_mut(plane.stride);
+            let row = &plane.samples[y * plane.stride..][..plane.stride];
+            let next = &plane.samples[(y + 1).min(plane.height - 1) * plane.stride..][..plane.stride];
+
+            even.copy_from_slice(row);
+
+            for ((out, a), b) in odd.iter_mut().zip(row).zip(next)
+            {
+                *out = ((u32::from(*a) + u32::from(*b)) >> 1) as u16;
+            }
+        }
+        plane.samples = samples;
+        plane.height = height;
+    }
+    plane
+}
@@ -245,8 +245,9 @@ impl HuffmanTable
                             k += (!0_i16 << mag_bits) + 1;
                         };
 
-                        // if result is small enough fit into fast ac table
-                        if (-128..=127).contains(&k)
+                        // if result is small enough fit into fast ac table,
+                        // the value is stored above 10 bits of an i16 so it must fit in 6 bits
+                        if (-32..=31).contains(&k)
                         {
                             fast_ac[i] = (k << 10) + (run << 4) + (len + mag_bits);
                         }
@@ -258,14 +259,15 @@ impl HuffmanTable
 
         // Validate symbols as being reasonable
         // For AC tables, we make no check, but accept all byte values 0..255
-        // For DC tables, we require symbols to be in range 0..15
+        // For DC tables, we require symbols to be in range 0..16, 16 is only
+        // valid for lossless images
         if is_dc
         {
             for i in 0..num_symbols
             {
                 let sym = self.values[i];
 
-                if sym > 15
+                if sym > 16
                 {
                     return Err(DecodeErrors::HuffmanDecode("Bad Huffman Table".to_string()));
                 }
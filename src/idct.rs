@@ -28,7 +28,8 @@
 use crate::decoder::IDCTPtr;
 #[cfg(feature = "X86")]
 use crate::idct::avx2::dequantize_and_idct_avx2;
-use crate::idct::scalar::dequantize_and_idct_int;
+pub(crate) use crate::idct::scalar::dequantize_and_idct_differential;
+use crate::idct::scalar::{dequantize_and_idct_int, dequantize_and_idct_int_12};
 
 #[cfg(feature = "x86")]
 mod avx2;
@@ -36,9 +37,18 @@ mod avx2;
 mod scalar;
 
 /// Choose an appropriate IDCT function
+///
+/// `precision` is the sample precision of the image, 12 bit images
+/// can only be handled by the wider scalar IDCT.
 
-pub fn choose_idct_func(use_unsafe: bool) -> IDCTPtr
+pub fn choose_idct_func(use_unsafe: bool, precision: u8) -> IDCTPtr
 {
+    if precision > 8
+    {
+        debug!("Using 12 bit scalar integer IDCT");
+
+        return dequantize_and_idct_int_12;
+    }
     if use_unsafe
     {
         #[cfg(all(feature = "x86", any(target_arch = "x86_64", target_arch = "x86")))]
@@ -125,3 +135,54 @@ fn test_min()
     assert_eq!(output_scalar, output_avx, "AVX and scalar do not match");
     assert_eq!(output_avx, &output, "Test for min IDCT fails");
 }
+
+#[test]
+#[cfg(feature = "x86")]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn test_random_blocks()
+{
+    use crate::misc::Aligned32;
+
+    if !is_x86_feature_detected!("avx2")
+    {
+        return;
+    }
+    // xorshift, so that every run checks the same blocks
+    let mut state = 0x2545_F491_u32;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state
+    };
+
+    for _ in 0..256
+    {
+        let mut qt_table = Aligned32([0; 64]);
+
+        qt_table.0.iter_mut().for_each(|x| *x = (next() % 16 + 1) as i32);
+
+        // two rows of four blocks, every other block has only a DC coefficient
+        let stride = 32;
+        let coeff = (0..8 * 64)
+            .map(|i| {
+                let limit = (1024 / qt_table.0[i % 64]) as u32;
+
+                if (i % 64 == 0 || (i / 64) % 2 == 0) && next() % 4 == 0
+                {
+                    (next() % (2 * limit + 1)) as i16 - limit as i16
+                }
+                else
+                {
+                    0
+                }
+            })
+            .collect::<Vec<i16>>();
+
+        let output_scalar = dequantize_and_idct_int(&coeff, &qt_table, stride, 2, 1);
+        let output_avx =
+            crate::idct::avx2::dequantize_and_idct_avx2(&coeff, &qt_table, stride, 2, 1);
+
+        assert_eq!(output_scalar, output_avx, "AVX and scalar do not match");
+    }
+}
@@ -160,10 +160,9 @@ unsafe fn dequantize_and_idct_int_avx2(
                 {
                     // AC terms all zero, idct of the block is  is (coeff[0] *qt[0])/8 + bias(128)
                     // (and clamped to 255)
+                    // (dequantization is done in 32 bits, 16 bit quantization tables may overflow an i16)
                     let idct_value = _mm_set1_epi16(
-                        ((vector[0].wrapping_mul(qt_table.0[0] as i16) >> 3) + 128)
-                            .max(0)
-                            .min(255),
+                        (((i32::from(vector[0]) * qt_table.0[0]) >> 3) + 128).clamp(0, 255) as i16,
                     );
                     macro_rules! store {
                         ($pos:tt,$value:tt) => {
@@ -330,12 +329,8 @@ unsafe fn dequantize_and_idct_int_avx2(
                 };
             }
 
-            transpose(
-                &mut row0, &mut row1, &mut row2, &mut row3, &mut row4, &mut row5, &mut row6,
-                &mut row7,
-            );
-
-            // Process rows
+            // Process columns, every register holds a row so the pass works down the columns.
+            // The order matters for rounding, it has to be the one of the scalar code
             dct_pass!(512, 10);
 
             transpose(
@@ -343,10 +338,14 @@ unsafe fn dequantize_and_idct_int_avx2(
                 &mut row7,
             );
 
-            // process columns
+            // process rows
             dct_pass!(SCALE_BITS, 17);
 
             // transpose to original
+            transpose(
+                &mut row0, &mut row1, &mut row2, &mut row3, &mut row4, &mut row5, &mut row6,
+                &mut row7,
+            );
 
             // Pack i32 to i16's,
             // clamp them to be between 0-255
@@ -45,7 +45,8 @@ pub fn dequantize_and_idct_int(
             if &vector[1..] == &[0_i16; 63]
             {
                 // okay then if you work, yaay, let's write you really quick
-                let coeff = [((vector[0].wrapping_mul(qt_table.0[0] as i16)) >> 3) + 128; 8];
+                // dequantize in 32 bits, 16 bit quantization tables may overflow an i16
+                let coeff = [clamp((dequantize(vector[0], qt_table.0[0]) >> 3) + 128); 8];
 
                 macro_rules! store {
                     ($index:tt) => {
@@ -281,6 +282,219 @@ pub fn dequantize_and_idct_int(
     return out_vector;
 }
 
+/// Perform Integer IDCT on 12 bit images and level shift (by adding 2048 to each element)
+///
+/// This is the same algorithm as [`dequantize_and_idct_int`] but intermediate values are
+/// carried in 64 bit integers, since 12 bit coefficients multiplied by the fixed point
+/// constants overflow an `i32`.
+///
+/// Output is clamped between 0 and 4095.
+#[allow(clippy::cast_possible_truncation)]
+pub fn dequantize_and_idct_int_12(
+    vector: &[i16], qt_table: &Aligned32<[i32; 64]>, stride: usize, samp_factors: usize,
+    v_samp: usize,
+) -> Vec<i16>
+{
+    const SCALE_BITS_12: i64 = 512 + 65536 + (2048 << 17);
+
+    let mut out_vector = vec![0; vector.len()];
+
+    let mut tmp = [0_i64; 64];
+
+    let chunks = vector.len() * v_samp / samp_factors;
+
+    for (in_vector, out_vector) in vector
+        .chunks_exact(chunks)
+        .zip(out_vector.chunks_exact_mut(chunks))
+    {
+        let mut x = 0;
+
+        for vector in in_vector.chunks_exact(64)
+        {
+            let mut pos = x;
+
+            if vector[1..] == [0_i16; 63]
+            {
+                // only a DC coefficient, the whole block is DC/8 level shifted
+                let value =
+                    clamp_12(((i64::from(vector[0]) * i64::from(qt_table.0[0])) >> 3) + 2048);
+
+                for _ in 0..8
+                {
+                    out_vector[pos..pos + 8].fill(value);
+                    pos += stride;
+                }
+            }
+            else
+            {
+                idct_columns_64(vector, qt_table, &mut tmp);
+                // rows
+                for row in tmp.chunks_exact(8)
+                {
+                    let (x0, x1, x2, x3, t0, t1, t2, t3) =
+                        idct_1d_64(row.try_into().unwrap(), SCALE_BITS_12);
+
+                    let out = &mut out_vector[pos..pos + 8];
+
+                    out[0] = clamp_12((x0 + t3) >> 17);
+                    out[1] = clamp_12((x1 + t2) >> 17);
+                    out[2] = clamp_12((x2 + t1) >> 17);
+                    out[3] = clamp_12((x3 + t0) >> 17);
+                    out[4] = clamp_12((x3 - t0) >> 17);
+                    out[5] = clamp_12((x2 - t1) >> 17);
+                    out[6] = clamp_12((x1 - t2) >> 17);
+                    out[7] = clamp_12((x0 - t3) >> 17);
+
+                    pos += stride;
+                }
+            }
+            x += 8;
+        }
+    }
+    return out_vector;
+}
+
+/// Perform Integer IDCT on the coefficients of a differential frame
+///
+/// Differential frames of hierarchical images code the difference to a reference,
+/// so unlike [`dequantize_and_idct_int_12`] the output is neither level shifted nor
+/// clamped. Blocks are in rows of `stride / 8` blocks and the output has rows of
+/// `stride` samples.
+#[allow(clippy::cast_possible_truncation)]
+pub fn dequantize_and_idct_differential(
+    vector: &[i16], qt_table: &Aligned32<[i32; 64]>, stride: usize,
+) -> Vec<i32>
+{
+    const SCALE_BITS_DIFF: i64 = 512 + 65536;
+
+    let mut out_vector = vec![0; vector.len()];
+
+    let mut tmp = [0_i64; 64];
+
+    let blocks_per_row = stride / 8;
+
+    for (i, vector) in vector.chunks_exact(64).enumerate()
+    {
+        let mut pos = (i / blocks_per_row) * 8 * stride + (i % blocks_per_row) * 8;
+
+        idct_columns_64(vector, qt_table, &mut tmp);
+
+        for row in tmp.chunks_exact(8)
+        {
+            let (x0, x1, x2, x3, t0, t1, t2, t3) =
+                idct_1d_64(row.try_into().unwrap(), SCALE_BITS_DIFF);
+
+            let out = &mut out_vector[pos..pos + 8];
+
+            out[0] = ((x0 + t3) >> 17) as i32;
+            out[1] = ((x1 + t2) >> 17) as i32;
+            out[2] = ((x2 + t1) >> 17) as i32;
+            out[3] = ((x3 + t0) >> 17) as i32;
+            out[4] = ((x3 - t0) >> 17) as i32;
+            out[5] = ((x2 - t1) >> 17) as i32;
+            out[6] = ((x1 - t2) >> 17) as i32;
+            out[7] = ((x0 - t3) >> 17) as i32;
+
+            pos += stride;
+        }
+    }
+    return out_vector;
+}
+
+/// Column pass of the IDCT in 64 bit integers, dequantizing the coefficients of a block
+///
+/// The output keeps 2 extra bits of precision for the row pass.
+fn idct_columns_64(vector: &[i16], qt_table: &Aligned32<[i32; 64]>, tmp: &mut [i64; 64])
+{
+    for ptr in 0..8
+    {
+        let coeff = |i: usize| i64::from(vector[i]) * i64::from(qt_table.0[i]);
+
+        let (x0, x1, x2, x3, t0, t1, t2, t3) = idct_1d_64(
+            [
+                coeff(ptr),
+                coeff(ptr + 8),
+                coeff(ptr + 16),
+                coeff(ptr + 24),
+                coeff(ptr + 32),
+                coeff(ptr + 40),
+                coeff(ptr + 48),
+                coeff(ptr + 56),
+            ],
+            512,
+        );
+
+        tmp[ptr] = (x0 + t3) >> 10;
+        tmp[ptr + 8] = (x1 + t2) >> 10;
+        tmp[ptr + 16] = (x2 + t1) >> 10;
+        tmp[ptr + 24] = (x3 + t0) >> 10;
+        tmp[ptr + 32] = (x3 - t0) >> 10;
+        tmp[ptr + 40] = (x2 - t1) >> 10;
+        tmp[ptr + 48] = (x1 - t2) >> 10;
+        tmp[ptr + 56] = (x0 - t3) >> 10;
+    }
+}
+
+/// One dimensional pass of the IDCT in 64 bit integers
+///
+/// Returns the even part (`x0..x3`, with `bias` added) and the odd part (`t0..t3`)
+/// which are combined by the callers.
+#[allow(clippy::many_single_char_names)]
+fn idct_1d_64(v: [i64; 8], bias: i64) -> (i64, i64, i64, i64, i64, i64, i64, i64)
+{
+    // even part
+    let p2 = v[2];
+    let p3 = v[6];
+    let p1 = (p2 + p3) * 2217;
+    let t2 = p1 + p3 * -7567;
+    let t3 = p1 + p2 * 3135;
+
+    let t0 = (v[0] + v[4]) << 12;
+    let t1 = (v[0] - v[4]) << 12;
+
+    let x0 = t0 + t3 + bias;
+    let x3 = t0 - t3 + bias;
+    let x1 = t1 + t2 + bias;
+    let x2 = t1 - t2 + bias;
+
+    // odd part
+    let mut t0 = v[7];
+    let mut t1 = v[5];
+    let mut t2 = v[3];
+    let mut t3 = v[1];
+
+    let p3 = t0 + t2;
+    let p4 = t1 + t3;
+    let p1 = t0 + t3;
+    let p2 = t1 + t2;
+    let p5 = (p3 + p4) * 4816;
+
+    t0 *= 1223;
+    t1 *= 8410;
+    t2 *= 12586;
+    t3 *= 6149;
+
+    let p1 = p5 + p1 * -3685;
+    let p2 = p5 + p2 * -10497;
+    let p3 = p3 * -8034;
+    let p4 = p4 * -1597;
+
+    t3 += p1 + p4;
+    t2 += p2 + p3;
+    t1 += p2 + p4;
+    t0 += p1 + p3;
+
+    (x0, x1, x2, x3, t0, t1, t2, t3)
+}
+
+/// Clamp values between 0 and 4095
+#[inline]
+#[allow(clippy::cast_possible_truncation)]
+fn clamp_12(a: i64) -> i16
+{
+    a.clamp(0, 4095) as i16
+}
+
 #[inline]
 #[allow(clippy::cast_possible_truncation)]
 /// Multiply a number by 4096
@@ -0,0 +1,316 @@
+//! Photoshop image resources and IPTC metadata
+//!
+//! Photoshop stores metadata in APP13 segments starting with `Photoshop 3.0\0`,
+//! followed by image resource blocks. Each block has the `8BIM` signature, an id,
+//! a name and its data. Resources too large for a segment continue in the next one.
+//!
+//! Captions, credits and keywords are IPTC-IIM datasets in the resource with id
+//! [`RESOURCE_IPTC`]. Every dataset has a record and a dataset number, e.g. the
+//! caption is dataset 120 of record 2, and some datasets like keywords may repeat.
+
+/// Id of the image resource holding IPTC datasets
+pub const RESOURCE_IPTC: u16 = 0x0404;
+
+/// Dataset of the coded character set, in the envelope record (1)
+pub const DATASET_CHARACTER_SET: (u8, u8) = (1, 90);
+/// Dataset of the title of the image, in the application record (2)
+pub const DATASET_OBJECT_NAME: (u8, u8) = (2, 5);
+/// Dataset of a keyword, may repeat
+pub const DATASET_KEYWORDS: (u8, u8) = (2, 25);
+/// Dataset of the date the image was created, as `CCYYMMDD`
+pub const DATASET_DATE_CREATED: (u8, u8) = (2, 55);
+/// Dataset of the time the image was created, as `HHMMSS±HHMM`
+pub const DATASET_TIME_CREATED: (u8, u8) = (2, 60);
+/// Dataset of a creator of the image, may repeat
+pub const DATASET_BY_LINE: (u8, u8) = (2, 80);
+/// Dataset of the city the image was taken in
+pub const DATASET_CITY: (u8, u8) = (2, 90);
+/// Dataset of the country the image was taken in
+pub const DATASET_COUNTRY: (u8, u8) = (2, 101);
+/// Dataset of the headline
+pub const DATASET_HEADLINE: (u8, u8) = (2, 105);
+/// Dataset of who should be credited for the image
+pub const DATASET_CREDIT: (u8, u8) = (2, 110);
+/// Dataset of the original owner of the image
+pub const DATASET_SOURCE: (u8, u8) = (2, 115);
+/// Dataset of the copyright notice
+pub const DATASET_COPYRIGHT_NOTICE: (u8, u8) = (2, 116);
+/// Dataset of the caption, or abstract
+pub const DATASET_CAPTION: (u8, u8) = (2, 120);
+
+/// The escape sequence the coded character set dataset holds for UTF-8
+const UTF8: &[u8] = b"\x1B%G";
+
+/// A Photoshop image resource
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ImageResource
+{
+    /// Id of the resource, e.g. [`RESOURCE_IPTC`]
+    pub id:   u16,
+    /// Name of the resource, usually empty
+    pub name: String,
+    /// Data of the resource
+    pub data: Vec<u8>,
+}
+
+/// Parse the image resource blocks following `Photoshop 3.0\0` in APP13 segments
+///
+/// Blocks are read until the data ends or a block is corrupt.
+pub(crate) fn parse_image_resources(data: &[u8]) -> Vec<ImageResource>
+{
+    let mut resources = Vec::new();
+    let mut position = 0;
+
+    while position < data.len()
+    {
+        let Some((resource, length)) = parse_image_resource(&data[position..])
+        else
+        {
+            warn!("Corrupt image resource at offset {position}, ignoring the rest");
+            break;
+        };
+        resources.push(resource);
+        position += length;
+    }
+
+    resources
+}
+
+/// Parse an image resource block, returning it and its length including padding
+fn parse_image_resource(data: &[u8]) -> Option<(ImageResource, usize)>
+{
+    if data.get(..4)? != b"8BIM"
+    {
+        return None;
+    }
+    let id = u16::from_be_bytes(data.get(4..6)?.try_into().unwrap());
+    // a pascal string padded to an even length
+    let name_length = usize::from(*data.get(6)?);
+    let name = data.get(7..7 + name_length)?;
+    let size_start = 7 + name_length + (name_length + 1) % 2;
+    let size = u32::from_be_bytes(data.get(size_start..size_start + 4)?.try_into().unwrap());
+    let start = size_start + 4;
+    let end = start.checked_add(usize::try_from(size).ok()?)?;
+    let resource_data = data.get(start..end)?;
+
+    let resource = ImageResource {
+        id,
+        name: name.iter().map(|x| char::from(*x)).collect(),
+        data: resource_data.to_vec(),
+    };
+    // data is padded to an even length too
+    let length = (end + end % 2).min(data.len());
+
+    Some((resource, length))
+}
+
+/// An IPTC dataset
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct IptcDataSet
+{
+    /// Record number, 1 for the envelope and 2 for the application record
+    pub record:  u8,
+    /// Dataset number in the record
+    pub dataset: u8,
+    /// Data of the dataset, text for most datasets
+    pub data:    Vec<u8>,
+}
+
+/// IPTC metadata of an image
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Iptc
+{
+    datasets: Vec<IptcDataSet>,
+    utf8:     bool,
+}
+
+impl Iptc
+{
+    /// Parse the datasets of an IPTC image resource
+    ///
+    /// Datasets are read until the data ends or a dataset is corrupt, the resource is
+    /// often padded with zeroes.
+    pub(crate) fn parse(data: &[u8]) -> Iptc
+    {
+        let mut datasets = Vec::new();
+        let mut position = 0;
+
+        while position < data.len() && data[position] == 0x1C
+        {
+            let Some((dataset, length)) = Iptc::parse_dataset(&data[position..])
+            else
+            {
+                warn!("Corrupt IPTC dataset at offset {position}, ignoring the rest");
+                break;
+            };
+            datasets.push(dataset);
+            position += length;
+        }
+
+        let utf8 = datasets
+            .iter()
+            .any(|x| (x.record, x.dataset) == DATASET_CHARACTER_SET && x.data == UTF8);
+
+        Iptc { datasets, utf8 }
+    }
+
+    /// Parse a dataset, returning it and its length
+    fn parse_dataset(data: &[u8]) -> Option<(IptcDataSet, usize)>
+    {
+        let record = *data.get(1)?;
+        let dataset = *data.get(2)?;
+        let size = u16::from_be_bytes(data.get(3..5)?.try_into().unwrap());
+
+        let (start, size) = if size & 0x8000 == 0
+        {
+            (5, usize::from(size))
+        }
+        else
+        {
+            // an extended dataset, the low bits are the length of the size
+            let length = usize::from(size & 0x7FFF);
+
+            if length == 0 || length > 4
+            {
+                return None;
+            }
+            let size = data
+                .get(5..5 + length)?
+                .iter()
+                .fold(0, |size, x| size << 8 | usize::from(*x));
+
+            (5 + length, size)
+        };
+        let end = start.checked_add(size)?;
+
+        let dataset = IptcDataSet {
+            record,
+            dataset,
+            data: data.get(start..end)?.to_vec(),
+        };
+
+        Some((dataset, end))
+    }
+
+    /// Every dataset, in the order they are stored
+    #[must_use]
+    pub fn datasets(&self) -> &[IptcDataSet]
+    {
+        &self.datasets
+    }
+
+    /// The text of the first `(record, dataset)`
+    ///
+    /// Text is UTF-8 if the coded character set dataset says so. Otherwise it is
+    /// read as UTF-8 if it is valid, and as ISO 8859-1 if it isn't.
+    #[must_use]
+    pub fn get(&self, dataset: (u8, u8)) -> Option<String>
+    {
+        self.get_all(dataset).into_iter().next()
+    }
+
+    /// The text of every `(record, dataset)`, for datasets which repeat
+    #[must_use]
+    pub fn get_all(&self, dataset: (u8, u8)) -> Vec<String>
+    {
+        self.datasets
+            .iter()
+            .filter(|x| (x.record, x.dataset) == dataset)
+            .map(|x| self.text(&x.data))
+            .collect()
+    }
+
+    fn text(&self, data: &[u8]) -> String
+    {
+        match std::str::from_utf8(data)
+        {
+            Ok(text) => text.to_string(),
+            Err(_) if self.utf8 => String::from_utf8_lossy(data).into_owned(),
+            Err(_) => data.iter().map(|x| char::from(*x)).collect(),
+        }
+    }
+
+    /// Title of the image
+    #[must_use]
+    pub fn object_name(&self) -> Option<String>
+    {
+        self.get(DATASET_OBJECT_NAME)
+    }
+
+    /// Headline
+    #[must_use]
+    pub fn headline(&self) -> Option<String>
+    {
+        self.get(DATASET_HEADLINE)
+    }
+
+    /// Caption, or abstract
+    #[must_use]
+    pub fn caption(&self) -> Option<String>
+    {
+        self.get(DATASET_CAPTION)
+    }
+
+    /// Keywords
+    #[must_use]
+    pub fn keywords(&self) -> Vec<String>
+    {
+        self.get_all(DATASET_KEYWORDS)
+    }
+
+    /// Creators of the image
+    #[must_use]
+    pub fn by_line(&self) -> Vec<String>
+    {
+        self.get_all(DATASET_BY_LINE)
+    }
+
+    /// Who should be credited for the image
+    #[must_use]
+    pub fn credit(&self) -> Option<String>
+    {
+        self.get(DATASET_CREDIT)
+    }
+
+    /// Original owner of the image
+    #[must_use]
+    pub fn source(&self) -> Option<String>
+    {
+        self.get(DATASET_SOURCE)
+    }
+
+    /// Copyright notice
+    #[must_use]
+    pub fn copyright_notice(&self) -> Option<String>
+    {
+        self.get(DATASET_COPYRIGHT_NOTICE)
+    }
+
+    /// City the image was taken in
+    #[must_use]
+    pub fn city(&self) -> Option<String>
+    {
+        self.get(DATASET_CITY)
+    }
+
+    /// Country the image was taken in
+    #[must_use]
+    pub fn country(&self) -> Option<String>
+    {
+        self.get(DATASET_COUNTRY)
+    }
+
+    /// Date the image was created, as `CCYYMMDD`
+    #[must_use]
+    pub fn date_created(&self) -> Option<String>
+    {
+        self.get(DATASET_DATE_CREATED)
+    }
+
+    /// Time the image was created, as `HHMMSS±HHMM`
+    #[must_use]
+    pub fn time_created(&self) -> Option<String>
+    {
+        self.get(DATASET_TIME_CREATED)
+    }
+}
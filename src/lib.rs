@@ -86,23 +86,31 @@
 #[macro_use]
 extern crate log;
 
-pub use crate::decoder::{Decoder, ImageInfo};
+pub use crate::decoder::{ComponentInfo, Decoder, HuffmanCodes, ImageInfo, Segment, Thumbnail};
 pub use crate::misc::ColorSpace;
 pub use crate::options::ZuneJpegOptions;
 
+mod arithmetic;
 mod bitstream;
 mod color_convert;
 mod components;
 mod decoder;
 pub mod errors;
+pub mod exif;
 mod headers;
+mod hierarchical;
 mod huffman;
 mod idct;
-mod marker;
+pub mod iptc;
+mod lossless;
+pub mod marker;
 mod mcu;
 mod mcu_prog;
 mod misc;
 mod options;
+mod orientation;
+mod quality;
+pub mod structure;
 mod unsafe_utils;
 mod upsampler;
 mod worker;
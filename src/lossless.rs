@@ -0,0 +1,597 @@
+//! Routines for lossless decoding
+//!
+//! Lossless images (Annex H of the spec) don't use the DCT, instead every sample is
+//! predicted from its already decoded left, upper and upper-left neighbours and only
+//! the difference to the prediction is entropy coded.
+//!
+//! Decoding is inherently serial, each sample depends on the ones before it, so we
+//! decode every scan into per component planes and only convert colors at the end.
+//!
+//! Samples are kept in `u16`'s, as lossless images can have any precision between 2 and 16
+//! bits.
+
+use std::io::Cursor;
+
+use crate::arithmetic::ArithmeticDecoder;
+use crate::bitstream::BitStream;
+use crate::color_convert::{cmyk_to_output_pixel, rgb_to_luma, ycbcr_to_rgb_pixel_u16};
+use crate::errors::DecodeErrors;
+use crate::headers::parse_sos;
+use crate::marker::Marker;
+use crate::misc::find_marker;
+use crate::worker::Sample;
+use crate::{ColorSpace, Decoder};
+
+/// A decoded component of a lossless image
+pub(crate) struct Plane
+{
+    /// Samples, before the point transform is undone
+    pub samples:  Vec<u16>,
+    /// Width of a row of samples, including samples padding the last MCU
+    pub stride:   usize,
+    /// Width of the component in samples
+    pub width:    usize,
+    /// Height of the component in samples
+    pub height:   usize,
+    /// Point transform of the scan the component was decoded in
+    pub pt:       u8,
+    /// Whether a scan has decoded this component
+    pub complete: bool,
+}
+
+impl Decoder
+{
+    /// Decode a lossless image
+    ///
+    /// This decodes every scan in the image, stopping at the first error.
+    pub(crate) fn decode_lossless<T: Sample>(
+        &mut self, reader: &mut Cursor<Vec<u8>>,
+    ) -> Result<Vec<T>, DecodeErrors>
+    {
+        if self.components.len() != self.input_colorspace.num_components()
+        {
+            return Err(DecodeErrors::Format(format!(
+                "Lossless images with {} components are not supported",
+                self.components.len()
+            )));
+        }
+
+        let (planes, marker) = self.decode_lossless_frame(reader)?;
+
+        if let Some(m @ (Marker::SOF(_) | Marker::EXP)) = marker
+        {
+            return Err(DecodeErrors::Format(format!(
+                "Unexpected marker {m:?} after the frame of a lossless image"
+            )));
+        }
+
+        self.lossless_to_colorspace(&planes)
+    }
+
+    /// Decode the scans of a lossless frame into per component planes
+    ///
+    /// Returns the planes and the marker ending the frame, the end of image, the
+    /// next frame of a hierarchical image or `None` if the data ran out.
+    pub(crate) fn decode_lossless_frame(
+        &mut self, reader: &mut Cursor<Vec<u8>>,
+    ) -> Result<(Vec<Plane>, Option<Marker>), DecodeErrors>
+    {
+        let (width, height) = self.frame_dimensions();
+
+        // number of MCU's in an interleaved scan
+        let mcus_x = width.div_ceil(self.h_max);
+        let mcus_y = height.div_ceil(self.v_max);
+
+        let mut planes = self
+            .components
+            .iter()
+            .map(|comp| {
+                let stride = mcus_x * comp.horizontal_sample;
+
+                Plane {
+                    samples: vec![0; stride * mcus_y * comp.vertical_sample],
+                    stride,
+                    width: (width * comp.horizontal_sample).div_ceil(self.h_max),
+                    height: (height * comp.vertical_sample).div_ceil(self.v_max),
+                    pt: 0,
+                    complete: false,
+                }
+            })
+            .collect::<Vec<Plane>>();
+
+        let mut stream = BitStream::new();
+        let mut arithmetic = ArithmeticDecoder::new(self.arithmetic_conditioning);
+        let mut seen_scans = 1;
+
+        self.decode_lossless_scan(reader, &mut stream, &mut arithmetic, &mut planes)?;
+
+        let mut marker = get_marker(reader, &mut stream, &mut arithmetic);
+
+        loop
+        {
+            match marker
+            {
+                // no more data, decode what we have
+                Some(Marker::EOI | Marker::SOF(_) | Marker::EXP) | None => break,
+                // tables and restart intervals may change between scans, DNL follows the first
+                Some(
+                    m @ (Marker::DHT
+                    | Marker::DAC
+                    | Marker::DRI
+                    | Marker::DNL
+                    | Marker::COM
+                    | Marker::APP(_)),
+                ) =>
+                {
+                    self.parse_marker_inner(m, reader)?;
+                }
+                Some(Marker::SOS) =>
+                {
+                    parse_sos(reader, self)?;
+
+                    seen_scans += 1;
+
+                    if seen_scans > self.options.get_max_scans()
+                    {
+                        return Err(DecodeErrors::Format(format!(
+                            "Too many scans, exceeded limit of {}",
+                            self.options.get_max_scans()
+                        )));
+                    }
+
+                    self.decode_lossless_scan(reader, &mut stream, &mut arithmetic, &mut planes)?;
+                }
+                Some(m) =>
+                {
+                    return Err(DecodeErrors::Format(format!(
+                        "Unexpected marker {m:?} between scans of a lossless image"
+                    )));
+                }
+            }
+            marker = get_marker(reader, &mut stream, &mut arithmetic);
+        }
+
+        if let Some(pos) = planes.iter().position(|x| !x.complete)
+        {
+            warn!("Component {pos} has no scan, its samples will be zero");
+        }
+
+        Ok((planes, marker))
+    }
+
+    /// Decode a single scan of a lossless image into `planes`
+    #[allow(
+        clippy::too_many_lines,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    fn decode_lossless_scan(
+        &mut self, reader: &mut Cursor<Vec<u8>>, stream: &mut BitStream,
+        arithmetic: &mut ArithmeticDecoder, planes: &mut [Plane],
+    ) -> Result<(), DecodeErrors>
+    {
+        let predictor = self.spec_start;
+        let pt = self.succ_low;
+
+        // differential frames of hierarchical images code the difference to
+        // the reference component without prediction, see table H.1
+        if self.info.sof.is_differential()
+        {
+            if predictor != 0
+            {
+                return Err(DecodeErrors::SosError(format!(
+                    "Invalid predictor {predictor} for differential frame, expected 0"
+                )));
+            }
+        }
+        else if !(1..=7).contains(&predictor)
+        {
+            return Err(DecodeErrors::SosError(format!(
+                "Invalid predictor {predictor} for lossless image, expected a value between 1 and 7"
+            )));
+        }
+        if pt >= self.precision
+        {
+            return Err(DecodeErrors::SosError(format!(
+                "Point transform {} is too large for a precision of {}",
+                pt, self.precision
+            )));
+        }
+
+        let z_order = self.z_order;
+        let scan_components = &z_order[..usize::from(self.num_scans)];
+
+        for &pos in scan_components
+        {
+            let comp = &self.components[pos];
+
+            // arithmetic coded images have no tables to check
+            if !self.is_arithmetic && self.dc_huffman_tables[comp.dc_huff_table].is_none()
+            {
+                return Err(DecodeErrors::HuffmanDecode(format!(
+                    "No Huffman table for component {:?}",
+                    comp.component_id
+                )));
+            }
+        }
+
+        // MCU dimensions of each component in this scan
+        //
+        // A single component scan is never interleaved, the MCU is then one sample.
+        let sampling = scan_components
+            .iter()
+            .map(|&pos| {
+                if scan_components.len() == 1
+                {
+                    (1, 1)
+                }
+                else
+                {
+                    let comp = &self.components[pos];
+
+                    (comp.horizontal_sample, comp.vertical_sample)
+                }
+            })
+            .collect::<Vec<(usize, usize)>>();
+
+        let (mcus_x, mcus_y) = if scan_components.len() == 1
+        {
+            let plane = &planes[scan_components[0]];
+
+            (plane.width, plane.height)
+        }
+        else
+        {
+            let (width, height) = self.frame_dimensions();
+
+            (width.div_ceil(self.h_max), height.div_ceil(self.v_max))
+        };
+
+        // Prediction restarts at the start of every restart interval, which must
+        // therefore contain whole MCU rows, see H.1.2.1
+        if !self.restart_interval.is_multiple_of(mcus_x)
+        {
+            return Err(DecodeErrors::Format(format!(
+                "Restart interval {} is not a multiple of the {} MCU's in a row",
+                self.restart_interval, mcus_x
+            )));
+        }
+
+        // differences decoded for one MCU row
+        let mut diffs = sampling
+            .iter()
+            .map(|(h, v)| vec![0_i32; mcus_x * h * v])
+            .collect::<Vec<Vec<i32>>>();
+
+        let initial_prediction = 1_i32 << (self.precision - pt - 1);
+
+        stream.reset();
+        arithmetic.reset(self.arithmetic_conditioning);
+
+        self.todo = self.restart_interval;
+
+        // the first row of the scan or of a restart interval
+        let mut first_row = true;
+
+        for mcu_y in 0..mcus_y
+        {
+            for mcu_x in 0..mcus_x
+            {
+                for ((&pos, diff), (h, v)) in scan_components.iter().zip(&mut diffs).zip(&sampling)
+                {
+                    let row_width = mcus_x * h;
+                    let table = self.components[pos].dc_huff_table;
+
+                    for y in 0..*v
+                    {
+                        for x in 0..*h
+                        {
+                            let column = mcu_x * h + x;
+                            let index = y * row_width + column;
+
+                            diff[index] = if self.is_arithmetic
+                            {
+                                // differences of the samples to the left and above, zero at
+                                // the start of a line and the first line, section H.1.4.3.1.1
+                                let da = if column == 0 { 0 } else { diff[index - 1] };
+                                let db = if y > 0
+                                {
+                                    diff[index - row_width]
+                                }
+                                else if first_row
+                                {
+                                    0
+                                }
+                                else
+                                {
+                                    // the last line of the previous MCU row, not yet overwritten
+                                    diff[(v - 1) * row_width + column]
+                                };
+
+                                arithmetic.decode_lossless_diff(reader, table, da, db)?
+                            }
+                            else
+                            {
+                                let table = self.dc_huffman_tables[table].as_ref().unwrap();
+
+                                stream.decode_lossless_diff(reader, table)?
+                            };
+                        }
+                    }
+                }
+
+                if self.restart_interval > 0
+                {
+                    self.todo -= 1;
+
+                    let is_last = mcu_y + 1 == mcus_y && mcu_x + 1 == mcus_x;
+
+                    if self.todo == 0 && !is_last
+                    {
+                        if self.is_arithmetic
+                        {
+                            self.handle_rst_arithmetic(arithmetic, reader)?;
+                        }
+                        else
+                        {
+                            self.handle_rst(stream, reader)?;
+                        }
+                    }
+                }
+            }
+
+            for ((&pos, diff), (h, v)) in scan_components.iter().zip(&diffs).zip(&sampling)
+            {
+                let plane = &mut planes[pos];
+                let row_width = mcus_x * h;
+
+                for y in 0..*v
+                {
+                    let row = mcu_y * v + y;
+
+                    if row >= plane.height
+                    {
+                        break;
+                    }
+
+                    let (previous, current) = plane.samples.split_at_mut(row * plane.stride);
+                    let current = &mut current[..plane.width];
+                    let diff = &diff[y * row_width..y * row_width + plane.width];
+
+                    if predictor == 0
+                    {
+                        // differences are stored modulo 2^16, section J.2.1
+                        for (out, diff) in current.iter_mut().zip(diff)
+                        {
+                            *out = *diff as u16;
+                        }
+                    }
+                    else if first_row && y == 0
+                    {
+                        undifference_first_row(diff, current, initial_prediction);
+                    }
+                    else
+                    {
+                        let previous = &previous[(row - 1) * plane.stride..][..plane.width];
+
+                        undifference_row(diff, previous, current, predictor);
+                    }
+                }
+                plane.pt = pt;
+                plane.complete = true;
+            }
+
+            // restarts only happen at the end of a row
+            first_row = self.restart_interval > 0 && self.todo == self.restart_interval;
+        }
+
+        Ok(())
+    }
+
+    /// Undo the point transform, up-sample components and convert them to the output colorspace
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::too_many_lines
+    )]
+    pub(crate) fn lossless_to_colorspace<T: Sample>(&self, planes: &[Plane])
+        -> Result<Vec<T>, DecodeErrors>
+    {
+        let width = usize::from(self.info.width);
+        let height = usize::from(self.info.height);
+
+        // up-sample by replicating samples of smaller components
+        let sample = |plane: usize, x: usize, y: usize| -> u16 {
+            let comp = &self.components[plane];
+            let plane = &planes[plane];
+
+            let x = x * comp.horizontal_sample / self.h_max;
+            let y = y * comp.vertical_sample / self.v_max;
+
+            plane.samples[y * plane.stride + x] << plane.pt
+        };
+
+        let out_colorspace = self.options.get_out_colorspace();
+        let num_components = out_colorspace.num_components();
+
+        let mut output = vec![T::default(); width * height * num_components];
+
+        match (self.input_colorspace, out_colorspace)
+        {
+            (ColorSpace::GRAYSCALE | ColorSpace::YCbCr, ColorSpace::GRAYSCALE) =>
+            {
+                for (y, row) in output.chunks_exact_mut(width).enumerate()
+                {
+                    for (x, out) in row.iter_mut().enumerate()
+                    {
+                        *out = T::from_u16(sample(0, x, y));
+                    }
+                }
+            }
+            (ColorSpace::YCbCr, ColorSpace::YCbCr) =>
+            {
+                for (y, row) in output.chunks_exact_mut(width * 3).enumerate()
+                {
+                    for (x, out) in row.chunks_exact_mut(3).enumerate()
+                    {
+                        for (i, value) in out.iter_mut().enumerate()
+                        {
+                            *value = T::from_u16(sample(i, x, y));
+                        }
+                    }
+                }
+            }
+            (ColorSpace::YCbCr, ColorSpace::RGB | ColorSpace::RGBA | ColorSpace::RGBX) =>
+            {
+                let mut pixel = [0_u16; 4];
+                let pixel = &mut pixel[..num_components];
+
+                for (y, row) in output.chunks_exact_mut(width * num_components).enumerate()
+                {
+                    for (x, out) in row.chunks_exact_mut(num_components).enumerate()
+                    {
+                        ycbcr_to_rgb_pixel_u16(
+                            i32::from(sample(0, x, y)),
+                            i32::from(sample(1, x, y)),
+                            i32::from(sample(2, x, y)),
+                            self.precision,
+                            pixel,
+                        );
+
+                        for (value, p) in out.iter_mut().zip(pixel.iter())
+                        {
+                            *value = T::from_u16(*p);
+                        }
+                    }
+                }
+            }
+            (ColorSpace::RGB, ColorSpace::GRAYSCALE) =>
+            {
+                for (y, row) in output.chunks_exact_mut(width).enumerate()
+                {
+                    for (x, out) in row.iter_mut().enumerate()
+                    {
+                        let [r, g, b] = [0, 1, 2].map(|i| i32::from(sample(i, x, y)));
+
+                        *out = T::from_u16(rgb_to_luma(r, g, b) as u16);
+                    }
+                }
+            }
+            (ColorSpace::RGB, ColorSpace::RGB | ColorSpace::RGBA | ColorSpace::RGBX) =>
+            {
+                let max_value = ((1_u32 << self.precision) - 1) as u16;
+
+                for (y, row) in output.chunks_exact_mut(width * num_components).enumerate()
+                {
+                    for (x, out) in row.chunks_exact_mut(num_components).enumerate()
+                    {
+                        for (i, value) in out.iter_mut().enumerate()
+                        {
+                            *value = if i == 3
+                            {
+                                T::from_u16(max_value)
+                            }
+                            else
+                            {
+                                T::from_u16(sample(i, x, y))
+                            };
+                        }
+                    }
+                }
+            }
+            (ColorSpace::CMYK | ColorSpace::YCCK, _) =>
+            {
+                let mut pixel = [0_u16; 4];
+                let pixel = &mut pixel[..num_components];
+                let inverted = self.adobe_transform.is_some();
+
+                for (y, row) in output.chunks_exact_mut(width * num_components).enumerate()
+                {
+                    for (x, out) in row.chunks_exact_mut(num_components).enumerate()
+                    {
+                        cmyk_to_output_pixel(
+                            [0, 1, 2, 3].map(|i| i32::from(sample(i, x, y))),
+                            self.input_colorspace,
+                            inverted,
+                            self.precision,
+                            out_colorspace,
+                            pixel,
+                        );
+
+                        for (value, p) in out.iter_mut().zip(pixel.iter())
+                        {
+                            *value = T::from_u16(*p);
+                        }
+                    }
+                }
+            }
+            (input, output) =>
+            {
+                return Err(DecodeErrors::Format(format!(
+                    "Conversion from {input:?} to {output:?} is not supported for lossless images"
+                )));
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+/// Undo differences of the first row of a scan or restart interval
+///
+/// Every sample is predicted from the one to its left, the first one
+/// from half the range of the samples.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn undifference_first_row(diff: &[i32], current: &mut [u16], initial_prediction: i32)
+{
+    let mut prediction = initial_prediction;
+
+    for (out, diff) in current.iter_mut().zip(diff)
+    {
+        *out = (prediction + diff) as u16;
+        prediction = i32::from(*out);
+    }
+}
+
+/// Undo differences of a row using predictor `predictor`, see table H.1
+///
+/// The first sample of the row is predicted from the sample above it.
+#[rustfmt::skip]
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn undifference_row(diff: &[i32], previous: &[u16], current: &mut [u16], predictor: u8)
+{
+    current[0] = (i32::from(previous[0]) + diff[0]) as u16;
+
+    for x in 1..current.len()
+    {
+        let ra = i32::from(current[x - 1]);
+        let rb = i32::from(previous[x]);
+        let rc = i32::from(previous[x - 1]);
+
+        let prediction = match predictor
+        {
+            1 => ra,
+            2 => rb,
+            3 => rc,
+            4 => ra + rb - rc,
+            5 => ra + ((rb - rc) >> 1),
+            6 => rb + ((ra - rc) >> 1),
+            _ => (ra + rb) >> 1,
+        };
+        // arithmetic is modulo 2^16, H.1.2.1
+        current[x] = (prediction + diff[x]) as u16;
+    }
+}
+
+/// Get the marker ending a scan
+fn get_marker(
+    reader: &mut Cursor<Vec<u8>>, stream: &mut BitStream, arithmetic: &mut ArithmeticDecoder,
+) -> Option<Marker>
+{
+    if let Some(marker) = stream.marker.take().or_else(|| arithmetic.take_marker())
+    {
+        return Some(marker);
+    }
+
+    find_marker(reader)
+}
@@ -1,5 +1,7 @@
+//! Markers of a JPEG image, see table B.1 of the spec
 #![allow(clippy::upper_case_acronyms)]
 
+/// A marker, the byte following a 0xFF
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Marker
 {
@@ -41,39 +43,97 @@ pub enum Marker
     APP(u8),
     /// Comment
     COM,
+    /// Define hierarchical progression
+    DHP,
+    /// Expand reference components
+    EXP,
+    /// Reserved for JPEG extensions, 0xFFC8
+    JPG,
+    /// Reserved for JPEG extensions, `JPG(n)` is 0xFFF0 to 0xFFFD for n between 0 and 13
+    JPGn(u8),
+    /// For temporary private use in arithmetic coding
+    TEM,
+    /// Reserved markers 0xFF02 to 0xFFBF, holding the second byte of the marker
+    RES(u8),
 }
 
 impl Marker
 {
+    /// Find the marker for the byte following a 0xFF
+    ///
+    /// Every byte is a marker, apart from 0x00, which stuffs a 0xFF in entropy
+    /// coded data, and 0xFF, which is a fill byte.
+    #[must_use]
     pub fn from_u8(n: u8) -> Option<Marker>
     {
-        use self::Marker::{APP, COM, DAC, DHT, DNL, DQT, DRI, EOI, RST, SOF, SOI, SOS};
+        use self::Marker::{
+            APP, COM, DAC, DHP, DHT, DNL, DQT, DRI, EOI, EXP, JPG, JPGn, RES, RST, SOF, SOI, SOS,
+            TEM,
+        };
 
         match n
         {
-            0xFE => Some(COM),
-            0xC0 => Some(SOF(0)),
-            0xC2 => Some(SOF(2)),
+            0x00 | 0xFF => None,
+            0x01 => Some(TEM),
+            0x02..=0xBF => Some(RES(n)),
             0xC4 => Some(DHT),
+            0xC8 => Some(JPG),
             0xCC => Some(DAC),
-            0xD0 => Some(RST(0)),
-            0xD1 => Some(RST(1)),
-            0xD2 => Some(RST(2)),
-            0xD3 => Some(RST(3)),
-            0xD4 => Some(RST(4)),
-            0xD5 => Some(RST(5)),
-            0xD6 => Some(RST(6)),
-            0xD7 => Some(RST(7)),
+            0xC0..=0xCF => Some(SOF(n - 0xC0)),
+            0xD0..=0xD7 => Some(RST(n - 0xD0)),
             0xD8 => Some(SOI),
             0xD9 => Some(EOI),
             0xDA => Some(SOS),
             0xDB => Some(DQT),
             0xDC => Some(DNL),
             0xDD => Some(DRI),
-            0xE0 => Some(APP(0)),
-            0xE1 => Some(APP(1)),
-            0xEE => Some(APP(14)),
-            _ => None,
+            0xDE => Some(DHP),
+            0xDF => Some(EXP),
+            0xE0..=0xEF => Some(APP(n - 0xE0)),
+            0xF0..=0xFD => Some(JPGn(n - 0xF0)),
+            0xFE => Some(COM),
         }
     }
+
+    /// The byte following the 0xFF of the marker
+    #[must_use]
+    pub fn to_u8(self) -> u8
+    {
+        use self::Marker::{
+            APP, COM, DAC, DHP, DHT, DNL, DQT, DRI, EOI, EXP, JPG, JPGn, RES, RST, SOF, SOI, SOS,
+            TEM,
+        };
+
+        match self
+        {
+            TEM => 0x01,
+            RES(n) => n,
+            DHT => 0xC4,
+            JPG => 0xC8,
+            DAC => 0xCC,
+            SOF(n) => 0xC0 + n,
+            RST(n) => 0xD0 + n,
+            SOI => 0xD8,
+            EOI => 0xD9,
+            SOS => 0xDA,
+            DQT => 0xDB,
+            DNL => 0xDC,
+            DRI => 0xDD,
+            DHP => 0xDE,
+            EXP => 0xDF,
+            APP(n) => 0xE0 + n,
+            JPGn(n) => 0xF0 + n,
+            COM => 0xFE,
+        }
+    }
+
+    /// Whether the marker stands alone, without a length and a segment following it
+    ///
+    /// These are SOI, EOI, RST(m) and TEM, every other marker starts a segment whose
+    /// length follows the marker, see B.1.1.3 and B.1.1.4 of the spec.
+    #[must_use]
+    pub fn is_standalone(self) -> bool
+    {
+        matches!(self, Marker::SOI | Marker::EOI | Marker::RST(_) | Marker::TEM)
+    }
 }
@@ -53,15 +53,17 @@
 //! This allows us to not use locks during decoding avoiding that overhead. and allowing more cleaner
 //! faster code in post processing..
 
-use std::cmp::min;
+use std::borrow::Cow;
 use std::io::Cursor;
 use std::sync::Arc;
 
+use crate::arithmetic::ArithmeticDecoder;
 use crate::bitstream::BitStream;
-use crate::components::{ComponentID, SubSampRatios};
 use crate::errors::DecodeErrors;
 use crate::marker::Marker;
-use crate::worker::post_process;
+use crate::misc::find_marker;
+use crate::orientation::OrientedBands;
+use crate::worker::{needed_components, post_process, ContextBands, Sample};
 use crate::{ColorSpace, Decoder};
 
 /// The size of a DC block for a MCU.
@@ -124,48 +126,28 @@ impl Decoder
     #[allow(clippy::similar_names,clippy::too_many_lines,clippy::cast_possible_truncation)]
     #[inline(never)]
     #[rustfmt::skip]
-    pub(crate) fn decode_mcu_ycbcr_baseline(
+    pub(crate) fn decode_mcu_ycbcr_baseline<T: Sample>(
         &mut self, reader: &mut Cursor<Vec<u8>>,
-    ) -> Result<Vec<u8>, DecodeErrors>
+    ) -> Result<Vec<T>, DecodeErrors>
     {
         self.check_component_dimensions()?;
-        // check dc and AC tables
-        self.check_tables()?;
+        // check dc and AC tables, arithmetic coded images have no tables to check
+        if !self.is_arithmetic
+        {
+            self.check_tables()?;
+        }
 
         let  mut scoped_pools = scoped_threadpool::Pool::new(
             self.options.get_threads());
         info!("Created {} worker threads", scoped_pools.thread_count());
 
-        let (mut mcu_width, mut mcu_height);
-        let mut bias = 1;
+        // Every MCU row holds vertical_sample rows of blocks for each component
+        let (mut mcu_width, mut mcu_height) = (self.mcu_x, self.mcu_y);
 
         if self.interleaved
         {
             // set upsampling functions
-            self.set_upsampling()?;
-
-            if self.sub_sample_ratio == SubSampRatios::H
-            {
-                // horizontal sub-sampling.
-
-                // Values for horizontal samples end halfway the image and do not complete an MCU width.
-                // To make it complete we multiply width by 2 and divide mcu_height by 2
-                mcu_width = self.mcu_x * 2;
-                mcu_height = self.mcu_y / 2;
-            } else if self.sub_sample_ratio == SubSampRatios::HV
-            {
-                mcu_width = self.mcu_x;
-                mcu_height = self.mcu_y / 2;
-                bias = 2;
-            } else {
-                mcu_width = self.mcu_x;
-                mcu_height = self.mcu_y;
-            }
-        } else {
-            // For non-interleaved images( (1*1) subsampling)
-            // number of MCU's are the widths (+7 to account for paddings) divided bu 8.
-            mcu_width = ((self.info.width + 7) / 8) as usize;
-            mcu_height = ((self.info.height + 7) / 8) as usize;
+            self.set_upsampling();
         }
 
         if self.input_colorspace == ColorSpace::GRAYSCALE && self.interleaved {
@@ -187,44 +169,45 @@ impl Decoder
             self.h_max = 1;
             self.options = self.options.set_out_colorspace(ColorSpace::GRAYSCALE);
             self.v_max = 1;
-            self.sub_sample_ratio = SubSampRatios::None;
             self.components[0].vertical_sample = 1;
             self.components[0].width_stride = mcu_width * 8;
             self.components[0].horizontal_sample = 1;
             mcu_height = ((self.info.height + 7) / 8) as usize;
-            bias = 1;
         }
-        // Size of our output image(width*height)
-        let capacity = usize::from(self.info.width + 8) * usize::from(self.info.height + 8);
-        let component_capacity = mcu_width * DCT_BLOCK;
+        let orientation = self.orientation();
+        // Size of our output image, every MCU row writes 8*v_max rows which may go
+        // past the image height. Rows past it are dropped when the image is turned upright.
+        let capacity = if orientation.is_upright()
+        {
+            usize::from(self.info.width) * mcu_height * 8 * self.v_max
+        } else {
+            usize::from(self.info.width) * usize::from(self.info.height)
+        };
         // Create an Arc of components to prevent cloning on every MCU width
         let global_component = Arc::new(self.components.clone());
-        let is_hv = self.sub_sample_ratio == SubSampRatios::HV;
-        // There are some images where we need to overallocate  especially for small buffers,
-        // because the chunking calculation will do it wrongly,
-        // this only applies to  small down-sampled images
-        // See https://github.com/etemesi254/zune-jpeg/issues/11
-        let extra_space = usize::from(self.interleaved) * 128 * usize::from(self.height()) * self.options.get_out_colorspace().num_components();
         // things needed for post processing that we can remove out of the loop
         let input = self.input_colorspace;
         let output = self.options.get_out_colorspace();
         let idct_func = self.idct_func;
         let color_convert_16 = self.color_convert_16;
-        let width = usize::from(self.width());
-        let h_max = self.h_max;
+        let width = usize::from(self.info.width);
+        let precision = self.precision;
         let v_max = self.v_max;
-        // Halfway width size, used for vertical sub-sampling to write |Y2| in the right position.
-        let width_stride = (component_capacity * self.components[0].vertical_sample * self.components[0].horizontal_sample * bias) >> 1;
-        let hv_width_stride = width_stride >> 1;
+        let inverted = self.adobe_transform.is_some();
+        let needed = needed_components(input, output);
 
         let mut stream = BitStream::new();
+        let mut arithmetic = ArithmeticDecoder::new(self.arithmetic_conditioning);
+        let is_arithmetic = self.is_arithmetic;
         // Storage for decoded pixels
-        let mut global_channel = vec![0; (capacity * self.options.get_out_colorspace().num_components()) + extra_space];
+        let mut global_channel = vec![T::default(); capacity * output.num_components()];
 
         // Split output into different blocks each containing enough space for an MCU width
-        let mut chunks =
-            global_channel.chunks_exact_mut(width * output.num_components() * 8 * h_max * v_max);
+        let mut chunks = OrientedBands::new(&mut global_channel, width, usize::from(self.info.height),
+                                            8 * v_max, output.num_components(), orientation);
         let mut tmp = [0; DCT_BLOCK];
+        let mut bands = ContextBands::new(global_component.clone(), idct_func, needed, width,
+                                          usize::from(self.info.height), mcu_height);
 
         // Argument for scoped threadpools, see file docs.
         scoped_pools.scoped::<_, Result<(), DecodeErrors>>(|scope| {
@@ -235,30 +218,31 @@ impl Decoder
                 // We allocate on every mcu_height since this is sent to a separate
                 // thread (that's how we're multi-threaded and thread safe).
 
-                let mut temporary = [vec![], vec![], vec![]];
+                let mut temporary = [vec![], vec![], vec![], vec![]];
 
                 for (pos, comp) in self.components.iter().enumerate()
                 {
                     // multiply capacity with sampling factor, it  should be 1*1 for un-sampled images
                     // Allocate only needed components.
-                    if min(self.options.get_out_colorspace().num_components() - 1, pos) == pos
+                    if pos < needed
                     {
-                        let len = component_capacity * comp.vertical_sample * comp.horizontal_sample * bias;
+                        let len = comp.width_stride * 8 * comp.vertical_sample;
 
                         temporary[pos] = vec![0; len];
                     }
                 }
-                // Bias only affects 4:2:0(chroma quartered) sub-sampled images.
-                // since we want to fetch two MCU rows before we send it to post process
-                for v in 0..bias
+                for j in 0..mcu_width
                 {
-                    for j in 0..mcu_width
-                    {
-                        // iterate over components
+                    // iterate over components
 
-                        for pos in 0..self.input_colorspace.num_components()
+                    for pos in 0..self.input_colorspace.num_components()
+                    {
+                        let component = &mut self.components[pos];
+                        // Arithmetic coded images don't use huffman tables.
+                        let tables = if is_arithmetic
                         {
-                            let component = &mut self.components[pos];
+                            None
+                        } else {
                             let dc_table = self.dc_huffman_tables[component.dc_huff_table & 3]
                                 .as_ref()
                                 .ok_or_else(|| {
@@ -276,96 +260,89 @@ impl Decoder
                                     ))
                                 })?;
 
-                            // If image is interleaved iterate over scan  components,
-                            // otherwise if it-s non-interleaved, these routines iterate in
-                            // trivial scanline order(Y,Cb,Cr)
-                            for v_samp in 0..component.vertical_sample
-                            {
-                                for h_samp in 0..component.horizontal_sample
-                                {
-                                    // only decode needed components
-                                    if min(self.options.get_out_colorspace().num_components() - 1, pos) == pos
-                                    {
-                                        // The spec  https://www.w3.org/Graphics/JPEG/itu-t81.pdf page 26
-
-                                        // Get position to write
-                                        // This is complex, don't even try to understand it. ~author
-                                        let is_y =
-                                            usize::from(component.component_id == ComponentID::Y);
-                                        // This only affects 4:2:0 images.
-                                        let y_offset = is_y
-                                            * v
-                                            * (hv_width_stride
-                                            + (hv_width_stride * (component.vertical_sample - 1)));
-                                        let another_stride =
-                                            (width_stride * v_samp * usize::from(!is_hv))
-                                                + hv_width_stride * v_samp * usize::from(is_hv);
-                                        let yet_another_stride = usize::from(is_hv)
-                                            * (width_stride >> 2)
-                                            * v
-                                            * usize::from(component.component_id != ComponentID::Y);
-                                        // offset calculator.
-                                        let start = (j * 64 * component.horizontal_sample)
-                                            + (h_samp * 64)
-                                            + another_stride
-                                            + y_offset
-                                            + yet_another_stride;
-                                        // It will always be zero since it's initialized per MCU height.
-                                        let tmp: &mut [i16; 64] = temporary.get_mut(pos).unwrap().get_mut(start..start + 64).unwrap().try_into().unwrap();
-
-                                        stream.decode_mcu_block(reader, dc_table, ac_table, tmp, &mut component.dc_pred)?;
-                                    } else {
-                                        // component not needed, decode and discard bits
-                                        stream.decode_mcu_block(reader, dc_table, ac_table, &mut tmp, &mut component.dc_pred)?;
-                                    }
-                                }
-                            }
-                            self.todo = self.todo.wrapping_sub(1);
-                            // after every interleaved MCU that's a mcu, count down restart markers.
-                            if self.todo == 0
-                            {
-                                self.handle_rst(&mut stream)?;
-                            }
+                            Some((dc_table, ac_table))
+                        };
 
-                            // In some corrupt images, it may occur that header markers occur in the stream.
-                            // The spec EXPLICITLY FORBIDS this, specifically, in
-                            // routine F.2.2.5  it says
-                            // `The only valid marker which may occur within the Huffman coded data is the RSTm marker.`
-                            //
-                            // But libjpeg-turbo allows it because of some weird reason. so I'll also
-                            // allow it because of some weird reason.
-                            if let Some(m) = stream.marker
+                        // If image is interleaved iterate over scan  components,
+                        // otherwise if it-s non-interleaved, these routines iterate in
+                        // trivial scanline order(Y,Cb,Cr)
+                        for v_samp in 0..component.vertical_sample
+                        {
+                            for h_samp in 0..component.horizontal_sample
                             {
-                                if m == Marker::EOI
+                                // only decode needed components
+                                if pos < needed
                                 {
-                                    break;
-                                }
+                                    // The spec  https://www.w3.org/Graphics/JPEG/itu-t81.pdf page 26
 
-                                if let Marker::RST(_) = m { continue }
+                                    // Blocks of an MCU are h_samp by v_samp, stored in rows
+                                    // of width_stride/8 blocks, like the image.
+                                    let start = 64 * (v_samp * (component.width_stride / 8)
+                                        + j * component.horizontal_sample
+                                        + h_samp);
+                                    // It will always be zero since it's initialized per MCU height.
+                                    let tmp: &mut [i16; 64] = temporary.get_mut(pos).unwrap().get_mut(start..start + 64).unwrap().try_into().unwrap();
 
-                                error!("Marker `{:?}` Found within Huffman Stream, possibly corrupt jpeg",m);
-                                self.parse_marker_inner(m, reader)?;
+                                    match tables
+                                    {
+                                        Some((dc_table, ac_table)) => stream.decode_mcu_block(reader, dc_table, ac_table, tmp, &mut component.dc_pred)?,
+                                        None => arithmetic.decode_mcu_block(reader, component.dc_huff_table, component.ac_huff_table, pos, tmp, &mut component.dc_pred)?,
+                                    }
+                                } else {
+                                    // component not needed, decode and discard bits
+                                    match tables
+                                    {
+                                        Some((dc_table, ac_table)) => stream.decode_mcu_block(reader, dc_table, ac_table, &mut tmp, &mut component.dc_pred)?,
+                                        None => arithmetic.decode_mcu_block(reader, component.dc_huff_table, component.ac_huff_table, pos, &mut tmp, &mut component.dc_pred)?,
+                                    }
+                                }
                             }
                         }
                     }
-                }
-                // Clone things, to make multithreading safe
-                let component = global_component.clone();
-                let next_chunk = chunks.next().unwrap();
-
-                scope.execute(move || {
+                    self.todo = self.todo.wrapping_sub(1);
+                    // after every interleaved MCU that's a mcu, count down restart markers.
+                    if self.todo == 0
+                    {
+                        if is_arithmetic
+                        {
+                            self.handle_rst_arithmetic(&mut arithmetic, reader)?;
+                        } else {
+                            self.handle_rst(&mut stream, reader)?;
+                        }
+                    }
 
-                    let mut coeff :[&[i16];3]=[&[];3];
+                    // In some corrupt images, it may occur that header markers occur in the stream.
+                    // The spec EXPLICITLY FORBIDS this, specifically, in
+                    // routine F.2.2.5  it says
+                    // `The only valid marker which may occur within the Huffman coded data is the RSTm marker.`
+                    //
+                    // But libjpeg-turbo allows it because of some weird reason. so I'll also
+                    // allow it because of some weird reason.
+                    if let Some(m) = stream.marker
+                    {
+                        // The bitstream reads ahead, so it may see EOI (or the DNL ending
+                        // the scan) while bits for the remaining MCU's are still buffered,
+                        // keep decoding those.
+                        if let Marker::RST(_) | Marker::EOI | Marker::DNL = m { continue }
 
-                    temporary.iter().enumerate().for_each(|(pos,x)|{
-                        coeff[pos] = x;
+                        error!("Marker `{m:?}` Found within Huffman Stream, possibly corrupt jpeg");
+                        self.parse_marker_inner(m, reader)?;
+                    }
+                }
+                // a band is post processed once the band below it is decoded
+                for band in bands.push(temporary.map(Cow::Owned))
+                {
+                    // Clone things, to make multithreading safe
+                    let component = global_component.clone();
+                    let next_chunk = chunks.next().unwrap();
+
+                    scope.execute(move || {
+                        post_process(band, &component,
+                                     idct_func, color_convert_16,
+                                     input, output, next_chunk,
+                                     width, precision, inverted);
                     });
-
-                    post_process(&coeff, &component,
-                                 idct_func, color_convert_16,
-                                 input, output, next_chunk,
-                                 width);
-                });
+                }
             }
             //everything is okay
             Ok(())
@@ -373,8 +350,8 @@ impl Decoder
         info!("Finished decoding image");
         // remove excess allocation for images.
         global_channel.truncate(
-            usize::from(self.width())
-                * usize::from(self.height())
+            usize::from(self.info.width)
+                * usize::from(self.info.height)
                 * self.options.get_out_colorspace().num_components(),
         );
         return Ok(global_channel);
@@ -383,10 +360,20 @@ impl Decoder
     // No-op if not using restarts
     // this routine is shared with mcu_prog
     #[cold]
-    pub(crate) fn handle_rst(&mut self, stream: &mut BitStream) -> Result<(), DecodeErrors>
+    pub(crate) fn handle_rst(
+        &mut self, stream: &mut BitStream, reader: &mut Cursor<Vec<u8>>,
+    ) -> Result<(), DecodeErrors>
     {
         self.todo = self.restart_interval;
 
+        if stream.marker.is_none()
+        {
+            // The bitstream may not have read up to the marker yet, whatever
+            // is left before it is padding, so drop it and find the marker.
+            stream.reset();
+            stream.marker = find_marker(reader);
+        }
+
         if let Some(marker) = stream.marker
         {
             // Found a marker
@@ -401,9 +388,9 @@ impl Decoder
                     self.components.iter_mut().for_each(|x| x.dc_pred = 0);
                     // Start iterating again. from position.
                 }
-                Marker::EOI =>
+                Marker::EOI | Marker::DNL =>
                 {
-                    // silent pass
+                    // silent pass, the scan is over
                 }
                 _ =>
                 {
@@ -416,4 +403,30 @@ impl Decoder
         }
         Ok(())
     }
+    /// Handle RST markers for arithmetic coded images
+    ///
+    /// The arithmetic decoder doesn't read ahead, so the marker may still be in the stream.
+    #[cold]
+    pub(crate) fn handle_rst_arithmetic(
+        &mut self, arithmetic: &mut ArithmeticDecoder, reader: &mut Cursor<Vec<u8>>,
+    ) -> Result<(), DecodeErrors>
+    {
+        self.todo = self.restart_interval;
+
+        match arithmetic.restart(reader)
+        {
+            Some(Marker::RST(_) | Marker::EOI | Marker::DNL) | None =>
+            {
+                // Initialize dc predictions to zero for all components
+                self.components.iter_mut().for_each(|x| x.dc_pred = 0);
+            }
+            Some(marker) =>
+            {
+                return Err(DecodeErrors::MCUError(format!(
+                    "Marker {marker:?} found in arithmetic coded data, possibly corrupt jpeg"
+                )));
+            }
+        }
+        Ok(())
+    }
 }
@@ -25,19 +25,23 @@
 //!
 //!
 //! So here we use a different scheme. Just decode everything and then finally use threads when post processing.
+//!
+//! Sequential images with more than one scan have the same problem, so they use this scheme too.
 
+use std::borrow::Cow;
 use std::io::Cursor;
 use std::sync::Arc;
 
+use crate::arithmetic::ArithmeticDecoder;
 use crate::bitstream::BitStream;
-use crate::components::{ComponentID, SubSampRatios};
 use crate::decoder::MAX_COMPONENTS;
 use crate::errors::DecodeErrors;
 use crate::errors::DecodeErrors::Format;
-use crate::headers::{parse_huffman, parse_sos};
+use crate::headers::parse_sos;
 use crate::marker::Marker;
-use crate::misc::read_byte;
-use crate::worker::post_process;
+use crate::misc::find_marker;
+use crate::orientation::OrientedBands;
+use crate::worker::{needed_components, post_process, ContextBands, Sample};
 use crate::{ColorSpace, Decoder};
 
 impl Decoder
@@ -45,16 +49,32 @@ impl Decoder
     /// Decode a progressive image
     ///
     /// This routine decodes a progressive image, stopping if it finds any error.
+    ///
+    /// Sequential images whose components are in separate scans are decoded here too,
+    /// their coefficients are complete after the last scan just like progressive ones.
+    pub(crate) fn decode_mcu_ycbcr_progressive<T: Sample>(
+        &mut self, reader: &mut Cursor<Vec<u8>>,
+    ) -> Result<Vec<T>, DecodeErrors>
+    {
+        let (block, _) = self.decode_coefficients(reader)?;
+
+        self.finish_progressive_decoding(&block)
+    }
+
+    /// Decode the coefficients of every scan of a frame
+    ///
+    /// Returns the coefficients of every component of the frame and the marker
+    /// ending the frame, which is the end of image unless the image is hierarchical.
     #[rustfmt::skip]
-    pub(crate) fn decode_mcu_ycbcr_progressive(
+    pub(crate) fn decode_coefficients(
         &mut self, reader: &mut Cursor<Vec<u8>>,
-    ) -> Result<Vec<u8>, DecodeErrors>
+    ) -> Result<([Vec<i16>; MAX_COMPONENTS], Marker), DecodeErrors>
     {
         self.check_component_dimensions()?;
         let mcu_height;
 
         // memory location for decoded pixels for components
-        let mut block = [vec![], vec![], vec![]];
+        let mut block = [vec![], vec![], vec![], vec![]];
         let mut mcu_width;
 
         let mut seen_scans = 1;
@@ -64,36 +84,42 @@ impl Decoder
             mcu_width = self.mcu_x;
             mcu_height = self.mcu_y;
         } else {
-            mcu_width = (self.info.width as usize + 7) / 8;
-            mcu_height = (self.info.height as usize + 7) / 8;
+            let (width, height) = self.frame_dimensions();
+
+            mcu_width = width.div_ceil(8);
+            mcu_height = height.div_ceil(8);
         }
 
         mcu_width *= 64;
 
-        for i in 0..self.input_colorspace.num_components()
+        // frames of hierarchical images may only contain some components
+        for (block, comp) in block.iter_mut().zip(&self.components)
         {
-            let comp = &self.components[i];
             let len = mcu_width * comp.vertical_sample * comp.horizontal_sample * mcu_height;
 
-            block[i] = vec![0; len];
+            *block = vec![0; len];
         }
 
         let mut stream = BitStream::new_progressive(self.succ_high, self.succ_low,
                                                     self.spec_start, self.spec_end);
+        let mut arithmetic = ArithmeticDecoder::new_progressive(self.arithmetic_conditioning, self.succ_low,
+                                                                self.spec_start, self.spec_end);
 
         // there are multiple scans in the stream, this should resolve the first scan
-        self.parse_entropy_coded_data(reader, &mut stream, &mut block)?;
+        self.parse_entropy_coded_data(reader, &mut stream, &mut arithmetic, &mut block)?;
 
         // extract marker
-        let mut marker = stream.marker.take().ok_or(DecodeErrors::FormatStatic("Marker missing where expected"))?;
+        // the scan may end before the bitstream reads up to the marker, so look for it in the reader too
+        let mut marker = get_marker(reader, &mut stream, &mut arithmetic).ok_or(DecodeErrors::FormatStatic("Marker missing where expected"))?;
         // if marker is EOI, we are done, otherwise continue scanning.
         'eoi: while marker != Marker::EOI
         {
 
             match marker
             {
-                Marker::DHT => {
-                    parse_huffman(self, reader)?;
+                // tables and restart intervals may change between scans, DNL follows the first
+                Marker::DHT | Marker::DAC | Marker::DRI | Marker::DNL | Marker::COM | Marker::APP(_) => {
+                    self.parse_marker_inner(marker, reader)?;
                 }
                 Marker::SOS =>
                     {
@@ -101,12 +127,13 @@ impl Decoder
 
                         stream.update_progressive_params(self.succ_high, self.succ_low,
                                                          self.spec_start, self.spec_end);
+                        arithmetic.update_progressive_params(self.succ_low, self.spec_start, self.spec_end);
 
                         // after every SOS, marker, parse data for that scan.
-                        self.parse_entropy_coded_data(reader, &mut stream, &mut block)?;
+                        self.parse_entropy_coded_data(reader, &mut stream, &mut arithmetic, &mut block)?;
                         // extract marker, might either indicate end of image or we continue
                         // scanning(hence the continue statement to determine).
-                        marker = get_marker(reader, &mut stream).ok_or(DecodeErrors::FormatStatic("Marker missing where expected"))?;
+                        marker = get_marker(reader, &mut stream, &mut arithmetic).ok_or(DecodeErrors::FormatStatic("Marker missing where expected"))?;
                         seen_scans+=1;
 
                         if seen_scans >  self.options.get_max_scans(){
@@ -122,26 +149,15 @@ impl Decoder
                     }
             }
 
-            marker = get_marker(reader, &mut stream).ok_or(DecodeErrors::FormatStatic("Marker missing where expected"))?;
+            marker = get_marker(reader, &mut stream, &mut arithmetic).ok_or(DecodeErrors::FormatStatic("Marker missing where expected"))?;
         }
 
-        self.finish_progressive_decoding(&block, mcu_width)
+        Ok((block, marker))
     }
 
     #[rustfmt::skip]
-    fn finish_progressive_decoding(&mut self, block: &[Vec<i16>; 3], mcu_width: usize) -> Result<Vec<u8>, DecodeErrors> {
-        self.set_upsampling()?;
-
-        let mut mcu_width = mcu_width;
-        let mut bias = 1;
-
-        if self.sub_sample_ratio == SubSampRatios::H
-        {
-            mcu_width *= 2;
-        }
-        if self.sub_sample_ratio == SubSampRatios::HV {
-            bias = 2;
-        }
+    fn finish_progressive_decoding<T: Sample>(&mut self, block: &[Vec<i16>; MAX_COMPONENTS]) -> Result<Vec<T>, DecodeErrors> {
+        self.set_upsampling();
 
         if self.input_colorspace == ColorSpace::GRAYSCALE && self.interleaved {
             /*
@@ -158,85 +174,84 @@ impl Decoder
                 return Err(DecodeErrors::FormatStatic("[strict-mode]: Grayscale image with down-sampled component."))
             }
             warn!("Grayscale image with down-sampled component, resetting component details");
+            // blocks were stored in rows of width_stride/8 blocks, so keep the stride
+            // and treat every block row as an MCU row.
             self.h_max = 1;
             self.v_max = 1;
-            self.sub_sample_ratio = SubSampRatios::None;
             self.components[0].vertical_sample = 1;
-            self.components[0].width_stride = mcu_width * 8;
-            self.components[0].horizontal_sample = mcu_width;
-            bias = 1;
+            self.components[0].horizontal_sample = 1;
         }
-        // remove items from  top block
-        let y = &block[0];
-        let cb = &block[1];
-        let cr = &block[2];
-        let extra_space = usize::from(self.interleaved) * 128 * usize::from(self.height()) * self.options.get_out_colorspace().num_components();
-        let capacity = usize::from(self.info.width + 8) * usize::from(self.info.height + 8);
+        // every MCU row writes 8*v_max rows which may go past the image height.
+        let mcu_rows = block[0].len() / (self.components[0].width_stride * 8 * self.components[0].vertical_sample);
+        let orientation = self.orientation();
+        // rows past the image height are dropped when the image is turned upright
+        let capacity = if orientation.is_upright() {
+            usize::from(self.info.width) * mcu_rows * 8 * self.v_max
+        } else {
+            usize::from(self.info.width) * usize::from(self.info.height)
+        };
 
-        let mut out_vector = vec![0_u8; capacity * self.options.get_out_colorspace().num_components() + extra_space];
+        let mut out_vector = vec![T::default(); capacity * self.options.get_out_colorspace().num_components()];
 
         // Things we need for multithreading.
-        let h_max = self.h_max;
         let v_max = self.v_max;
         let components = Arc::new(self.components.clone());
         let input = self.input_colorspace;
         let output = self.options.get_out_colorspace();
         let idct_func = self.idct_func;
         let color_convert_16 = self.color_convert_16;
-        let width = usize::from(self.width());
+        let width = usize::from(self.info.width);
+        let precision = self.precision;
+        let inverted = self.adobe_transform.is_some();
         // Divide the output into small blocks and send to threads/
-        let chunks_size = width * self.options.get_out_colorspace().num_components() * 8 * h_max * v_max;
-        let out_chunks = out_vector.chunks_exact_mut(chunks_size);
-        // Chunk sizes. Each determine how many pixels go per thread.
-        let y_chunk_size =
-            mcu_width * self.components[0].vertical_sample * self.components[0].horizontal_sample * bias;
-        // Divide into chunks
-        let y_chunk = y.chunks_exact(y_chunk_size);
-
+        let mut out_chunks = OrientedBands::new(&mut out_vector, width, usize::from(self.info.height),
+                                            8 * v_max, output.num_components(), orientation);
+        // Chunk sizes. Each determine how many pixels go per thread, divide every component into chunks
+        let mut component_chunks = block
+            .iter()
+            .zip(&self.components)
+            .take(self.input_colorspace.num_components())
+            .map(|(block, comp)| block.chunks_exact(comp.width_stride * 8 * comp.vertical_sample))
+            .collect::<Vec<_>>();
+
+        let height = usize::from(self.info.height);
+        let mut bands = ContextBands::new(components.clone(), idct_func, needed_components(input, output),
+                                          width, height, mcu_rows.min(height.div_ceil(8 * v_max)));
         let mut pool = scoped_threadpool::Pool::new(self.options.get_threads());
 
-        if self.input_colorspace.num_components() == 3 {
-            let cb_chunk_size =
-                mcu_width * self.components[1].vertical_sample * self.components[1].horizontal_sample * bias;
-
-            let cb_chunk = cb.chunks_exact(cb_chunk_size);
-            let cr_chunk = cr.chunks_exact(cb_chunk_size);
-            // open threads.
-            pool.scoped(|scope| {
-                for (((y, cb), cr), out) in y_chunk
-                    .zip(cb_chunk)
-                    .zip(cr_chunk)
-                    .zip(out_chunks)
-                {
-                    let component = components.clone();
+        // open threads.
+        pool.scoped(|scope| {
+            'chunks: loop
+            {
+                let mut coeff = [Cow::Borrowed(&[][..]), Cow::Borrowed(&[]), Cow::Borrowed(&[]), Cow::Borrowed(&[])];
 
-                    scope.execute(move || {
-                        post_process(&[y, cb, cr], &component, idct_func, color_convert_16,
-                                          input, output, out, width,
-                        );
-                    });
+                for (coeff, chunk) in coeff.iter_mut().zip(&mut component_chunks)
+                {
+                    match chunk.next()
+                    {
+                        Some(chunk) => *coeff = Cow::Borrowed(chunk),
+                        None => break 'chunks,
+                    }
                 }
-            });
-        } else {
-            // one component
-            pool.scoped(|scope| {
-                for (y, out) in y_chunk.zip(out_chunks)
+                // a band is post processed once the band below it is decoded
+                for band in bands.push(coeff)
                 {
+                    let Some(out) = out_chunks.next() else { break 'chunks };
                     let component = components.clone();
 
                     scope.execute(move || {
-                        post_process(&[y, &[], &[]], &component, idct_func, color_convert_16,
-                                          input, output, out, width,
+                        post_process(band, &component, idct_func, color_convert_16,
+                                     input, output, out, width, precision, inverted,
                         );
                     });
                 }
-            });
-        }
+            }
+        });
         debug!("Finished decoding image");
 
         out_vector.truncate(
-            usize::from(self.width())
-                * usize::from(self.height())
+            usize::from(self.info.width)
+                * usize::from(self.info.height)
                 * self.options.get_out_colorspace().num_components(),
         );
 
@@ -247,17 +262,30 @@ impl Decoder
     #[rustfmt::skip]
     #[allow(clippy::too_many_lines)]
     fn parse_entropy_coded_data(
-        &mut self, reader: &mut Cursor<Vec<u8>>, stream: &mut BitStream, buffer: &mut [Vec<i16>; 3],
+        &mut self, reader: &mut Cursor<Vec<u8>>, stream: &mut BitStream,
+        arithmetic: &mut ArithmeticDecoder, buffer: &mut [Vec<i16>; MAX_COMPONENTS],
     ) -> Result<bool, DecodeErrors>
     {
         self.check_component_dimensions()?;
         stream.reset();
+        arithmetic.reset(self.arithmetic_conditioning);
+        // restart intervals start over with every scan
+        if self.restart_interval != 0
+        {
+            self.todo = self.restart_interval;
+        }
         self.components.iter_mut().for_each(|x| x.dc_pred = 0);
 
         if usize::from(self.num_scans) > self.input_colorspace.num_components() {
             return Err(Format(format!("Number of scans {} cannot be greater than number of components, {}", self.num_scans, self.input_colorspace.num_components())));
         }
 
+        if !self.is_progressive
+        {
+            // a sequential image whose components are spread over several scans
+            return self.parse_sequential_scan(reader, stream, arithmetic, buffer);
+        }
+
         if self.num_scans == 1
         {
             // Safety checks
@@ -275,19 +303,17 @@ impl Decoder
                 return Err(DecodeErrors::Format(format!("Cannot find component {}, corrupt image", k)));
             }
 
-            let (mcu_width, mcu_height);
-            // For Y channel  or non interleaved scans , mcu's is the image dimensions divided
-            // by 8
-            if self.components[k].component_id == ComponentID::Y || !self.interleaved
-            {
-                mcu_width = ((self.info.width + 7) / 8) as usize;
-                mcu_height = ((self.info.height + 7) / 8) as usize;
-            } else {
-                // For other channels, in an interleaved mcu, number of MCU's
-                // are determined by some weird maths done in headers.rs->parse_sos()
-                mcu_width = self.mcu_x;
-                mcu_height = self.mcu_y;
-            }
+            // Non interleaved scans cover the component in trivial scanline order, its
+            // dimensions are the image dimensions scaled by its sampling factors,
+            // see A.2.2 and A.1.1 of the spec.
+            let (width, height) = self.frame_dimensions();
+            let component = &self.components[k];
+            let comp_width = (width * component.horizontal_sample).div_ceil(self.h_max);
+            let comp_height = (height * component.vertical_sample).div_ceil(self.v_max);
+
+            let mcu_width = comp_width.div_ceil(8);
+            let mcu_height = comp_height.div_ceil(8);
+
             let mut i = 0;
             let mut j = 0;
 
@@ -305,7 +331,18 @@ impl Decoder
                         .unwrap().get_mut(start..start + 64)
                         .unwrap().try_into().unwrap();
 
-                    if self.spec_start == 0
+                    if self.is_arithmetic
+                    {
+                        let component = &mut self.components[k];
+
+                        match (self.spec_start == 0, self.succ_high == 0)
+                        {
+                            (true, true) => arithmetic.decode_prog_dc_first(reader, component.dc_huff_table, k, &mut data[0], &mut component.dc_pred)?,
+                            (true, false) => arithmetic.decode_prog_dc_refine(reader, &mut data[0])?,
+                            (false, true) => arithmetic.decode_mcu_ac_first(reader, component.ac_huff_table, data)?,
+                            (false, false) => arithmetic.decode_mcu_ac_refine(reader, component.ac_huff_table, data)?,
+                        }
+                    } else if self.spec_start == 0
                     {
                         let pos = self.components[k].dc_huff_table & (MAX_COMPONENTS - 1);
                         let dc_table = self.dc_huffman_tables.get(pos)
@@ -347,6 +384,9 @@ impl Decoder
 
                                 i += (j + stream.eob_run as usize - 1) / mcu_width;
                                 j = (j + stream.eob_run as usize - 1) % mcu_width;
+                                // skipped blocks still count towards the restart interval
+                                let skipped = usize::try_from(stream.eob_run - 1).unwrap_or(0);
+                                self.todo = self.todo.wrapping_sub(skipped);
                                 stream.eob_run = 0;
                             } else {
                                 stream.decode_mcu_ac_first(reader, ac_table, data)?;
@@ -357,11 +397,17 @@ impl Decoder
                         }
                     }
                     j += 1;
-                    self.todo -= 1;
+                    self.todo = self.todo.wrapping_sub(1);
 
-                    if self.todo == 0
+                    // no restart marker follows the last MCU of a scan
+                    if self.todo == 0 && (j < mcu_width || i + 1 < mcu_height)
                     {
-                        self.handle_rst(stream)?;
+                        if self.is_arithmetic
+                        {
+                            self.handle_rst_arithmetic(arithmetic, reader)?;
+                        } else {
+                            self.handle_rst(stream, reader)?;
+                        }
                     }
                 }
                 j = 0;
@@ -392,10 +438,16 @@ impl Decoder
                         }
 
                         let component = &mut self.components[n];
-                        let huff_table = self.dc_huffman_tables.get(component.dc_huff_table)
-                            .ok_or_else(|| DecodeErrors::Format(format!("No huffman table for component:{}", component.dc_huff_table)))?
-                            .as_ref()
-                            .ok_or_else(|| DecodeErrors::Format(format!("Huffman table at index  {} not initialized", component.dc_huff_table)))?;
+                        // Arithmetic coded images don't use huffman tables.
+                        let huff_table = if self.is_arithmetic
+                        {
+                            None
+                        } else {
+                            Some(self.dc_huffman_tables.get(component.dc_huff_table)
+                                .ok_or_else(|| DecodeErrors::Format(format!("No huffman table for component:{}", component.dc_huff_table)))?
+                                .as_ref()
+                                .ok_or_else(|| DecodeErrors::Format(format!("Huffman table at index  {} not initialized", component.dc_huff_table)))?)
+                        };
 
                         for v_samp in 0..component.vertical_sample
                         {
@@ -407,20 +459,28 @@ impl Decoder
                                 // data will contain the position for this coefficient in our array.
                                 let data = &mut buffer[n as usize][position];
 
-                                if self.succ_high == 0
+                                match (huff_table, self.succ_high == 0)
                                 {
-                                    stream.decode_prog_dc_first(reader, huff_table, data, &mut component.dc_pred)?;
-                                } else {
-                                    stream.decode_prog_dc_refine(reader, data)?;
+                                    (Some(huff_table), true) => stream.decode_prog_dc_first(reader, huff_table, data, &mut component.dc_pred)?,
+                                    (Some(_), false) => stream.decode_prog_dc_refine(reader, data)?,
+                                    (None, true) => arithmetic.decode_prog_dc_first(reader, component.dc_huff_table, n, data, &mut component.dc_pred)?,
+                                    (None, false) => arithmetic.decode_prog_dc_refine(reader, data)?,
                                 }
                             }
                         }
-                        // We want wrapping subtraction here because it means
-                        // we get a higher number in the case this underflows
-                        self.todo = self.todo.wrapping_sub(1);
-                        // after every scan that's a mcu, count down restart markers.
-                        if self.todo == 0 {
-                            self.handle_rst(stream)?;
+                    }
+                    // We want wrapping subtraction here because it means
+                    // we get a higher number in the case this underflows
+                    self.todo = self.todo.wrapping_sub(1);
+                    // after every interleaved MCU, count down restart markers.
+                    // no restart marker follows the last MCU of a scan
+                    if self.todo == 0 && (j + 1 < self.mcu_x || i + 1 < self.mcu_y)
+                    {
+                        if self.is_arithmetic
+                        {
+                            self.handle_rst_arithmetic(arithmetic, reader)?;
+                        } else {
+                            self.handle_rst(stream, reader)?;
                         }
                     }
                 }
@@ -428,46 +488,119 @@ impl Decoder
         }
         return Ok(true);
     }
-}
 
-///Get a marker from the bit-stream.
-///
-/// This reads until it gets a marker or end of file is encountered
-fn get_marker(reader: &mut Cursor<Vec<u8>>, stream: &mut BitStream) -> Option<Marker>
-{
-    if let Some(marker) = stream.marker
+    /// Decode a scan of a sequential image which doesn't hold every component
+    ///
+    /// Blocks are decoded whole and stored like the coefficients of progressive
+    /// images, the image is post processed after the last scan.
+    #[rustfmt::skip]
+    fn parse_sequential_scan(
+        &mut self, reader: &mut Cursor<Vec<u8>>, stream: &mut BitStream,
+        arithmetic: &mut ArithmeticDecoder, buffer: &mut [Vec<i16>; MAX_COMPONENTS],
+    ) -> Result<bool, DecodeErrors>
     {
-        stream.marker = None;
-        return Some(marker);
-    }
+        let num_scans = usize::from(self.num_scans);
+        let scan_components = self.z_order;
 
-    // read until we get a marker
-    let len = u64::try_from(reader.get_ref().len()).unwrap();
-    loop
-    {
-        let marker = read_byte(reader).ok()?;
+        if let Some(k) = scan_components[..num_scans].iter().find(|k| **k >= self.components.len())
+        {
+            return Err(DecodeErrors::Format(format!("Cannot find component {k}, corrupt image")));
+        }
 
-        if marker == 255
+        let (mcus_x, mcus_y) = if num_scans == 1
         {
-            let mut r = read_byte(reader).ok()?;
-            // 0xFF 0XFF(some images may be like that)
-            while r == 0xFF
-            {
-                r = read_byte(reader).ok()?;
-            }
+            // a scan of a single component isn't interleaved, it covers the component in
+            // trivial scanline order, see A.2.2 of the spec.
+            let (width, height) = self.frame_dimensions();
+            let component = &self.components[scan_components[0]];
+            let comp_width = (width * component.horizontal_sample).div_ceil(self.h_max);
+            let comp_height = (height * component.vertical_sample).div_ceil(self.v_max);
+
+            (comp_width.div_ceil(8), comp_height.div_ceil(8))
+        } else {
+            (self.mcu_x, self.mcu_y)
+        };
 
-            if r != 0
+        for i in 0..mcus_y
+        {
+            for j in 0..mcus_x
             {
-                return Marker::from_u8(r)
-                    .ok_or_else(|| DecodeErrors::Format(format!("Unknown marker 0xFF{:X}", r)))
-                    .ok();
-            }
+                for &n in &scan_components[..num_scans]
+                {
+                    let component = &mut self.components[n];
+                    // Arithmetic coded images don't use huffman tables.
+                    let tables = if self.is_arithmetic
+                    {
+                        None
+                    } else {
+                        let dc_table = self.dc_huffman_tables[component.dc_huff_table & 3]
+                            .as_ref()
+                            .ok_or_else(|| DecodeErrors::HuffmanDecode(format!("No DC table for component {:?}", component.component_id)))?;
+                        let ac_table = self.ac_huffman_tables[component.ac_huff_table & 3]
+                            .as_ref()
+                            .ok_or_else(|| DecodeErrors::HuffmanDecode(format!("No AC table for component {:?}", component.component_id)))?;
 
-            if reader.position() >= len
-            {
-                // end of buffer
-                return None;
+                        Some((dc_table, ac_table))
+                    };
+                    // an MCU of a single component scan is one block
+                    let (h_samples, v_samples) = if num_scans == 1
+                    {
+                        (1, 1)
+                    } else {
+                        (component.horizontal_sample, component.vertical_sample)
+                    };
+
+                    for v_samp in 0..v_samples
+                    {
+                        for h_samp in 0..h_samples
+                        {
+                            let x2 = j * h_samples + h_samp;
+                            let y2 = i * v_samples + v_samp;
+                            let position = 64 * (x2 + y2 * component.width_stride / 8);
+
+                            let data: &mut [i16; 64] = buffer[n]
+                                .get_mut(position..position + 64)
+                                .ok_or(DecodeErrors::FormatStatic("Scan covers more blocks than the image, corrupt jpeg"))?
+                                .try_into()
+                                .unwrap();
+
+                            match tables
+                            {
+                                Some((dc_table, ac_table)) => stream.decode_mcu_block(reader, dc_table, ac_table, data, &mut component.dc_pred)?,
+                                None => arithmetic.decode_mcu_block(reader, component.dc_huff_table, component.ac_huff_table, n, data, &mut component.dc_pred)?,
+                            }
+                        }
+                    }
+                }
+                self.todo = self.todo.wrapping_sub(1);
+                // no restart marker follows the last MCU of a scan
+                if self.todo == 0 && (j + 1 < mcus_x || i + 1 < mcus_y)
+                {
+                    if self.is_arithmetic
+                    {
+                        self.handle_rst_arithmetic(arithmetic, reader)?;
+                    } else {
+                        self.handle_rst(stream, reader)?;
+                    }
+                }
             }
         }
+        return Ok(true);
     }
 }
+
+///Get a marker from the bit-stream.
+///
+/// This reads until it gets a marker or end of file is encountered
+fn get_marker(
+    reader: &mut Cursor<Vec<u8>>, stream: &mut BitStream, arithmetic: &mut ArithmeticDecoder,
+) -> Option<Marker>
+{
+    if let Some(marker) = stream.marker.take().or_else(|| arithmetic.take_marker())
+    {
+        return Some(marker);
+    }
+
+    // read until we get a marker
+    find_marker(reader)
+}
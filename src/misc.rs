@@ -2,9 +2,10 @@
 #![allow(dead_code)]
 
 use std::fmt;
-use std::io::{BufReader, Read};
+use std::io::{BufReader, Cursor, Read};
 
 use crate::errors::DecodeErrors;
+use crate::marker::Marker;
 
 /// Start of baseline DCT Huffman coding
 
@@ -22,6 +23,15 @@ pub const START_OF_FRAME_PROG_DCT: u16 = 0xffc2;
 
 pub const START_OF_FRAME_LOS_SEQ: u16 = 0xffc3;
 
+/// Start of differential sequential DCT Huffman coding
+pub const START_OF_FRAME_DIFF_SEQ_DCT: u16 = 0xffc5;
+
+/// Start of differential progressive DCT Huffman coding
+pub const START_OF_FRAME_DIFF_PROG_DCT: u16 = 0xffc6;
+
+/// Start of differential lossless Huffman coding
+pub const START_OF_FRAME_DIFF_LOS: u16 = 0xffc7;
+
 /// Start of extended sequential DCT arithmetic coding
 
 pub const START_OF_FRAME_EXT_AR: u16 = 0xffc9;
@@ -34,6 +44,15 @@ pub const START_OF_FRAME_PROG_DCT_AR: u16 = 0xffca;
 
 pub const START_OF_FRAME_LOS_SEQ_AR: u16 = 0xffcb;
 
+/// Start of differential sequential DCT arithmetic coding
+pub const START_OF_FRAME_DIFF_SEQ_AR: u16 = 0xffcd;
+
+/// Start of differential progressive DCT arithmetic coding
+pub const START_OF_FRAME_DIFF_PROG_AR: u16 = 0xffce;
+
+/// Start of differential lossless arithmetic coding
+pub const START_OF_FRAME_DIFF_LOS_AR: u16 = 0xffcf;
+
 /// Undo run length encoding of coefficients by placing them in natural order
 #[rustfmt::skip]
 pub const UN_ZIGZAG: [usize; 64 + 16] = [
@@ -155,6 +174,18 @@ pub enum SOFMarkers
     ProgressiveDctArithmetic,
     /// Lossless ( sequential), arithmetic coding
     LosslessArithmetic,
+    /// Differential sequential DCT, Huffman coding
+    DifferentialSequentialDctHuffman,
+    /// Differential progressive DCT, Huffman coding
+    DifferentialProgressiveDctHuffman,
+    /// Differential sequential DCT, arithmetic coding
+    DifferentialSequentialDctArithmetic,
+    /// Differential progressive DCT, arithmetic coding
+    DifferentialProgressiveDctArithmetic,
+    /// Differential lossless (sequential), Huffman coding
+    DifferentialLosslessHuffman,
+    /// Differential lossless (sequential), arithmetic coding
+    DifferentialLosslessArithmetic,
 }
 
 impl Default for SOFMarkers
@@ -176,6 +207,8 @@ impl SOFMarkers
             Self::BaselineDct
                 | Self::ExtendedSequentialHuffman
                 | Self::ExtendedSequentialDctArithmetic
+                | Self::DifferentialSequentialDctHuffman
+                | Self::DifferentialSequentialDctArithmetic
         )
     }
 
@@ -183,7 +216,27 @@ impl SOFMarkers
 
     pub fn is_lossless(self) -> bool
     {
-        matches!(self, Self::LosslessHuffman | Self::LosslessArithmetic)
+        matches!(
+            self,
+            Self::LosslessHuffman
+                | Self::LosslessArithmetic
+                | Self::DifferentialLosslessHuffman
+                | Self::DifferentialLosslessArithmetic
+        )
+    }
+
+    /// Check whether a marker is a differential frame of a hierarchical image
+    pub fn is_differential(self) -> bool
+    {
+        matches!(
+            self,
+            Self::DifferentialSequentialDctHuffman
+                | Self::DifferentialProgressiveDctHuffman
+                | Self::DifferentialSequentialDctArithmetic
+                | Self::DifferentialProgressiveDctArithmetic
+                | Self::DifferentialLosslessHuffman
+                | Self::DifferentialLosslessArithmetic
+        )
     }
 
     /// Check whether a marker is a progressive marker or not
@@ -192,7 +245,10 @@ impl SOFMarkers
     {
         matches!(
             self,
-            Self::ProgressiveDctHuffman | Self::ProgressiveDctArithmetic
+            Self::ProgressiveDctHuffman
+                | Self::ProgressiveDctArithmetic
+                | Self::DifferentialProgressiveDctHuffman
+                | Self::DifferentialProgressiveDctArithmetic
         )
     }
 
@@ -209,6 +265,12 @@ impl SOFMarkers
             START_OF_FRAME_LOS_SEQ_AR => Some(Self::LosslessArithmetic),
             START_OF_FRAME_EXT_SEQ => Some(Self::ExtendedSequentialHuffman),
             START_OF_FRAME_EXT_AR => Some(Self::ExtendedSequentialDctArithmetic),
+            START_OF_FRAME_DIFF_SEQ_DCT => Some(Self::DifferentialSequentialDctHuffman),
+            START_OF_FRAME_DIFF_PROG_DCT => Some(Self::DifferentialProgressiveDctHuffman),
+            START_OF_FRAME_DIFF_SEQ_AR => Some(Self::DifferentialSequentialDctArithmetic),
+            START_OF_FRAME_DIFF_PROG_AR => Some(Self::DifferentialProgressiveDctArithmetic),
+            START_OF_FRAME_DIFF_LOS => Some(Self::DifferentialLosslessHuffman),
+            START_OF_FRAME_DIFF_LOS_AR => Some(Self::DifferentialLosslessArithmetic),
             _ => None,
         }
     }
@@ -233,6 +295,30 @@ impl fmt::Debug for SOFMarkers
             }
             Self::ProgressiveDctArithmetic => write!(f, "Progressive DCT, arithmetic coding"),
             Self::LosslessArithmetic => write!(f, "Lossless (sequential) arithmetic coding"),
+            Self::DifferentialSequentialDctHuffman =>
+            {
+                write!(f, "Differential sequential DCT, Huffman coding")
+            }
+            Self::DifferentialProgressiveDctHuffman =>
+            {
+                write!(f, "Differential progressive DCT, Huffman coding")
+            }
+            Self::DifferentialSequentialDctArithmetic =>
+            {
+                write!(f, "Differential sequential DCT, arithmetic coding")
+            }
+            Self::DifferentialProgressiveDctArithmetic =>
+            {
+                write!(f, "Differential progressive DCT, arithmetic coding")
+            }
+            Self::DifferentialLosslessHuffman =>
+            {
+                write!(f, "Differential lossless (sequential) Huffman coding")
+            }
+            Self::DifferentialLosslessArithmetic =>
+            {
+                write!(f, "Differential lossless (sequential) arithmetic coding")
+            }
         }
     }
 }
@@ -260,6 +346,31 @@ where
     Ok(tmp[0])
 }
 
+/// Read bytes until we find a marker, skipping over anything else
+///
+/// # Returns
+/// - The marker, or `None` if we run out of data or the marker is unknown
+pub(crate) fn find_marker(reader: &mut Cursor<Vec<u8>>) -> Option<Marker>
+{
+    loop
+    {
+        if read_byte(reader).ok()? == 0xFF
+        {
+            let mut r = read_byte(reader).ok()?;
+            // 0xFF 0XFF(some images may be like that)
+            while r == 0xFF
+            {
+                r = read_byte(reader).ok()?;
+            }
+
+            if r != 0
+            {
+                return Marker::from_u8(r);
+            }
+        }
+    }
+}
+
 /// Read two `u8`'s from a buffer and create a `u16` from the bytes in Big
 /// Endian order.
 ///
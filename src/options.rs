@@ -22,6 +22,9 @@ pub struct ZuneJpegOptions
     max_scans:      usize,
     /// Treat warnings as errors.
     strict_mode:    bool,
+    /// Rotate and flip images upright using
+    /// their Exif orientation
+    auto_orient:    bool,
 }
 impl Default for ZuneJpegOptions
 {
@@ -35,6 +38,7 @@ impl Default for ZuneJpegOptions
             max_height:     1 << 14,
             max_scans:      64,
             strict_mode:    false,
+            auto_orient:    false,
         }
     }
 }
@@ -157,4 +161,23 @@ impl ZuneJpegOptions
         self.strict_mode = choice;
         self
     }
+    /// Get if the library will return images upright, using their Exif orientation
+    #[must_use]
+    pub const fn get_auto_orientation(&self) -> bool
+    {
+        self.auto_orient
+    }
+    /// Set whether to rotate and flip images upright, using their Exif orientation
+    ///
+    /// When set, decoded pixels are returned the way the image should be displayed and
+    /// the width and height reported by the decoder are swapped for images which are
+    /// rotated by 90 or 270 degrees.
+    ///
+    /// Default is false.
+    #[must_use]
+    pub fn set_auto_orientation(mut self, choice: bool) -> ZuneJpegOptions
+    {
+        self.auto_orient = choice;
+        self
+    }
 }
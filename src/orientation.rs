@@ -0,0 +1,286 @@
+//! Turning images upright using their Exif orientation
+//!
+//! Images are decoded in bands of MCU rows, each band is color converted and written
+//! to its own part of the output by a worker thread. When an image has to be rotated
+//! or flipped, the part of the output a band covers is no longer contiguous, for
+//! rotations it is a few pixels of every output row.
+//!
+//! [`OrientedBands`] splits the output into the (disjoint) parts written by each band,
+//! so that workers can write bands to where they end up in the upright image without
+//! a separate copy of the whole image.
+
+use std::mem::take;
+
+/// How the stored image has to be transformed to display it upright
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct Orientation
+{
+    /// Rows of the stored image become columns
+    transpose:    bool,
+    /// Stored rows are read from the bottom
+    flip_rows:    bool,
+    /// Stored columns are read from the right
+    flip_columns: bool,
+}
+
+impl Orientation
+{
+    /// The transform for an Exif orientation tag, unknown values leave the image as is
+    pub(crate) fn from_exif(orientation: u16) -> Orientation
+    {
+        let (transpose, flip_rows, flip_columns) = match orientation
+        {
+            // mirrored horizontally
+            2 => (false, false, true),
+            // rotated 180 degrees
+            3 => (false, true, true),
+            // mirrored vertically
+            4 => (false, true, false),
+            // mirrored horizontally and rotated 270 degrees clockwise
+            5 => (true, false, false),
+            // rotated 90 degrees clockwise
+            6 => (true, true, false),
+            // mirrored horizontally and rotated 90 degrees clockwise
+            7 => (true, true, true),
+            // rotated 270 degrees clockwise
+            8 => (true, false, true),
+            _ => (false, false, false),
+        };
+
+        Orientation {
+            transpose,
+            flip_rows,
+            flip_columns,
+        }
+    }
+
+    /// Whether the image is already upright
+    pub(crate) fn is_upright(self) -> bool
+    {
+        self == Orientation::default()
+    }
+
+    /// Whether the width and height of the upright image are swapped
+    pub(crate) fn swaps_dimensions(self) -> bool
+    {
+        self.transpose
+    }
+}
+
+/// Where a band of decoded pixels is written
+pub(crate) enum BandOutput<'a, T>
+{
+    /// The band is written as is, rows of the band may go past the image height
+    Upright(&'a mut [T]),
+    /// The band is rotated or flipped into the upright image
+    Oriented(OrientedBand<'a, T>),
+}
+
+/// The parts of the upright image a band of stored rows ends up in
+pub(crate) struct OrientedBand<'a, T>
+{
+    /// One line per stored row, or one per stored column when transposing,
+    /// in the order they are stored
+    lines:       Vec<&'a mut [T]>,
+    /// Number of stored rows in the band
+    rows:        usize,
+    width:       usize,
+    components:  usize,
+    orientation: Orientation,
+}
+
+impl<T: Copy> OrientedBand<'_, T>
+{
+    /// Write `band`, rows of `width` pixels as they are stored, to the upright image
+    pub(crate) fn write(&mut self, band: &[T])
+    {
+        let components = self.components;
+        let row_length = self.width * components;
+
+        if self.orientation.transpose
+        {
+            for (x, line) in self.lines.iter_mut().enumerate()
+            {
+                for (y, out) in line.chunks_exact_mut(components).enumerate()
+                {
+                    let y = if self.orientation.flip_rows { self.rows - 1 - y } else { y };
+                    let start = y * row_length + x * components;
+
+                    out.copy_from_slice(&band[start..start + components]);
+                }
+            }
+        }
+        else
+        {
+            for (line, row) in self.lines.iter_mut().zip(band.chunks_exact(row_length))
+            {
+                if self.orientation.flip_columns
+                {
+                    for (out, pixel) in line
+                        .chunks_exact_mut(components)
+                        .zip(row.chunks_exact(components).rev())
+                    {
+                        out.copy_from_slice(pixel);
+                    }
+                }
+                else
+                {
+                    line.copy_from_slice(row);
+                }
+            }
+        }
+    }
+}
+
+/// Splits the output image into the parts written by each band of stored rows
+pub(crate) struct OrientedBands<'a, T>
+{
+    /// Parts of the output no band has been given yet, the whole image or when
+    /// transposing one slice per output row
+    remaining:   Vec<&'a mut [T]>,
+    width:       usize,
+    /// Stored rows not yet given to a band
+    rows_left:   usize,
+    band_rows:   usize,
+    components:  usize,
+    orientation: Orientation,
+}
+
+impl<'a, T> OrientedBands<'a, T>
+{
+    /// Split `output` into bands of `band_rows` stored rows
+    ///
+    /// For upright images `output` holds whole bands, rows past the image height
+    /// included, otherwise it holds exactly `width * height` pixels of `components`
+    /// samples.
+    pub(crate) fn new(
+        output: &'a mut [T], width: usize, height: usize, band_rows: usize, components: usize,
+        orientation: Orientation,
+    ) -> OrientedBands<'a, T>
+    {
+        let remaining = if orientation.transpose
+        {
+            // upright rows are stored columns, height pixels long
+            output.chunks_exact_mut(height * components).collect()
+        }
+        else
+        {
+            vec![output]
+        };
+
+        OrientedBands {
+            remaining,
+            width,
+            rows_left: height,
+            band_rows,
+            components,
+            orientation,
+        }
+    }
+}
+
+impl<'a, T> Iterator for OrientedBands<'a, T>
+{
+    type Item = BandOutput<'a, T>;
+
+    fn next(&mut self) -> Option<Self::Item>
+    {
+        if self.orientation.is_upright()
+        {
+            let output = take(&mut self.remaining[0]);
+            let length = self.width * self.components * self.band_rows;
+
+            if output.len() < length
+            {
+                return None;
+            }
+            let (band, rest) = output.split_at_mut(length);
+
+            self.remaining[0] = rest;
+
+            return Some(BandOutput::Upright(band));
+        }
+        if self.rows_left == 0
+        {
+            return None;
+        }
+        let rows = self.band_rows.min(self.rows_left);
+        // a flipped image is filled from the end
+        let split = |part: &'a mut [T], length: usize, flip: bool| -> (&'a mut [T], &'a mut [T]) {
+            if flip
+            {
+                let (rest, band) = part.split_at_mut(part.len() - length);
+
+                (band, rest)
+            }
+            else
+            {
+                part.split_at_mut(length)
+            }
+        };
+        let flip_rows = self.orientation.flip_rows;
+        let mut lines = Vec::new();
+
+        if self.orientation.transpose
+        {
+            for part in &mut self.remaining
+            {
+                let (band, rest) = split(take(part), rows * self.components, flip_rows);
+
+                *part = rest;
+                lines.push(band);
+            }
+            // upright rows are read from the last stored column
+            if self.orientation.flip_columns
+            {
+                lines.reverse();
+            }
+        }
+        else
+        {
+            let row_length = self.width * self.components;
+            let (band, rest) = split(take(&mut self.remaining[0]), rows * row_length, flip_rows);
+
+            self.remaining[0] = rest;
+            lines.extend(band.chunks_exact_mut(row_length));
+
+            if flip_rows
+            {
+                lines.reverse();
+            }
+        }
+        self.rows_left -= rows;
+
+        Some(BandOutput::Oriented(OrientedBand {
+            lines,
+            rows,
+            width: self.width,
+            components: self.components,
+            orientation: self.orientation,
+        }))
+    }
+}
+
+/// Turn a whole decoded image upright
+///
+/// Used for images which aren't decoded in bands.
+pub(crate) fn orient<T: Copy + Default>(
+    pixels: Vec<T>, width: usize, height: usize, components: usize, orientation: Orientation,
+) -> Vec<T>
+{
+    if orientation.is_upright()
+    {
+        return pixels;
+    }
+    let mut output = vec![T::default(); pixels.len()];
+
+    for band in OrientedBands::new(&mut output, width, height, height, components, orientation)
+    {
+        if let BandOutput::Oriented(mut band) = band
+        {
+            band.write(&pixels);
+        }
+    }
+
+    output
+}
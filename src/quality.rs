@@ -0,0 +1,85 @@
+//! Estimate the quality an image was encoded with from its quantization tables
+//!
+//! Encoders derived from the IJG library scale the example tables of Annex K of
+//! the spec by a quality between 1 and 100, so the quality whose scaled tables are
+//! closest to those of the image is a good estimate, and exact for those encoders.
+
+use crate::decoder::MAX_COMPONENTS;
+
+/// Table K.1, luminance quantization table in natural order
+#[rustfmt::skip]
+const LUMINANCE: [u16; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61,
+    12, 12, 14, 19, 26, 58, 60, 55,
+    14, 13, 16, 24, 40, 57, 69, 56,
+    14, 17, 22, 29, 51, 87, 80, 62,
+    18, 22, 37, 56, 68, 109, 103, 77,
+    24, 35, 55, 64, 81, 104, 113, 92,
+    49, 64, 78, 87, 103, 121, 120, 101,
+    72, 92, 95, 98, 112, 100, 103, 99,
+];
+
+/// Table K.2, chrominance quantization table in natural order
+#[rustfmt::skip]
+const CHROMINANCE: [u16; 64] = [
+    17, 18, 24, 47, 99, 99, 99, 99,
+    18, 21, 26, 66, 99, 99, 99, 99,
+    24, 26, 56, 99, 99, 99, 99, 99,
+    47, 66, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+];
+
+/// Scale an example table entry the way `jpeg_set_quality` does
+fn scale(value: u16, quality: u32, limit: u32) -> u32
+{
+    let factor = if quality < 50 { 5000 / quality } else { 200 - quality * 2 };
+
+    ((u32::from(value) * factor + 50) / 100).clamp(1, limit)
+}
+
+/// Estimate the IJG quality of `tables`, table 0 for luminance and the others for
+/// chrominance
+///
+/// Returns None if there are no tables.
+pub(crate) fn estimate_quality(tables: &[Option<[u16; 64]>; MAX_COMPONENTS]) -> Option<u8>
+{
+    if tables.iter().all(Option::is_none)
+    {
+        return None;
+    }
+    // baseline encoders limit entries to 8 bits
+    let limit = if tables.iter().flatten().flatten().any(|x| *x > 255)
+    {
+        32767
+    }
+    else
+    {
+        255
+    };
+
+    (1..=100_u8).min_by_key(|x| distance(tables, u32::from(*x), limit))
+}
+
+/// Sum of the differences between `tables` and the example tables scaled by `quality`
+fn distance(tables: &[Option<[u16; 64]>; MAX_COMPONENTS], quality: u32, limit: u32) -> u32
+{
+    let mut distance = 0;
+
+    for (i, table) in tables.iter().enumerate()
+    {
+        if let Some(table) = table
+        {
+            let example = if i == 0 { &LUMINANCE } else { &CHROMINANCE };
+
+            for (example, value) in example.iter().zip(table)
+            {
+                distance += scale(*example, quality, limit).abs_diff(u32::from(*value));
+            }
+        }
+    }
+
+    distance
+}
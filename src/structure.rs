@@ -0,0 +1,195 @@
+//! The layout of a JPEG image, marker by marker
+//!
+//! [`JpegStructure`] walks a buffer and lists every marker with its offset, length
+//! and payload, borrowed from the buffer. Entropy coded data following a scan
+//! header is listed with its extent without being decoded, as is data after the end
+//! of image marker, so nothing in the buffer is left out.
+//!
+//! This is meant for inspecting and debugging images, it doesn't check that the
+//! markers make sense together.
+
+use crate::errors::DecodeErrors;
+use crate::marker::Marker;
+use crate::misc::read_u16_be;
+
+/// What an entry of the structure holds
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryKind
+{
+    /// A marker, and the segment following it unless it stands alone
+    Marker(Marker),
+    /// Entropy coded data following a scan header, restart markers included
+    EntropyCoded,
+    /// Bytes between segments which aren't a marker
+    Unknown,
+    /// Data after the end of image marker
+    Trailing,
+}
+
+/// An entry of the structure
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Entry<'a>
+{
+    /// What the entry holds
+    pub kind:   EntryKind,
+    /// Offset from the start of the buffer, of the 0xFF for markers
+    pub offset: usize,
+    /// Number of bytes the entry takes, including the marker and length of a segment
+    pub length: usize,
+    /// Payload of the entry, the segment without its marker and length for markers
+    pub data:   &'a [u8],
+}
+
+/// Every marker and piece of data in a JPEG image, in file order
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JpegStructure<'a>
+{
+    entries: Vec<Entry<'a>>,
+}
+
+impl<'a> JpegStructure<'a>
+{
+    /// Walk the markers of `data`
+    ///
+    /// Walking carries on through every scan and the end of image marker until the
+    /// buffer ends. A segment longer than what is left of the buffer is cut short.
+    ///
+    /// # Errors
+    /// - The buffer doesn't start with a start of image marker
+    /// - A segment has a length smaller than 2, or the buffer ends before its length
+    pub fn parse(data: &'a [u8]) -> Result<JpegStructure<'a>, DecodeErrors>
+    {
+        let magic_bytes = read_u16_be(&mut &data[..])?;
+
+        if magic_bytes != 0xFFD8
+        {
+            return Err(DecodeErrors::IllegalMagicBytes(magic_bytes));
+        }
+        let mut entries = Vec::new();
+        let mut position = 0;
+
+        while position < data.len()
+        {
+            // a 0xFF is a fill byte which may come before a marker, see B.1.1.2 of the spec
+            if data[position..].starts_with(&[0xFF, 0xFF])
+            {
+                position += 1;
+                continue;
+            }
+            let marker = data.get(position + 1).and_then(|x| Marker::from_u8(*x));
+
+            let Some(marker) = marker.filter(|_| data[position] == 0xFF)
+            else
+            {
+                let end = next_marker(data, position + 1, false);
+
+                warn!("Found {} bytes which aren't a marker at {}", end - position, position);
+
+                entries.push(Entry {
+                    kind:   EntryKind::Unknown,
+                    offset: position,
+                    length: end - position,
+                    data:   &data[position..end],
+                });
+                position = end;
+                continue;
+            };
+            let end = if marker.is_standalone()
+            {
+                position + 2
+            }
+            else
+            {
+                let length = read_u16_be(&mut data.get(position + 2..).unwrap_or_default())?;
+
+                if length < 2
+                {
+                    return Err(DecodeErrors::Format(format!(
+                        "Found a marker with invalid length : {length}"
+                    )));
+                }
+                (position + 2 + usize::from(length)).min(data.len())
+            };
+
+            entries.push(Entry {
+                kind:   EntryKind::Marker(marker),
+                offset: position,
+                length: end - position,
+                data:   data.get(position + 4..end).unwrap_or_default(),
+            });
+            position = end;
+
+            if marker == Marker::SOS
+            {
+                let end = next_marker(data, position, true);
+
+                if end > position
+                {
+                    entries.push(Entry {
+                        kind:   EntryKind::EntropyCoded,
+                        offset: position,
+                        length: end - position,
+                        data:   &data[position..end],
+                    });
+                }
+                position = end;
+            }
+            else if marker == Marker::EOI && position < data.len()
+            {
+                entries.push(Entry {
+                    kind:   EntryKind::Trailing,
+                    offset: position,
+                    length: data.len() - position,
+                    data:   &data[position..],
+                });
+                position = data.len();
+            }
+        }
+
+        Ok(JpegStructure { entries })
+    }
+
+    /// Every entry, in file order
+    #[must_use]
+    pub fn entries(&self) -> &[Entry<'a>]
+    {
+        &self.entries
+    }
+
+    /// Entries of `marker`, in file order
+    pub fn find(&self, marker: Marker) -> impl Iterator<Item = &Entry<'a>>
+    {
+        self.entries
+            .iter()
+            .filter(move |x| x.kind == EntryKind::Marker(marker))
+    }
+
+    /// Data after the end of image marker, if any
+    #[must_use]
+    pub fn trailing_data(&self) -> Option<&'a [u8]>
+    {
+        self.entries
+            .iter()
+            .find(|x| x.kind == EntryKind::Trailing)
+            .map(|x| x.data)
+    }
+}
+
+/// Find the offset of the next marker from `start`, or the end of `data`
+///
+/// A 0xFF followed by 0x00 is a stuffed byte and not a marker. Restart markers are
+/// skipped too in entropy coded data.
+pub(crate) fn next_marker(data: &[u8], start: usize, entropy_coded: bool) -> usize
+{
+    data.get(start..)
+        .unwrap_or_default()
+        .windows(2)
+        .position(|x| match x
+        {
+            [0xFF, 0x00] => false,
+            [0xFF, 0xD0..=0xD7] => !entropy_coded,
+            [0xFF, _] => true,
+            _ => false,
+        })
+        .map_or(data.len(), |x| start + x)
+}
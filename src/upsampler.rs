@@ -67,21 +67,40 @@
 //! # Horizontal vertical downsampling/chroma quartering.
 //!
 //! Carry out a vertical filter in the first pass, then a horizontal filter in the second pass.
+//! The vertical pass isn't rounded, the sums `3*near+far` are filtered horizontally
+//! and rounded once like libjpeg does.
+//!
+//! # Context rows
+//!
+//! Components are up-sampled a band of MCU rows at a time. The first and last rows of
+//! a band are filtered with the last row of the band above and the first row of the
+//! band below, which up-samplers get alongside their input, so that bands join
+//! without a seam. At the top and bottom of the image these are the edge rows
+//! themselves.
+//!
+//! # Other ratios
+//!
+//! Chroma which is a third or a quarter of the image size (e.g 4:1:1 images) is
+//! up-sampled by replicating samples, like libjpeg does for ratios it has no
+//! fancy up-sampler for.
 #[cfg(feature = "x86")]
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 pub use sse::upsample_horizontal_sse;
 
 use crate::components::UpSampler;
-pub use crate::upsampler::scalar::{upsample_horizontal, upsample_vertical};
+pub use crate::upsampler::scalar::{upsample_generic, upsample_hv, upsample_vertical};
+#[cfg(test)]
+use crate::upsampler::scalar::upsample_horizontal;
 
 mod avx2;
 mod scalar;
 mod sse;
 
 // choose best possible implementation for this platform
-pub fn choose_horizontal_samp_function(use_unsafe:bool) -> UpSampler
+pub fn choose_horizontal_samp_function(use_unsafe: bool) -> UpSampler
 {
-    if use_unsafe {
+    if use_unsafe
+    {
         #[cfg(all(feature = "x86", any(target_arch = "x86_64", target_arch = "x86")))]
         {
             if is_x86_feature_detected!("sse4.1")
@@ -94,25 +113,51 @@ pub fn choose_horizontal_samp_function(use_unsafe:bool) -> UpSampler
     debug!("Using scalar H up-sampler");
     return scalar::upsample_horizontal;
 }
-pub fn choose_hv_samp_function(use_unsafe:bool) -> UpSampler
+
+/// Choose the up-sampler for a component whose samples cover
+/// `h_ratio` by `v_ratio` pixels
+///
+/// Returns `None` when the component doesn't need up-sampling.
+pub fn choose_upsampler(h_ratio: usize, v_ratio: usize, use_unsafe: bool) -> Option<UpSampler>
 {
-    if use_unsafe {
+    match (h_ratio, v_ratio)
+    {
+        (1, 1) => None,
+        (2, 1) => Some(choose_horizontal_samp_function(use_unsafe)),
+        (1, 2) => Some(upsample_vertical),
+        (2, 2) => Some(choose_hv_samp_function(use_unsafe)),
+        _ => Some(upsample_generic),
+    }
+}
+
+/// Whether the up-sampler for a component whose samples cover `h_ratio` by
+/// `v_ratio` pixels filters with the rows above and below its input
+pub fn uses_context_rows(h_ratio: usize, v_ratio: usize) -> bool
+{
+    matches!((h_ratio, v_ratio), (1 | 2, 2))
+}
+
+/// Choose the up-sampler for components sampled at half the width and height
+pub fn choose_hv_samp_function(use_unsafe: bool) -> UpSampler
+{
+    if use_unsafe
+    {
         #[cfg(all(feature = "x86", any(target_arch = "x86_64", target_arch = "x86")))]
         {
             if is_x86_feature_detected!("avx2")
             {
-                debug!("Using avx HV up-sampler");
-                return avx2::upsample_hv_simd;
+                debug!("Using avx2 HV up-sampler");
+                return avx2::upsample_hv_avx2;
             }
         }
     }
-    debug!("using scalar HV up-sampler");
-    return scalar::upsample_hv;
+    debug!("Using scalar HV up-sampler");
+    return upsample_hv;
 }
 
 /// Upsample nothing
 
-pub fn upsample_no_op(_: &[i16], _: usize) -> Vec<i16>
+pub fn upsample_no_op(_: &[i16], _: &[i16], _: &[i16], _: usize, _: usize, _: usize) -> Vec<i16>
 {
     return Vec::new();
 }
@@ -127,9 +172,28 @@ fn upsample_sse_v1()
 {
     let v: Vec<i16> = (0..128).collect();
 
+    for width in [6, 7, 8, 13, 64]
+    {
+        let len = v.len() / width * width;
+
+        assert_eq!(
+            upsample_horizontal_sse(&v[..len], &[], &[], width, width * 2, len * 2),
+            upsample_horizontal(&v[..len], &[], &[], width, width * 2, len * 2),
+            "Algorithms do not match"
+        );
+    }
+}
+
+#[test]
+#[cfg(feature = "x86")]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn upsample_sse_v2()
+{
+    let v: Vec<i16> = (0..1280).rev().collect();
+
     assert_eq!(
-        upsample_horizontal_sse(&v, v.len() * 2),
-        crate::upsampler::scalar::upsample_horizontal(&v, v.len() * 2),
+        upsample_horizontal_sse(&v, &[], &[], 160, 320, v.len() * 2),
+        upsample_horizontal(&v, &[], &[], 160, 320, v.len() * 2),
         "Algorithms do not match"
     );
 }
@@ -137,15 +201,68 @@ fn upsample_sse_v1()
 #[test]
 #[cfg(feature = "x86")]
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-fn upsample_sse_v2()
+fn upsample_avx2_hv()
 {
-    use crate::upsampler::scalar::upsample_horizontal;
+    if !is_x86_feature_detected!("avx2")
+    {
+        return;
+    }
+    // 12 bit samples, which overflow signed 16 bit sums
+    let v: Vec<i16> = (0..1200_i32).map(|x| ((x * 2731 + x * x * 17) % 4096) as i16).collect();
 
-    let v: Vec<i16> = (0..1280).rev().collect();
+    for width in [16, 17, 30, 40, 75]
+    {
+        let len = v.len() / width * width;
+        let (above, below) = (&v[len - width..len], &v[..width]);
+
+        assert_eq!(
+            avx2::upsample_hv_avx2(&v[..len], above, below, width, width * 2, len * 4),
+            upsample_hv(&v[..len], above, below, width, width * 2, len * 4),
+            "Algorithms do not match"
+        );
+    }
+}
+
+#[test]
+fn upsample_rows_separately()
+{
+    // two rows, the end of the first row shouldn't blend with the start of the second
+    let v = [10, 20, 30, 200, 210, 220];
 
     assert_eq!(
-        upsample_horizontal_sse(&v, v.len() * 2),
-        upsample_horizontal(&v, v.len() * 2),
-        "Algorithms do not match"
+        upsample_horizontal(&v, &[], &[], 3, 6, 12),
+        [10, 13, 17, 23, 27, 30, 200, 203, 207, 213, 217, 220]
+    );
+    assert_eq!(
+        upsample_vertical(&v, &v[..3], &v[3..], 3, 3, 12),
+        [10, 20, 30, 58, 68, 78, 152, 162, 172, 200, 210, 220]
+    );
+}
+
+#[test]
+fn upsample_context_rows()
+{
+    // the first and last rows lean towards the rows around the input
+    let v = [100, 100, 200, 200];
+
+    assert_eq!(
+        upsample_vertical(&v, &[0, 20], &[255, 255], 2, 2, 8),
+        [75, 80, 125, 125, 175, 175, 214, 214]
+    );
+    // rounded once, a vertical pass followed by a horizontal pass gives 76 for the second sample
+    assert_eq!(
+        upsample_hv(&v[..2], &[0, 7], &[100, 100], 2, 4, 8),
+        [75, 75, 76, 77, 100, 100, 100, 100]
+    );
+}
+
+#[test]
+fn upsample_replicate()
+{
+    let v = [1, 2, 3, 4];
+
+    assert_eq!(
+        upsample_generic(&v, &[], &[], 2, 6, 24),
+        [1, 1, 1, 2, 2, 2, 1, 1, 1, 2, 2, 2, 3, 3, 3, 4, 4, 4, 3, 3, 3, 4, 4, 4]
     );
 }
@@ -2,14 +2,34 @@
 ///
 /// The up-sampling algorithm used is libjpeg-turbo `fancy_upsampling` which is
 /// a linear interpolation or triangle filter, see module docs for explanation
-pub fn upsample_horizontal(input: &[i16], output_len: usize) -> Vec<i16>
+pub fn upsample_horizontal(
+    input: &[i16], _above: &[i16], _below: &[i16], input_width: usize, output_width: usize,
+    output_len: usize,
+) -> Vec<i16>
 {
     let mut out = vec![0; output_len];
 
-    assert!(
-        out.len() > 4 && input.len() > 2,
-        "Too Short of a vector, cannot upsample"
-    );
+    // every row is filtered separately, so pixels at the end of a row don't
+    // bleed into the next one
+    for (input_row, output_row) in input
+        .chunks_exact(input_width)
+        .zip(out.chunks_exact_mut(output_width))
+    {
+        upsample_horizontal_row(input_row, output_row);
+    }
+    return out;
+}
+
+/// Upsample a single row to twice its width
+pub fn upsample_horizontal_row(input: &[i16], out: &mut [i16])
+{
+    if input.len() < 2
+    {
+        out[0] = input[0];
+        out[1] = input[0];
+        return;
+    }
+
     out[0] = input[0];
 
     out[1] = (input[0] * 3 + input[1] + 2) >> 2;
@@ -19,13 +39,15 @@ pub fn upsample_horizontal(input: &[i16], output_len: usize) -> Vec<i16>
     // The readable code is
     //
     //      for i in 1..input.len() - 1{
-    //         let sample = 3 * input[i] + 2;
-    //         out[i * 2] = (sample + input[i - 1]) >> 2;
-    //         out[i * 2 + 1] = (sample + input[i + 1]) >> 2;
+    //         let sample = 3 * input[i];
+    //         out[i * 2] = (sample + input[i - 1] + 1) >> 2;
+    //         out[i * 2 + 1] = (sample + input[i + 1] + 2) >> 2;
     //     }
     //
     // The output of a pixel is determined by it's surrounding neighbours but we attach more weight to it's nearest
     // neighbour (input[i]) than to the next nearest neighbour.
+    // Like libjpeg, outputs leaning left round down and those leaning right round up
+    // so that there is no bias in either direction.
 
     for (output_window, input_window) in out[2..].chunks_exact_mut(2).zip(input.windows(3))
     {
@@ -34,133 +56,167 @@ pub fn upsample_horizontal(input: &[i16], output_len: usize) -> Vec<i16>
 
         let input_window: &[i16; 3] = input_window.try_into().unwrap();
 
-        let sample = 3 * input_window[1] + 2;
+        let sample = 3 * input_window[1];
 
-        output_window[0] = (sample + input_window[0]) >> 2;
+        output_window[0] = (sample + input_window[0] + 1) >> 2;
 
-        output_window[1] = (sample + input_window[2]) >> 2;
+        output_window[1] = (sample + input_window[2] + 2) >> 2;
     }
     // handle last two portions (in the most ugliest of ways)
 
     // Get lengths
-    let out_len = out.len() - 2;
+    let out_len = input.len() * 2 - 2;
     let input_len = input.len() - 2;
 
     // slice the output vector
-    let f_out: &mut [i16; 2] = out.get_mut(out_len..).unwrap().try_into().unwrap();
+    let f_out: &mut [i16; 2] = out.get_mut(out_len..out_len + 2).unwrap().try_into().unwrap();
     // get a slice of the input vector
     let i_last: &[i16; 2] = input.get(input_len..).unwrap().try_into().unwrap();
 
     // write out manually..
-    f_out[0] = (3 * i_last[0] + i_last[1] + 2) >> 2;
+    f_out[0] = (3 * i_last[1] + i_last[0] + 1) >> 2;
 
     f_out[1] = i_last[1];
-
-    return out;
 }
-/// Vertical upsampling (which I don't trust but it works anyway)
+
+/// Upsample vertically
 ///
-/// The algorithm is still a bi-linear filter with some caveats.
-pub fn upsample_vertical(input: &[i16], output_len: usize) -> Vec<i16>
+/// Every row gives two rows, the first one leaning towards the row above and the
+/// second one to the row below. The first and last rows are weighed with `above`
+/// and `below`, the rows next to the input in the image, which are the first and
+/// last rows themselves at the edges of the image.
+pub fn upsample_vertical(
+    input: &[i16], above: &[i16], below: &[i16], input_width: usize, _output_width: usize,
+    output_len: usize,
+) -> Vec<i16>
 {
-    // the caveats, row_near and row_far for the first row point to the same
-    // array, that boils to a nearest neighbour upsampling?
-
-    // if row_near doesn't have data (next returns None), it uses it's
-    // previous row, so this again becomes a nearest neighbour
-
-    // How many pixels we need to skip to the next MCU row.
-    let stride = input.len() >> 3;
-
-    // We have 8 rows and we want 16 rows
-    let mut row_near = input.chunks_exact(stride);
-
-    // row far should point one row below row_near, if row near is in row 3, row far is in
-    // row 4.
-    let mut row_far = input.chunks_exact(stride);
+    let stride = input_width;
 
     let mut out = vec![0; output_len];
-    // nearest row
-    let mut rw_n = row_near.next().unwrap();
-    // farthest row;
-    let mut rw_f = row_far.next().unwrap();
-    // previous row, for out of edge cases, e.g if we reach a row and
-    // we don't have data for that row, we use the previous row
-    let mut previous;
 
-    let mut i = 0;
+    let rows = input.len() / stride;
 
-    let mut next_row = true;
-
-    for _ in 0..8
+    for (i, output_rows) in out.chunks_exact_mut(stride * 2).take(rows).enumerate()
     {
-        // A bit of cheat here.
-        //
-        // Let me explain, split at mut will return two slices, one containing 0..stride
-        // and another containing what remains.
-        //
-        // The length of out_near => stride, remainder will ALWAYS be greater than or equal to stride.
-        // now this is important with the loop below, we are using zip iterators which have an interesting
-        // property, they stop if any iterator doesn't have elements
-        //
-        // So we know remainder will be >= stride but out_near will be stride, therefore the zip will
-        // effectively stop when we write two rows.
-        //
-        // Ideally all of this is done to eliminate bounds check, the compiler will vectorize this
-        // better without the bounds check.
-        let (out_near, remainder) = out[i..].split_at_mut(stride);
-
-        for (((near, far), on), of) in rw_n
+        let row = &input[i * stride..(i + 1) * stride];
+        // row above and below the current one
+        let above = if i == 0 { above } else { &input[(i - 1) * stride..i * stride] };
+        let below = if i + 1 == rows { below } else { &input[(i + 1) * stride..][..stride] };
+
+        let (out_near, out_far) = output_rows.split_at_mut(stride);
+
+        for (((sample, above), below), (on, of)) in row
             .iter()
-            .zip(rw_f.iter())
-            .zip(out_near.iter_mut())
-            .zip(remainder.iter_mut())
+            .zip(above.iter())
+            .zip(below.iter())
+            .zip(out_near.iter_mut().zip(out_far.iter_mut()))
         {
             // Yes this can be easily accelerated, with SSE or AVX,
             // but it's a maintenance overhead and the compiler does an amazing work
             // here so Id rather not do it (even libjpeg doesn't :) )
+            let sample = 3 * sample;
 
-            // near row
-            *on = ((*near) * 3 + (*far) + 2) >> 2;
-            // far row
-            *of = ((*far) * 3 + (*near) + 2) >> 2;
+            // rounded like libjpeg, down for the upper row and up for the lower one
+            *on = (sample + above + 1) >> 2;
+
+            *of = (sample + below + 2) >> 2;
         }
-        // we wrote two adjacent lines update i to point to next position
-        // in output buffer.
-        i += stride * 2;
+    }
+    return out;
+}
 
-        previous = rw_n;
+/// Upsample horizontally and vertically
+///
+/// Like libjpeg, every output row is made from the sums `3*near+far` of the input
+/// rows closest to it, which are then filtered horizontally and rounded once, so
+/// this isn't the same as a vertical pass followed by a horizontal one.
+/// `above` and `below` are the rows next to the input in the image.
+pub fn upsample_hv(
+    input: &[i16], above: &[i16], below: &[i16], input_width: usize, output_width: usize,
+    output_len: usize,
+) -> Vec<i16>
+{
+    let stride = input_width;
 
-        rw_n = row_near.next().unwrap_or(previous);
+    let mut out = vec![0; output_len];
+    // column sums, 12 bit samples overflow an i16 once filtered horizontally
+    let mut sums = vec![0_i32; stride];
+
+    let rows = input.len() / stride;
+
+    for (i, output_rows) in out.chunks_exact_mut(output_width * 2).take(rows).enumerate()
+    {
+        let row = &input[i * stride..(i + 1) * stride];
+        let above = if i == 0 { above } else { &input[(i - 1) * stride..i * stride] };
+        let below = if i + 1 == rows { below } else { &input[(i + 1) * stride..][..stride] };
+
+        let (out_near, out_far) = output_rows.split_at_mut(output_width);
 
-        rw_f = row_far.next().unwrap_or(rw_n);
-        // okay here I'm lazy. Ideally what i want s simply this.
-        // for the first row, keep row_near and row_far to the same, row, but for sub-sequent rows,
-        // row_far should point to the next row(1 row further than this row_near).
-        if next_row
+        for (far, out) in [(above, out_near), (below, out_far)]
         {
-            rw_f = row_far.next().unwrap_or(rw_n);
-            next_row = false;
+            for ((sum, sample), far) in sums.iter_mut().zip(row).zip(far)
+            {
+                *sum = 3 * i32::from(*sample) + i32::from(*far);
+            }
+            upsample_sums_row(&sums, out);
         }
     }
     return out;
 }
-pub fn upsample_hv(input: &[i16], output_len: usize) -> Vec<i16>
+
+/// Filter a row of column sums horizontally into a row twice its width
+///
+/// This is the second pass of [`upsample_hv`], the samples are weighed by 16 so the
+/// result is rounded like libjpeg, down for outputs leaning left and up for those
+/// leaning right.
+#[allow(clippy::cast_possible_truncation)]
+pub fn upsample_sums_row(sums: &[i32], out: &mut [i16])
 {
-    //  a hv upsample is simply a two pass sample, first sample vertically, then sample horizontally
-    // because we spent too much time writing our horizontal and vertical sub sampling  to
-    // create outputs twice their inputs, we can just do this and we know they will work
-    // Furthermore, we know it will be fast, upsample vertical and horizontal are super optimized
-    // to eliminate bounds check.
+    let last = sums.len() - 1;
+
+    // the first and last sums are weighed with themselves
+    out[0] = ((4 * sums[0] + 8) >> 4) as i16;
+
+    // out[i*2+1] leans from sums[i] to the right, out[i*2+2] from sums[i+1] to the left
+    for (output_window, input_window) in out[1..].chunks_exact_mut(2).zip(sums.windows(2))
+    {
+        output_window[0] = ((3 * input_window[0] + input_window[1] + 7) >> 4) as i16;
+
+        output_window[1] = ((3 * input_window[1] + input_window[0] + 8) >> 4) as i16;
+    }
 
-    // But there is an AVX version obviously
+    out[last * 2 + 1] = ((4 * sums[last] + 7) >> 4) as i16;
+}
+
+/// Upsample by any whole factor by replicating samples
+///
+/// This is what libjpeg does for ratios without a fancy up-sampler, e.g the
+/// chroma of 4:1:1 images which is a quarter of the image width.
+pub fn upsample_generic(
+    input: &[i16], _above: &[i16], _below: &[i16], input_width: usize, output_width: usize,
+    output_len: usize,
+) -> Vec<i16>
+{
+    let mut out = vec![0; output_len];
 
-    // But this is a good place for optimization if one wants to tackle that.
+    let h_factor = output_width / input_width;
+    let v_factor = (output_len / output_width) / (input.len() / input_width);
 
-    // first pass, do vertical sampling
-    let first_pass = upsample_vertical(input, input.len() * 2);
-    //second pass, do horizontal sampling
-    let second_pass = upsample_horizontal(&first_pass, output_len);
+    for (input_row, output_rows) in input
+        .chunks_exact(input_width)
+        .zip(out.chunks_exact_mut(output_width * v_factor))
+    {
+        let (first, rest) = output_rows.split_at_mut(output_width);
 
-    return second_pass;
+        for (sample, out) in input_row.iter().zip(first.chunks_exact_mut(h_factor))
+        {
+            out.fill(*sample);
+        }
+        // remaining rows are copies of the first
+        for row in rest.chunks_exact_mut(output_width)
+        {
+            row.copy_from_slice(first);
+        }
+    }
+    return out;
 }
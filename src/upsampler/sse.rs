@@ -6,70 +6,60 @@
 use std::arch::x86::*;
 #[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::*;
-use std::convert::TryInto;
+
+use crate::upsampler::scalar::upsample_horizontal_row;
 
 #[inline]
-pub fn upsample_horizontal_sse(input: &[i16], output_len: usize) -> Vec<i16>
+pub fn upsample_horizontal_sse(
+    input: &[i16], _above: &[i16], _below: &[i16], input_width: usize, output_width: usize,
+    output_len: usize,
+) -> Vec<i16>
 {
-    unsafe { upsample_horizontal_sse_u(input, output_len) }
+    let mut out = vec![0; output_len];
+
+    for (input_row, output_row) in input
+        .chunks_exact(input_width)
+        .zip(out.chunks_exact_mut(output_width))
+    {
+        if input_row.len() < 6
+        {
+            // too short for the vector loop
+            upsample_horizontal_row(input_row, output_row);
+        }
+        else
+        {
+            unsafe { upsample_horizontal_row_sse(input_row, output_row) }
+        }
+    }
+    return out;
 }
 
-/// Upsample using SSE to improve speed
+/// Upsample a row using SSE to improve speed
 ///
 /// The sampling filter is bi-linear or triangle filter
 #[target_feature(enable = "sse2")]
 //Some things are weird...
 #[target_feature(enable = "sse4.1")]
 #[inline]
-pub unsafe fn upsample_horizontal_sse_u(input: &[i16], output_len: usize) -> Vec<i16>
+unsafe fn upsample_horizontal_row_sse(input: &[i16], out: &mut [i16])
 {
-    //let mut out = align_zero_alloc::<i16, 16>(output_len);
-    let mut out = vec![0; output_len];
-
-    // set first 8 pixels linearly
     // Assert that out has more than 8 elements and input has more than 4
     // Do this before otherwise Rust will bounds check all of these items like some
     // paranoid guy.
-    assert!(out.len() > 8 && input.len() > 5);
-
-    //@ OPTIMIZE TIP: Borrow slices of a definite length and turn them into a reference
-    // array to eliminate bounds checking.
-    let f_out: &mut [i16; 8] = out.get_mut(0..8).unwrap().try_into().unwrap();
-
-    // We can do better here, since Rust loads input[y] twice but it can store it in
-    // a register but enough ugly code
-    f_out[0] = input[0];
-
-    f_out[1] = (input[0] * 3 + input[1] + 2) >> 2;
-
-    f_out[2] = (input[1] * 3 + input[0] + 2) >> 2;
-
-    f_out[3] = (input[1] * 3 + input[2] + 2) >> 2;
-
-    f_out[4] = (input[2] * 3 + input[1] + 2) >> 2;
+    assert!(out.len() >= input.len() * 2 && input.len() > 5);
 
-    f_out[5] = (input[2] * 3 + input[3] + 2) >> 2;
-
-    f_out[6] = (input[3] * 3 + input[2] + 2) >> 2;
-
-    f_out[7] = (input[3] * 3 + input[4] + 2) >> 2;
-
-    // maths
-    // The poop here is to calculate how many 8 items we can interleave without
-    // reading past the array We can't do the last 8 using SSE, because we will
-    // read after, and neither can we read from the first 8 because
-    // we'll underflow in the first array so those are handled differently
     let inl = input.len();
 
-    // times we can iterate without going past array;
+    out[0] = input[0];
 
-    // For the rest of the pixels use normal instructions
-    // Process using SSE for as many times as we can
+    out[1] = (input[0] * 3 + input[1] + 2) >> 2;
 
-    for i in 1..(inl >> 2) - 1
-    {
-        let pos = i << 2;
+    // Process using SSE for as many times as we can, every iteration reads
+    // input[pos-1..pos+5] so we stop 5 samples before the end.
+    let mut pos = 1;
 
+    while pos + 5 <= inl
+    {
         let mut yn = _mm_loadl_epi64(input.get_unchecked(pos..).as_ptr().cast());
 
         yn = _mm_unpacklo_epi16(yn, yn); //[a,a,b,b,c,c,d,d]
@@ -95,40 +85,33 @@ pub unsafe fn upsample_horizontal_sse_u(input: &[i16], output_len: usize) -> Vec
         let an = _mm_add_epi16(_mm_slli_epi16::<1>(yn), yn);
 
         // hoping this favours ILP because they don't depend on each other?
-        let bn = _mm_add_epi16(nn, _mm_set1_epi16(2));
+        // outputs leaning left round down and those leaning right round up, like libjpeg
+        let bn = _mm_add_epi16(nn, _mm_set_epi16(2, 1, 2, 1, 2, 1, 2, 1));
 
-        // (input[x]*3+input[y]+2)>>2;
+        // (input[x]*3+input[y]+bias)>>2;
         let cn = _mm_srai_epi16::<2>(_mm_add_epi16(an, bn));
 
         // write to array
         _mm_storeu_si128(
-            out.get_mut(i * 8..(i * 8) + 8).unwrap().as_mut_ptr().cast(),
+            out.get_mut(pos * 2..(pos * 2) + 8).unwrap().as_mut_ptr().cast(),
             cn,
         );
+        pos += 4;
     }
 
-    // Do the last 8 manually because we can't  do it  with SSE because of out of bounds access
-    let ol = out.len() - 8;
-
-    let il = input.len() - 4;
-
-    let l_out: &mut [i16; 8] = out.get_mut(ol..ol + 8).unwrap().try_into().unwrap();
-
-    l_out[0] = (input[il] * 3 + input[il - 1] + 2) >> 2;
-
-    l_out[1] = (input[il] * 3 + input[il + 1] + 2) >> 2;
-
-    l_out[2] = (input[il + 1] * 3 + input[il] + 2) >> 2;
-
-    l_out[3] = (input[il + 1] * 3 + input[il + 1] + 2) >> 2;
+    // Do the rest manually because we can't  do it  with SSE because of out of bounds access
+    while pos < inl - 1
+    {
+        let sample = input[pos] * 3;
 
-    l_out[4] = (input[il + 2] * 3 + input[il + 2] + 2) >> 2;
+        out[pos * 2] = (sample + input[pos - 1] + 1) >> 2;
 
-    l_out[5] = (input[il + 2] * 3 + input[il + 1] + 2) >> 2;
+        out[pos * 2 + 1] = (sample + input[pos + 1] + 2) >> 2;
 
-    l_out[6] = (input[il + 2] * 3 + input[il + 3] + 2) >> 2;
+        pos += 1;
+    }
 
-    l_out[7] = input[il + 3];
+    out[inl * 2 - 2] = (input[inl - 1] * 3 + input[inl - 2] + 1) >> 2;
 
-    return out;
+    out[inl * 2 - 1] = input[inl - 1];
 }
@@ -1,26 +1,341 @@
+use std::borrow::Cow;
 use std::cmp::min;
 use std::convert::TryInto;
+use std::sync::Arc;
 
-use crate::color_convert::{ycbcr_to_grayscale, ycbcr_to_ycbcr};
+use crate::color_convert::{
+    cmyk_to_output, rgb_to_grayscale, rgb_to_rgb_u16, ycbcr_to_grayscale, ycbcr_to_rgb_u16,
+    ycbcr_to_ycbcr,
+};
 use crate::components::Components;
-use crate::decoder::{ColorConvert16Ptr, IDCTPtr};
+use crate::decoder::{ColorConvert16Ptr, IDCTPtr, MAX_COMPONENTS};
 use crate::misc::ColorSpace;
+use crate::orientation::BandOutput;
+use crate::upsampler::uses_context_rows;
+
+/// A type decoded pixels can be written out as
+///
+/// 8 bit images are written as `u8`'s while images with a higher sample precision
+/// are written as `u16`'s so that no precision is lost.
+pub(crate) trait Sample: Copy + Default + Send + Sync
+{
+    /// Convert a sample which has already been clamped to the image precision
+    fn from_i16(sample: i16) -> Self;
+
+    /// Convert a sample which fits in the image precision
+    fn from_u16(sample: u16) -> Self;
+
+    /// Carry out YCbCr or RGB to RGB(A/X) conversion for an MCU width
+    #[allow(clippy::too_many_arguments)]
+    fn color_convert_rgb(
+        mcu_block: &[Vec<i16>; 3], width: usize, stride: usize, input_colorspace: ColorSpace,
+        output_colorspace: ColorSpace, color_convert_16: ColorConvert16Ptr, precision: u8,
+        output: &mut [Self],
+    );
+}
+
+impl Sample for u8
+{
+    #[inline]
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn from_i16(sample: i16) -> Self
+    {
+        sample as u8
+    }
+
+    #[inline]
+    #[allow(clippy::cast_possible_truncation)]
+    fn from_u16(sample: u16) -> Self
+    {
+        sample as u8
+    }
+
+    fn color_convert_rgb(
+        mcu_block: &[Vec<i16>; 3], width: usize, stride: usize, _: ColorSpace,
+        output_colorspace: ColorSpace, color_convert_16: ColorConvert16Ptr, _: u8,
+        output: &mut [Self],
+    )
+    {
+        // color_convert_16 was chosen for the input colorspace
+        color_convert_rgb(
+            mcu_block,
+            width,
+            stride,
+            output_colorspace,
+            color_convert_16,
+            output,
+        );
+    }
+}
+
+impl Sample for u16
+{
+    #[inline]
+    #[allow(clippy::cast_sign_loss)]
+    fn from_i16(sample: i16) -> Self
+    {
+        sample as u16
+    }
+
+    #[inline]
+    fn from_u16(sample: u16) -> Self
+    {
+        sample
+    }
+
+    fn color_convert_rgb(
+        mcu_block: &[Vec<i16>; 3], width: usize, stride: usize, input_colorspace: ColorSpace,
+        output_colorspace: ColorSpace, _: ColorConvert16Ptr, precision: u8, output: &mut [Self],
+    )
+    {
+        if input_colorspace == ColorSpace::RGB
+        {
+            rgb_to_rgb_u16(mcu_block, width, stride, precision, output_colorspace, output);
+        }
+        else
+        {
+            ycbcr_to_rgb_u16(mcu_block, width, stride, precision, output_colorspace, output);
+        }
+    }
+}
+
+/// Number of components that have to be decoded to convert `input_colorspace` to
+/// `output_colorspace`
+///
+/// Grayscale output from YCbCr only needs the Y component, but every output channel
+/// of an RGB, CMYK or YCCK image depends on all of its components.
+pub(crate) fn needed_components(input_colorspace: ColorSpace, output_colorspace: ColorSpace) -> usize
+{
+    match input_colorspace
+    {
+        ColorSpace::RGB | ColorSpace::CMYK | ColorSpace::YCCK => input_colorspace.num_components(),
+        _ => min(
+            input_colorspace.num_components(),
+            output_colorspace.num_components(),
+        ),
+    }
+}
+
+/// A band of MCU rows on its way to post processing
+///
+/// Components which are up-sampled vertically are filtered with the last row of the
+/// band above and the first row of the band below, so they are transformed as soon
+/// as their band is decoded, see [`ContextBands`]. The other components are
+/// transformed with the rest of the post processing.
+pub(crate) struct Band<'a>
+{
+    /// Coefficients of the components which haven't been transformed yet
+    coeff:   [Cow<'a, [i16]>; MAX_COMPONENTS],
+    /// Samples of the components which have been transformed
+    samples: [Vec<i16>; MAX_COMPONENTS],
+    /// Last row of the band above, for components up-sampled vertically
+    above:   [Vec<i16>; MAX_COMPONENTS],
+    /// First row of the band below, for components up-sampled vertically
+    below:   [Vec<i16>; MAX_COMPONENTS],
+}
+
+/// Bands of an image, held back until the band below them is decoded
+///
+/// Like libjpeg's context rows, the rows next to a band are taken from the bands
+/// around it, or are the edge rows of the band at the top and bottom of the image.
+pub(crate) struct ContextBands<'a>
+{
+    components: Arc<Vec<Components>>,
+    idct_func:  IDCTPtr,
+    /// Whether a component is transformed as soon as its band is decoded
+    context:    [bool; MAX_COMPONENTS],
+    width:      usize,
+    height:     usize,
+    /// Number of bands of the image, and number of bands decoded so far
+    bands:      usize,
+    decoded:    usize,
+    /// The last band decoded, waiting for the band below it
+    pending:    Option<Band<'a>>,
+}
+
+impl<'a> ContextBands<'a>
+{
+    /// Bands of `bands` MCU rows of an image `width` by `height` pixels, of which
+    /// the first `needed` components are post processed
+    pub(crate) fn new(
+        components: Arc<Vec<Components>>, idct_func: IDCTPtr, needed: usize, width: usize,
+        height: usize, bands: usize,
+    ) -> ContextBands<'a>
+    {
+        let (h_max, v_max) = max_sampling(&components);
+        let mut context = [false; MAX_COMPONENTS];
+
+        for (context, component) in context.iter_mut().zip(components.iter()).take(needed)
+        {
+            *context = uses_context_rows(
+                h_max / component.horizontal_sample,
+                v_max / component.vertical_sample,
+            );
+        }
+
+        ContextBands {
+            components,
+            idct_func,
+            context,
+            width,
+            height,
+            bands,
+            decoded: 0,
+            pending: None,
+        }
+    }
+
+    /// Add the coefficients of the next band, and return the bands which are ready
+    /// for post processing
+    ///
+    /// That is the band above it, and the band itself if it is the last one.
+    pub(crate) fn push(&mut self, mut coeff: [Cow<'a, [i16]>; MAX_COMPONENTS]) -> Vec<Band<'a>>
+    {
+        let (h_max, v_max) = max_sampling(&self.components);
+        let mut samples = [vec![], vec![], vec![], vec![]];
+
+        self.decoded += 1;
+
+        let last = self.decoded == self.bands;
+
+        for (i, component) in self.components.iter().enumerate()
+        {
+            if !self.context[i]
+            {
+                continue;
+            }
+            samples[i] = transform(&coeff[i], component, self.idct_func, self.width, h_max);
+            coeff[i] = Cow::Borrowed(&[]);
+
+            if last
+            {
+                // rows past the height of the component are repeats of its last row,
+                // so that the last row is weighed with itself.
+                let stride = component.width_stride;
+                let band_rows = samples[i].len() / stride;
+                let height = (self.height * component.vertical_sample).div_ceil(v_max);
+                let rows = height.saturating_sub((self.bands - 1) * band_rows).max(1);
+
+                if rows < band_rows
+                {
+                    let (rows, padding) = samples[i].split_at_mut(rows * stride);
+                    let last_row = &rows[rows.len() - stride..];
+
+                    for row in padding.chunks_exact_mut(stride)
+                    {
+                        row.copy_from_slice(last_row);
+                    }
+                }
+            }
+        }
+
+        let mut band = Band {
+            coeff,
+            above: self.rows(&samples, true),
+            below: self.rows(&samples, false),
+            samples,
+        };
+        let mut ready = Vec::with_capacity(2);
+
+        if let Some(mut previous) = self.pending.take()
+        {
+            previous.below = self.rows(&band.samples, true);
+            band.above = self.rows(&previous.samples, false);
+
+            ready.push(previous);
+        }
+        if last
+        {
+            ready.push(band);
+        }
+        else
+        {
+            self.pending = Some(band);
+        }
+        ready
+    }
+
+    /// First or last rows of the components in `samples` which need them
+    fn rows(&self, samples: &[Vec<i16>; MAX_COMPONENTS], first: bool) -> [Vec<i16>; MAX_COMPONENTS]
+    {
+        let mut rows = [vec![], vec![], vec![], vec![]];
+
+        for ((row, samples), component) in rows.iter_mut().zip(samples).zip(self.components.iter())
+        {
+            let stride = component.width_stride;
+
+            if samples.len() >= stride
+            {
+                let start = if first { 0 } else { samples.len() - stride };
+
+                *row = samples[start..start + stride].to_vec();
+            }
+        }
+        rows
+    }
+}
+
+/// Largest horizontal and vertical sampling factors of the components
+fn max_sampling(component_data: &[Components]) -> (usize, usize)
+{
+    let h_max = component_data.iter().map(|c| c.horizontal_sample).max().unwrap_or(1);
+    let v_max = component_data.iter().map(|c| c.vertical_sample).max().unwrap_or(1);
+
+    (h_max, v_max)
+}
+
+/// Dequantize and carry out the inverse DCT of a component of a band
+///
+/// Components up-sampled horizontally have the samples past their width replaced
+/// by their last column, libjpeg only up-samples the width of a component so its
+/// last column is weighed with itself.
+fn transform(
+    coeff: &[i16], component: &Components, idct_func: IDCTPtr, width: usize, h_max: usize,
+) -> Vec<i16>
+{
+    // The component has vertical_sample rows of blocks, each stride pixels wide
+    let mut samples = idct_func(
+        coeff,
+        &component.quantization_table,
+        component.width_stride,
+        component.vertical_sample,
+        1,
+    );
+
+    if h_max == 2 * component.horizontal_sample
+    {
+        let columns = (width * component.horizontal_sample).div_ceil(h_max);
+
+        if columns < component.width_stride
+        {
+            for row in samples.chunks_exact_mut(component.width_stride)
+            {
+                let last = row[columns - 1];
+
+                row[columns..].fill(last);
+            }
+        }
+    }
+    samples
+}
+
 /// Handle everything else in jpeg processing that doesn't involve bitstream decoding
 ///
 /// This handles routines for images which are interleaved for non-interleaved use post_process_non_interleaved
 ///
 /// # Arguments
-/// - coeff - Contains Y,Cb,Cr components straight from the bitstream decoder
+/// - band - Contains Y,Cb,Cr (and K) components straight from the bitstream decoder
 /// - component_data - Contains metadata for unprocessed values, e.g QT tables and such
 /// - idct_func - IDCT function pointer
 /// - color_convert_16 - Carry out color conversion on 2 mcu's
 /// - color_convert - Carry out color conversion on a single MCU
 /// - input_colorspace - The colorspace the image is in
 /// - output_colorspace: Colorspace to change the value to
-/// - output - Where to write the converted data
+/// - output - Where to write the converted data, rotated or flipped if the image isn't upright
 /// - mcu_len - Number of MCU's per width
 /// - width - Width of the image.
-/// - position: Offset from which to write the pixels
+/// - precision - Sample precision of the image, 8 or 12 bits.
+/// - inverted - Whether CMYK samples are inverted, as written by Adobe applications
 #[allow(
 clippy::too_many_arguments,
 clippy::cast_sign_loss,
@@ -29,20 +344,19 @@ clippy::doc_markdown,
 clippy::single_match
 )]
 #[rustfmt::skip]
-pub(crate) fn post_process(
-    coeff: &[&[i16]; 3],
+pub(crate) fn post_process<T: Sample>(
+    band: Band,
     component_data: &[Components],
     idct_func: IDCTPtr,
     color_convert_16: ColorConvert16Ptr,
     input_colorspace: ColorSpace,
     output_colorspace: ColorSpace,
-    output: &mut [u8],
+    output: BandOutput<T>,
     width: usize,
+    precision: u8,
+    inverted: bool,
 ) // so many parameters..
 {
-    // maximum sampling factors are in Y-channel, no need to pass them.
-    let h_samp = component_data[0].horizontal_sample;
-    let v_samp = component_data[0].vertical_sample;
     // carry out dequantization and inverse DCT
 
     // So we want to carry out IDCT and upsampling
@@ -56,81 +370,108 @@ pub(crate) fn post_process(
     // (1)      -> (1) => Decode 1 component
     // RGB -> RGBA
     // (3) -> (4) => Decode 3 channels
-    let x = min(
-        input_colorspace.num_components(),
-        output_colorspace.num_components(),
-    );
-    let mut unprocessed = [vec![], vec![], vec![]];
-
+    let x = needed_components(input_colorspace, output_colorspace);
+    let (h_max, _) = max_sampling(component_data);
+    let Band { coeff, mut samples, above, below } = band;
 
-    (0..x).for_each(|z| {
-        // Calculate stride
-        // Stride is basically how many pixels must we traverse to write an MCU
-        // e.g For an 8*8 MCU in a 16*16 image
-        // stride is 16 because move 16 pixels from location, you are below where you are writing
+    // components up-sampled vertically were transformed when the band was decoded
+    for ((samples, coeff), component) in samples.iter_mut().zip(&coeff).zip(component_data).take(x)
+    {
+        if samples.is_empty()
+        {
+            *samples = transform(coeff, component, idct_func, width, h_max);
+        }
+    }
 
-        // carry out IDCT.
-        let v_samp_idct = {
-            if z == 0 { 1 } else { v_samp }
-        };
-        unprocessed[z] = idct_func(coeff[z],
-                                   &component_data[z].quantization_table,
-                                   component_data[z].width_stride,
-                                   h_samp * v_samp,
-                                   v_samp_idct);
-    });
-
-    post_process_inner(&mut unprocessed, component_data, color_convert_16,
-                       input_colorspace, output_colorspace, output,  width);
+    post_process_inner(&mut samples, &above, &below, component_data, color_convert_16,
+                       input_colorspace, output_colorspace, output,  width, precision, inverted);
 }
 
 #[rustfmt::skip]
-pub(crate) fn post_process_inner(
-    unprocessed: &mut [Vec<i16>; 3], component_data: &[Components],
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn post_process_inner<T: Sample>(
+    unprocessed: &mut [Vec<i16>; MAX_COMPONENTS], above: &[Vec<i16>; MAX_COMPONENTS],
+    below: &[Vec<i16>; MAX_COMPONENTS], component_data: &[Components],
     color_convert_16: ColorConvert16Ptr,
-    input_colorspace: ColorSpace, output_colorspace: ColorSpace, output: &mut [u8],
-    width: usize,
+    input_colorspace: ColorSpace, output_colorspace: ColorSpace, output: BandOutput<T>,
+    width: usize, precision: u8, inverted: bool,
 ) // so many parameters..
 {
-    let x = min(
-        input_colorspace.num_components(),
-        output_colorspace.num_components(),
-    );
-    // maximum sampling factors are in Y-channel, no need to pass them.
-    let h_samp = component_data[0].horizontal_sample;
-    let v_samp = component_data[0].vertical_sample;
+    let x = needed_components(input_colorspace, output_colorspace);
+    let (h_max, v_max) = max_sampling(component_data);
 
-    if h_samp != 1 || v_samp != 1
+    // Width of a row including fill samples, every component has this width after up-sampling
+    let stride = component_data[0].width_stride / component_data[0].horizontal_sample * h_max;
+    let len = stride * v_max * 8;
+
+    for (i, (samples, component)) in unprocessed.iter_mut().zip(component_data).take(x).enumerate()
     {
-        // carry out upsampling , the return vector overwrites the original vector
-        for i in 1..x
+        if component.horizontal_sample != h_max || component.vertical_sample != v_max
         {
-            unprocessed[i] = (component_data[i].up_sampler)(&unprocessed[i], unprocessed[0].len());
+            // carry out upsampling , the return vector overwrites the original vector
+            *samples = (component.up_sampler)(samples, &above[i], &below[i],
+                                              component.width_stride, stride, len);
         }
     }
 
+    // bands which aren't upright are converted here and then written where they belong
+    let mut converted = vec![];
+
+    let (output, mut oriented) = match output
+    {
+        BandOutput::Upright(output) => (output, None),
+        BandOutput::Oriented(band) =>
+        {
+            converted = vec![T::default(); width * output_colorspace.num_components() * 8 * v_max];
+
+            (&mut converted[..], Some(band))
+        }
+    };
+
     // color convert
     match (input_colorspace, output_colorspace)
     {
         (ColorSpace::YCbCr | ColorSpace::GRAYSCALE, ColorSpace::GRAYSCALE) =>
         {
-            ycbcr_to_grayscale(&unprocessed[0], width, output);
+            ycbcr_to_grayscale(&unprocessed[0], width, stride, output);
         }
 
         (ColorSpace::YCbCr, ColorSpace::YCbCr) =>
         {
-            ycbcr_to_ycbcr(unprocessed, width, h_samp, v_samp, output);
+            ycbcr_to_ycbcr(first_three(unprocessed), width, stride, output);
         }
 
-        (ColorSpace::YCbCr, ColorSpace::RGB | ColorSpace::RGBA | ColorSpace::RGBX) =>
+        (ColorSpace::YCbCr | ColorSpace::RGB, ColorSpace::RGB | ColorSpace::RGBA | ColorSpace::RGBX) =>
         {
-            color_convert_ycbcr(unprocessed, width, h_samp, v_samp,
-                output_colorspace, color_convert_16,  output);
+            T::color_convert_rgb(first_three(unprocessed), width, stride,
+                input_colorspace, output_colorspace, color_convert_16, precision, output);
+        }
+
+        (ColorSpace::RGB, ColorSpace::GRAYSCALE) =>
+        {
+            rgb_to_grayscale(first_three(unprocessed), width, stride, output);
+        }
+
+        (ColorSpace::CMYK | ColorSpace::YCCK, _) =>
+        {
+            cmyk_to_output(unprocessed, width, stride, precision,
+                           input_colorspace, inverted, output_colorspace, output);
         }
         // For the other components we do nothing(currently)
         _ =>
         {}
     }
+
+    if let Some(band) = &mut oriented
+    {
+        band.write(&converted);
+    }
+}
+
+/// The Y, Cb and Cr (or R, G and B) components
+fn first_three(unprocessed: &[Vec<i16>; MAX_COMPONENTS]) -> &[Vec<i16>; 3]
+{
+    unprocessed[..3].try_into().unwrap()
 }
 
 /// Do color-conversion for interleaved MCU
@@ -140,112 +481,65 @@ pub(crate) fn post_process_inner(
     clippy::needless_pass_by_value,
     clippy::unwrap_used
 )]
-fn color_convert_ycbcr(
-    mcu_block: &[Vec<i16>; 3], width: usize, h_samp: usize, v_samp: usize,
-    output_colorspace: ColorSpace, color_convert_16: ColorConvert16Ptr, output: &mut [u8],
+fn color_convert_rgb(
+    mcu_block: &[Vec<i16>; 3], width: usize, width_chunk: usize, output_colorspace: ColorSpace,
+    color_convert_16: ColorConvert16Ptr, output: &mut [u8],
 )
 {
-    let mcu_chunks = mcu_block[0].len() / (h_samp * v_samp);
-    // Width of image which takes into account fill bytes(it may be larger than actual width).
-    let width_chunk = mcu_chunks >> 3;
-    let stride = width * output_colorspace.num_components();
+    // width_chunk is the width of the image which takes into account fill bytes
+    // (it may be larger than actual width).
+    let num_components = output_colorspace.num_components();
+    let stride = width * num_components;
 
-    let mut start = 0;
-    let mut end = stride;
-    // over allocate to account for fill bytes
-    let mut temp = vec![];
+    // Pixels at the end of a row are converted into this buffer and then copied out,
+    // color_convert_16 may write up to 64 bytes which may not fit in the row.
+    let mut temp = [0; 64];
 
-    if width < 16
-    {
-        // Allocate temporary buffer for small widths less than
-        // 16.
-        temp = vec![0; 16 * output_colorspace.num_components()];
-    }
     // We need to chunk per width to ensure we can discard extra values at the end of the width.
     // Since the encoder may pad bits to ensure the width is a multiple of 8.
-    for ((y_width, cb_width), cr_width) in mcu_block[0]
+    for (((y_width, cb_width), cr_width), out) in mcu_block[0]
         .chunks_exact(width_chunk)
         .zip(mcu_block[1].chunks_exact(width_chunk))
         .zip(mcu_block[2].chunks_exact(width_chunk))
+        .zip(output.chunks_exact_mut(stride))
     {
-        let elements = (y_width.len() / 16).saturating_sub(1);
-
         let mut position = 0;
-        let out = &mut output[start..end];
-
-        if width < 16
-        {
-            // allocate temporary buffers for the values received from idct
-            let mut y_out = [0; 16];
-            let mut cb_out = [0; 16];
-            let mut cr_out = [0; 16];
-            // copy those small widths to that buffer
-            y_out[0..y_width.len()].copy_from_slice(y_width);
-            cb_out[0..cb_width.len()].copy_from_slice(cb_width);
-            cr_out[0..cr_width.len()].copy_from_slice(cr_width);
-            // we handle widths less than 16 a bit differently, allocating a temporary
-            // buffer and writing to that and then flushing to the out buffer
-            // because of the optimizations applied below,
-            (color_convert_16)(&y_out, &cb_out, &cr_out, &mut temp, &mut 0);
-            // copy to stride
-            out[position..position + width * output_colorspace.num_components()]
-                .copy_from_slice(&temp[0..width * output_colorspace.num_components()]);
-            // increment position
-            start += stride;
-            end += stride;
-            // next
-            continue;
-        }
+        let mut x = 0;
 
         // Chunk in outputs of 16 to pass to color_convert as an array of 16 i16's.
-        for ((y, cb), cr) in y_width
-            .chunks_exact(16)
-            .zip(cb_width.chunks_exact(16))
-            .zip(cr_width.chunks_exact(16))
-            .take(elements)
+        while x + 16 <= width && position + 64 <= stride
         {
             (color_convert_16)(
-                y.try_into().unwrap(),
-                cb.try_into().unwrap(),
-                cr.try_into().unwrap(),
+                y_width[x..x + 16].try_into().unwrap(),
+                cb_width[x..x + 16].try_into().unwrap(),
+                cr_width[x..x + 16].try_into().unwrap(),
                 out,
                 &mut position,
             );
+            x += 16;
         }
 
         // we have more pixels in the end that can't be handled by the main loop.
-        // move pointer back a little bit to get last 16 bytes,
-        // color convert, and overwrite
-        // This means some values will be color converted twice.
-
-        let diff = 64_usize.saturating_sub(stride.saturating_sub(position));
-
-        position = position.saturating_sub(diff);
-
-        (color_convert_16)(
-            y_width
-                .rchunks_exact(16)
-                .next()
-                .unwrap()
-                .try_into()
-                .unwrap(),
-            cb_width
-                .rchunks_exact(16)
-                .next()
-                .unwrap()
-                .try_into()
-                .unwrap(),
-            cr_width
-                .rchunks_exact(16)
-                .next()
-                .unwrap()
-                .try_into()
-                .unwrap(),
-            out,
-            &mut position,
-        );
+        while x < width
+        {
+            let pixels = min(16, width - x);
+            let end = min(x + 16, y_width.len());
 
-        start += stride;
-        end += stride;
+            let mut y_out = [0; 16];
+            let mut cb_out = [0; 16];
+            let mut cr_out = [0; 16];
+
+            y_out[..end - x].copy_from_slice(&y_width[x..end]);
+            cb_out[..end - x].copy_from_slice(&cb_width[x..end]);
+            cr_out[..end - x].copy_from_slice(&cr_width[x..end]);
+
+            (color_convert_16)(&y_out, &cb_out, &cr_out, &mut temp, &mut 0);
+
+            out[position..position + pixels * num_components]
+                .copy_from_slice(&temp[..pixels * num_components]);
+
+            position += pixels * num_components;
+            x += pixels;
+        }
     }
 }
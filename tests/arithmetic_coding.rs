@@ -0,0 +1,126 @@
+//! Tests for images using arithmetic coding
+//!
+//! Every arithmetic coded image has a Huffman coded counterpart encoded
+//! with the same settings, the entropy coding is lossless so both should
+//! decode to exactly the same pixels.
+use zune_jpeg::errors::DecodeErrors;
+use zune_jpeg::{ColorSpace, Decoder, ZuneJpegOptions};
+
+use crate::common::read;
+
+mod common;
+
+fn assert_same_as_huffman(name: &str, options: ZuneJpegOptions)
+{
+    let arithmetic = read(&format!("{}.jpg", name));
+    let huffman = read(&format!("{}_huffman.jpg", name));
+
+    let mut decoder = Decoder::new_with_options(options);
+
+    let expected = Decoder::new_with_options(options)
+        .decode_buffer_u16(&huffman)
+        .unwrap();
+    let pixels = decoder.decode_buffer_u16(&arithmetic).unwrap();
+
+    assert_eq!(decoder.width(), 48);
+    assert_eq!(decoder.height(), 32);
+    assert!(pixels == expected, "Images differ for {}", name);
+}
+
+#[test]
+fn arithmetic_420()
+{
+    assert_same_as_huffman("arithmetic_420", ZuneJpegOptions::new());
+}
+
+#[test]
+fn arithmetic_422()
+{
+    assert_same_as_huffman("arithmetic_422", ZuneJpegOptions::new());
+}
+
+#[test]
+fn arithmetic_restart_markers()
+{
+    assert_same_as_huffman("arithmetic_restart", ZuneJpegOptions::new());
+}
+
+#[test]
+fn arithmetic_grayscale()
+{
+    assert_same_as_huffman(
+        "arithmetic_grayscale",
+        ZuneJpegOptions::new().set_out_colorspace(ColorSpace::GRAYSCALE),
+    );
+}
+
+#[test]
+fn arithmetic_12_bit()
+{
+    assert_same_as_huffman("arithmetic_12_bit", ZuneJpegOptions::new());
+}
+
+#[test]
+fn arithmetic_progressive_420()
+{
+    assert_same_as_huffman("arithmetic_progressive_420", ZuneJpegOptions::new());
+}
+
+#[test]
+fn arithmetic_progressive_restart_markers()
+{
+    // the restart interval also changes between scans
+    assert_same_as_huffman("arithmetic_progressive_restart", ZuneJpegOptions::new());
+}
+
+#[test]
+fn arithmetic_progressive_12_bit()
+{
+    assert_same_as_huffman("arithmetic_progressive_12_bit", ZuneJpegOptions::new());
+}
+
+#[test]
+fn arithmetic_progressive_large()
+{
+    let path = env!("CARGO_MANIFEST_DIR").to_string() + "/test-images/test-arithmetic-coding.jpg";
+
+    let mut decoder = Decoder::new();
+    let pixels = decoder.decode_file(path).unwrap();
+
+    assert_eq!(
+        pixels.len(),
+        usize::from(decoder.width()) * usize::from(decoder.height()) * 3
+    );
+}
+
+#[test]
+fn arithmetic_8_bit_output()
+{
+    let expected = Decoder::new()
+        .decode_buffer(&read("arithmetic_420_huffman.jpg"))
+        .unwrap();
+    let pixels = Decoder::new()
+        .decode_buffer(&read("arithmetic_420.jpg"))
+        .unwrap();
+
+    assert!(pixels == expected);
+}
+
+#[test]
+fn invalid_dac()
+{
+    let mut data = read("arithmetic_grayscale.jpg");
+    // find the DAC segment, table 0 AC conditioning is its last byte
+    let pos = data
+        .windows(2)
+        .position(|x| x == [0xFF, 0xCC])
+        .unwrap();
+    let length = usize::from(u16::from_be_bytes([data[pos + 2], data[pos + 3]]));
+
+    // AC conditioning must be between 1 and 63
+    data[pos + 1 + length] = 64;
+
+    let err = Decoder::new().decode_buffer(&data).unwrap_err();
+
+    assert!(matches!(err, DecodeErrors::Format(_)), "{:?}", err);
+}
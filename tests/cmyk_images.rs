@@ -0,0 +1,147 @@
+//! Tests for four component CMYK and YCCK images
+//!
+//! The images encode a known amount of ink for every component, Adobe
+//! images store it inverted.
+use zune_jpeg::errors::DecodeErrors;
+use zune_jpeg::{ColorSpace, Decoder, ZuneJpegOptions};
+
+use crate::common::read;
+
+mod common;
+
+const WIDTH: usize = 48;
+const HEIGHT: usize = 32;
+
+/// The amount of ink encoded at `(x,y)` of component `c`
+#[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+fn ink(x: usize, y: usize, c: usize) -> i32
+{
+    let x = x as f64;
+    let y = y as f64;
+    let c = c as f64;
+
+    (128.0 + 100.0 * (x / 9.0 + c).sin() * (y / 7.0 + c / 2.0).cos()) as i32
+}
+
+fn decode(name: &str, colorspace: ColorSpace) -> Vec<u8>
+{
+    let mut decoder = Decoder::new_with_options(ZuneJpegOptions::new().set_out_colorspace(colorspace));
+    let pixels = decoder.decode_buffer(&read(name)).unwrap();
+
+    assert_eq!(usize::from(decoder.width()), WIDTH);
+    assert_eq!(usize::from(decoder.height()), HEIGHT);
+    assert_eq!(decoder.info().unwrap().components, 4);
+    assert_eq!(pixels.len(), WIDTH * HEIGHT * colorspace.num_components());
+
+    pixels
+}
+
+/// Check the CMYK output of `name` against the encoded ink, skipping `border` columns
+/// on the left and right
+fn assert_ink(name: &str, tolerance: i32, border: usize)
+{
+    let pixels = decode(name, ColorSpace::CMYK);
+
+    for (i, pixel) in pixels.chunks_exact(4).enumerate()
+    {
+        let (x, y) = (i % WIDTH, i / WIDTH);
+
+        if x < border || x >= WIDTH - border
+        {
+            continue;
+        }
+        for (c, value) in pixel.iter().enumerate()
+        {
+            let expected = ink(x, y, c);
+
+            assert!(
+                (i32::from(*value) - expected).abs() <= tolerance,
+                "{}: component {} at ({},{}) is {}, expected {}",
+                name,
+                c,
+                x,
+                y,
+                value,
+                expected
+            );
+        }
+    }
+}
+
+#[test]
+fn cmyk_adobe()
+{
+    assert_ink("cmyk_adobe.jpg", 3, 0);
+}
+
+#[test]
+fn cmyk_adobe_progressive()
+{
+    assert_ink("cmyk_adobe_progressive.jpg", 3, 0);
+}
+
+#[test]
+fn cmyk_without_adobe_marker()
+{
+    // samples are not inverted without the Adobe marker
+    assert_ink("cmyk_no_adobe.jpg", 3, 0);
+}
+
+#[test]
+fn ycck_progressive_subsampled()
+{
+    // the first component has twice the horizontal resolution, edges are
+    // less accurate after up-sampling
+    assert_ink("ycck_adobe_progressive_422.jpg", 8, 1);
+}
+
+#[test]
+fn ycck_output_is_stored_samples()
+{
+    let ycck = decode("ycck_adobe_progressive_422.jpg", ColorSpace::YCCK);
+    let cmyk = decode("ycck_adobe_progressive_422.jpg", ColorSpace::CMYK);
+
+    // K is stored inverted
+    for (ycck, cmyk) in ycck.chunks_exact(4).zip(cmyk.chunks_exact(4))
+    {
+        assert_eq!(ycck[3], 255 - cmyk[3]);
+    }
+}
+
+#[test]
+fn cmyk_to_rgb()
+{
+    for name in ["cmyk_adobe.jpg", "ycck_adobe_progressive_422.jpg"]
+    {
+        let cmyk = decode(name, ColorSpace::CMYK);
+        let rgb = decode(name, ColorSpace::RGB);
+        let rgba = decode(name, ColorSpace::RGBA);
+
+        for ((cmyk, rgb), rgba) in cmyk
+            .chunks_exact(4)
+            .zip(rgb.chunks_exact(3))
+            .zip(rgba.chunks_exact(4))
+        {
+            let white = 255 - u32::from(cmyk[3]);
+
+            for c in 0..3
+            {
+                let expected = ((255 - u32::from(cmyk[c])) * white + 127) / 255;
+
+                assert_eq!(u32::from(rgb[c]), expected);
+            }
+            assert_eq!(&rgba[..3], rgb);
+            assert_eq!(rgba[3], 255);
+        }
+    }
+}
+
+#[test]
+fn cmyk_to_ycbcr_unsupported()
+{
+    let err = Decoder::new_with_options(ZuneJpegOptions::new().set_out_colorspace(ColorSpace::YCbCr))
+        .decode_buffer(&read("cmyk_adobe.jpg"))
+        .unwrap_err();
+
+    assert!(matches!(err, DecodeErrors::Format(_)), "{:?}", err);
+}
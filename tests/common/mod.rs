@@ -0,0 +1,34 @@
+//! Helpers shared by the integration tests
+//!
+//! Every test file compiles this module on its own and uses a part of it.
+#![allow(dead_code)]
+
+use zune_jpeg::Decoder;
+
+/// Read an image from `tests/inputs`
+pub fn read(name: &str) -> Vec<u8>
+{
+    let path = env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/" + name;
+
+    std::fs::read(path).unwrap()
+}
+
+/// `sampling_420.jpg` with `segments` inserted after the start of image marker
+pub fn with_segments(segments: &[Vec<u8>]) -> Vec<u8>
+{
+    let mut data = read("sampling_420.jpg");
+
+    data.splice(2..2, segments.concat());
+
+    data
+}
+
+/// A decoder which has read the headers of `data`
+pub fn headers(data: &[u8]) -> Decoder
+{
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(data).unwrap();
+
+    decoder
+}
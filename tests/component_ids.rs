@@ -0,0 +1,132 @@
+//! Tests for images with component identifiers other than 1,2,3
+//!
+//! Component identifiers only link scans to components, changing them in
+//! the frame and scan headers must decode to the same pixels.
+use zune_jpeg::errors::DecodeErrors;
+use zune_jpeg::{ColorSpace, Decoder, ZuneJpegOptions};
+
+use crate::common::read;
+
+mod common;
+
+/// Replace component identifiers `1..` in frame and scan headers with `ids`
+fn renumber(data: &[u8], ids: &[u8]) -> Vec<u8>
+{
+    let mut data = data.to_vec();
+    let mut pos = 2;
+
+    while pos + 4 < data.len()
+    {
+        assert_eq!(data[pos], 0xFF);
+
+        let marker = data[pos + 1];
+        let length = usize::from(u16::from_be_bytes([data[pos + 2], data[pos + 3]]));
+        let segment = pos + 4;
+
+        match marker
+        {
+            // start of frame
+            0xC0..=0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF =>
+            {
+                for c in 0..usize::from(data[segment + 5])
+                {
+                    let id = &mut data[segment + 6 + 3 * c];
+
+                    *id = ids[usize::from(*id) - 1];
+                }
+            }
+            // start of scan
+            0xDA =>
+            {
+                for c in 0..usize::from(data[segment])
+                {
+                    let id = &mut data[segment + 1 + 2 * c];
+
+                    *id = ids[usize::from(*id) - 1];
+                }
+            }
+            0xD9 => break,
+            _ => (),
+        }
+        pos += 2 + length;
+
+        if marker == 0xDA
+        {
+            // skip entropy coded data, up to a marker other than a restart marker
+            while data[pos] != 0xFF || data[pos + 1] == 0 || (0xD0..=0xD7).contains(&data[pos + 1])
+            {
+                pos += 1;
+            }
+        }
+    }
+    data
+}
+
+fn decode(data: &[u8], options: ZuneJpegOptions) -> Result<Vec<u16>, DecodeErrors>
+{
+    Decoder::new_with_options(options).decode_buffer_u16(data)
+}
+
+fn assert_same_pixels(name: &str, ids: &[u8], options: ZuneJpegOptions)
+{
+    let data = read(name);
+
+    let expected = decode(&data, options).unwrap();
+    let pixels = decode(&renumber(&data, ids), options).unwrap();
+
+    assert!(pixels == expected, "Images differ for {} with ids {:?}", name, ids);
+}
+
+#[test]
+fn zero_based_ids()
+{
+    assert_same_pixels("arithmetic_420_huffman.jpg", &[0, 1, 2], ZuneJpegOptions::new());
+}
+
+#[test]
+fn zero_based_ids_progressive()
+{
+    assert_same_pixels("progressive_12_bit_rgb.jpg", &[0, 1, 2], ZuneJpegOptions::new());
+}
+
+#[test]
+fn rgb_ids_in_jfif_image()
+{
+    // a JFIF segment means the image is YCbCr whatever the identifiers
+    assert_same_pixels("arithmetic_422_huffman.jpg", b"RGB", ZuneJpegOptions::new());
+}
+
+#[test]
+fn shared_ids()
+{
+    // scans refer to components sharing an identifier in frame header order
+    assert_same_pixels("arithmetic_420_huffman.jpg", &[7, 7, 7], ZuneJpegOptions::new());
+}
+
+#[test]
+fn cmyk_ids()
+{
+    assert_same_pixels(
+        "cmyk_adobe.jpg",
+        b"CMYK",
+        ZuneJpegOptions::new().set_out_colorspace(ColorSpace::CMYK),
+    );
+}
+
+#[test]
+fn unknown_scan_component()
+{
+    let data = read("arithmetic_420_huffman.jpg");
+    // scans still refer to component 3
+    let mut changed = renumber(&data, &[1, 2, 9]);
+    let sos = changed
+        .windows(2)
+        .position(|x| x == [0xFF, 0xDA])
+        .unwrap();
+
+    changed[sos + 5 + 2 * 2] = 3;
+
+    let err = decode(&changed, ZuneJpegOptions::new()).unwrap_err();
+
+    assert!(matches!(err, DecodeErrors::SofError(_)), "{:?}", err);
+}
@@ -0,0 +1,144 @@
+//! Tests for images whose height is defined by a DNL segment after the first scan
+//!
+//! The images are made from other test images by setting the height in the frame
+//! header to zero and adding a DNL segment after the first scan.
+use zune_jpeg::errors::DecodeErrors;
+use zune_jpeg::Decoder;
+
+use crate::common::read;
+
+mod common;
+
+/// Position of the frame header marker
+fn find_sof(data: &[u8]) -> usize
+{
+    data.windows(2)
+        .position(|w| w[0] == 0xFF && matches!(w[1], 0xC0..=0xC3 | 0xC9..=0xCB))
+        .unwrap()
+}
+
+/// Position of the first marker after the entropy coded data of the first scan
+fn end_of_first_scan(data: &[u8]) -> usize
+{
+    let sos = data.windows(2).position(|w| w == [0xFF, 0xDA]).unwrap();
+    let length = usize::from(u16::from_be_bytes([data[sos + 2], data[sos + 3]]));
+    let mut position = sos + 2 + length;
+
+    // skip stuffed bytes and restart markers
+    while data[position] != 0xFF || matches!(data[position + 1], 0x00 | 0xD0..=0xD7)
+    {
+        position += 1;
+    }
+    position
+}
+
+/// Move the height of `name` from the frame header to a DNL segment
+fn with_dnl(name: &str) -> Vec<u8>
+{
+    let mut data = read(name);
+    let sof = find_sof(&data);
+    let height = [data[sof + 5], data[sof + 6]];
+
+    data[sof + 5] = 0;
+    data[sof + 6] = 0;
+
+    let end = end_of_first_scan(&data);
+
+    data.splice(end..end, [0xFF, 0xDC, 0x00, 0x04, height[0], height[1]]);
+
+    data
+}
+
+fn assert_same_as_original(name: &str)
+{
+    let mut decoder = Decoder::new();
+    let expected = decoder.decode_buffer(&read(name)).unwrap();
+    let height = decoder.height();
+
+    let mut decoder = Decoder::new();
+    let pixels = decoder.decode_buffer(&with_dnl(name)).unwrap();
+
+    assert_eq!(decoder.height(), height, "{}", name);
+    assert!(pixels == expected, "{}: pixels differ", name);
+}
+
+#[test]
+fn dnl_baseline()
+{
+    assert_same_as_original("sampling_420.jpg");
+    assert_same_as_original("cmyk_adobe.jpg");
+}
+
+#[test]
+fn dnl_with_restart_markers()
+{
+    assert_same_as_original("arithmetic_restart_huffman.jpg");
+    assert_same_as_original("arithmetic_restart.jpg");
+}
+
+#[test]
+fn dnl_progressive()
+{
+    assert_same_as_original("sampling_411_progressive.jpg");
+    assert_same_as_original("arithmetic_progressive_restart.jpg");
+}
+
+#[test]
+fn dnl_lossless()
+{
+    assert_same_as_original("lossless_predictor_1.jpg");
+    assert_same_as_original("lossless_arithmetic_scans.jpg");
+}
+
+#[test]
+fn dnl_after_segment_holding_its_bytes()
+{
+    let name = "sampling_420.jpg";
+    let mut data = with_dnl(name);
+    let end = end_of_first_scan(&data);
+
+    // a comment whose text looks like a DNL segment defining a single line
+    data.splice(end..end, [0xFF, 0xFE, 0x00, 0x08, 0xFF, 0xDC, 0x00, 0x04, 0x00, 0x01]);
+
+    let mut decoder = Decoder::new();
+    let pixels = decoder.decode_buffer(&data).unwrap();
+
+    assert_eq!(decoder.height(), 53);
+    assert!(pixels == Decoder::new().decode_buffer(&read(name)).unwrap());
+}
+
+#[test]
+fn dnl_read_headers()
+{
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&with_dnl("sampling_420.jpg")).unwrap();
+
+    assert_eq!(decoder.height(), 53);
+}
+
+#[test]
+fn zero_height_without_dnl()
+{
+    let mut data = read("sampling_420.jpg");
+    let sof = find_sof(&data);
+
+    data[sof + 5] = 0;
+    data[sof + 6] = 0;
+
+    assert!(Decoder::new().decode_buffer(&data).is_err());
+}
+
+#[test]
+fn zero_width()
+{
+    let mut data = read("sampling_420.jpg");
+    let sof = find_sof(&data);
+
+    data[sof + 7] = 0;
+    data[sof + 8] = 0;
+
+    let err = Decoder::new().decode_buffer(&data).unwrap_err();
+
+    assert!(matches!(err, DecodeErrors::ZeroError), "{:?}", err);
+}
@@ -0,0 +1,168 @@
+//! Tests for the Exif metadata read from APP1 segments
+use zune_jpeg::exif::{ExifValue, GpsPosition, Ifd};
+use zune_jpeg::Decoder;
+
+use crate::common::{headers, read};
+
+mod common;
+
+/// Offset of the TIFF header in the Exif segment of `data`
+fn tiff_start(data: &[u8]) -> usize
+{
+    data.windows(6).position(|x| x == b"Exif\0\0").unwrap() + 6
+}
+
+fn assert_close(a: f64, b: f64)
+{
+    assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+}
+
+#[test]
+fn little_endian()
+{
+    let decoder = headers(&read("exif_little_endian.jpg"));
+    let exif = decoder.exif().unwrap();
+
+    assert!(!exif.is_big_endian());
+    assert_eq!(exif.orientation(), Some(6));
+    assert_eq!(exif.make(), Some("Canon"));
+    assert_eq!(exif.model(), Some("EOS 5D"));
+    assert_eq!(exif.capture_time(), Some("2023:07:14 09:30:00"));
+
+    let GpsPosition {
+        latitude,
+        longitude,
+        altitude,
+    } = exif.gps().unwrap();
+
+    assert_close(latitude, 48.0 + 51.0 / 60.0 + 24.0 / 3600.0);
+    assert_close(longitude, 2.0 + 21.0 / 60.0 + 7.2 / 3600.0);
+    assert_close(altitude.unwrap(), 17.5);
+}
+
+#[test]
+fn big_endian()
+{
+    let decoder = headers(&read("exif_big_endian.jpg"));
+    let exif = decoder.exif().unwrap();
+
+    assert!(exif.is_big_endian());
+    assert_eq!(exif.orientation(), Some(6));
+    assert_eq!(exif.make(), Some("Canon"));
+    assert_eq!(exif.model(), Some("EOS 5D"));
+    assert_eq!(exif.capture_time(), Some("2023:07:14 09:30:00"));
+
+    // the big endian file is south west and below sea level
+    let position = exif.gps().unwrap();
+
+    assert_close(position.latitude, -(48.0 + 51.0 / 60.0 + 24.0 / 3600.0));
+    assert_close(position.longitude, -(2.0 + 21.0 / 60.0 + 7.2 / 3600.0));
+    assert_close(position.altitude.unwrap(), -17.5);
+}
+
+#[test]
+fn tags_of_every_ifd()
+{
+    let decoder = headers(&read("exif_big_endian.jpg"));
+    let exif = decoder.exif().unwrap();
+
+    assert_eq!(
+        exif.get(Ifd::Primary, 0x0132),
+        Some(&ExifValue::Ascii("2024:01:02 03:04:05".to_string()))
+    );
+    assert_eq!(exif.get(Ifd::Exif, 0x829A), Some(&ExifValue::Rational(vec![(1, 250)])));
+    assert_eq!(exif.get(Ifd::Exif, 0x8827), Some(&ExifValue::Short(vec![400])));
+    assert_eq!(exif.get(Ifd::Gps, 0x0005), Some(&ExifValue::Byte(vec![1])));
+    // ISO speed is only in the Exif IFD
+    assert_eq!(exif.get(Ifd::Primary, 0x8827), None);
+    assert_eq!(exif.entries().len(), 6 + 3 + 6);
+}
+
+#[test]
+fn raw_tiff_data()
+{
+    for name in ["exif_little_endian.jpg", "exif_big_endian.jpg"]
+    {
+        let data = read(name);
+        let start = tiff_start(&data);
+        let length = usize::from(u16::from_be_bytes([data[start - 8], data[start - 7]]));
+        let decoder = headers(&data);
+
+        assert_eq!(decoder.exif().unwrap().raw(), &data[start..start + length - 8]);
+    }
+}
+
+#[test]
+fn capture_time_falls_back_to_date_time()
+{
+    let mut data = read("exif_little_endian.jpg");
+    let start = tiff_start(&data);
+    // rename the DateTimeOriginal tag of the Exif IFD
+    let tag = data.windows(2).rposition(|x| x == [0x03, 0x90]).unwrap();
+
+    assert!(tag > start);
+    data[tag] = 0x04;
+
+    let decoder = headers(&data);
+
+    assert_eq!(decoder.exif().unwrap().capture_time(), Some("2024:01:02 03:04:05"));
+}
+
+#[test]
+fn no_exif()
+{
+    let decoder = headers(&read("sampling_420.jpg"));
+
+    assert!(decoder.exif().is_none());
+}
+
+#[test]
+fn ifd_out_of_bounds()
+{
+    let mut data = read("exif_big_endian.jpg");
+    let start = tiff_start(&data);
+
+    data[start + 4..start + 8].copy_from_slice(&0x00FF_FFFF_u32.to_be_bytes());
+
+    let mut decoder = Decoder::new();
+
+    // the image still decodes, without any tags
+    decoder.decode_buffer(&data).unwrap();
+
+    let exif = decoder.exif().unwrap();
+
+    assert!(exif.entries().is_empty());
+    assert_eq!(exif.orientation(), None);
+}
+
+#[test]
+fn ifd_pointing_back_to_itself()
+{
+    let mut data = read("exif_little_endian.jpg");
+    let start = tiff_start(&data);
+    // the Exif IFD pointer is the fifth entry of IFD0 at offset 8
+    let value = start + 8 + 2 + 12 * 4 + 8;
+
+    assert_eq!(data[value - 8..value - 6], [0x69, 0x87]);
+    data[value..value + 4].copy_from_slice(&8_u32.to_le_bytes());
+
+    let decoder = headers(&data);
+    let exif = decoder.exif().unwrap();
+
+    assert_eq!(exif.make(), Some("Canon"));
+    assert_eq!(exif.get(Ifd::Exif, 0x8827), None);
+    assert!(exif.gps().is_some());
+}
+
+#[test]
+fn not_a_tiff_header()
+{
+    let mut data = read("exif_little_endian.jpg");
+    let start = tiff_start(&data);
+
+    data[start] = b'X';
+
+    let decoder = headers(&data);
+
+    assert!(decoder.exif().is_none());
+}
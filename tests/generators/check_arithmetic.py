@@ -0,0 +1,286 @@
+#!/usr/bin/env python3
+"""Check the arithmetic encoder of lossless.py against libjpeg
+
+    python3 tests/generators/check_arithmetic.py tests/inputs
+
+The arithmetic_*.jpg fixtures were converted from their Huffman coded twins by
+libjpeg's `jpegtran -arithmetic`. This reads the coefficients of every Huffman
+coded twin, codes them again with the arithmetic encoder of lossless.py and the
+DCT statistical model of T.81 section F.1.4, and checks that the result is byte
+for byte what libjpeg wrote.
+
+This covers the coding engine (Annex D), the probability estimates and the
+coding of signs and magnitudes, which the lossless model of section H.1.4.3
+shares with the DC model. Only the choice of the lossless contexts from the two
+neighbouring differences is left unchecked.
+"""
+import struct
+import sys
+
+from lossless import ArithEncoder
+
+ZIGZAG_LEN = 64
+
+
+def segments(data):
+    """Yield (marker, payload, position after the payload) for the segments before EOI"""
+    i = 2
+    while True:
+        marker = data[i + 1]
+        if marker == 0xD9:
+            return
+        length = struct.unpack('>H', data[i + 2:i + 4])[0]
+        yield marker, data[i + 4:i + 2 + length], i + 2 + length
+        if marker == 0xDA:
+            return
+        i += 2 + length
+
+
+def frame_info(data):
+    """Components as (id, h, v), the scan components as (index, dc table, ac table), the
+    restart interval, the DAC segment payloads and where the entropy coded data starts"""
+    comps = []; scan = []; restart = 0; dac = []; start = 0
+    for marker, seg, end in segments(data):
+        if marker in (0xC0, 0xC1, 0xC9):
+            for k in range(seg[5]):
+                cid, hv = seg[6 + 3 * k], seg[7 + 3 * k]
+                comps.append((cid, hv >> 4, hv & 15))
+        elif marker == 0xDD:
+            restart = struct.unpack('>H', seg[:2])[0]
+        elif marker == 0xCC:
+            dac.append(seg)
+        elif marker == 0xDA:
+            for k in range(seg[0]):
+                cid, tables = seg[1 + 2 * k], seg[2 + 2 * k]
+                index = [c[0] for c in comps].index(cid)
+                scan.append((index, tables >> 4, tables & 15))
+            start = end
+    return comps, scan, restart, dac, start
+
+
+def huffman_tables(data):
+    tables = {}
+    for marker, seg, _ in segments(data):
+        if marker != 0xC4:
+            continue
+        j = 0
+        while j < len(seg):
+            tc_th = seg[j]; counts = seg[j + 1:j + 17]; n = sum(counts)
+            values = seg[j + 17:j + 17 + n]; j += 17 + n
+            code = 0; k = 0; table = {}
+            for length in range(1, 17):
+                for _ in range(counts[length - 1]):
+                    table[(length, code)] = values[k]; k += 1; code += 1
+                code <<= 1
+            tables[tc_th] = table
+    return tables
+
+
+def entropy_coded_intervals(data, start):
+    """Split the entropy coded data at restart markers, removing stuffed zero bytes"""
+    intervals = [bytearray()]
+    p = start
+    while True:
+        if data[p] == 0xFF:
+            if data[p + 1] == 0:
+                intervals[-1].append(0xFF); p += 2; continue
+            if 0xD0 <= data[p + 1] <= 0xD7:
+                intervals.append(bytearray()); p += 2; continue
+            return intervals
+        intervals[-1].append(data[p]); p += 1
+
+
+def mcu_layout(comps, scan, width, height):
+    hmax = max(c[1] for c in comps); vmax = max(c[2] for c in comps)
+    if len(scan) == 1:
+        _, h, v = comps[scan[0][0]]
+        w = -(-width * h // hmax); hh = -(-height * v // vmax)
+        return -(-w // 8), -(-hh // 8), [1]
+    blocks = [comps[index][1] * comps[index][2] for index, _, _ in scan]
+    return -(-width // (8 * hmax)), -(-height // (8 * vmax)), blocks
+
+
+def dimensions(data):
+    for marker, seg, _ in segments(data):
+        if marker in (0xC0, 0xC1, 0xC9):
+            return struct.unpack('>H', seg[3:5])[0], struct.unpack('>H', seg[1:3])[0]
+
+
+def read_coefficients(data):
+    """Blocks of every restart interval as lists of (scan component, coefficients in zigzag order)"""
+    comps, scan, restart, _, start = frame_info(data)
+    tables = huffman_tables(data)
+    width, height = dimensions(data)
+    mcus_x, mcus_y, blocks = mcu_layout(comps, scan, width, height)
+    total = mcus_x * mcus_y
+    intervals = []
+
+    for raw in entropy_coded_intervals(data, start):
+        bits = ''.join(format(b, '08b') for b in raw)
+        pos = 0
+
+        def huffman(table):
+            nonlocal pos
+            code = 0
+            for length in range(1, 17):
+                code = (code << 1) | int(bits[pos]); pos += 1
+                if (length, code) in table:
+                    return table[(length, code)]
+            raise ValueError('bad Huffman code')
+
+        def receive(s):
+            nonlocal pos
+            if s == 0:
+                return 0
+            v = int(bits[pos:pos + s], 2); pos += s
+            return v - (1 << s) + 1 if v < (1 << (s - 1)) else v
+
+        prediction = [0] * len(scan)
+        interval = []
+        mcus = restart if restart else total
+        done = sum(len(x) for x in intervals)
+        for _ in range(min(mcus, total - done)):
+            mcu = []
+            for k, (_, dc, ac) in enumerate(scan):
+                for _ in range(blocks[k]):
+                    block = [0] * ZIGZAG_LEN
+                    prediction[k] += receive(huffman(tables[dc]))
+                    block[0] = prediction[k]
+                    i = 1
+                    while i < ZIGZAG_LEN:
+                        rs = huffman(tables[16 | ac]); r, s = rs >> 4, rs & 15
+                        if s == 0:
+                            if r != 15:
+                                break
+                            i += 16; continue
+                        i += r; block[i] = receive(s); i += 1
+                    mcu.append((k, block))
+            interval.append(mcu)
+        intervals.append(interval)
+    return intervals
+
+
+def encode_dc(enc, stats, context, v, lower, upper):
+    """Code the DC difference `v` in the conditioning category `context` like figures F.4,
+    F.8 and F.9, returning the category of the next difference"""
+    st = context
+    if v == 0:
+        enc.encode(stats, st, 0)
+        return 0
+    enc.encode(stats, st, 1)
+    if v > 0:
+        enc.encode(stats, st + 1, 0); st += 2; context = 4
+    else:
+        v = -v; enc.encode(stats, st + 1, 1); st += 3; context = 8
+    m = 0
+    v -= 1
+    if v:
+        enc.encode(stats, st, 1)
+        m = 1; v2 = v >> 1; st = 20
+        while v2:
+            enc.encode(stats, st, 1); m <<= 1; st += 1; v2 >>= 1
+    enc.encode(stats, st, 0)
+    if m < (1 << lower) >> 1:
+        context = 0
+    elif m > (1 << upper) >> 1:
+        context += 8
+    st += 14
+    m >>= 1
+    while m:
+        enc.encode(stats, st, 1 if m & v else 0); m >>= 1
+    return context
+
+
+def encode_ac(enc, stats, fixed, block, kx, start=1, end=ZIGZAG_LEN - 1):
+    """Code the coefficients `start` to `end` of a block like figures F.5 to F.9"""
+    last = max([i for i in range(start, end + 1) if block[i]], default=0)
+    i = start
+    while i <= last:
+        st = 3 * (i - 1)
+        enc.encode(stats, st, 0)
+        while block[i] == 0:
+            enc.encode(stats, st + 1, 0); st += 3; i += 1
+        enc.encode(stats, st + 1, 1)
+        v = block[i]
+        if v > 0:
+            enc.encode(fixed, 0, 0)
+        else:
+            v = -v; enc.encode(fixed, 0, 1)
+        st += 2
+        m = 0
+        v -= 1
+        if v:
+            enc.encode(stats, st, 1)
+            m = 1; v2 = v >> 1
+            if v2:
+                enc.encode(stats, st, 1)
+                m <<= 1
+                st = 189 if i <= kx else 217
+                v2 >>= 1
+                while v2:
+                    enc.encode(stats, st, 1); m <<= 1; st += 1; v2 >>= 1
+        enc.encode(stats, st, 0)
+        st += 14
+        m >>= 1
+        while m:
+            enc.encode(stats, st, 1 if m & v else 0); m >>= 1
+        i += 1
+    # the end of block decision, only when the last coefficient of the band is zero
+    if i <= end:
+        enc.encode(stats, 3 * (i - 1), 1)
+
+
+def encode_dct(intervals, scan, conditioning):
+    """Arithmetic code the blocks like section F.1.4, as libjpeg's jcarith.c does"""
+    out = bytearray()
+    for n, interval in enumerate(intervals):
+        if n:
+            out += bytes([0xFF, 0xD0 + (n - 1) % 8])
+        enc = ArithEncoder()
+        dc_stats = [[0] * 64 for _ in range(4)]
+        ac_stats = [[0] * 256 for _ in range(4)]
+        fixed = [113]
+        last_dc = [0] * len(scan)
+        dc_context = [0] * len(scan)
+        for mcu in interval:
+            for k, block in mcu:
+                _, dc_table, ac_table = scan[k]
+                lower, upper = conditioning[0][dc_table]
+                dc_context[k] = encode_dc(enc, dc_stats[dc_table], dc_context[k], block[0] - last_dc[k],
+                                          lower, upper)
+                last_dc[k] = block[0]
+                encode_ac(enc, ac_stats[ac_table], fixed, block, conditioning[1][ac_table])
+        out += enc.finish()
+    return bytes(out)
+
+
+def conditioning_tables(dac):
+    """DC (L, U) and AC Kx of every table, from the DAC segments or the defaults"""
+    dc = {t: (0, 1) for t in range(4)}
+    ac = {t: 5 for t in range(4)}
+    for seg in dac:
+        for j in range(0, len(seg), 2):
+            tc, tb, value = seg[j] >> 4, seg[j] & 15, seg[j + 1]
+            if tc == 0:
+                dc[tb] = (value & 15, value >> 4)
+            else:
+                ac[tb] = value
+    return dc, ac
+
+
+def check(directory, name):
+    huffman = open(f'{directory}/{name}_huffman.jpg', 'rb').read()
+    arithmetic = open(f'{directory}/{name}.jpg', 'rb').read()
+    _, scan, _, dac, start = frame_info(arithmetic)
+    end = arithmetic.rindex(b'\xff\xd9')
+    expected = arithmetic[start:end]
+    coded = encode_dct(read_coefficients(huffman), scan, conditioning_tables(dac))
+    assert coded == expected, f'{name}: {len(coded)} bytes differ from the {len(expected)} libjpeg wrote'
+    print(f'{name}: {len(coded)} bytes match libjpeg')
+
+
+if __name__ == '__main__':
+    names = ['arithmetic_grayscale', 'arithmetic_420', 'arithmetic_422', 'arithmetic_12_bit',
+             'arithmetic_restart']
+    for name in names:
+        check(sys.argv[1], name)
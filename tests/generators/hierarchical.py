@@ -0,0 +1,531 @@
+#!/usr/bin/env python3
+"""Hierarchical JPEG encoder (T.81 Annex J) writing the hierarchical_*.jpg fixtures
+
+    python3 tests/generators/hierarchical.py tests/inputs
+
+Every image codes `pattern()` of lossless.py. The frames and the references they are
+coded against are computed here, independently of the decoder. Only the IDCT of the
+DCT based frames is a port of the decoder's, see README.md.
+"""
+import math
+import struct
+import sys
+
+from check_arithmetic import conditioning_tables, encode_dc, encode_ac, encode_dct
+from lossless import (pattern, gen_optimal_table, codes_from, BitWriter, category, predict,
+                      ArithEncoder, arith_encode_diff)
+
+
+def wrap(d):
+    d &= 0xFFFF
+    return d - 0x10000 if d >= 0x8000 else d
+
+
+def expand(plane, eh, ev):
+    if eh:
+        w = len(plane[0])
+        plane = [[v for x in range(w) for v in (row[x], (row[x] + row[min(x + 1, w - 1)]) >> 1)] for row in plane]
+    if ev:
+        h = len(plane)
+        out = []
+        for y in range(h):
+            out.append(list(plane[y]))
+            out.append([(a + b) >> 1 for a, b in zip(plane[y], plane[min(y + 1, h - 1)])])
+        plane = out
+    return plane
+
+
+def frame(out, marker, width, height, precision, comps, scans, values, arith, differential):
+    """values[i][y][x] is the sample (or difference for differential frames) of frame component i.
+    Returns the reconstruction of every component (pt undone)."""
+    hmax = max(c[1] for c in comps); vmax = max(c[2] for c in comps)
+    mx = -(-width // hmax); my = -(-height // vmax)
+    out += bytes([0xFF, marker]) + struct.pack('>HBHHB', 8 + 3 * len(comps), precision, height, width, len(comps))
+    for (cid, h, v) in comps:
+        out += bytes([cid, (h << 4) | v, 0])
+    dims = []
+    for ci, (cid, h, v) in enumerate(comps):
+        dims.append((-(-width * h // hmax), -(-height * v // vmax)))
+    recon = [None] * len(comps)
+    for scan in scans:
+        idx = scan['comps']; pred = scan['pred']; pt = scan['pt']; ri = scan.get('restart', 0)
+        init = 1 << (precision - pt - 1)
+        # coded planes padded to whole MCUs by replication
+        coded = []
+        for i in idx:
+            cw, ch = dims[i]
+            pw = mx * comps[i][1] if len(idx) > 1 else cw
+            ph = my * comps[i][2] if len(idx) > 1 else ch
+            coded.append([[values[i][min(y, ch - 1)][min(x, cw - 1)] >> pt for x in range(pw)] for y in range(ph)])
+        for k, i in enumerate(idx):
+            cw, ch = dims[i]
+            recon[i] = [[wrap(coded[k][y][x] << pt) & 0xFFFF if differential else (coded[k][y][x] << pt)
+                         for x in range(cw)] for y in range(ch)]
+        if len(idx) == 1:
+            samp = [(1, 1)]; smx, smy = dims[idx[0]]
+        else:
+            samp = [(comps[i][1], comps[i][2]) for i in idx]; smx, smy = mx, my
+        first_rows = {0}
+        if ri:
+            assert ri % smx == 0
+            for k in range(0, smx * smy, ri):
+                first_rows.add(k // smx)
+        symbols = []
+        mcu = 0; rst = 0
+        diffs = [[[0] * (smx * h) for _ in range(smy * v)] for (h, v) in samp]
+        for yy in range(smy):
+            for xx in range(smx):
+                if ri and mcu and mcu % ri == 0:
+                    symbols.append(('RST', rst % 8)); rst += 1
+                for k, (h, v) in enumerate(samp):
+                    pl = coded[k]
+                    for j in range(v):
+                        for i in range(h):
+                            x = xx * h + i; y = yy * v + j
+                            fr = (yy in first_rows) and j == 0
+                            p = 0 if pred == 0 else predict(pl, x, y, pred, fr, init)
+                            d = wrap(pl[y][x] - p)
+                            diffs[k][y][x] = d
+                            da = diffs[k][y][x - 1] if x > 0 else 0
+                            db = 0 if fr else diffs[k][y - 1][x]
+                            symbols.append((k, d, da, db))
+                mcu += 1
+        if ri or 'dri' in scan:
+            out += b'\xff\xdd' + struct.pack('>HH', 4, ri)
+        tables = []
+        if not arith:
+            for k in range(len(idx)):
+                freq = [0] * 17
+                for s in symbols:
+                    if s[0] == k:
+                        freq[category(s[1]) if s[1] != -32768 else 16] += 1
+                bits, vals = gen_optimal_table(freq)
+                tables.append(codes_from(bits, vals))
+                out += b'\xff\xc4' + struct.pack('>HB', 2 + 1 + 16 + len(vals), k) + bytes(bits) + bytes(vals)
+        out += b'\xff\xda' + struct.pack('>HB', 6 + 2 * len(idx), len(idx))
+        for k, i in enumerate(idx):
+            out += bytes([comps[i][0], k << 4])
+        out += bytes([pred, 0, pt])
+        if arith:
+            enc = ArithEncoder(); stats = [[0] * 158 for _ in range(4)]
+            for s in symbols:
+                if s[0] == 'RST':
+                    out += enc.finish(); out += bytes([0xFF, 0xD0 + s[1]])
+                    enc = ArithEncoder(); stats = [[0] * 158 for _ in range(4)]
+                    continue
+                k, d, da, db = s
+                arith_encode_diff(enc, stats[k], d, da, db, 0, 1)
+            out += enc.finish()
+        else:
+            bw = BitWriter()
+            for s in symbols:
+                if s[0] == 'RST':
+                    bw.flush(); out += bw.out; bw.out = bytearray()
+                    out += bytes([0xFF, 0xD0 + s[1]])
+                    continue
+                k, d = s[0], s[1]
+                codes = tables[k]
+                if d == -32768:
+                    code, l = codes[16]; bw.put(code, l); continue
+                c = category(d)
+                code, l = codes[c]; bw.put(code, l)
+                if c:
+                    bw.put(d if d > 0 else d - 1 + (1 << c), c)
+            bw.flush(); out += bw.out
+    return recon
+
+
+def full(c, w, h, p, sx=1, sy=1):
+    return [[pattern(min(x * sx, W - 1), min(y * sy, H - 1), c, p) for x in range(w)] for y in range(h)]
+
+
+def diff_values(target, reference):
+    return [[wrap(t - r) for t, r in zip(trow, rrow)] for trow, rrow in zip(target, reference)]
+
+
+def crop(plane, w, h):
+    return [row[:w] for row in plane[:h]]
+
+
+def dhp(out, width, height, precision, comps):
+    out += b'\xff\xde' + struct.pack('>HBHHB', 8 + 3 * len(comps), precision, height, width, len(comps))
+    for (cid, h, v) in comps:
+        out += bytes([cid, (h << 4) | v, 0])
+
+
+def exp(out, eh, ev):
+    out += b'\xff\xdf' + struct.pack('>HB', 3, (eh << 4) | ev)
+
+
+def add(reference, diff):
+    return [[(r + d) & 0xFFFF for r, d in zip(rrow, drow)] for rrow, drow in zip(reference, diff)]
+
+
+# ---------------------------------------------------------------- DCT based frames
+
+ZIGZAG = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5,
+    12, 19, 26, 33, 40, 48, 41, 34, 27, 20, 13, 6, 7, 14, 21, 28,
+    35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51,
+    58, 59, 52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+]
+
+# Table K.1 of T.81, in natural order
+LUMA = [
+    16, 11, 10, 16, 24, 40, 51, 61, 12, 12, 14, 19, 26, 58, 60, 55,
+    14, 13, 16, 24, 40, 57, 69, 56, 14, 17, 22, 29, 51, 87, 80, 62,
+    18, 22, 37, 56, 68, 109, 103, 77, 24, 35, 55, 64, 81, 104, 113, 92,
+    49, 64, 78, 87, 103, 121, 120, 101, 72, 92, 95, 98, 112, 100, 103, 99,
+]
+
+COS = [[math.cos((2 * x + 1) * u * math.pi / 16) for x in range(8)] for u in range(8)]
+
+
+def fdct(samples):
+    """The forward DCT of section A.3.3, of a block in natural order"""
+    out = []
+    for v in range(8):
+        for u in range(8):
+            cu = math.sqrt(0.5) if u == 0 else 1
+            cv = math.sqrt(0.5) if v == 0 else 1
+            total = sum(samples[y * 8 + x] * COS[u][x] * COS[v][y] for y in range(8) for x in range(8))
+            out.append(cu * cv * total / 4)
+    return out
+
+
+def idct_1d(v, bias):
+    p1 = (v[2] + v[6]) * 2217
+    t2 = p1 + v[6] * -7567
+    t3 = p1 + v[2] * 3135
+    t0 = (v[0] + v[4]) << 12
+    t1 = (v[0] - v[4]) << 12
+    x0 = t0 + t3 + bias; x3 = t0 - t3 + bias; x1 = t1 + t2 + bias; x2 = t1 - t2 + bias
+    t0, t1, t2, t3 = v[7], v[5], v[3], v[1]
+    p3 = t0 + t2; p4 = t1 + t3; p1 = t0 + t3; p2 = t1 + t2
+    p5 = (p3 + p4) * 4816
+    t0 *= 1223; t1 *= 8410; t2 *= 12586; t3 *= 6149
+    p1 = p5 + p1 * -3685; p2 = p5 + p2 * -10497; p3 = p3 * -8034; p4 = p4 * -1597
+    t3 += p1 + p4; t2 += p2 + p3; t1 += p2 + p4; t0 += p1 + p3
+    return [x0 + t3, x1 + t2, x2 + t1, x3 + t0, x3 - t0, x2 - t1, x1 - t2, x0 - t3]
+
+
+def idct(coefficients, table, precision, differential):
+    """The integer IDCT of the decoder, of a block in natural order
+
+    The reconstruction must be what the decoder computes for the final lossless frames
+    to give back `pattern()` exactly, so this follows src/idct/scalar.rs, including its
+    shortcut for blocks without AC coefficients."""
+    shift = 0 if differential else 1 << (precision - 1)
+    top = (1 << precision) - 1
+    dequantized = [c * q for c, q in zip(coefficients, table)]
+    if not differential and not any(coefficients[1:]):
+        return [min(max((dequantized[0] >> 3) + shift, 0), top)] * 64
+    tmp = [0] * 64
+    for x in range(8):
+        column = idct_1d([dequantized[x + 8 * y] for y in range(8)], 512)
+        for y in range(8):
+            tmp[x + 8 * y] = column[y] >> 10
+    out = []
+    for y in range(8):
+        out += [v >> 17 for v in idct_1d(tmp[8 * y:8 * y + 8], 512 + 65536 + (shift << 17))]
+    if not differential:
+        out = [min(max(v, 0), top) for v in out]
+    return out
+
+
+def dqt(out, tables):
+    for tq, table in tables.items():
+        pq = 1 if max(table) > 255 else 0
+        out += b'\xff\xdb' + struct.pack('>HB', 3 + 64 * (pq + 1), (pq << 4) | tq)
+        out += b''.join(struct.pack('>H' if pq else 'B', table[ZIGZAG[i]]) for i in range(64))
+
+
+def dht(out, tc, th, freq):
+    bits, vals = gen_optimal_table(freq)
+    out += b'\xff\xc4' + struct.pack('>HB', 2 + 1 + 16 + len(vals), (tc << 4) | th) + bytes(bits) + bytes(vals)
+    return codes_from(bits, vals)
+
+
+def magnitude(v):
+    """Category and additional bits of a value, section F.1.2.1"""
+    c = category(v)
+    return c, v if v >= 0 else v - 1 + (1 << c)
+
+
+def ac_symbols(block, start, end):
+    """Run length coded coefficients `start` to `end` of a block, sections F.1.2.2 and G.1.2.2"""
+    symbols = []
+    run = 0
+    last = max([i for i in range(start, end + 1) if block[i]], default=0)
+    for i in range(start, last + 1):
+        if block[i] == 0:
+            run += 1
+            continue
+        while run > 15:
+            symbols.append((0xF0, 0, 0)); run -= 16
+        c, bits = magnitude(block[i])
+        symbols.append(((run << 4) | c, bits, c))
+        run = 0
+    if last < end:
+        symbols.append((0x00, 0, 0))
+    return symbols
+
+
+def dct_frame(out, marker, width, height, precision, comps, scans, values, tables, differential):
+    """Code the components of a DCT based frame
+
+    comps are (id, h, v, quantization table), values[i][y][x] the samples of component i
+    or their differences to the reference for differential frames. Scans have the
+    components, the spectral selection `ss` to `se`, the successive approximation `ah`,
+    `al` and optionally a restart interval. Returns the reconstruction of every
+    component as the decoder computes it."""
+    arithmetic = marker in (0xC9, 0xCA, 0xCD, 0xCE)
+    progressive = marker in (0xC2, 0xC6, 0xCA, 0xCE)
+    hmax = max(c[1] for c in comps); vmax = max(c[2] for c in comps)
+    mx = -(-width // (8 * hmax)); my = -(-height // (8 * vmax))
+    out += bytes([0xFF, marker]) + struct.pack('>HBHHB', 8 + 3 * len(comps), precision, height, width, len(comps))
+    for (cid, h, v, tq) in comps:
+        out += bytes([cid, (h << 4) | v, tq])
+
+    shift = 0 if differential else 1 << (precision - 1)
+    dims = []; coefficients = []; recon = []
+    for ci, (cid, h, v, tq) in enumerate(comps):
+        cw = -(-width * h // hmax); ch = -(-height * v // vmax)
+        dims.append((cw, ch))
+        plane = [[values[ci][min(y, ch - 1)][min(x, cw - 1)] - shift for x in range(mx * h * 8)]
+                 for y in range(my * v * 8)]
+        blocks = []
+        for by in range(my * v):
+            row = []
+            for bx in range(mx * h):
+                samples = [plane[by * 8 + y][bx * 8 + x] for y in range(8) for x in range(8)]
+                row.append([round(f / q) for f, q in zip(fdct(samples), tables[tq])])
+            blocks.append(row)
+        coefficients.append(blocks)
+        rec = [[0] * cw for _ in range(ch)]
+        for by in range(-(-ch // 8)):
+            for bx in range(-(-cw // 8)):
+                samples = idct(blocks[by][bx], tables[tq], precision, differential)
+                for y in range(8):
+                    for x in range(8):
+                        if by * 8 + y < ch and bx * 8 + x < cw:
+                            rec[by * 8 + y][bx * 8 + x] = samples[y * 8 + x]
+        recon.append(rec)
+
+    for scan in scans:
+        idx = scan['comps']; ss = scan.get('ss', 0); se = scan.get('se', 63)
+        ah = scan.get('ah', 0); al = scan.get('al', 0)
+        assert ss == 0 or (ah == 0 and al == 0), 'AC successive approximation is not supported'
+        # blocks of every MCU in zigzag order, tagged with their position in the scan
+        zigzag = lambda block: [block[ZIGZAG[i]] for i in range(64)]
+        if len(idx) == 1:
+            cw, ch = dims[idx[0]]
+            mcus = [[(0, zigzag(coefficients[idx[0]][by][bx]))]
+                    for by in range(-(-ch // 8)) for bx in range(-(-cw // 8))]
+        else:
+            mcus = [[(k, zigzag(coefficients[i][yy * comps[i][2] + j][xx * comps[i][1] + h]))
+                     for k, i in enumerate(idx) for j in range(comps[i][2]) for h in range(comps[i][1])]
+                    for yy in range(my) for xx in range(mx)]
+        restart = scan.get('restart', 0)
+        intervals = [mcus[k:k + restart] for k in range(0, len(mcus), restart)] if restart else [mcus]
+        if 'restart' in scan:
+            out += b'\xff\xdd' + struct.pack('>HH', 4, restart)
+
+        if arithmetic:
+            header = b''
+        else:
+            # symbols of every interval, DC ones tagged with 0 and AC ones with 1
+            symbols = []
+            for interval in intervals:
+                prediction = [0] * len(idx)
+                coded = []
+                for mcu in interval:
+                    for k, block in mcu:
+                        if ss == 0 and ah == 0:
+                            dc = block[0] >> al
+                            c, bits = magnitude(dc - prediction[k])
+                            prediction[k] = dc
+                            coded.append((0, k, c, bits, c))
+                        elif ss == 0:
+                            coded.append((None, k, 0, (block[0] >> al) & 1, 1))
+                        if se > 0 and ah == 0:
+                            coded += [(1, k, rs, bits, n) for rs, bits, n in ac_symbols(block, max(ss, 1), se)]
+                symbols.append(coded)
+            codes = {}
+            header = bytearray()
+            for tc in (0, 1):
+                for k in range(len(idx)):
+                    freq = [0] * 256
+                    for interval in symbols:
+                        for s in interval:
+                            if s[0] == tc and s[1] == k:
+                                freq[s[2]] += 1
+                    if any(freq):
+                        codes[(tc, k)] = dht(header, tc, k, freq)
+        out += header
+        out += b'\xff\xda' + struct.pack('>HB', 6 + 2 * len(idx), len(idx))
+        for k, i in enumerate(idx):
+            out += bytes([comps[i][0], (k << 4) | k])
+        out += bytes([ss, se, (ah << 4) | al])
+
+        if arithmetic and not progressive:
+            out += encode_dct(intervals, [(i, k, k) for k, i in enumerate(idx)], conditioning_tables([]))
+            continue
+        for n, interval in enumerate(intervals):
+            if n:
+                out += bytes([0xFF, 0xD0 + (n - 1) % 8])
+            if arithmetic:
+                # the progressive model of section G.1.3, as libjpeg's jcarith.c codes it
+                enc = ArithEncoder()
+                dc_stats = [[0] * 64 for _ in idx]; ac_stats = [[0] * 256 for _ in idx]
+                fixed = [113]; prediction = [0] * len(idx); context = [0] * len(idx)
+                for mcu in interval:
+                    for k, block in mcu:
+                        if ss == 0 and ah == 0:
+                            dc = block[0] >> al
+                            context[k] = encode_dc(enc, dc_stats[k], context[k], dc - prediction[k], 0, 1)
+                            prediction[k] = dc
+                        elif ss == 0:
+                            enc.encode(fixed, 0, (block[0] >> al) & 1)
+                        else:
+                            encode_ac(enc, ac_stats[k], fixed, block, 5, ss, se)
+                out += enc.finish()
+            else:
+                bw = BitWriter()
+                for tc, k, symbol, bits, length in symbols[n]:
+                    if tc is not None:
+                        code, size = codes[(tc, k)][symbol]; bw.put(code, size)
+                    if length:
+                        bw.put(bits, length)
+                bw.flush(); out += bw.out
+    return recon
+
+
+def add_clamped(reference, diff, precision):
+    top = (1 << precision) - 1
+    return [[min(max(r + d, 0), top) for r, d in zip(rrow, drow)] for rrow, drow in zip(reference, diff)]
+
+
+W, H = 37, 23
+
+if __name__ == '__main__':
+    d = sys.argv[1]
+
+    # grayscale, half resolution lossless frame, then a full resolution differential frame
+    out = bytearray(b'\xff\xd8')
+    dhp(out, W, H, 8, [(1, 1, 1)])
+    ref = frame(out, 0xC3, 19, 12, 8, [(1, 1, 1)], [dict(comps=[0], pred=1, pt=0)], [full(0, 19, 12, 8, 2, 2)],
+                False, False)[0]
+    exp(out, 1, 1)
+    ref = crop(expand(ref, 1, 1), W, H)
+    target = full(0, W, H, 8)
+    frame(out, 0xC7, W, H, 8, [(1, 1, 1)], [dict(comps=[0], pred=0, pt=0)], [diff_values(target, ref)], False, True)
+    out += b'\xff\xd9'
+    open(d + '/hierarchical_huffman.jpg', 'wb').write(out)
+
+    # arithmetic coded, three frames expanding horizontally then vertically
+    P = 12
+    out = bytearray(b'\xff\xd8')
+    dhp(out, W, H, P, [(1, 1, 1)])
+    ref = frame(out, 0xCB, 19, 12, P, [(1, 1, 1)], [dict(comps=[0], pred=7, pt=0)], [full(0, 19, 12, P, 2, 2)],
+                True, False)[0]
+    exp(out, 1, 0)
+    ref = crop(expand(ref, 1, 0), W, 12)
+    target = full(0, W, 12, P, 1, 2)
+    d1 = frame(out, 0xCF, W, 12, P, [(1, 1, 1)], [dict(comps=[0], pred=0, pt=1, restart=W * 4)],
+               [diff_values(target, ref)], True, True)[0]
+    ref = add(ref, d1)
+    exp(out, 0, 1)
+    ref = crop(expand(ref, 0, 1), W, H)
+    target = full(0, W, H, P)
+    frame(out, 0xCF, W, H, P, [(1, 1, 1)], [dict(comps=[0], pred=0, pt=0, dri=True)], [diff_values(target, ref)], True, True)
+    out += b'\xff\xd9'
+    open(d + '/hierarchical_arithmetic.jpg', 'wb').write(out)
+
+    # three components, luma coded once at full resolution, chroma at half resolution and refined
+    out = bytearray(b'\xff\xd8')
+    comps = [(1, 1, 1), (2, 1, 1), (3, 1, 1)]
+    dhp(out, W, H, 8, comps)
+    frame(out, 0xC3, W, H, 8, [comps[0]], [dict(comps=[0], pred=1, pt=0)], [full(0, W, H, 8)], False, False)
+    refs = frame(out, 0xC3, 19, 12, 8, comps[1:], [dict(comps=[0, 1], pred=2, pt=0)],
+                 [full(1, 19, 12, 8, 2, 2), full(2, 19, 12, 8, 2, 2)], False, False)
+    exp(out, 1, 1)
+    refs = [crop(expand(r, 1, 1), W, H) for r in refs]
+    frame(out, 0xC7, W, H, 8, comps[1:], [dict(comps=[0], pred=0, pt=0), dict(comps=[1], pred=0, pt=0)],
+          [diff_values(full(c + 1, W, H, 8), refs[c]) for c in range(2)], False, True)
+    out += b'\xff\xd9'
+    open(d + '/hierarchical_components.jpg', 'wb').write(out)
+
+    # DCT based frames, each ending with a lossless frame so that the output is exact. The
+    # quantization tables of the later frames are defined before the first one, they persist.
+    flat = [2] * 64
+
+    # grayscale, a baseline frame, a differential sequential and a differential progressive one
+    out = bytearray(b'\xff\xd8')
+    dhp(out, W, H, 8, [(1, 1, 1)])
+    dqt(out, {0: LUMA, 1: flat})
+    ref = dct_frame(out, 0xC0, 19, 12, 8, [(1, 1, 1, 0)], [dict(comps=[0])], [full(0, 19, 12, 8, 2, 2)], {0: LUMA, 1: flat},
+                    False)[0]
+    exp(out, 1, 1)
+    ref = crop(expand(ref, 1, 1), W, H)
+    target = full(0, W, H, 8)
+    q = [4 * v for v in flat]
+    dqt(out, {2: q})
+    d1 = dct_frame(out, 0xC5, W, H, 8, [(1, 1, 1, 2)], [dict(comps=[0], restart=4)], [diff_values(target, ref)], {2: q},
+                   True)[0]
+    ref = add_clamped(ref, d1, 8)
+    progression = [dict(comps=[0], ss=0, se=0, al=1, restart=0), dict(comps=[0], ss=0, se=0, ah=1),
+                   dict(comps=[0], ss=1, se=5), dict(comps=[0], ss=6, se=63)]
+    d2 = dct_frame(out, 0xC6, W, H, 8, [(1, 1, 1, 1)], progression, [diff_values(target, ref)], {1: flat}, True)[0]
+    ref = add_clamped(ref, d2, 8)
+    frame(out, 0xC7, W, H, 8, [(1, 1, 1)], [dict(comps=[0], pred=0, pt=0)], [diff_values(target, ref)], False, True)
+    out += b'\xff\xd9'
+    open(d + '/hierarchical_dct_huffman.jpg', 'wb').write(out)
+
+    # YCbCr 4:2:0, arithmetic coded, interleaved and non-interleaved scans
+    out = bytearray(b'\xff\xd8')
+    comps = [(1, 2, 2), (2, 1, 1), (3, 1, 1)]
+    chroma = [v + 8 for v in LUMA]
+    tables = {0: LUMA, 1: chroma, 2: flat}
+    dhp(out, W, H, 8, comps)
+    dqt(out, tables)
+    refs = dct_frame(out, 0xC9, 19, 12, 8, [(1, 2, 2, 0), (2, 1, 1, 1), (3, 1, 1, 1)],
+                     [dict(comps=[0, 1, 2], restart=2)],
+                     [full(0, 19, 12, 8, 2, 2), full(1, 10, 6, 8, 4, 4), full(2, 10, 6, 8, 4, 4)], tables, False)
+    exp(out, 1, 1)
+    dims = [(W, H), (19, 12), (19, 12)]
+    refs = [crop(expand(r, 1, 1), w, h) for r, (w, h) in zip(refs, dims)]
+    targets = [full(c, w, h, 8) for c, (w, h) in enumerate(dims)]
+    differential = [(1, 2, 2, 2), (2, 1, 1, 2), (3, 1, 1, 2)]
+    diffs = dct_frame(out, 0xCD, W, H, 8, differential,
+                      [dict(comps=[0], restart=0), dict(comps=[1]), dict(comps=[2])],
+                      [diff_values(t, r) for t, r in zip(targets, refs)], tables, True)
+    refs = [add_clamped(r, d_, 8) for r, d_ in zip(refs, diffs)]
+    progression = [dict(comps=[0, 1, 2], ss=0, se=0, al=1), dict(comps=[0, 1, 2], ss=0, se=0, ah=1),
+                   dict(comps=[1], ss=1, se=63), dict(comps=[0], ss=1, se=9), dict(comps=[2], ss=1, se=63),
+                   dict(comps=[0], ss=10, se=63)]
+    diffs = dct_frame(out, 0xCE, W, H, 8, differential, progression,
+                      [diff_values(t, r) for t, r in zip(targets, refs)], tables, True)
+    refs = [add_clamped(r, d_, 8) for r, d_ in zip(refs, diffs)]
+    frame(out, 0xCF, W, H, 8, comps, [dict(comps=[0, 1, 2], pred=0, pt=0)],
+          [diff_values(t, r) for t, r in zip(targets, refs)], True, True)
+    out += b'\xff\xd9'
+    open(d + '/hierarchical_dct_arithmetic.jpg', 'wb').write(out)
+
+    # 12 bit grayscale, a progressive arithmetic frame expanded horizontally, 16 bit tables
+    P = 12
+    out = bytearray(b'\xff\xd8')
+    tables = {0: [16 * v for v in LUMA], 3: [8 * v for v in flat]}
+    dhp(out, W, H, P, [(1, 1, 1)])
+    dqt(out, tables)
+    ref = dct_frame(out, 0xCA, 19, H, P, [(1, 1, 1, 0)],
+                    [dict(comps=[0], ss=0, se=0), dict(comps=[0], ss=1, se=2), dict(comps=[0], ss=3, se=63)],
+                    [full(0, 19, H, P, 2, 1)], tables, False)[0]
+    exp(out, 1, 0)
+    ref = crop(expand(ref, 1, 0), W, H)
+    target = full(0, W, H, P)
+    d1 = dct_frame(out, 0xCD, W, H, P, [(1, 1, 1, 3)], [dict(comps=[0])], [diff_values(target, ref)], tables, True)[0]
+    ref = add_clamped(ref, d1, P)
+    frame(out, 0xCF, W, H, P, [(1, 1, 1)], [dict(comps=[0], pred=0, pt=0)], [diff_values(target, ref)], True, True)
+    out += b'\xff\xd9'
+    open(d + '/hierarchical_dct_12_bit.jpg', 'wb').write(out)
@@ -0,0 +1,462 @@
+#!/usr/bin/env python3
+"""Lossless JPEG encoder (T.81 Annex H) writing the lossless_*.jpg fixtures
+
+    python3 tests/generators/lossless.py tests/inputs
+
+The fixtures code `pattern()`, which the tests in tests/lossless_images.rs compare
+the decoded samples with. See README.md for how the encoder was checked.
+"""
+import struct
+import sys
+
+
+def pattern(x, y, c, p):
+    m = (1 << p) - 1
+    if y == 2:
+        return (x % 2) << (p - 1)
+    if (x + y) % 11 == 0:
+        return m
+    if (3 * x + y) % 13 == 0:
+        return 0
+    v = x * x * 3 + y * 5 + x * y + c * 1000 + ((x ^ y) & 7) * 31 + (x * 7919 + y * 104729) % 997
+    return v & m
+
+
+def gen_optimal_table(freq):
+    # K.2, freq: dict symbol->count for symbols 0..16, plus reserved symbol 256
+    freq = list(freq) + [0] * (257 - len(freq))
+    freq[256] = 1
+    codesize = [0] * 257
+    others = [-1] * 257
+    while True:
+        c1 = -1; v = 10**18
+        for i in range(257):
+            if freq[i] and freq[i] <= v:
+                v = freq[i]; c1 = i
+        c2 = -1; v = 10**18
+        for i in range(257):
+            if freq[i] and freq[i] <= v and i != c1:
+                v = freq[i]; c2 = i
+        if c2 < 0:
+            break
+        freq[c1] += freq[c2]; freq[c2] = 0
+        codesize[c1] += 1
+        while others[c1] >= 0:
+            c1 = others[c1]; codesize[c1] += 1
+        others[c1] = c2
+        codesize[c2] += 1
+        while others[c2] >= 0:
+            c2 = others[c2]; codesize[c2] += 1
+    bits = [0] * 33
+    for i in range(257):
+        if codesize[i]:
+            bits[codesize[i]] += 1
+    for i in range(32, 16, -1):
+        while bits[i] > 0:
+            j = i - 2
+            while bits[j] == 0:
+                j -= 1
+            bits[i] -= 2; bits[i - 1] += 1; bits[j + 1] += 2; bits[j] -= 1
+    i = 16
+    while bits[i] == 0:
+        i -= 1
+    bits[i] -= 1
+    huffval = []
+    for size in range(1, 33):
+        for j in range(256):
+            if codesize[j] == size:
+                huffval.append(j)
+    return bits[1:17], huffval
+
+
+def codes_from(bits, huffval):
+    codes = {}
+    code = 0; k = 0
+    for l in range(1, 17):
+        for _ in range(bits[l - 1]):
+            codes[huffval[k]] = (code, l); k += 1; code += 1
+        code <<= 1
+    return codes
+
+
+class BitWriter:
+    def __init__(self):
+        self.out = bytearray(); self.acc = 0; self.n = 0
+
+    def put(self, v, n):
+        for i in range(n - 1, -1, -1):
+            self.acc = (self.acc << 1) | ((v >> i) & 1); self.n += 1
+            if self.n == 8:
+                self.out.append(self.acc)
+                if self.acc == 0xFF:
+                    self.out.append(0)
+                self.acc = 0; self.n = 0
+
+    def flush(self):
+        while self.n:
+            self.put(1, 1)
+
+
+def category(d):
+    return abs(d).bit_length()
+
+
+def predict(plane, x, y, pred, first_row, init):
+    if first_row:
+        return init if x == 0 else plane[y][x - 1]
+    if x == 0:
+        return plane[y - 1][x]
+    ra = plane[y][x - 1]; rb = plane[y - 1][x]; rc = plane[y - 1][x - 1]
+    return [None, ra, rb, rc, ra + rb - rc, ra + ((rb - rc) >> 1), rb + ((ra - rc) >> 1), (ra + rb) >> 1][pred]
+
+
+def encode(path, width, height, precision, comps, scans, samples=None):
+    """comps: list of (id, h, v); scans: list of dict(comps=[idx], pred, pt, restart)"""
+    hmax = max(c[1] for c in comps); vmax = max(c[2] for c in comps)
+    mx = -(-width // hmax); my = -(-height // vmax)
+    out = bytearray(b'\xff\xd8')
+    out += b'\xff\xc3' + struct.pack('>HBHHB', 8 + 3 * len(comps), precision, height, width, len(comps))
+    for (cid, h, v) in comps:
+        out += bytes([cid, (h << 4) | v, 0])
+    # full resolution component planes, padded by replication to whole MCUs
+    planes = []
+    for ci, (cid, h, v) in enumerate(comps):
+        cw = -(-width * h // hmax); ch = -(-height * v // vmax)
+        pw = mx * h; ph = my * v
+        pl = []
+        for y in range(ph):
+            row = []
+            for x in range(pw):
+                xx = min(x, cw - 1); yy = min(y, ch - 1)
+                row.append(samples(xx, yy, ci) if samples else pattern(xx, yy, ci, precision))
+            pl.append(row)
+        planes.append((pl, cw, ch))
+    for scan in scans:
+        idx = scan['comps']; pred = scan['pred']; pt = scan['pt']; ri = scan.get('restart', 0)
+        init = 1 << (precision - pt - 1)
+        shifted = [[[s >> pt for s in row] for row in planes[i][0]] for i in idx]
+        if len(idx) == 1:
+            cw, ch = planes[idx[0]][1], planes[idx[0]][2]
+            samp = [(1, 1)]; smx, smy = cw, ch
+        else:
+            samp = [(comps[i][1], comps[i][2]) for i in idx]; smx, smy = mx, my
+        # differences for each component sample, in coding order
+        # prediction in a row uses the first-row rule at the start of the scan and after restarts
+        symbols = []  # list of (component position, diff) or ('RST', n)
+        mcu = 0; first_rows = set([0])
+        if ri:
+            assert ri % smx == 0
+            for k in range(0, smx * smy, ri):
+                first_rows.add(k // smx)
+        rst = 0
+        for yy in range(smy):
+            for xx in range(smx):
+                if ri and mcu and mcu % ri == 0:
+                    symbols.append(('RST', rst % 8)); rst += 1
+                for k, (h, v) in enumerate(samp):
+                    pl = shifted[k]
+                    for j in range(v):
+                        for i in range(h):
+                            x = xx * h + i; y = yy * v + j
+                            fr = (yy in first_rows) and j == 0
+                            p = predict(pl, x, y, pred, fr, init)
+                            d = (pl[y][x] - p) & 0xFFFF
+                            if d >= 0x8000:
+                                d -= 0x10000
+                            symbols.append((k, d))
+                mcu += 1
+        tables = []
+        for k in range(len(idx)):
+            freq = [0] * 17
+            for s in symbols:
+                if s[0] == k:
+                    c = category(s[1]) if s[1] != -32768 else 16
+                    freq[c] += 1
+            bits, vals = gen_optimal_table(freq)
+            tables.append((bits, vals, codes_from(bits, vals)))
+            out += b'\xff\xc4' + struct.pack('>HB', 2 + 1 + 16 + len(vals), k) + bytes(bits) + bytes(vals)
+        if 'dri' in scan or ri:
+            out += b'\xff\xdd' + struct.pack('>HH', 4, ri)
+        out += b'\xff\xda' + struct.pack('>HB', 6 + 2 * len(idx), len(idx))
+        for k, i in enumerate(idx):
+            out += bytes([comps[i][0], k << 4])
+        out += bytes([pred, 0, pt])
+        bw = BitWriter()
+        for s in symbols:
+            if s[0] == 'RST':
+                bw.flush(); out += bw.out; bw.out = bytearray()
+                out += bytes([0xFF, 0xD0 + s[1]])
+                continue
+            k, d = s
+            codes = tables[k][2]
+            if d == -32768:
+                code, l = codes[16]; bw.put(code, l); continue
+            c = category(d)
+            code, l = codes[c]; bw.put(code, l)
+            if c:
+                bw.put(d if d > 0 else d - 1 + (1 << c), c)
+        bw.flush(); out += bw.out
+    out += b'\xff\xd9'
+    open(path, 'wb').write(out)
+
+
+def main_huffman(d):
+    W, H = 37, 23
+    encode(d + '/lossless_predictor_1.jpg', W, H, 16, [(1, 1, 1)], [dict(comps=[0], pred=1, pt=0)])
+    encode(d + '/lossless_predictor_2.jpg', W, H, 8, [(1, 1, 1)], [dict(comps=[0], pred=2, pt=0)])
+    encode(d + '/lossless_predictor_3.jpg', W, H, 12, [(1, 1, 1)], [dict(comps=[0], pred=3, pt=0, restart=W * 2)])
+    encode(d + '/lossless_predictor_4.jpg', W, H, 2, [(1, 1, 1)], [dict(comps=[0], pred=4, pt=0)])
+    encode(d + '/lossless_predictor_5.jpg', W, H, 10, [(1, 1, 1), (2, 1, 1), (3, 1, 1)], [dict(comps=[0, 1, 2], pred=5, pt=0)])
+    encode(d + '/lossless_predictor_6.jpg', W, H, 8, [(1, 2, 1), (2, 1, 1), (3, 1, 1)],
+           [dict(comps=[0], pred=6, pt=2), dict(comps=[1], pred=6, pt=2), dict(comps=[2], pred=6, pt=2)])
+    encode(d + '/lossless_predictor_7.jpg', W, H, 16, [(1, 2, 2), (2, 1, 1), (3, 1, 1)],
+           [dict(comps=[0, 1, 2], pred=7, pt=3, restart=19 * 3)])
+    encode(d + '/lossless_point_transform.jpg', W, H, 16, [(1, 1, 1)], [dict(comps=[0], pred=1, pt=14)])
+
+
+# ---------------------------------------------------------------- arithmetic coding
+
+# Table D.2 of T.81, (Qe, Next_Index_LPS, Next_Index_MPS, Switch_MPS) for every state. The
+# last state isn't in the spec, it is the fixed 0.5 estimate libjpeg codes AC signs with.
+QE = [
+    (0x5A1D, 1, 1, 1), (0x2586, 14, 2, 0), (0x1114, 16, 3, 0), (0x080B, 18, 4, 0),
+    (0x03D8, 20, 5, 0), (0x01DA, 23, 6, 0), (0x00E5, 25, 7, 0), (0x006F, 28, 8, 0),
+    (0x0036, 30, 9, 0), (0x001A, 33, 10, 0), (0x000D, 35, 11, 0), (0x0006, 9, 12, 0),
+    (0x0003, 10, 13, 0), (0x0001, 12, 13, 0), (0x5A7F, 15, 15, 1), (0x3F25, 36, 16, 0),
+    (0x2CF2, 38, 17, 0), (0x207C, 39, 18, 0), (0x17B9, 40, 19, 0), (0x1182, 42, 20, 0),
+    (0x0CEF, 43, 21, 0), (0x09A1, 45, 22, 0), (0x072F, 46, 23, 0), (0x055C, 48, 24, 0),
+    (0x0406, 49, 25, 0), (0x0303, 51, 26, 0), (0x0240, 52, 27, 0), (0x01B1, 54, 28, 0),
+    (0x0144, 56, 29, 0), (0x00F5, 57, 30, 0), (0x00B7, 59, 31, 0), (0x008A, 60, 32, 0),
+    (0x0068, 62, 33, 0), (0x004E, 63, 34, 0), (0x003B, 32, 35, 0), (0x002C, 33, 9, 0),
+    (0x5AE1, 37, 37, 1), (0x484C, 64, 38, 0), (0x3A0D, 65, 39, 0), (0x2EF1, 67, 40, 0),
+    (0x261F, 68, 41, 0), (0x1F33, 69, 42, 0), (0x19A8, 70, 43, 0), (0x1518, 72, 44, 0),
+    (0x1177, 73, 45, 0), (0x0E74, 74, 46, 0), (0x0BFB, 75, 47, 0), (0x09F8, 77, 48, 0),
+    (0x0861, 78, 49, 0), (0x0706, 79, 50, 0), (0x05CD, 48, 51, 0), (0x04DE, 50, 52, 0),
+    (0x040F, 50, 53, 0), (0x0363, 51, 54, 0), (0x02D4, 52, 55, 0), (0x025C, 53, 56, 0),
+    (0x01F8, 54, 57, 0), (0x01A4, 55, 58, 0), (0x0160, 56, 59, 0), (0x0125, 57, 60, 0),
+    (0x00F6, 58, 61, 0), (0x00CB, 59, 62, 0), (0x00AB, 61, 63, 0), (0x008F, 61, 32, 0),
+    (0x5B12, 65, 65, 1), (0x4D04, 80, 66, 0), (0x412C, 81, 67, 0), (0x37D8, 82, 68, 0),
+    (0x2FE8, 83, 69, 0), (0x293C, 84, 70, 0), (0x2379, 86, 71, 0), (0x1EDF, 87, 72, 0),
+    (0x1AA9, 87, 73, 0), (0x174E, 72, 74, 0), (0x1424, 72, 75, 0), (0x119C, 74, 76, 0),
+    (0x0F6B, 74, 77, 0), (0x0D51, 75, 78, 0), (0x0BB6, 77, 79, 0), (0x0A40, 77, 48, 0),
+    (0x5832, 80, 81, 1), (0x4D1C, 88, 82, 0), (0x438E, 89, 83, 0), (0x3BDD, 90, 84, 0),
+    (0x34EE, 91, 85, 0), (0x2EAE, 92, 86, 0), (0x299A, 93, 87, 0), (0x2516, 86, 71, 0),
+    (0x5570, 88, 89, 1), (0x4CA9, 95, 90, 0), (0x44D9, 96, 91, 0), (0x3E22, 97, 92, 0),
+    (0x3824, 99, 93, 0), (0x32B4, 99, 94, 0), (0x2E17, 93, 86, 0), (0x56A8, 95, 96, 1),
+    (0x4F46, 101, 97, 0), (0x47E5, 102, 98, 0), (0x41CF, 103, 99, 0), (0x3C3D, 104, 100, 0),
+    (0x375E, 99, 93, 0), (0x5231, 105, 102, 0), (0x4C0F, 106, 103, 0), (0x4639, 107, 104, 0),
+    (0x415E, 103, 99, 0), (0x5627, 105, 106, 1), (0x50E7, 108, 107, 0), (0x4B85, 109, 103, 0),
+    (0x5597, 110, 109, 0), (0x504F, 111, 107, 0), (0x5A10, 110, 111, 1), (0x5522, 112, 109, 0),
+    (0x59EB, 112, 111, 1), (0x5A1D, 113, 113, 0),
+]
+
+
+class ArithEncoder:
+    def __init__(self):
+        self.out = bytearray(); self.a = 0x10000; self.c = 0; self.ct = 11
+        self.buffer = -1; self.sc = 0; self.zc = 0
+
+    def emit(self, b):
+        self.out.append(b & 0xFF)
+
+    def encode(self, stats, idx, val):
+        sv = stats[idx]
+        qe, nl, nm, sw = QE[sv & 0x7F]
+        self.a -= qe
+        if val != (sv >> 7):
+            if self.a >= qe:
+                self.c += self.a; self.a = qe
+            stats[idx] = (sv & 0x80) ^ (nl | (sw << 7))
+        else:
+            if self.a >= 0x8000:
+                return
+            if self.a < qe:
+                self.c += self.a; self.a = qe
+            stats[idx] = (sv & 0x80) ^ nm
+        while True:
+            self.a <<= 1; self.c <<= 1; self.ct -= 1
+            if self.ct == 0:
+                temp = self.c >> 19
+                if temp > 0xFF:
+                    if self.buffer >= 0:
+                        while self.zc:
+                            self.emit(0); self.zc -= 1
+                        self.emit(self.buffer + 1)
+                        if self.buffer + 1 == 0xFF:
+                            self.emit(0)
+                    self.zc += self.sc; self.sc = 0
+                    self.buffer = temp & 0xFF
+                elif temp == 0xFF:
+                    self.sc += 1
+                else:
+                    if self.buffer == 0:
+                        self.zc += 1
+                    elif self.buffer >= 0:
+                        while self.zc:
+                            self.emit(0); self.zc -= 1
+                        self.emit(self.buffer)
+                    if self.sc:
+                        while self.zc:
+                            self.emit(0); self.zc -= 1
+                        while self.sc:
+                            self.emit(0xFF); self.emit(0); self.sc -= 1
+                    self.buffer = temp & 0xFF
+                self.c &= 0x7FFFF; self.ct += 8
+            if self.a >= 0x8000:
+                break
+
+    def finish(self):
+        temp = (self.a - 1 + self.c) & 0xFFFF0000
+        self.c = temp + 0x8000 if temp < self.c else temp
+        self.c <<= self.ct
+        if self.c & 0xF8000000:
+            if self.buffer >= 0:
+                while self.zc:
+                    self.emit(0); self.zc -= 1
+                self.emit(self.buffer + 1)
+                if self.buffer + 1 == 0xFF:
+                    self.emit(0)
+            self.zc += self.sc; self.sc = 0
+        else:
+            if self.buffer == 0:
+                self.zc += 1
+            elif self.buffer >= 0:
+                while self.zc:
+                    self.emit(0); self.zc -= 1
+                self.emit(self.buffer)
+            if self.sc:
+                while self.zc:
+                    self.emit(0); self.zc -= 1
+                while self.sc:
+                    self.emit(0xFF); self.emit(0); self.sc -= 1
+        if self.c & 0x7FFF800:
+            while self.zc:
+                self.emit(0); self.zc -= 1
+            self.emit((self.c >> 19) & 0xFF)
+            if ((self.c >> 19) & 0xFF) == 0xFF:
+                self.emit(0)
+            if self.c & 0x7F800:
+                self.emit((self.c >> 11) & 0xFF)
+                if ((self.c >> 11) & 0xFF) == 0xFF:
+                    self.emit(0)
+        return bytes(self.out)
+
+
+def cond_category(d, l, u):
+    m = abs(d)
+    neg = 1 if d < 0 else 0
+    if m <= (1 << l) >> 1:
+        return 0
+    if m <= (1 << u):
+        return 1 + neg
+    return 3 + neg
+
+
+def arith_encode_diff(enc, stats, v, da, db, l, u):
+    dbc = cond_category(db, l, u)
+    st = (5 * cond_category(da, l, u) + dbc) * 4
+    if v == 0:
+        enc.encode(stats, st, 0); return
+    enc.encode(stats, st, 1)
+    if v > 0:
+        enc.encode(stats, st + 1, 0); st += 2
+    else:
+        v = -v; enc.encode(stats, st + 1, 1); st += 3
+    m = 0
+    v -= 1
+    if v:
+        enc.encode(stats, st, 1)
+        m = 1; v2 = v
+        st = 129 if dbc > 2 else 100
+        v2 >>= 1
+        while v2:
+            enc.encode(stats, st, 1); m <<= 1; st += 1; v2 >>= 1
+    enc.encode(stats, st, 0)
+    st += 14
+    m >>= 1
+    while m:
+        enc.encode(stats, st, 1 if (m & v) else 0); m >>= 1
+
+
+def encode_arith(path, width, height, precision, comps, scans, dac=None):
+    """Like encode, but arithmetic coded (SOF11). dac: dict table -> (L, U)"""
+    hmax = max(c[1] for c in comps); vmax = max(c[2] for c in comps)
+    mx = -(-width // hmax); my = -(-height // vmax)
+    out = bytearray(b'\xff\xd8')
+    out += b'\xff\xcb' + struct.pack('>HBHHB', 8 + 3 * len(comps), precision, height, width, len(comps))
+    for (cid, h, v) in comps:
+        out += bytes([cid, (h << 4) | v, 0])
+    planes = []
+    for ci, (cid, h, v) in enumerate(comps):
+        cw = -(-width * h // hmax); ch = -(-height * v // vmax)
+        pl = [[pattern(min(x, cw - 1), min(y, ch - 1), ci, precision) for x in range(mx * h)] for y in range(my * v)]
+        planes.append((pl, cw, ch))
+    conditioning = {t: (0, 1) for t in range(4)}
+    if dac:
+        conditioning.update(dac)
+        body = b''.join(bytes([t, (lu[1] << 4) | lu[0]]) for t, lu in dac.items())
+        out += b'\xff\xcc' + struct.pack('>H', 2 + len(body)) + body
+    for scan in scans:
+        idx = scan['comps']; pred = scan['pred']; pt = scan['pt']; ri = scan.get('restart', 0)
+        tbl = scan.get('tables', list(range(len(idx))))
+        init = 1 << (precision - pt - 1)
+        shifted = [[[s >> pt for s in row] for row in planes[i][0]] for i in idx]
+        if len(idx) == 1:
+            samp = [(1, 1)]; smx, smy = planes[idx[0]][1], planes[idx[0]][2]
+        else:
+            samp = [(comps[i][1], comps[i][2]) for i in idx]; smx, smy = mx, my
+        if ri:
+            assert ri % smx == 0
+            out += b'\xff\xdd' + struct.pack('>HH', 4, ri)
+        out += b'\xff\xda' + struct.pack('>HB', 6 + 2 * len(idx), len(idx))
+        for k, i in enumerate(idx):
+            out += bytes([comps[i][0], tbl[k] << 4])
+        out += bytes([pred, 0, pt])
+        # differences of every coded sample, per component, [y][x] in scan coordinates
+        diffs = [[[0] * (smx * h) for _ in range(smy * v)] for (h, v) in samp]
+        enc = ArithEncoder(); stats = [[0] * 158 for _ in range(4)]
+        first_rows = {0}
+        if ri:
+            for k in range(0, smx * smy, ri):
+                first_rows.add(k // smx)
+        mcu = 0; rst = 0
+        for yy in range(smy):
+            for xx in range(smx):
+                if ri and mcu and mcu % ri == 0:
+                    out += enc.finish(); out += bytes([0xFF, 0xD0 + rst % 8]); rst += 1
+                    enc = ArithEncoder(); stats = [[0] * 158 for _ in range(4)]
+                for k, (h, v) in enumerate(samp):
+                    pl = shifted[k]; l, u = conditioning[tbl[k]]
+                    for j in range(v):
+                        for i in range(h):
+                            x = xx * h + i; y = yy * v + j
+                            fr = (yy in first_rows) and j == 0
+                            p = predict(pl, x, y, pred, fr, init)
+                            d = (pl[y][x] - p) & 0xFFFF
+                            if d >= 0x8000:
+                                d -= 0x10000
+                            diffs[k][y][x] = d
+                            da = diffs[k][y][x - 1] if x > 0 else 0
+                            db = 0 if fr else diffs[k][y - 1][x]
+                            arith_encode_diff(enc, stats[tbl[k]], d, da, db, l, u)
+                mcu += 1
+        out += enc.finish()
+    out += b'\xff\xd9'
+    open(path, 'wb').write(out)
+
+
+def main_arith(d):
+    W, H = 37, 23
+    encode_arith(d + '/lossless_arithmetic_16_bit.jpg', W, H, 16, [(1, 1, 1)], [dict(comps=[0], pred=1, pt=0)])
+    encode_arith(d + '/lossless_arithmetic_8_bit.jpg', W, H, 8, [(1, 1, 1)], [dict(comps=[0], pred=4, pt=0)],
+                 dac={0: (2, 5)})
+    encode_arith(d + '/lossless_arithmetic_restart.jpg', W, H, 12, [(1, 1, 1)], [dict(comps=[0], pred=7, pt=1, restart=W * 3)])
+    encode_arith(d + '/lossless_arithmetic_420.jpg', W, H, 10, [(1, 2, 2), (2, 1, 1), (3, 1, 1)],
+                 [dict(comps=[0, 1, 2], pred=6, pt=0, tables=[0, 1, 1], restart=19 * 4)], dac={1: (1, 3)})
+    encode_arith(d + '/lossless_arithmetic_scans.jpg', W, H, 8, [(1, 1, 1), (2, 1, 1), (3, 1, 1)],
+                 [dict(comps=[0], pred=5, pt=0), dict(comps=[1, 2], pred=2, pt=2)])
+
+
+if __name__ == '__main__':
+    main_huffman(sys.argv[1])
+    main_arith(sys.argv[1])
@@ -0,0 +1,139 @@
+//! Tests for ICC profiles split into chunks over APP2 segments
+use zune_jpeg::Decoder;
+
+use crate::common::{read, with_segments};
+
+mod common;
+
+/// A stand in for a profile, long enough to be split
+fn profile() -> Vec<u8>
+{
+    (0..3000_u32).map(|x| (x * 7 % 251) as u8).collect()
+}
+
+/// An APP2 segment holding chunk `sequence` of `count`
+fn chunk(sequence: u8, count: u8, data: &[u8]) -> Vec<u8>
+{
+    let mut segment = vec![0xFF, 0xE2];
+
+    segment.extend_from_slice(&u16::try_from(data.len() + 16).unwrap().to_be_bytes());
+    segment.extend_from_slice(b"ICC_PROFILE\0");
+    segment.extend_from_slice(&[sequence, count]);
+    segment.extend_from_slice(data);
+
+    segment
+}
+
+fn icc_profile(data: &[u8]) -> Option<Vec<u8>>
+{
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(data).unwrap();
+    decoder.icc_profile()
+}
+
+#[test]
+fn single_chunk()
+{
+    let profile = profile();
+    let data = with_segments(&[chunk(1, 1, &profile)]);
+
+    assert_eq!(icc_profile(&data), Some(profile));
+}
+
+#[test]
+fn chunks_out_of_order()
+{
+    let profile = profile();
+    let parts = profile.chunks(1000).collect::<Vec<_>>();
+    let data = with_segments(&[
+        chunk(2, 3, parts[1]),
+        chunk(3, 3, parts[2]),
+        chunk(1, 3, parts[0]),
+    ]);
+
+    assert_eq!(icc_profile(&data), Some(profile));
+}
+
+#[test]
+fn missing_chunk()
+{
+    let profile = profile();
+    let parts = profile.chunks(1000).collect::<Vec<_>>();
+    let data = with_segments(&[chunk(1, 3, parts[0]), chunk(3, 3, parts[2])]);
+
+    assert_eq!(icc_profile(&data), None);
+}
+
+#[test]
+fn duplicate_chunk_is_ignored()
+{
+    let profile = profile();
+    let parts = profile.chunks(1500).collect::<Vec<_>>();
+    let data = with_segments(&[
+        chunk(1, 2, parts[0]),
+        chunk(1, 2, &[0; 10]),
+        chunk(2, 2, parts[1]),
+    ]);
+
+    assert_eq!(icc_profile(&data), Some(profile));
+}
+
+#[test]
+fn inconsistent_chunk_counts()
+{
+    let profile = profile();
+    let parts = profile.chunks(1500).collect::<Vec<_>>();
+    let data = with_segments(&[chunk(1, 2, parts[0]), chunk(2, 3, parts[1])]);
+
+    assert_eq!(icc_profile(&data), None);
+}
+
+#[test]
+fn sequence_number_out_of_range()
+{
+    let profile = profile();
+
+    for (sequence, count) in [(0, 1), (2, 1)]
+    {
+        let data = with_segments(&[chunk(sequence, count, &profile)]);
+
+        assert_eq!(icc_profile(&data), None);
+    }
+}
+
+#[test]
+fn other_app2_segments_are_skipped()
+{
+    let profile = profile();
+    let mut flashpix = vec![0xFF, 0xE2, 0x00, 0x0A];
+
+    flashpix.extend_from_slice(b"FPXR\0\0\0\0");
+
+    let data = with_segments(&[flashpix, chunk(1, 1, &profile)]);
+    let mut decoder = Decoder::new();
+
+    // the image still decodes
+    decoder.decode_buffer(&data).unwrap();
+
+    assert_eq!(decoder.icc_profile(), Some(profile));
+}
+
+#[test]
+fn no_profile()
+{
+    assert_eq!(icc_profile(&read("sampling_420.jpg")), None);
+}
+
+#[test]
+fn headers_read_twice()
+{
+    let profile = profile();
+    let data = with_segments(&[chunk(1, 1, &profile)]);
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&data).unwrap();
+    decoder.decode_buffer(&data).unwrap();
+
+    assert_eq!(decoder.icc_profile(), Some(profile));
+}
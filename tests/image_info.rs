@@ -0,0 +1,154 @@
+//! Tests for the image information available after reading headers
+use zune_jpeg::{ColorSpace, ComponentInfo, Decoder, ImageInfo};
+
+use crate::common::read;
+
+mod common;
+
+fn info(name: &str) -> ImageInfo
+{
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&read(name)).unwrap();
+    decoder.info().unwrap()
+}
+
+/// Scans counted by walking the markers of an image
+fn scans(name: &str) -> usize
+{
+    Decoder::scans(&read(name)).unwrap()
+}
+
+/// Sampling factors of every component
+fn sampling(info: &ImageInfo) -> Vec<(u8, u8)>
+{
+    info.component_info
+        .iter()
+        .map(|x| (x.horizontal_sampling, x.vertical_sampling))
+        .collect()
+}
+
+#[test]
+fn baseline()
+{
+    let info = info("sampling_420.jpg");
+
+    assert_eq!(
+        info.component_info[0],
+        ComponentInfo {
+            id:                  1,
+            horizontal_sampling: 2,
+            vertical_sampling:   2,
+            quantization_table:  0,
+        }
+    );
+    assert_eq!(sampling(&info), [(2, 2), (1, 1), (1, 1)]);
+    assert_eq!(info.component_info[2].quantization_table, 1);
+    assert_eq!(info.precision, 8);
+    assert_eq!(scans("sampling_420.jpg"), 1);
+    assert_eq!(info.restart_interval, 0);
+    assert!(info.jfif);
+    assert_eq!(info.adobe_transform, None);
+    assert_eq!(info.colorspace, ColorSpace::YCbCr);
+}
+
+#[test]
+fn progressive_scans()
+{
+    let mixed = info("sampling_mixed_progressive.jpg");
+
+    assert_eq!(scans("sampling_mixed_progressive.jpg"), 10);
+    assert_eq!(sampling(&mixed), [(2, 2), (1, 1), (2, 1)]);
+    assert_eq!(scans("ycck_adobe_progressive_422.jpg"), 18);
+}
+
+#[test]
+fn restart_interval_and_separate_scans()
+{
+    let info = info("scans_separate_restart.jpg");
+
+    assert_eq!(info.restart_interval, 7);
+    assert_eq!(scans("scans_separate_restart.jpg"), 3);
+}
+
+#[test]
+fn adobe_colorspaces()
+{
+    for (name, transform, colorspace) in [
+        ("cmyk_adobe.jpg", Some(0), ColorSpace::CMYK),
+        ("rgb_adobe.jpg", Some(0), ColorSpace::RGB),
+        ("ycck_adobe_progressive_422.jpg", Some(2), ColorSpace::YCCK),
+        ("rgb_ids.jpg", None, ColorSpace::RGB),
+    ]
+    {
+        let info = info(name);
+
+        assert!(!info.jfif, "{}", name);
+        assert_eq!(info.adobe_transform, transform, "{}", name);
+        assert_eq!(info.colorspace, colorspace, "{}", name);
+    }
+}
+
+#[test]
+fn adobe_segment_of_previous_image()
+{
+    // the transform of a YCCK image mustn't carry over to a CMYK image without one
+    let mut decoder = Decoder::new();
+
+    decoder
+        .read_headers(&read("ycck_adobe_progressive_422.jpg"))
+        .unwrap();
+    decoder.read_headers(&read("cmyk_no_adobe.jpg")).unwrap();
+
+    let info = decoder.info().unwrap();
+
+    assert_eq!(info.adobe_transform, None);
+    assert_eq!(info.colorspace, ColorSpace::CMYK);
+}
+
+#[test]
+fn jfif_segment_of_previous_image()
+{
+    // 'R','G','B' identifiers mean RGB once the JFIF image before is gone
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&read("sampling_420.jpg")).unwrap();
+    decoder.read_headers(&read("rgb_ids.jpg")).unwrap();
+
+    let info = decoder.info().unwrap();
+
+    assert!(!info.jfif);
+    assert_eq!(info.colorspace, ColorSpace::RGB);
+}
+
+#[test]
+fn component_ids()
+{
+    let ids = info("rgb_ids.jpg")
+        .component_info
+        .iter()
+        .map(|x| x.id)
+        .collect::<Vec<_>>();
+
+    assert_eq!(ids, b"RGB");
+}
+
+#[test]
+fn precision()
+{
+    let lossless = info("lossless_arithmetic_16_bit.jpg");
+
+    assert_eq!(lossless.precision, 16);
+    assert_eq!(lossless.colorspace, ColorSpace::GRAYSCALE);
+    assert_eq!(info("extended_12_bit_rgb.jpg").precision, 12);
+}
+
+#[test]
+fn hierarchical_frames()
+{
+    let info = info("hierarchical_components.jpg");
+
+    // components from the DHP segment, and the scans of every frame
+    assert_eq!(info.component_info.len(), 3);
+    assert_eq!(scans("hierarchical_components.jpg"), 4);
+}
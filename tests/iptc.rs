@@ -0,0 +1,218 @@
+//! Tests for Photoshop image resources and IPTC datasets in APP13 segments
+use zune_jpeg::iptc::{IptcDataSet, DATASET_CAPTION, RESOURCE_IPTC};
+use zune_jpeg::Decoder;
+
+use crate::common::{headers, read, with_segments};
+
+mod common;
+
+/// An IPTC dataset
+fn dataset(record: u8, number: u8, data: &[u8]) -> Vec<u8>
+{
+    let mut dataset = vec![0x1C, record, number];
+
+    dataset.extend_from_slice(&u16::try_from(data.len()).unwrap().to_be_bytes());
+    dataset.extend_from_slice(data);
+
+    dataset
+}
+
+/// An image resource block with its padding
+fn resource(id: u16, name: &str, data: &[u8]) -> Vec<u8>
+{
+    let mut block = b"8BIM".to_vec();
+
+    block.extend_from_slice(&id.to_be_bytes());
+    block.push(u8::try_from(name.len()).unwrap());
+    block.extend_from_slice(name.as_bytes());
+
+    if name.len() % 2 == 0
+    {
+        block.push(0);
+    }
+    block.extend_from_slice(&u32::try_from(data.len()).unwrap().to_be_bytes());
+    block.extend_from_slice(data);
+
+    if data.len() % 2 == 1
+    {
+        block.push(0);
+    }
+
+    block
+}
+
+/// An APP13 segment holding `resources`
+fn app13(resources: &[u8]) -> Vec<u8>
+{
+    let mut segment = vec![0xFF, 0xED];
+
+    segment.extend_from_slice(&u16::try_from(resources.len() + 16).unwrap().to_be_bytes());
+    segment.extend_from_slice(b"Photoshop 3.0\0");
+    segment.extend_from_slice(resources);
+
+    segment
+}
+
+/// Datasets of a news picture
+fn news_datasets() -> Vec<u8>
+{
+    [
+        dataset(1, 90, b"\x1B%G"),
+        dataset(2, 0, &[0, 4]),
+        dataset(2, 5, b"Harbour"),
+        dataset(2, 25, b"boats"),
+        dataset(2, 25, b"sunrise"),
+        dataset(2, 25, "Zürich".as_bytes()),
+        dataset(2, 55, b"20240102"),
+        dataset(2, 60, b"063000+0100"),
+        dataset(2, 80, b"A. Photographer"),
+        dataset(2, 90, b"Hamburg"),
+        dataset(2, 101, b"Germany"),
+        dataset(2, 105, b"Boats leave at dawn"),
+        dataset(2, 110, b"Agency"),
+        dataset(2, 115, b"Newsroom"),
+        dataset(2, 116, b"(c) Agency 2024"),
+        dataset(2, 120, b"Fishing boats leave the harbour at sunrise."),
+    ]
+    .concat()
+}
+
+#[test]
+fn news_picture()
+{
+    let data = with_segments(&[app13(&resource(RESOURCE_IPTC, "", &news_datasets()))]);
+    let iptc = headers(&data).iptc().unwrap();
+
+    assert_eq!(iptc.object_name().as_deref(), Some("Harbour"));
+    assert_eq!(iptc.keywords(), ["boats", "sunrise", "Zürich"]);
+    assert_eq!(iptc.date_created().as_deref(), Some("20240102"));
+    assert_eq!(iptc.time_created().as_deref(), Some("063000+0100"));
+    assert_eq!(iptc.by_line(), ["A. Photographer"]);
+    assert_eq!(iptc.city().as_deref(), Some("Hamburg"));
+    assert_eq!(iptc.country().as_deref(), Some("Germany"));
+    assert_eq!(iptc.headline().as_deref(), Some("Boats leave at dawn"));
+    assert_eq!(iptc.credit().as_deref(), Some("Agency"));
+    assert_eq!(iptc.source().as_deref(), Some("Newsroom"));
+    assert_eq!(iptc.copyright_notice().as_deref(), Some("(c) Agency 2024"));
+    assert_eq!(
+        iptc.caption().as_deref(),
+        Some("Fishing boats leave the harbour at sunrise.")
+    );
+    assert_eq!(
+        iptc.datasets()[1],
+        IptcDataSet {
+            record:  2,
+            dataset: 0,
+            data:    vec![0, 4],
+        }
+    );
+    assert_eq!(iptc.datasets().len(), 16);
+}
+
+#[test]
+fn latin1_text()
+{
+    // ISO 8859-1 is assumed without a coded character set
+    let datasets = dataset(2, 90, b"Z\xFCrich");
+    let data = with_segments(&[app13(&resource(RESOURCE_IPTC, "", &datasets))]);
+    let iptc = headers(&data).iptc().unwrap();
+
+    assert_eq!(iptc.city().as_deref(), Some("Zürich"));
+}
+
+#[test]
+fn extended_dataset()
+{
+    let caption = "A long caption. ".repeat(10);
+    let mut datasets = vec![0x1C, 2, 120, 0x80, 0x04];
+
+    datasets.extend_from_slice(&u32::try_from(caption.len()).unwrap().to_be_bytes());
+    datasets.extend_from_slice(caption.as_bytes());
+    datasets.extend_from_slice(&dataset(2, 110, b"Agency"));
+
+    let data = with_segments(&[app13(&resource(RESOURCE_IPTC, "", &datasets))]);
+    let iptc = headers(&data).iptc().unwrap();
+
+    assert_eq!(iptc.get(DATASET_CAPTION), Some(caption));
+    assert_eq!(iptc.credit().as_deref(), Some("Agency"));
+}
+
+#[test]
+fn other_resources()
+{
+    // resolution info, a named resource with odd lengths and the IPTC resource
+    let resources = [
+        resource(0x03ED, "", &[0, 72, 0, 0, 0, 1, 0, 1, 0, 72, 0, 0, 0, 1, 0, 1]),
+        resource(0x0BB7, "clip", &[1, 2, 3]),
+        resource(RESOURCE_IPTC, "", &dataset(2, 120, b"Caption")),
+    ]
+    .concat();
+    let decoder = headers(&with_segments(&[app13(&resources)]));
+    let found = decoder.image_resources();
+
+    assert_eq!(found.len(), 3);
+    assert_eq!(found[0].id, 0x03ED);
+    assert_eq!(found[1].name, "clip");
+    assert_eq!(found[1].data, [1, 2, 3]);
+    assert_eq!(decoder.iptc().unwrap().caption().as_deref(), Some("Caption"));
+}
+
+#[test]
+fn resources_split_over_segments()
+{
+    let block = resource(RESOURCE_IPTC, "", &news_datasets());
+    let (first, second) = block.split_at(100);
+    let data = with_segments(&[app13(first), app13(second)]);
+    let iptc = headers(&data).iptc().unwrap();
+
+    assert_eq!(iptc.keywords().len(), 3);
+    assert!(iptc.caption().is_some());
+}
+
+#[test]
+fn padding_after_datasets()
+{
+    let mut datasets = dataset(2, 105, b"Headline");
+
+    datasets.extend_from_slice(&[0; 6]);
+
+    let data = with_segments(&[app13(&resource(RESOURCE_IPTC, "", &datasets))]);
+    let iptc = headers(&data).iptc().unwrap();
+
+    assert_eq!(iptc.datasets().len(), 1);
+}
+
+#[test]
+fn truncated_dataset()
+{
+    let mut datasets = dataset(2, 105, b"Headline");
+    let mut truncated = dataset(2, 120, b"Caption");
+
+    truncated.truncate(9);
+    datasets.extend_from_slice(&truncated);
+
+    let data = with_segments(&[app13(&resource(RESOURCE_IPTC, "", &datasets))]);
+    let mut decoder = Decoder::new();
+
+    // the image still decodes, with the datasets before the corrupt one
+    decoder.decode_buffer(&data).unwrap();
+
+    let iptc = decoder.iptc().unwrap();
+
+    assert_eq!(iptc.headline().as_deref(), Some("Headline"));
+    assert_eq!(iptc.caption(), None);
+}
+
+#[test]
+fn no_iptc()
+{
+    let decoder = headers(&read("sampling_420.jpg"));
+
+    assert!(decoder.image_resources().is_empty());
+    assert!(decoder.iptc().is_none());
+
+    // resources without IPTC
+    let data = with_segments(&[app13(&resource(0x03ED, "", &[0; 16]))]);
+
+    assert!(headers(&data).iptc().is_none());
+}
@@ -0,0 +1,187 @@
+//! Tests for the information read from JFIF and JFXX APP0 segments
+use zune_jpeg::{Decoder, Thumbnail};
+
+use crate::common::read;
+
+mod common;
+
+/// Replace the APP0 segment libjpeg wrote with `segment`, which holds the data after the length
+fn with_app0(segment: &[u8]) -> Vec<u8>
+{
+    let mut data = read("sampling_420.jpg");
+    let length = usize::from(u16::from_be_bytes([data[4], data[5]]));
+    let mut app0 = vec![0xFF, 0xE0];
+
+    app0.extend_from_slice(&u16::try_from(segment.len() + 2).unwrap().to_be_bytes());
+    app0.extend_from_slice(segment);
+
+    data.splice(2..4 + length, app0);
+
+    data
+}
+
+/// A JFIF segment with the given density and no thumbnail
+fn jfif(units: u8, x: u16, y: u16) -> Vec<u8>
+{
+    let mut segment = b"JFIF\0\x01\x02".to_vec();
+
+    segment.push(units);
+    segment.extend_from_slice(&x.to_be_bytes());
+    segment.extend_from_slice(&y.to_be_bytes());
+    segment.extend_from_slice(&[0, 0]);
+
+    segment
+}
+
+fn info(data: &[u8]) -> zune_jpeg::ImageInfo
+{
+    let mut decoder = Decoder::new();
+
+    decoder.decode_buffer(data).unwrap();
+    decoder.info().unwrap()
+}
+
+#[test]
+fn jfif_written_by_libjpeg()
+{
+    let info = info(&read("sampling_420.jpg"));
+
+    assert_eq!(info.jfif_version, Some((1, 1)));
+    assert_eq!(info.pixel_density, 0);
+    assert_eq!((info.x_density, info.y_density), (1, 1));
+    assert_eq!(info.dpi(), None);
+    assert_eq!(info.thumbnail, None);
+}
+
+#[test]
+fn dots_per_inch()
+{
+    let info = info(&with_app0(&jfif(1, 300, 150)));
+
+    assert_eq!(info.jfif_version, Some((1, 2)));
+    assert_eq!(info.pixel_density, 1);
+    assert_eq!(info.dpi(), Some((300, 150)));
+}
+
+#[test]
+fn dots_per_centimeter()
+{
+    let info = info(&with_app0(&jfif(2, 118, 28)));
+
+    assert_eq!(info.pixel_density, 2);
+    assert_eq!((info.x_density, info.y_density), (118, 28));
+    assert_eq!(info.dpi(), Some((300, 71)));
+}
+
+#[test]
+fn density_from_headers_only()
+{
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&with_app0(&jfif(1, 72, 72))).unwrap();
+
+    assert_eq!(decoder.info().unwrap().dpi(), Some((72, 72)));
+}
+
+#[test]
+fn jfif_rgb_thumbnail()
+{
+    let mut segment = jfif(0, 1, 1);
+    let pixels = [255, 0, 0, 0, 255, 0];
+
+    segment.truncate(segment.len() - 2);
+    segment.extend_from_slice(&[2, 1]);
+    segment.extend_from_slice(&pixels);
+
+    let info = info(&with_app0(&segment));
+
+    assert_eq!(
+        info.thumbnail,
+        Some(Thumbnail::Rgb {
+            width:  2,
+            height: 1,
+            pixels: pixels.to_vec(),
+        })
+    );
+}
+
+#[test]
+fn jfxx_jpeg_thumbnail()
+{
+    let thumbnail = read("arithmetic_grayscale.jpg");
+    let mut segment = b"JFXX\0\x10".to_vec();
+
+    segment.extend_from_slice(&thumbnail);
+
+    let mut data = with_app0(&jfif(1, 96, 96));
+    let mut jfxx = vec![0xFF, 0xE0];
+
+    jfxx.extend_from_slice(&u16::try_from(segment.len() + 2).unwrap().to_be_bytes());
+    jfxx.extend_from_slice(&segment);
+
+    // the JFXX segment follows the JFIF segment
+    let after_jfif = 4 + usize::from(u16::from_be_bytes([data[4], data[5]]));
+
+    data.splice(after_jfif..after_jfif, jfxx);
+
+    let info = info(&data);
+
+    assert_eq!(info.dpi(), Some((96, 96)));
+    assert_eq!(info.thumbnail, Some(Thumbnail::Jpeg(thumbnail)));
+}
+
+#[test]
+fn jfxx_palette_thumbnail()
+{
+    let colors: Vec<u8> = (0..=255).flat_map(|x| [x, x, 255 - x]).collect();
+    let mut segment = b"JFXX\0\x11\x02\x02".to_vec();
+
+    segment.extend_from_slice(&colors);
+    segment.extend_from_slice(&[0, 10, 20, 30]);
+
+    let info = info(&with_app0(&segment));
+
+    assert_eq!(
+        info.thumbnail,
+        Some(Thumbnail::Palette {
+            width:   2,
+            height:  2,
+            palette: colors,
+            pixels:  vec![0, 10, 20, 30],
+        })
+    );
+}
+
+#[test]
+fn truncated_thumbnail_is_ignored()
+{
+    let mut segment = b"JFXX\0\x13\x04\x04".to_vec();
+
+    segment.extend_from_slice(&[0; 10]);
+
+    let info = info(&with_app0(&segment));
+
+    assert_eq!(info.thumbnail, None);
+}
+
+#[test]
+fn headers_read_twice()
+{
+    // the JFIF segment of the first image isn't kept for one without any
+    let mut segment = jfif(0, 1, 1);
+
+    segment.truncate(segment.len() - 2);
+    segment.extend_from_slice(&[1, 1, 0, 0, 255]);
+
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&with_app0(&segment)).unwrap();
+    assert!(decoder.info().unwrap().thumbnail.is_some());
+
+    decoder.read_headers(&read("rgb_ids.jpg")).unwrap();
+
+    let info = decoder.info().unwrap();
+
+    assert_eq!(info.jfif_version, None);
+    assert_eq!(info.thumbnail, None);
+}
@@ -0,0 +1,413 @@
+//! Tests for lossless images
+//!
+//! The images encode a known pattern, lossless decoding must give back
+//! exactly the same samples. They are written by the encoders in tests/generators,
+//! see the README there for how these were checked.
+use zune_jpeg::errors::DecodeErrors;
+use zune_jpeg::{ColorSpace, Decoder, ZuneJpegOptions};
+
+use crate::common::read;
+
+mod common;
+
+const WIDTH: usize = 37;
+const HEIGHT: usize = 23;
+
+/// The sample encoded at `(x,y)` of component `c` for an image with `precision` bits
+fn pattern(x: usize, y: usize, c: usize, precision: u8) -> u16
+{
+    let max = (1_usize << precision) - 1;
+
+    let value = if y == 2
+    {
+        // alternating between 0 and half the range, differences of 32768 for 16 bit images
+        (x % 2) << (precision - 1)
+    }
+    else if (x + y) % 11 == 0
+    {
+        max
+    }
+    else if (3 * x + y) % 13 == 0
+    {
+        0
+    }
+    else
+    {
+        x * x * 3
+            + y * 5
+            + x * y
+            + c * 1000
+            + ((x ^ y) & 7) * 31
+            + (x * 7919 + y * 104_729) % 997
+    };
+
+    (value & max) as u16
+}
+
+/// Expected samples of a component subsampled by `(h, v)` relative to the largest component,
+/// with the lowest `pt` bits dropped by the point transform
+fn expected_component(c: usize, h: usize, v: usize, precision: u8, pt: u8) -> Vec<u16>
+{
+    let mut samples = Vec::with_capacity(WIDTH * HEIGHT);
+
+    for y in 0..HEIGHT
+    {
+        for x in 0..WIDTH
+        {
+            samples.push((pattern(x / h, y / v, c, precision) >> pt) << pt);
+        }
+    }
+    samples
+}
+
+fn interleave(components: &[Vec<u16>]) -> Vec<u16>
+{
+    (0..WIDTH * HEIGHT)
+        .flat_map(|i| components.iter().map(move |c| c[i]))
+        .collect()
+}
+
+fn decode(name: &str, options: ZuneJpegOptions) -> (Decoder, Vec<u16>)
+{
+    let mut decoder = Decoder::new_with_options(options);
+    let pixels = decoder.decode_buffer_u16(&read(name)).unwrap();
+
+    assert_eq!(usize::from(decoder.width()), WIDTH);
+    assert_eq!(usize::from(decoder.height()), HEIGHT);
+
+    (decoder, pixels)
+}
+
+#[test]
+fn lossless_predictor_1_16_bit()
+{
+    let (decoder, pixels) = decode("lossless_predictor_1.jpg", ZuneJpegOptions::new());
+
+    assert_eq!(decoder.precision(), 16);
+    assert!(pixels == expected_component(0, 1, 1, 16, 0));
+}
+
+#[test]
+fn lossless_predictor_2()
+{
+    let (_, pixels) = decode("lossless_predictor_2.jpg", ZuneJpegOptions::new());
+
+    assert!(pixels == expected_component(0, 1, 1, 8, 0));
+}
+
+#[test]
+fn lossless_predictor_3_restart_markers()
+{
+    let (decoder, pixels) = decode("lossless_predictor_3.jpg", ZuneJpegOptions::new());
+
+    assert_eq!(decoder.precision(), 12);
+    assert!(pixels == expected_component(0, 1, 1, 12, 0));
+}
+
+#[test]
+fn lossless_predictor_4_2_bit()
+{
+    let (decoder, pixels) = decode("lossless_predictor_4.jpg", ZuneJpegOptions::new());
+
+    assert_eq!(decoder.precision(), 2);
+    assert!(pixels == expected_component(0, 1, 1, 2, 0));
+}
+
+#[test]
+fn lossless_predictor_5_interleaved()
+{
+    let (_, pixels) = decode(
+        "lossless_predictor_5.jpg",
+        ZuneJpegOptions::new().set_out_colorspace(ColorSpace::YCbCr),
+    );
+
+    let expected = interleave(&[
+        expected_component(0, 1, 1, 10, 0),
+        expected_component(1, 1, 1, 10, 0),
+        expected_component(2, 1, 1, 10, 0),
+    ]);
+
+    assert!(pixels == expected);
+}
+
+#[test]
+fn lossless_predictor_6_multiple_scans()
+{
+    // each component in its own scan, the luma component has twice the horizontal
+    // resolution of chroma and the samples have a point transform of 2
+    let (_, pixels) = decode(
+        "lossless_predictor_6.jpg",
+        ZuneJpegOptions::new().set_out_colorspace(ColorSpace::YCbCr),
+    );
+
+    let expected = interleave(&[
+        expected_component(0, 1, 1, 8, 2),
+        expected_component(1, 2, 1, 8, 2),
+        expected_component(2, 2, 1, 8, 2),
+    ]);
+
+    assert!(pixels == expected);
+}
+
+#[test]
+fn lossless_predictor_7_subsampled()
+{
+    // 4:2:0 interleaved scan with restart markers and a point transform of 3
+    let (_, pixels) = decode(
+        "lossless_predictor_7.jpg",
+        ZuneJpegOptions::new().set_out_colorspace(ColorSpace::YCbCr),
+    );
+
+    let expected = interleave(&[
+        expected_component(0, 1, 1, 16, 3),
+        expected_component(1, 2, 2, 16, 3),
+        expected_component(2, 2, 2, 16, 3),
+    ]);
+
+    assert!(pixels == expected);
+}
+
+#[test]
+fn lossless_point_transform_above_13()
+{
+    // a point transform only has to be smaller than the precision in lossless images
+    let (_, pixels) = decode("lossless_point_transform.jpg", ZuneJpegOptions::new());
+
+    assert!(pixels == expected_component(0, 1, 1, 16, 14));
+}
+
+#[test]
+fn lossless_rgb_output()
+{
+    let (_, ycbcr) = decode(
+        "lossless_predictor_5.jpg",
+        ZuneJpegOptions::new().set_out_colorspace(ColorSpace::YCbCr),
+    );
+    let (_, rgba) = decode(
+        "lossless_predictor_5.jpg",
+        ZuneJpegOptions::new().set_out_colorspace(ColorSpace::RGBA),
+    );
+
+    assert_eq!(rgba.len(), WIDTH * HEIGHT * 4);
+
+    for (ycbcr, rgba) in ycbcr.chunks_exact(3).zip(rgba.chunks_exact(4))
+    {
+        // neutral chroma keeps luma
+        if ycbcr[1] == 512 && ycbcr[2] == 512
+        {
+            assert_eq!(&rgba[..3], &[ycbcr[0]; 3]);
+        }
+        assert!(rgba.iter().all(|x| *x < 1024));
+        assert_eq!(rgba[3], 1023);
+    }
+}
+
+#[test]
+fn lossless_8_bit_output()
+{
+    // 16 bit samples are scaled down
+    let pixels = Decoder::new()
+        .decode_buffer(&read("lossless_predictor_1.jpg"))
+        .unwrap();
+
+    let expected = expected_component(0, 1, 1, 16, 0)
+        .iter()
+        .map(|x| (x >> 8) as u8)
+        .collect::<Vec<u8>>();
+
+    assert!(pixels == expected);
+
+    // and 2 bit samples scaled up
+    let pixels = Decoder::new()
+        .decode_buffer(&read("lossless_predictor_4.jpg"))
+        .unwrap();
+
+    let expected = expected_component(0, 1, 1, 2, 0)
+        .iter()
+        .map(|x| (x << 6) as u8)
+        .collect::<Vec<u8>>();
+
+    assert!(pixels == expected);
+}
+
+#[test]
+fn lossless_arithmetic_16_bit()
+{
+    let (decoder, pixels) = decode("lossless_arithmetic_16_bit.jpg", ZuneJpegOptions::new());
+
+    assert_eq!(decoder.precision(), 16);
+    assert!(pixels == expected_component(0, 1, 1, 16, 0));
+}
+
+#[test]
+fn lossless_arithmetic_conditioning()
+{
+    // uses a DAC segment with non default conditioning bounds
+    let (_, pixels) = decode("lossless_arithmetic_8_bit.jpg", ZuneJpegOptions::new());
+
+    assert!(pixels == expected_component(0, 1, 1, 8, 0));
+}
+
+#[test]
+fn lossless_arithmetic_restart_markers()
+{
+    let (_, pixels) = decode("lossless_arithmetic_restart.jpg", ZuneJpegOptions::new());
+
+    assert!(pixels == expected_component(0, 1, 1, 12, 1));
+}
+
+#[test]
+fn lossless_arithmetic_subsampled()
+{
+    // both chroma components share a conditioning table
+    let (_, pixels) = decode(
+        "lossless_arithmetic_420.jpg",
+        ZuneJpegOptions::new().set_out_colorspace(ColorSpace::YCbCr),
+    );
+
+    let expected = interleave(&[
+        expected_component(0, 1, 1, 10, 0),
+        expected_component(1, 2, 2, 10, 0),
+        expected_component(2, 2, 2, 10, 0),
+    ]);
+
+    assert!(pixels == expected);
+}
+
+#[test]
+fn lossless_arithmetic_multiple_scans()
+{
+    let (_, pixels) = decode(
+        "lossless_arithmetic_scans.jpg",
+        ZuneJpegOptions::new().set_out_colorspace(ColorSpace::YCbCr),
+    );
+
+    let expected = interleave(&[
+        expected_component(0, 1, 1, 8, 0),
+        expected_component(1, 1, 1, 8, 2),
+        expected_component(2, 1, 1, 8, 2),
+    ]);
+
+    assert!(pixels == expected);
+}
+
+#[test]
+fn lossless_invalid_predictor()
+{
+    let mut data = read("lossless_predictor_2.jpg");
+    // the predictor is the first byte after the component specifications of SOS
+    let pos = data
+        .windows(2)
+        .position(|x| x == [0xFF, 0xDA])
+        .unwrap();
+
+    data[pos + 7] = 8;
+
+    let err = Decoder::new().decode_buffer(&data).unwrap_err();
+
+    assert!(matches!(err, DecodeErrors::SosError(_)), "{:?}", err);
+}
+
+#[test]
+fn hierarchical_huffman()
+{
+    // a half resolution frame, expanded and refined by a differential frame
+    let (decoder, pixels) = decode("hierarchical_huffman.jpg", ZuneJpegOptions::new());
+
+    assert_eq!(decoder.info().unwrap().components, 1);
+    assert!(pixels == expected_component(0, 1, 1, 8, 0));
+}
+
+#[test]
+fn hierarchical_arithmetic()
+{
+    // expanded horizontally then vertically, the middle frame has a point transform
+    let (decoder, pixels) = decode("hierarchical_arithmetic.jpg", ZuneJpegOptions::new());
+
+    assert_eq!(decoder.precision(), 12);
+    assert!(pixels == expected_component(0, 1, 1, 12, 0));
+}
+
+#[test]
+fn hierarchical_frames_with_some_components()
+{
+    // luma is in the first frame, chroma in the following ones
+    let (decoder, pixels) = decode(
+        "hierarchical_components.jpg",
+        ZuneJpegOptions::new().set_out_colorspace(ColorSpace::YCbCr),
+    );
+
+    let expected = interleave(&[
+        expected_component(0, 1, 1, 8, 0),
+        expected_component(1, 1, 1, 8, 0),
+        expected_component(2, 1, 1, 8, 0),
+    ]);
+
+    assert_eq!(decoder.info().unwrap().components, 3);
+    assert!(pixels == expected);
+}
+
+#[test]
+fn hierarchical_then_sequential()
+{
+    // the hierarchy of the first image mustn't apply to the next one
+    let mut decoder = Decoder::new();
+
+    decoder
+        .decode_buffer(&read("hierarchical_dct_arithmetic.jpg"))
+        .unwrap();
+
+    let pixels = decoder.decode_buffer(&read("sampling_420.jpg")).unwrap();
+
+    assert_eq!(decoder.width(), 75);
+    assert_eq!(decoder.height(), 53);
+    assert_eq!(pixels.len(), 75 * 53 * 3);
+}
+
+#[test]
+fn hierarchical_dct_huffman()
+{
+    // a baseline frame, then a differential sequential and a differential progressive frame
+    // with restart markers, closed by a lossless frame which makes the output exact
+    for options in [ZuneJpegOptions::new(), ZuneJpegOptions::new().set_use_unsafe(false)]
+    {
+        let (_, pixels) = decode("hierarchical_dct_huffman.jpg", options);
+
+        assert!(pixels == expected_component(0, 1, 1, 8, 0));
+    }
+}
+
+#[test]
+fn hierarchical_dct_arithmetic()
+{
+    // 4:2:0, interleaved and non-interleaved scans of sequential and progressive frames
+    let expected = interleave(&[
+        expected_component(0, 1, 1, 8, 0),
+        expected_component(1, 2, 2, 8, 0),
+        expected_component(2, 2, 2, 8, 0),
+    ]);
+
+    for options in [ZuneJpegOptions::new(), ZuneJpegOptions::new().set_use_unsafe(false)]
+    {
+        let (decoder, pixels) = decode(
+            "hierarchical_dct_arithmetic.jpg",
+            options.set_out_colorspace(ColorSpace::YCbCr),
+        );
+
+        assert_eq!(decoder.info().unwrap().components, 3);
+        assert!(pixels == expected);
+    }
+}
+
+#[test]
+fn hierarchical_dct_12_bit()
+{
+    // a progressive first frame and 16 bit quantization tables
+    for options in [ZuneJpegOptions::new(), ZuneJpegOptions::new().set_use_unsafe(false)]
+    {
+        let (decoder, pixels) = decode("hierarchical_dct_12_bit.jpg", options);
+
+        assert_eq!(decoder.precision(), 12);
+        assert!(pixels == expected_component(0, 1, 1, 12, 0));
+    }
+}
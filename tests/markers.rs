@@ -0,0 +1,107 @@
+//! Tests for how markers between headers are handled
+use zune_jpeg::errors::DecodeErrors;
+use zune_jpeg::Decoder;
+
+use crate::common::read;
+
+mod common;
+
+/// Position of the frame header marker
+fn find_sof(data: &[u8]) -> usize
+{
+    data.windows(2).position(|w| w == [0xFF, 0xC0]).unwrap()
+}
+
+/// Insert `segment` right after the SOI marker
+fn insert_after_soi(name: &str, segment: &[u8]) -> Vec<u8>
+{
+    let mut data = read(name);
+
+    data.splice(2..2, segment.iter().copied());
+
+    data
+}
+
+fn assert_same_as_original(data: &[u8])
+{
+    let expected = Decoder::new().decode_buffer(&read("sampling_420.jpg")).unwrap();
+    let pixels = Decoder::new().decode_buffer(data).unwrap();
+
+    assert!(pixels == expected, "pixels differ");
+}
+
+#[test]
+fn segments_are_skipped()
+{
+    // APP2 to APP15, JPG, JPGn, reserved markers and COM are followed by a length
+    let markers = (0xE2..=0xEF).chain([0xC8, 0xF0, 0xF5, 0xFD, 0x02, 0x4F, 0xBF, 0xFE]);
+
+    for marker in markers
+    {
+        // the segment holds bytes which look like markers, they mustn't be parsed
+        let segment = [0xFF, marker, 0x00, 0x06, 0xFF, 0xD9, 0xFF, 0xC4];
+        let data = insert_after_soi("sampling_420.jpg", &segment);
+
+        assert_same_as_original(&data);
+    }
+}
+
+#[test]
+fn standalone_markers_are_skipped()
+{
+    // TEM and RST markers have no length, what follows is the next marker
+    for marker in [0x01, 0xD0, 0xD7]
+    {
+        let data = insert_after_soi("sampling_420.jpg", &[0xFF, marker]);
+
+        assert_same_as_original(&data);
+    }
+}
+
+#[test]
+fn fill_bytes_before_markers()
+{
+    let mut data = read("sampling_420.jpg");
+    let sof = find_sof(&data);
+
+    data.splice(sof..sof, [0xFF, 0xFF, 0xFF]);
+
+    assert_same_as_original(&data);
+}
+
+#[test]
+fn extended_sequential_huffman()
+{
+    // SOF1 images with 8 bit samples are the same as baseline ones
+    let mut data = read("sampling_420.jpg");
+    let sof = find_sof(&data);
+
+    data[sof + 1] = 0xC1;
+
+    assert_same_as_original(&data);
+}
+
+#[test]
+fn differential_dct_frames()
+{
+    // differential frames are only valid in hierarchical images, after a DHP segment
+    for marker in [0xC5, 0xC6, 0xCD, 0xCE]
+    {
+        let mut data = read("sampling_420.jpg");
+        let sof = find_sof(&data);
+
+        data[sof + 1] = marker;
+
+        let err = Decoder::new().decode_buffer(&data).unwrap_err();
+
+        assert!(
+            matches!(
+                err,
+                DecodeErrors::FormatStatic("Differential frame outside of a hierarchical image")
+            ),
+            "0xFF{:X}: {:?}",
+            marker,
+            err
+        );
+    }
+}
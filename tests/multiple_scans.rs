@@ -0,0 +1,54 @@
+//! Tests for sequential images whose components are spread over several scans
+//!
+//! The images hold the same coefficients as an image with a single interleaved
+//! scan, so they have to decode to the same pixels.
+use zune_jpeg::{ColorSpace, Decoder, ZuneJpegOptions};
+
+use crate::common::read;
+
+mod common;
+
+fn decode(name: &str, colorspace: ColorSpace) -> Vec<u8>
+{
+    let mut decoder =
+        Decoder::new_with_options(ZuneJpegOptions::new().set_out_colorspace(colorspace));
+
+    decoder.decode_buffer(&read(name)).unwrap()
+}
+
+fn assert_same_pixels(name: &str, single_scan: &str)
+{
+    for colorspace in [ColorSpace::RGB, ColorSpace::GRAYSCALE, ColorSpace::YCbCr]
+    {
+        let pixels = decode(name, colorspace);
+        let expected = decode(single_scan, colorspace);
+
+        assert!(pixels == expected, "{}: {:?} pixels differ", name, colorspace);
+    }
+}
+
+#[test]
+fn scan_per_component()
+{
+    // Y, then Cb, then Cr
+    assert_same_pixels("scans_separate.jpg", "sampling_420.jpg");
+}
+
+#[test]
+fn chroma_in_one_scan()
+{
+    // Y, then an interleaved scan of Cb and Cr
+    assert_same_pixels("scans_chroma_interleaved.jpg", "sampling_420.jpg");
+}
+
+#[test]
+fn scans_with_restart_markers()
+{
+    assert_same_pixels("scans_separate_restart.jpg", "sampling_422.jpg");
+}
+
+#[test]
+fn arithmetic_scans()
+{
+    assert_same_pixels("scans_separate_arithmetic.jpg", "sampling_420.jpg");
+}
@@ -0,0 +1,164 @@
+//! Tests for turning images upright using their Exif orientation
+use zune_jpeg::{ColorSpace, Decoder, ZuneJpegOptions};
+
+use crate::common::read;
+
+mod common;
+
+/// `name` with the Exif segment of `exif_little_endian.jpg` set to `orientation`
+/// inserted after the start of image marker
+fn with_orientation(name: &str, orientation: u16) -> Vec<u8>
+{
+    let exif = read("exif_little_endian.jpg");
+    let start = exif.windows(2).position(|x| x == [0xFF, 0xE1]).unwrap();
+    let length = usize::from(u16::from_be_bytes([exif[start + 2], exif[start + 3]]));
+    let mut segment = exif[start..start + 2 + length].to_vec();
+    // the orientation is the third entry of IFD0, the TIFF header is after the
+    // marker, length and Exif\0\0
+    let value = 10 + 8 + 2 + 12 * 2 + 8;
+
+    assert_eq!(segment[value - 8..value - 6], [0x12, 0x01]);
+    segment[value..value + 2].copy_from_slice(&orientation.to_le_bytes());
+
+    let mut data = read(name);
+
+    data.splice(2..2, segment);
+
+    data
+}
+
+fn decode(data: &[u8], options: ZuneJpegOptions) -> (Vec<u8>, usize, usize)
+{
+    let mut decoder = Decoder::new_with_options(options);
+    let pixels = decoder.decode_buffer(data).unwrap();
+
+    (
+        pixels,
+        usize::from(decoder.width()),
+        usize::from(decoder.height()),
+    )
+}
+
+/// Turn `pixels` upright, one pixel at a time
+fn reference(
+    pixels: &[u8], width: usize, height: usize, components: usize, orientation: u16,
+) -> Vec<u8>
+{
+    let (out_width, out_height) = if orientation >= 5 { (height, width) } else { (width, height) };
+    let mut output = Vec::with_capacity(pixels.len());
+
+    for y in 0..out_height
+    {
+        for x in 0..out_width
+        {
+            let (source_x, source_y) = match orientation
+            {
+                2 => (width - 1 - x, y),
+                3 => (width - 1 - x, height - 1 - y),
+                4 => (x, height - 1 - y),
+                5 => (y, x),
+                6 => (y, height - 1 - x),
+                7 => (width - 1 - y, height - 1 - x),
+                8 => (width - 1 - y, x),
+                _ => (x, y),
+            };
+            let start = (source_y * width + source_x) * components;
+
+            output.extend_from_slice(&pixels[start..start + components]);
+        }
+    }
+
+    output
+}
+
+fn assert_orientations(name: &str, options: ZuneJpegOptions)
+{
+    let (stored, width, height) = decode(&read(name), options);
+    let components = stored.len() / (width * height);
+
+    for orientation in 1..=8
+    {
+        let data = with_orientation(name, orientation);
+        let (upright, out_width, out_height) =
+            decode(&data, options.set_auto_orientation(true));
+
+        if orientation >= 5
+        {
+            assert_eq!((out_width, out_height), (height, width));
+        }
+        else
+        {
+            assert_eq!((out_width, out_height), (width, height));
+        }
+        assert!(
+            upright == reference(&stored, width, height, components, orientation),
+            "{} with orientation {} isn't upright",
+            name,
+            orientation
+        );
+    }
+}
+
+#[test]
+fn baseline()
+{
+    // 75x53, the last MCU row is cut by the image height
+    assert_orientations("sampling_420.jpg", ZuneJpegOptions::new());
+    assert_orientations("sampling_411.jpg", ZuneJpegOptions::new());
+}
+
+#[test]
+fn progressive()
+{
+    assert_orientations("sampling_411_progressive.jpg", ZuneJpegOptions::new());
+}
+
+#[test]
+fn twelve_bit()
+{
+    assert_orientations("extended_12_bit_rgb.jpg", ZuneJpegOptions::new());
+}
+
+#[test]
+fn output_colorspaces()
+{
+    for colorspace in [ColorSpace::GRAYSCALE, ColorSpace::RGBA, ColorSpace::YCbCr]
+    {
+        let options = ZuneJpegOptions::new().set_out_colorspace(colorspace);
+
+        assert_orientations("sampling_420.jpg", options);
+    }
+}
+
+#[test]
+fn lossless()
+{
+    assert_orientations("lossless_predictor_1.jpg", ZuneJpegOptions::new());
+}
+
+#[test]
+fn orientation_ignored_by_default()
+{
+    let data = with_orientation("sampling_420.jpg", 6);
+    let (pixels, width, height) = decode(&data, ZuneJpegOptions::new());
+    let (stored, ..) = decode(&read("sampling_420.jpg"), ZuneJpegOptions::new());
+
+    assert_eq!((width, height), (75, 53));
+    assert!(pixels == stored);
+}
+
+#[test]
+fn dimensions_after_headers()
+{
+    let data = with_orientation("sampling_420.jpg", 8);
+    let mut decoder =
+        Decoder::new_with_options(ZuneJpegOptions::new().set_auto_orientation(true));
+
+    decoder.read_headers(&data).unwrap();
+
+    assert_eq!((decoder.width(), decoder.height()), (53, 75));
+
+    let info = decoder.info().unwrap();
+
+    assert_eq!((info.width, info.height), (53, 75));
+}
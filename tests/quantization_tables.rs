@@ -0,0 +1,156 @@
+//! Tests for images with 16 bit quantization tables
+//!
+//! The images were encoded at a very low quality so that the encoder
+//! had to use 16 bit tables, they were encoded from the same pattern as
+//! the one in `twelve_bit_images.rs`
+use zune_jpeg::{ColorSpace, Decoder, ZuneJpegOptions};
+
+use crate::common::read;
+
+mod common;
+
+const WIDTH: usize = 48;
+const HEIGHT: usize = 32;
+
+/// Luma of the pattern the test images were encoded from, in 12 bits
+fn source_luma() -> Vec<f64>
+{
+    let mut pixels = Vec::with_capacity(WIDTH * HEIGHT);
+
+    for y in 0..HEIGHT
+    {
+        for x in 0..WIDTH
+        {
+            let rgb: Vec<f64> = (0..3)
+                .map(|c| {
+                    let value =
+                        1500.0 * (x as f64 / 6.0 + c as f64).sin() * (y as f64 / 5.0).cos();
+
+                    f64::from(2048 + value as i32)
+                })
+                .collect();
+
+            pixels.push(0.299 * rgb[0] + 0.587 * rgb[1] + 0.114 * rgb[2]);
+        }
+    }
+    pixels
+}
+
+/// Rewrite all 8 bit quantization tables in the image as 16 bit tables
+fn widen_quantization_tables(data: &[u8]) -> Vec<u8>
+{
+    let mut output = data[..2].to_vec();
+    let mut pos = 2;
+
+    loop
+    {
+        let marker = data[pos + 1];
+        let length = usize::from(u16::from_be_bytes([data[pos + 2], data[pos + 3]]));
+
+        if marker == 0xDB
+        {
+            let mut tables = vec![];
+            let mut table = pos + 4;
+
+            while table < pos + 2 + length
+            {
+                assert_eq!(data[table] >> 4, 0, "Table is already 16 bits");
+
+                tables.push(data[table] | 0x10);
+                tables.extend(data[table + 1..table + 65].iter().flat_map(|x| [0, *x]));
+                table += 65;
+            }
+            output.extend_from_slice(&[0xFF, 0xDB]);
+            output.extend_from_slice(&(tables.len() as u16 + 2).to_be_bytes());
+            output.extend_from_slice(&tables);
+        }
+        else
+        {
+            output.extend_from_slice(&data[pos..pos + 2 + length]);
+        }
+        pos += 2 + length;
+
+        if marker == 0xDA
+        {
+            break;
+        }
+    }
+    output.extend_from_slice(&data[pos..]);
+    output
+}
+
+fn decode_grayscale(data: &[u8]) -> (Vec<u16>, u8)
+{
+    let mut decoder =
+        Decoder::new_with_options(ZuneJpegOptions::new().set_out_colorspace(ColorSpace::GRAYSCALE));
+
+    let pixels = decoder.decode_buffer_u16(data).unwrap();
+
+    assert_eq!(usize::from(decoder.width()), WIDTH);
+    assert_eq!(usize::from(decoder.height()), HEIGHT);
+
+    (pixels, decoder.precision())
+}
+
+fn assert_close_to_source(pixels: &[u16], precision: u8, tolerance: f64)
+{
+    let scale = f64::from(1 << (12 - precision));
+
+    for (pos, (a, b)) in pixels.iter().zip(source_luma()).enumerate()
+    {
+        let expected = b / scale;
+
+        assert!(
+            (f64::from(*a) - expected).abs() <= tolerance,
+            "Sample {} differs, decoded {} expected {}",
+            pos,
+            a,
+            expected
+        );
+    }
+}
+
+#[test]
+fn qt_16_bit_8_bit_precision()
+{
+    let (pixels, precision) = decode_grayscale(&read("qt_16_bit_8_bit_precision.jpg"));
+
+    assert_eq!(precision, 8);
+    assert_close_to_source(&pixels, precision, 64.0);
+}
+
+#[test]
+fn qt_16_bit_12_bit_precision()
+{
+    let (pixels, precision) = decode_grayscale(&read("qt_16_bit_12_bit_precision.jpg"));
+
+    assert_eq!(precision, 12);
+    assert_close_to_source(&pixels, precision, 256.0);
+}
+
+#[test]
+fn qt_16_bit_rgb()
+{
+    let pixels = Decoder::new()
+        .decode_buffer_u16(&read("qt_16_bit_12_bit_precision.jpg"))
+        .unwrap();
+
+    assert_eq!(pixels.len(), WIDTH * HEIGHT * 3);
+}
+
+#[test]
+fn qt_16_bit_same_as_8_bit()
+{
+    // the same tables stored in 16 bits should decode to exactly the same image
+    for name in ["huffman_third_index.jpg", "extended_12_bit_rgb.jpg"]
+    {
+        let data = read(name);
+
+        let narrow = Decoder::new().decode_buffer_u16(&data).unwrap();
+        let wide = Decoder::new()
+            .decode_buffer_u16(&widen_quantization_tables(&data))
+            .unwrap();
+
+        assert!(narrow == wide, "Images differ for {}", name);
+    }
+}
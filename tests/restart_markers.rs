@@ -0,0 +1,21 @@
+//! Tests for restart markers in progressive images
+use zune_jpeg::Decoder;
+
+fn decode(name: &str) -> Vec<u16>
+{
+    let path = env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/" + name;
+
+    Decoder::new()
+        .decode_buffer_u16(&std::fs::read(path).unwrap())
+        .unwrap()
+}
+
+#[test]
+fn progressive_restart_intervals()
+{
+    // the same coefficients, with restart intervals changing between scans and EOB
+    // runs across restart intervals, and without any restart markers
+    let expected = decode("progressive_no_restart.jpg");
+
+    assert!(decode("progressive_restart.jpg") == expected);
+}
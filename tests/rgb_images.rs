@@ -0,0 +1,168 @@
+//! Tests for images whose three components are stored as RGB without a YCbCr transform
+//!
+//! The images encode a known value for every component, the dimensions
+//! aren't a multiple of 16 so the ends of rows are tested too.
+use zune_jpeg::errors::DecodeErrors;
+use zune_jpeg::{ColorSpace, Decoder, ZuneJpegOptions};
+
+use crate::common::read;
+
+mod common;
+
+const WIDTH: usize = 45;
+const HEIGHT: usize = 29;
+
+/// The value encoded at `(x,y)` of component `c`
+#[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+fn value(x: usize, y: usize, c: usize) -> i32
+{
+    let x = x as f64;
+    let y = y as f64;
+    let c = c as f64;
+
+    (128.0 + 100.0 * (x / 8.0 + 2.0 * c).sin() * (y / 6.0 + c).cos()) as i32
+}
+
+fn decode(name: &str, colorspace: ColorSpace) -> Vec<u8>
+{
+    decode_with(name, ZuneJpegOptions::new().set_out_colorspace(colorspace))
+}
+
+fn decode_with(name: &str, options: ZuneJpegOptions) -> Vec<u8>
+{
+    let colorspace = options.get_out_colorspace();
+    let mut decoder = Decoder::new_with_options(options);
+    let pixels = decoder.decode_buffer(&read(name)).unwrap();
+
+    assert_eq!(usize::from(decoder.width()), WIDTH);
+    assert_eq!(usize::from(decoder.height()), HEIGHT);
+    assert_eq!(pixels.len(), WIDTH * HEIGHT * colorspace.num_components());
+
+    pixels
+}
+
+/// Check the RGB output of `name` against the encoded values
+fn assert_rgb(name: &str)
+{
+    assert_rgb_with(name, ZuneJpegOptions::new());
+}
+
+fn assert_rgb_with(name: &str, options: ZuneJpegOptions)
+{
+    let pixels = decode_with(name, options.set_out_colorspace(ColorSpace::RGB));
+
+    for (i, pixel) in pixels.chunks_exact(3).enumerate()
+    {
+        let (x, y) = (i % WIDTH, i / WIDTH);
+
+        for (c, sample) in pixel.iter().enumerate()
+        {
+            let expected = value(x, y, c);
+
+            assert!(
+                (i32::from(*sample) - expected).abs() <= 3,
+                "{}: component {} at ({},{}) is {}, expected {}",
+                name,
+                c,
+                x,
+                y,
+                sample,
+                expected
+            );
+        }
+    }
+}
+
+/// RGBA output should be the RGB output with an opaque alpha channel
+fn assert_rgba_matches_rgb(name: &str)
+{
+    assert_rgba_matches_rgb_with(name, ZuneJpegOptions::new());
+}
+
+fn assert_rgba_matches_rgb_with(name: &str, options: ZuneJpegOptions)
+{
+    let rgb = decode_with(name, options.set_out_colorspace(ColorSpace::RGB));
+    let rgba = decode_with(name, options.set_out_colorspace(ColorSpace::RGBA));
+
+    for (i, (rgb, rgba)) in rgb.chunks_exact(3).zip(rgba.chunks_exact(4)).enumerate()
+    {
+        assert_eq!(rgb, &rgba[..3], "{}: pixel {} differs", name, i);
+        assert_eq!(rgba[3], 255);
+    }
+}
+
+#[test]
+fn rgb_adobe()
+{
+    assert_rgb("rgb_adobe.jpg");
+}
+
+#[test]
+fn rgb_adobe_progressive()
+{
+    assert_rgb("rgb_adobe_progressive.jpg");
+}
+
+#[test]
+fn rgb_component_ids()
+{
+    // no Adobe segment, the 'R','G','B' identifiers say the image is RGB
+    assert_rgb("rgb_ids.jpg");
+}
+
+#[test]
+fn rgb_adobe_numeric_ids()
+{
+    // identifiers 1,2,3 but an Adobe transform of 0
+    assert_rgb("rgb_adobe_numeric_ids.jpg");
+}
+
+#[test]
+fn rgb_to_rgba()
+{
+    assert_rgba_matches_rgb("rgb_adobe.jpg");
+    assert_rgba_matches_rgb("rgb_adobe_progressive.jpg");
+}
+
+#[test]
+fn ycbcr_to_rgba()
+{
+    assert_rgba_matches_rgb("ycbcr_45x29.jpg");
+}
+
+#[test]
+fn rgb_without_simd()
+{
+    // the scalar IDCT rounds differently, so only check against the encoded values
+    let options = ZuneJpegOptions::new().set_use_unsafe(false);
+
+    assert_rgb_with("rgb_adobe.jpg", options);
+    assert_rgba_matches_rgb_with("rgb_adobe.jpg", options);
+    assert_rgba_matches_rgb_with("ycbcr_45x29.jpg", options);
+}
+
+#[test]
+fn rgb_to_grayscale()
+{
+    let rgb = decode("rgb_adobe.jpg", ColorSpace::RGB);
+    let gray = decode("rgb_adobe.jpg", ColorSpace::GRAYSCALE);
+
+    for (pixel, luma) in rgb.chunks_exact(3).zip(gray.iter())
+    {
+        let [r, g, b] = [0, 1, 2].map(|c| i32::from(pixel[c]));
+        let expected = (19595 * r + 38470 * g + 7471 * b + 32768) >> 16;
+
+        assert_eq!(i32::from(*luma), expected);
+    }
+}
+
+#[test]
+fn rgb_to_ycbcr_unsupported()
+{
+    let mut decoder =
+        Decoder::new_with_options(ZuneJpegOptions::new().set_out_colorspace(ColorSpace::YCbCr));
+
+    let err = decoder.decode_buffer(&read("rgb_adobe.jpg")).unwrap_err();
+
+    assert!(matches!(err, DecodeErrors::Format(_)), "{:?}", err);
+}
@@ -0,0 +1,231 @@
+//! Tests for images with chroma sampling layouts besides 4:4:4, 4:2:2 and 4:2:0
+//!
+//! The images encode a smooth known pattern so that the chroma lost to
+//! sub-sampling stays small.
+use zune_jpeg::{ColorSpace, Decoder, ZuneJpegOptions};
+
+use crate::common::read;
+
+mod common;
+
+const WIDTH: usize = 75;
+const HEIGHT: usize = 53;
+
+/// The value encoded at `(x,y)` of component `c`
+#[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+fn value(x: usize, y: usize, c: usize) -> i32
+{
+    let x = x as f64;
+    let y = y as f64;
+    let c = c as f64;
+
+    (128.0 + 90.0 * (x / 14.0 + 2.0 * c).sin() * (y / 11.0 + c).cos()) as i32
+}
+
+/// Check the RGB output of `name` against the encoded values
+#[allow(clippy::cast_precision_loss)]
+fn assert_rgb(name: &str, options: ZuneJpegOptions)
+{
+    let mut decoder = Decoder::new_with_options(options.set_out_colorspace(ColorSpace::RGB));
+    let pixels = decoder.decode_buffer(&read(name)).unwrap();
+
+    assert_eq!(usize::from(decoder.width()), WIDTH);
+    assert_eq!(usize::from(decoder.height()), HEIGHT);
+    assert_eq!(pixels.len(), WIDTH * HEIGHT * 3);
+
+    // chroma that changes within a sub-sampled block can't be recovered, so allow a
+    // large error for single samples but expect a small one on average
+    let mut total_error = 0;
+
+    for (i, pixel) in pixels.chunks_exact(3).enumerate()
+    {
+        let (x, y) = (i % WIDTH, i / WIDTH);
+
+        for (c, sample) in pixel.iter().enumerate()
+        {
+            let expected = value(x, y, c);
+            let error = (i32::from(*sample) - expected).abs();
+
+            assert!(
+                error <= 24,
+                "{}: component {} at ({},{}) is {}, expected {}",
+                name,
+                c,
+                x,
+                y,
+                sample,
+                expected
+            );
+            total_error += error;
+        }
+    }
+    assert!(
+        f64::from(total_error) / (WIDTH * HEIGHT * 3) as f64 <= 4.0,
+        "{}: average error is too large",
+        name
+    );
+}
+
+fn assert_layout(name: &str)
+{
+    assert_rgb(name, ZuneJpegOptions::new());
+    assert_rgb(name, ZuneJpegOptions::new().set_use_unsafe(false));
+}
+
+#[test]
+fn sampling_411()
+{
+    assert_layout("sampling_411.jpg");
+}
+
+#[test]
+fn sampling_411_progressive()
+{
+    assert_layout("sampling_411_progressive.jpg");
+}
+
+#[test]
+fn sampling_440()
+{
+    assert_layout("sampling_440.jpg");
+}
+
+#[test]
+fn sampling_410()
+{
+    assert_layout("sampling_410.jpg");
+}
+
+#[test]
+fn sampling_410_arithmetic()
+{
+    assert_layout("sampling_410_arithmetic.jpg");
+}
+
+#[test]
+fn sampling_factors_of_three()
+{
+    assert_layout("sampling_31.jpg");
+    assert_layout("sampling_32.jpg");
+}
+
+#[test]
+fn sampling_14()
+{
+    assert_layout("sampling_14.jpg");
+}
+
+#[test]
+fn sampling_422_and_420()
+{
+    assert_layout("sampling_422.jpg");
+    assert_layout("sampling_420.jpg");
+}
+
+#[test]
+fn chroma_sampled_differently()
+{
+    // Cb is 1x1 while Cr is 2x1
+    assert_layout("sampling_mixed.jpg");
+    assert_layout("sampling_mixed_progressive.jpg");
+}
+
+#[test]
+fn luma_not_the_largest()
+{
+    // Y is 1x1 while Cb is 2x2 and Cr is 1x2, so the luma is up-sampled too
+    assert_layout("sampling_luma_not_max.jpg");
+    assert_layout("sampling_luma_not_max_progressive.jpg");
+    assert_layout("sampling_luma_not_max_arithmetic.jpg");
+}
+
+#[test]
+fn fancy_upsampling_matches_libjpeg()
+{
+    // The references were written by `djpeg -ppm`, the output of 4:4:4 images differs by up
+    // to 3 from libjpeg, so an up-sampler which filters band edges differently shows up
+    // as larger errors every 8*v_max rows.
+    for name in ["sampling_420", "sampling_440", "sampling_luma_not_max"]
+    {
+        let reference = read(&format!("{}_djpeg.ppm", name));
+        let header = b"P6\n75 53\n255\n";
+
+        assert_eq!(&reference[..header.len()], header);
+
+        for options in [ZuneJpegOptions::new(), ZuneJpegOptions::new().set_use_unsafe(false)]
+        {
+            let pixels = Decoder::new_with_options(options)
+                .decode_buffer(&read(&format!("{}.jpg", name)))
+                .unwrap();
+
+            assert_eq!(pixels.len(), reference.len() - header.len());
+
+            for (i, (a, b)) in pixels.iter().zip(&reference[header.len()..]).enumerate()
+            {
+                assert!(
+                    a.abs_diff(*b) <= 3,
+                    "{}: pixel ({},{}) is {}, libjpeg has {}",
+                    name,
+                    i / 3 % WIDTH,
+                    i / 3 / WIDTH,
+                    a,
+                    b
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn grayscale_from_sub_sampled()
+{
+    // only the luma is decoded, it has to match the luma of the RGB output
+    for name in [
+        "sampling_411.jpg",
+        "sampling_32.jpg",
+        "sampling_14.jpg",
+        "sampling_luma_not_max.jpg",
+    ]
+    {
+        let rgb = Decoder::new().decode_buffer(&read(name)).unwrap();
+        let gray = Decoder::new_with_options(
+            ZuneJpegOptions::new().set_out_colorspace(ColorSpace::GRAYSCALE),
+        )
+        .decode_buffer(&read(name))
+        .unwrap();
+
+        assert_eq!(gray.len(), WIDTH * HEIGHT);
+
+        for (pixel, luma) in rgb.chunks_exact(3).zip(gray.iter())
+        {
+            let [r, g, b] = [0, 1, 2].map(|c| f64::from(pixel[c]));
+            let expected = 0.299 * r + 0.587 * g + 0.114 * b;
+
+            assert!((f64::from(*luma) - expected).abs() <= 3.0, "{}", name);
+        }
+    }
+}
+
+#[test]
+fn sampling_factor_out_of_range()
+{
+    let mut data = read("sampling_420.jpg");
+    let sof = data.windows(2).position(|w| w == [0xFF, 0xC0]).unwrap();
+
+    // the sampling factors of the first component, set horizontal to 5
+    data[sof + 11] = 0x52;
+
+    assert!(Decoder::new().decode_buffer(&data).is_err());
+}
+
+#[test]
+fn fractional_sampling_ratio()
+{
+    let mut data = read("sampling_31.jpg");
+    let sof = data.windows(2).position(|w| w == [0xFF, 0xC0]).unwrap();
+
+    // Cb becomes 2x1 with a 3x1 luma, it would have to be up-sampled by 1.5
+    data[sof + 14] = 0x21;
+
+    assert!(Decoder::new().decode_buffer(&data).is_err());
+}
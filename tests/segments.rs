@@ -0,0 +1,133 @@
+//! Tests for COM segments and APPn segments kept as they are
+use zune_jpeg::{Decoder, Segment};
+
+use crate::common::{headers, with_segments};
+
+mod common;
+
+/// A segment with the second marker byte `marker`
+fn segment(marker: u8, data: &[u8]) -> Vec<u8>
+{
+    let mut segment = vec![0xFF, marker];
+
+    segment.extend_from_slice(&u16::try_from(data.len() + 2).unwrap().to_be_bytes());
+    segment.extend_from_slice(data);
+
+    segment
+}
+
+#[test]
+fn segments_in_file_order()
+{
+    let segments = [
+        segment(0xFE, b"Created with GIMP"),
+        segment(0xEF, b"Ducky"),
+        segment(0xE1, b"unknown app1"),
+    ];
+    let decoder = headers(&with_segments(&segments));
+    let found = decoder
+        .segments()
+        .iter()
+        .filter(|x| x.offset < 2 + segments.concat().len())
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        found,
+        [
+            &Segment {
+                marker: 0xFE,
+                offset: 2,
+                data:   b"Created with GIMP".to_vec(),
+            },
+            &Segment {
+                marker: 0xEF,
+                offset: 2 + segments[0].len(),
+                data:   b"Ducky".to_vec(),
+            },
+            &Segment {
+                marker: 0xE1,
+                offset: 2 + segments[0].len() + segments[1].len(),
+                data:   b"unknown app1".to_vec(),
+            },
+        ]
+    );
+    assert!(found[0].is_comment());
+    assert_eq!(found[0].app(), None);
+    assert_eq!(found[1].app(), Some(15));
+    assert_eq!(found[2].app(), Some(1));
+}
+
+#[test]
+fn interpreted_segments_are_not_kept()
+{
+    let icc = [&b"ICC_PROFILE\0"[..], &[1, 1], &[0; 100]].concat();
+    let xmp = [&b"http://ns.adobe.com/xap/1.0/\0"[..], b"<x:xmpmeta/>"].concat();
+    let segments = [
+        segment(0xE2, &icc),
+        segment(0xE1, &xmp),
+        segment(0xED, b"Photoshop 3.0\0"),
+        segment(0xEE, b"Adobe\0\x64\0\0\0\0\x01"),
+        segment(0xFE, b"comment"),
+    ];
+    let decoder = headers(&with_segments(&segments));
+    let kept = decoder
+        .segments()
+        .iter()
+        .filter(|x| x.offset < 2 + segments.concat().len())
+        .collect::<Vec<_>>();
+
+    assert_eq!(kept.len(), 1);
+    assert!(kept[0].is_comment());
+    assert_eq!(kept[0].offset, 2 + segments[..4].concat().len());
+    assert!(decoder.icc_profile().is_some());
+    assert!(decoder.xmp().is_some());
+}
+
+#[test]
+fn unrecognized_segments_of_interpreted_markers_are_kept()
+{
+    let flashpix = segment(0xE2, b"FPXR\0\0\0\0");
+    let short_adobe = segment(0xEE, b"Adobe");
+    let decoder = headers(&with_segments(&[flashpix, short_adobe]));
+    let markers = decoder
+        .segments()
+        .iter()
+        .map(|x| x.marker)
+        .collect::<Vec<_>>();
+
+    assert!(markers.starts_with(&[0xE2, 0xEE]));
+}
+
+#[test]
+fn empty_segment()
+{
+    let decoder = headers(&with_segments(&[segment(0xFE, b"")]));
+
+    assert_eq!(decoder.segments()[0].data, b"");
+}
+
+#[test]
+fn image_still_decodes()
+{
+    let data = with_segments(&[segment(0xFE, b"comment"), segment(0xEA, &[0xFF; 300])]);
+    let mut decoder = Decoder::new();
+
+    decoder.decode_buffer(&data).unwrap();
+
+    assert_eq!(decoder.segments()[1].data, [0xFF; 300]);
+}
+
+#[test]
+fn headers_read_twice()
+{
+    let data = with_segments(&[segment(0xFE, b"comment")]);
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&data).unwrap();
+
+    let count = decoder.segments().len();
+
+    decoder.decode_buffer(&data).unwrap();
+
+    assert_eq!(decoder.segments().len(), count);
+}
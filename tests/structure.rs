@@ -0,0 +1,184 @@
+//! Tests for listing the markers of an image with `JpegStructure`
+use zune_jpeg::errors::DecodeErrors;
+use zune_jpeg::marker::Marker;
+use zune_jpeg::structure::{EntryKind, JpegStructure};
+
+use crate::common::read;
+
+mod common;
+
+/// Check the entries cover the whole buffer, one after the other
+fn check_extents(data: &[u8])
+{
+    let structure = JpegStructure::parse(data).unwrap();
+    let mut position = 0;
+
+    for entry in structure.entries()
+    {
+        assert!(entry.offset >= position, "{:?} overlaps", entry);
+        // only fill bytes may come between entries
+        assert!(data[position..entry.offset].iter().all(|x| *x == 0xFF));
+
+        position = entry.offset + entry.length;
+    }
+    assert_eq!(position, data.len());
+}
+
+#[test]
+fn baseline()
+{
+    let data = read("sampling_420.jpg");
+    let structure = JpegStructure::parse(&data).unwrap();
+    let entries = structure.entries();
+
+    assert_eq!(entries[0].kind, EntryKind::Marker(Marker::SOI));
+    assert_eq!((entries[0].offset, entries[0].length), (0, 2));
+    assert_eq!(entries[1].kind, EntryKind::Marker(Marker::APP(0)));
+    assert!(entries[1].data.starts_with(b"JFIF\0"));
+
+    let kinds = entries.iter().map(|x| x.kind).collect::<Vec<_>>();
+
+    assert_eq!(
+        kinds[kinds.len() - 3..],
+        [
+            EntryKind::Marker(Marker::SOS),
+            EntryKind::EntropyCoded,
+            EntryKind::Marker(Marker::EOI)
+        ]
+    );
+    assert_eq!(structure.find(Marker::SOF(0)).count(), 1);
+    assert_eq!(structure.trailing_data(), None);
+
+    check_extents(&data);
+}
+
+#[test]
+fn segment_payload_is_borrowed()
+{
+    let data = read("sampling_420.jpg");
+    let structure = JpegStructure::parse(&data).unwrap();
+    let sof = structure.find(Marker::SOF(0)).next().unwrap();
+    let length = usize::from(u16::from_be_bytes([data[sof.offset + 2], data[sof.offset + 3]]));
+
+    assert_eq!(sof.length, length + 2);
+    assert_eq!(sof.data, &data[sof.offset + 4..sof.offset + 2 + length]);
+    // precision of the frame
+    assert_eq!(sof.data[0], 8);
+}
+
+#[test]
+fn every_scan_is_listed()
+{
+    for name in ["sampling_mixed_progressive.jpg", "scans_separate.jpg"]
+    {
+        let data = read(name);
+        let structure = JpegStructure::parse(&data).unwrap();
+        let scans = structure.find(Marker::SOS).count();
+        let entropy_coded = structure
+            .entries()
+            .iter()
+            .filter(|x| x.kind == EntryKind::EntropyCoded)
+            .count();
+
+        assert!(scans > 1);
+        assert_eq!(scans, entropy_coded);
+
+        check_extents(&data);
+    }
+}
+
+#[test]
+fn restart_markers_stay_in_entropy_coded_data()
+{
+    let data = read("scans_separate_restart.jpg");
+    let structure = JpegStructure::parse(&data).unwrap();
+
+    assert!(structure.find(Marker::DRI).count() > 0);
+    assert!(structure
+        .entries()
+        .iter()
+        .all(|x| !matches!(x.kind, EntryKind::Marker(Marker::RST(_)))));
+    assert!(structure
+        .entries()
+        .iter()
+        .filter(|x| x.kind == EntryKind::EntropyCoded)
+        .any(|x| x.data.windows(2).any(|x| x == [0xFF, 0xD0])));
+
+    check_extents(&data);
+}
+
+#[test]
+fn trailing_data()
+{
+    let mut data = read("sampling_420.jpg");
+
+    data.extend_from_slice(b"trailing data");
+
+    let structure = JpegStructure::parse(&data).unwrap();
+
+    assert_eq!(structure.trailing_data(), Some(&b"trailing data"[..]));
+
+    check_extents(&data);
+}
+
+#[test]
+fn trailing_data_of_a_phone_image()
+{
+    let data = read("google_pixel.jpg");
+    let structure = JpegStructure::parse(&data).unwrap();
+
+    assert!(structure.trailing_data().unwrap().len() > 1000);
+
+    check_extents(&data);
+}
+
+#[test]
+fn hierarchical()
+{
+    let data = read("hierarchical_huffman.jpg");
+    let structure = JpegStructure::parse(&data).unwrap();
+
+    assert_eq!(structure.find(Marker::DHP).count(), 1);
+
+    check_extents(&data);
+}
+
+#[test]
+fn fill_bytes_and_unknown_bytes()
+{
+    let mut data = read("sampling_420.jpg");
+
+    // fill bytes before the APP0 marker, and stray bytes after it
+    data.splice(2..2, [0xFF, 0xFF]);
+    data.splice(22..22, *b"junk");
+
+    let structure = JpegStructure::parse(&data).unwrap();
+    let entries = structure.entries();
+
+    assert_eq!(entries[1].kind, EntryKind::Marker(Marker::APP(0)));
+    assert_eq!(entries[1].offset, 4);
+    assert_eq!(entries[2].kind, EntryKind::Unknown);
+    assert_eq!(entries[2].data, b"junk");
+
+    check_extents(&data);
+}
+
+#[test]
+fn truncated_segment()
+{
+    let data = read("sampling_420.jpg");
+    let structure = JpegStructure::parse(&data[..30]).unwrap();
+    let last = structure.entries().last().unwrap();
+
+    assert_eq!(last.offset + last.length, 30);
+}
+
+#[test]
+fn not_a_jpeg()
+{
+    assert!(matches!(
+        JpegStructure::parse(b"\x89PNG\r\n"),
+        Err(DecodeErrors::IllegalMagicBytes(0x8950))
+    ));
+    assert!(JpegStructure::parse(&[]).is_err());
+}
@@ -0,0 +1,155 @@
+//! Tests for reading quantization and Huffman tables, and estimating the quality
+use zune_jpeg::marker::Marker;
+use zune_jpeg::structure::JpegStructure;
+use zune_jpeg::{Decoder, HuffmanCodes};
+
+use crate::common::read;
+
+mod common;
+
+fn headers(name: &str) -> Decoder
+{
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&read(name)).unwrap();
+
+    decoder
+}
+
+#[test]
+fn estimated_quality()
+{
+    // qualities the images were encoded with by cjpeg
+    for (name, quality) in [
+        ("sampling_420.jpg", 95),
+        ("arithmetic_420.jpg", 90),
+        ("arithmetic_grayscale.jpg", 90),
+        ("rgb_adobe.jpg", 95),
+        ("google_pixel_progressive.jpg", 94),
+        ("large_vertical_samp_7680_4320.jpg", 80),
+        ("extended_12_bit_rgb.jpg", 100),
+        // 16 bit tables
+        ("qt_16_bit_8_bit_precision.jpg", 4),
+    ]
+    {
+        assert_eq!(headers(name).estimated_quality(), Some(quality), "{}", name);
+    }
+}
+
+#[test]
+fn lossless_images_have_no_quality()
+{
+    let decoder = headers("lossless_predictor_1.jpg");
+
+    assert_eq!(decoder.estimated_quality(), None);
+    assert_eq!(decoder.quantization_table(0), None);
+}
+
+#[test]
+fn quantization_tables_in_natural_order()
+{
+    // table K.1 scaled by 1250% for quality 4
+    let decoder = headers("qt_16_bit_8_bit_precision.jpg");
+    let table = decoder.quantization_table(0).unwrap();
+
+    assert_eq!(table[0], 200);
+    // first row, then the first column
+    assert_eq!(table[1], 138);
+    assert_eq!(table[8], 150);
+    assert_eq!(table[63], 1238);
+    assert!(decoder.quantization_table(1).is_some());
+    assert_eq!(decoder.quantization_table(2), None);
+    assert_eq!(decoder.quantization_table(4), None);
+}
+
+#[test]
+fn quantization_tables_are_kept_after_decoding()
+{
+    let data = read("sampling_420.jpg");
+    let mut decoder = Decoder::new();
+
+    decoder.decode_buffer(&data).unwrap();
+
+    assert!(decoder.quantization_table(0).is_some());
+    assert_eq!(decoder.estimated_quality(), Some(95));
+}
+
+/// Huffman tables of the DHT segments of `data`, with their class and index
+fn dht_tables(data: &[u8]) -> Vec<(u8, u8, HuffmanCodes)>
+{
+    let structure = JpegStructure::parse(data).unwrap();
+    let mut tables = Vec::new();
+
+    for entry in structure.find(Marker::DHT)
+    {
+        let mut segment = entry.data;
+
+        while !segment.is_empty()
+        {
+            let mut counts = [0; 16];
+
+            counts.copy_from_slice(&segment[1..17]);
+
+            let count = counts.iter().map(|x| usize::from(*x)).sum::<usize>();
+            let codes = HuffmanCodes {
+                counts,
+                values: segment[17..17 + count].to_vec(),
+            };
+
+            tables.push((segment[0] >> 4, segment[0] & 15, codes));
+            segment = &segment[17 + count..];
+        }
+    }
+
+    tables
+}
+
+#[test]
+fn huffman_tables()
+{
+    for name in ["sampling_420.jpg", "huffman_third_index.jpg", "google_pixel.jpg"]
+    {
+        let data = read(name);
+        let mut decoder = Decoder::new();
+
+        decoder.read_headers(&data).unwrap();
+
+        let tables = dht_tables(&data);
+
+        assert!(!tables.is_empty());
+
+        for (class, index, codes) in tables
+        {
+            let table = if class == 0
+            {
+                decoder.dc_huffman_table(usize::from(index))
+            }
+            else
+            {
+                decoder.ac_huffman_table(usize::from(index))
+            };
+
+            assert_eq!(table, Some(codes), "{} {} {}", name, class, index);
+        }
+    }
+}
+
+#[test]
+fn standard_dc_luminance_table()
+{
+    let table = headers("sampling_420.jpg").dc_huffman_table(0).unwrap();
+
+    // table K.3
+    assert_eq!(table.counts, [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(table.values, (0..12).collect::<Vec<u8>>());
+}
+
+#[test]
+fn arithmetic_images_have_no_huffman_tables()
+{
+    let decoder = headers("arithmetic_420.jpg");
+
+    assert_eq!(decoder.dc_huffman_table(0), None);
+    assert_eq!(decoder.ac_huffman_table(0), None);
+    assert!(decoder.quantization_table(0).is_some());
+}
@@ -0,0 +1,133 @@
+//! Tests for images with 12 bits of sample precision
+//!
+//! The images were encoded from a synthetic pattern which we regenerate here
+//! and compare the decoded samples against.
+use zune_jpeg::{ColorSpace, Decoder, ZuneJpegOptions};
+
+use crate::common::read;
+
+mod common;
+
+const WIDTH: usize = 48;
+const HEIGHT: usize = 32;
+/// Maximum difference allowed between decoded and original samples
+const TOLERANCE: i32 = 8;
+
+/// The pattern the test images were encoded from, as RGB samples
+fn source_pattern() -> Vec<i32>
+{
+    let mut pixels = Vec::with_capacity(WIDTH * HEIGHT * 3);
+
+    for y in 0..HEIGHT
+    {
+        for x in 0..WIDTH
+        {
+            for c in 0..3
+            {
+                let value = 1500.0 * (x as f64 / 6.0 + c as f64).sin() * (y as f64 / 5.0).cos();
+
+                pixels.push(2048 + value as i32);
+            }
+        }
+    }
+    pixels
+}
+
+fn assert_close(decoded: &[u16], expected: &[i32])
+{
+    assert_eq!(decoded.len(), expected.len());
+
+    for (pos, (a, b)) in decoded.iter().zip(expected).enumerate()
+    {
+        assert!(
+            (i32::from(*a) - b).abs() <= TOLERANCE,
+            "Sample {} differs, decoded {} expected {}",
+            pos,
+            a,
+            b
+        );
+    }
+}
+
+#[test]
+fn extended_12_bit_rgb()
+{
+    let mut decoder = Decoder::new();
+
+    let pixels = decoder
+        .decode_buffer_u16(&read("extended_12_bit_rgb.jpg"))
+        .unwrap();
+
+    assert_eq!(decoder.precision(), 12);
+    assert_eq!(usize::from(decoder.width()), WIDTH);
+    assert_eq!(usize::from(decoder.height()), HEIGHT);
+
+    assert_close(&pixels, &source_pattern());
+}
+
+#[test]
+fn extended_12_bit_grayscale()
+{
+    let mut decoder =
+        Decoder::new_with_options(ZuneJpegOptions::new().set_out_colorspace(ColorSpace::GRAYSCALE));
+
+    let pixels = decoder
+        .decode_buffer_u16(&read("extended_12_bit_grayscale.jpg"))
+        .unwrap();
+
+    let expected: Vec<i32> = source_pattern()
+        .chunks_exact(3)
+        .map(|rgb| {
+            let luma = 0.299 * f64::from(rgb[0]) + 0.587 * f64::from(rgb[1]) + 0.114 * f64::from(rgb[2]);
+
+            luma.round() as i32
+        })
+        .collect();
+
+    assert_close(&pixels, &expected);
+}
+
+#[test]
+fn progressive_12_bit_rgb()
+{
+    let mut decoder = Decoder::new();
+
+    let pixels = decoder
+        .decode_buffer_u16(&read("progressive_12_bit_rgb.jpg"))
+        .unwrap();
+
+    assert_eq!(decoder.precision(), 12);
+
+    assert_close(&pixels, &source_pattern());
+}
+
+#[test]
+fn twelve_bit_scaled_to_8_bits()
+{
+    let data = read("extended_12_bit_rgb.jpg");
+
+    let full = Decoder::new().decode_buffer_u16(&data).unwrap();
+    let scaled = Decoder::new().decode_buffer(&data).unwrap();
+
+    assert_eq!(full.len(), scaled.len());
+
+    for (a, b) in full.iter().zip(scaled.iter())
+    {
+        assert_eq!((a >> 4) as u8, *b);
+    }
+}
+
+#[test]
+fn eight_bit_widened_to_u16()
+{
+    let path = env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/huffman_third_index.jpg";
+    let data = std::fs::read(path).unwrap();
+
+    let mut decoder = Decoder::new();
+
+    let full = decoder.decode_buffer_u16(&data).unwrap();
+    let narrow = Decoder::new().decode_buffer(&data).unwrap();
+
+    assert_eq!(decoder.precision(), 8);
+    assert!(full.iter().zip(narrow.iter()).all(|(a, b)| *a == u16::from(*b)));
+}
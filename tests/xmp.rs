@@ -0,0 +1,197 @@
+//! Tests for XMP packets and extended XMP chunks in APP1 segments
+use zune_jpeg::Decoder;
+
+use crate::common::{headers, read, with_segments};
+
+mod common;
+
+const GUID: &str = "0123456789ABCDEF0123456789ABCDEF";
+
+fn app1(data: &[u8]) -> Vec<u8>
+{
+    let mut segment = vec![0xFF, 0xE1];
+
+    segment.extend_from_slice(&u16::try_from(data.len() + 2).unwrap().to_be_bytes());
+    segment.extend_from_slice(data);
+
+    segment
+}
+
+/// An APP1 segment with the main XMP packet
+fn main_packet(packet: &str) -> Vec<u8>
+{
+    app1(&[b"http://ns.adobe.com/xap/1.0/\0", packet.as_bytes()].concat())
+}
+
+/// An APP1 segment with a chunk of the extended XMP packet
+fn extended_chunk(guid: &str, length: usize, offset: usize, data: &[u8]) -> Vec<u8>
+{
+    app1(
+        &[
+            &b"http://ns.adobe.com/xmp/extension/\0"[..],
+            guid.as_bytes(),
+            &u32::try_from(length).unwrap().to_be_bytes(),
+            &u32::try_from(offset).unwrap().to_be_bytes(),
+            data,
+        ]
+        .concat(),
+    )
+}
+
+/// A main packet pointing to the extended packet with `guid`
+fn packet_with_extension(guid: &str) -> String
+{
+    format!(
+        concat!(
+            "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"><rdf:RDF>",
+            "<rdf:Description xmp:Rating=\"4\" xmpNote:HasExtendedXMP=\"{}\"/>",
+            "</rdf:RDF></x:xmpmeta>"
+        ),
+        guid
+    )
+}
+
+/// An extended packet, long enough to be split
+fn extended_packet() -> String
+{
+    let caption = "A caption that doesn't fit in the main packet. ".repeat(40);
+
+    format!("<x:xmpmeta><dc:description>{}</dc:description></x:xmpmeta>", caption)
+}
+
+/// Segments holding `packet` split into chunks of `size` bytes, in reverse order
+fn split(guid: &str, packet: &str, size: usize) -> Vec<Vec<u8>>
+{
+    let bytes = packet.as_bytes();
+
+    (0..bytes.len())
+        .step_by(size)
+        .rev()
+        .map(|offset| {
+            let end = (offset + size).min(bytes.len());
+
+            extended_chunk(guid, bytes.len(), offset, &bytes[offset..end])
+        })
+        .collect()
+}
+
+#[test]
+fn main_packet_only()
+{
+    let packet = "<x:xmpmeta><rdf:RDF><rdf:Description xmp:Rating=\"5\"/></rdf:RDF></x:xmpmeta>";
+    let decoder = headers(&with_segments(&[main_packet(packet)]));
+
+    assert_eq!(decoder.xmp(), Some(packet));
+    assert_eq!(decoder.extended_xmp(), None);
+}
+
+#[test]
+fn extended_chunks_out_of_order()
+{
+    let packet = packet_with_extension(GUID);
+    let extended = extended_packet();
+    let mut segments = vec![main_packet(&packet)];
+
+    segments.extend(split(GUID, &extended, 700));
+
+    let decoder = headers(&with_segments(&segments));
+
+    assert_eq!(decoder.xmp(), Some(packet.as_str()));
+    assert_eq!(decoder.extended_xmp(), Some(extended));
+}
+
+#[test]
+fn property_written_as_an_element()
+{
+    let packet = format!(
+        "<rdf:Description><xmpNote:HasExtendedXMP>{}</xmpNote:HasExtendedXMP>",
+        GUID
+    );
+    let extended = extended_packet();
+    let mut segments = split(GUID, &extended, 1000);
+
+    // the main packet doesn't have to come first
+    segments.push(main_packet(&packet));
+
+    let decoder = headers(&with_segments(&segments));
+
+    assert_eq!(decoder.extended_xmp(), Some(extended));
+}
+
+#[test]
+fn chunks_of_other_packets_are_ignored()
+{
+    let other = "FEDCBA9876543210FEDCBA9876543210";
+    let extended = extended_packet();
+    let mut segments = vec![main_packet(&packet_with_extension(GUID))];
+
+    segments.extend(split(other, "<x:xmpmeta>stale</x:xmpmeta>", 10));
+    segments.extend(split(GUID, &extended, 500));
+
+    let decoder = headers(&with_segments(&segments));
+
+    assert_eq!(decoder.extended_xmp(), Some(extended));
+}
+
+#[test]
+fn missing_chunk()
+{
+    let mut segments = vec![main_packet(&packet_with_extension(GUID))];
+    let mut chunks = split(GUID, &extended_packet(), 500);
+
+    chunks.remove(1);
+    segments.extend(chunks);
+
+    let decoder = headers(&with_segments(&segments));
+
+    assert!(decoder.xmp().is_some());
+    assert_eq!(decoder.extended_xmp(), None);
+}
+
+#[test]
+fn extension_without_a_reference()
+{
+    let packet = "<x:xmpmeta><rdf:RDF><rdf:Description xmp:Rating=\"1\"/></rdf:RDF></x:xmpmeta>";
+    let mut segments = vec![main_packet(packet)];
+
+    segments.extend(split(GUID, &extended_packet(), 500));
+
+    let decoder = headers(&with_segments(&segments));
+
+    assert_eq!(decoder.extended_xmp(), None);
+}
+
+#[test]
+fn invalid_utf8_packet()
+{
+    let segment = app1(&[&b"http://ns.adobe.com/xap/1.0/\0"[..], &[0xC3, 0x28]].concat());
+    let mut decoder = Decoder::new();
+
+    // the image still decodes
+    decoder.decode_buffer(&with_segments(&[segment])).unwrap();
+
+    assert_eq!(decoder.xmp(), None);
+}
+
+#[test]
+fn exif_and_xmp()
+{
+    let packet = packet_with_extension(GUID);
+    let mut data = read("exif_big_endian.jpg");
+
+    data.splice(2..2, main_packet(&packet));
+
+    let decoder = headers(&data);
+
+    assert_eq!(decoder.exif().unwrap().model(), Some("EOS 5D"));
+    assert_eq!(decoder.xmp(), Some(packet.as_str()));
+}
+
+#[test]
+fn no_xmp()
+{
+    let decoder = headers(&read("sampling_420.jpg"));
+
+    assert_eq!(decoder.xmp(), None);
+    assert_eq!(decoder.extended_xmp(), None);
+}